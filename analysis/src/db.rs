@@ -11,5 +11,7 @@ pub async fn get_dbhandler() -> DBHandler {
             eprintln!("connection error: {}", e);
         }
     });
-    DBHandler { client }
+    DBHandler {
+        client: client.into(),
+    }
 }
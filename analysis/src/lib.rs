@@ -3,6 +3,8 @@ pub mod kafka_handler;
 pub mod utils;
 
 use kafka_handler::KafkaReader;
+use model::general_model::VersionWithTag;
+use model::repo_sync_model::{MessageKind, MessageModel, UploadStatus};
 use serde::Deserialize;
 use std::error::Error;
 use std::fs;
@@ -41,9 +43,6 @@ struct Config {
 ///    - The main process should only handle scheduling and general exception handling, delegating specific details to the tool implementation
 /// 5. The current implementation greatly reduces the flexibility and extensibility of tools.json,
 ///    which goes against the original intention of configuration-driven and plugin-based design.
-#[allow(unused_variables)]
-#[allow(clippy::needless_borrows_for_generic_args)]
-#[allow(clippy::let_unit_value)]
 pub async fn analyse_once(
     kafka_reader: &KafkaReader,
     output_path: &str,
@@ -53,6 +52,22 @@ pub async fn analyse_once(
     let tools = config.tools;
 
     let message = kafka_reader.read_single_message().await.unwrap();
+    let upload_id =
+        (message.message_kind == MessageKind::User).then(|| message.extra_field.clone());
+    report_upload(upload_id.as_deref(), UploadStatus::Analysing, None).await;
+    let result = sensleak_scan(&tools, &message, output_path).await;
+    report_upload_result(upload_id.as_deref(), &result).await;
+    result
+}
+
+#[allow(unused_variables)]
+#[allow(clippy::needless_borrows_for_generic_args)]
+#[allow(clippy::let_unit_value)]
+async fn sensleak_scan(
+    tools: &[ToolConfig],
+    message: &MessageModel,
+    output_path: &str,
+) -> Result<(), Box<dyn Error>> {
     tracing::info!("Analysis receive {:?}", message);
     tracing::info!(
         "name:{},git_url:{:?}",
@@ -71,7 +86,7 @@ pub async fn analyse_once(
 
     tracing::info!("code_path:{:?}", repo_path.clone());
 
-    for tool in &tools {
+    for tool in tools {
         for command in &tool.run {
             let output_file = PathBuf::from(output_path)
                 .join(&tool.name)
@@ -117,9 +132,6 @@ pub async fn analyse_once(
     Ok(())
 }
 
-#[allow(unused_variables)]
-#[allow(clippy::needless_borrows_for_generic_args)]
-#[allow(clippy::let_unit_value)]
 /// Input: a message with version
 /// output: a file
 pub async fn analyse_once_mirchecker(
@@ -127,6 +139,20 @@ pub async fn analyse_once_mirchecker(
     output_path: &str,
 ) -> Result<(), Box<dyn Error>> {
     let message = kafka_reader.read_single_message_mirchecker().await.unwrap();
+    let upload_id = message.upload_id.clone();
+    report_upload(upload_id.as_deref(), UploadStatus::Analysing, None).await;
+    let result = mirchecker_version(&message, output_path).await;
+    report_upload_result(upload_id.as_deref(), &result).await;
+    result
+}
+
+#[allow(unused_variables)]
+#[allow(clippy::needless_borrows_for_generic_args)]
+#[allow(clippy::let_unit_value)]
+async fn mirchecker_version(
+    message: &VersionWithTag,
+    output_path: &str,
+) -> Result<(), Box<dyn Error>> {
     tracing::info!("Analysis receive {:?}", message);
     tracing::info!(
         "name:{},git_url:{:?}",
@@ -252,3 +278,25 @@ pub async fn analyse_once_mirchecker(
 
     Ok(())
 }
+
+/// Moves the user upload a message came from, if any, to `status`; see
+/// `UploadStatus::previous` for the moves that are kept.
+async fn report_upload(upload_id: Option<&str>, status: UploadStatus, message: Option<&str>) {
+    let Some(id) = upload_id else {
+        return;
+    };
+    let dbhandler = get_dbhandler().await;
+    if let Err(e) = dbhandler
+        .update_upload_status(id, status, None, message)
+        .await
+    {
+        tracing::error!("failed to set upload {} to {}: {}", id, status.as_str(), e);
+    }
+}
+
+async fn report_upload_result(upload_id: Option<&str>, result: &Result<(), Box<dyn Error>>) {
+    match result {
+        Ok(()) => report_upload(upload_id, UploadStatus::Analysed, None).await,
+        Err(e) => report_upload(upload_id, UploadStatus::Failed, Some(&e.to_string())).await,
+    }
+}
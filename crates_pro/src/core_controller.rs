@@ -64,6 +64,13 @@ impl CoreController {
                         .unwrap_or_else(|x| panic!("{}", x));
                 }
 
+                match tudriver::migration::migrate_from_env().await {
+                    Ok(versions) => {
+                        tracing::info!("graph schema migrated, applied {:?}", versions)
+                    }
+                    Err(e) => tracing::error!("Failed to migrate graph schema: {}", e),
+                }
+
                 let mut import_driver = ImportDriver::new(dont_clone).await;
                 let mut count = 0;
                 let is_importing = Arc::new(AtomicBool::new(false));
@@ -0,0 +1,400 @@
+//! Version ranges of security advisories.
+//!
+//! RustSec advisories are stored with their `patched` and `unaffected` lists
+//! joined by `|`, each entry being a comma separated Cargo requirement such as
+//! `>= 1.8.4, < 1.9.0`. CVE records only carry a `start_version` and an
+//! `end_version`, both inclusive and either of them possibly missing.
+//!
+//! Both are parsed into `semver::VersionReq` sets here, so that every lookup
+//! answers "is this version affected?" the same way.
+
+use std::fmt;
+
+use semver::{Comparator, Op, Version, VersionReq};
+
+/// Separator between the requirements of a `patched`/`unaffected` list.
+const REQ_SEPARATOR: char = '|';
+
+/// Values written into the advisory tables when a field is empty. `*` is not
+/// one of them: in a `patched` or `unaffected` list it matches every version.
+const EMPTY_MARKERS: [&str; 3] = ["", "Null", "None"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdvisoryRangeError {
+    /// A `patched`, `unaffected` or CVE bound could not be parsed.
+    InvalidRequirement { input: String, reason: String },
+    /// The version being checked is not a semver version.
+    InvalidVersion { input: String, reason: String },
+}
+
+impl fmt::Display for AdvisoryRangeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AdvisoryRangeError::InvalidRequirement { input, reason } => {
+                write!(f, "invalid advisory requirement '{}': {}", input, reason)
+            }
+            AdvisoryRangeError::InvalidVersion { input, reason } => {
+                write!(f, "invalid version '{}': {}", input, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AdvisoryRangeError {}
+
+pub fn parse_version(raw: &str) -> Result<Version, AdvisoryRangeError> {
+    Version::parse(raw.trim()).map_err(|e| AdvisoryRangeError::InvalidVersion {
+        input: raw.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn is_empty_marker(raw: &str) -> bool {
+    EMPTY_MARKERS.contains(&raw.trim())
+}
+
+/// A CVE bound that is empty or `*` leaves that side of the range open.
+fn is_open_bound(raw: &str) -> bool {
+    is_empty_marker(raw) || raw.trim() == "*"
+}
+
+/// A union of requirements: a version is matched if any of them matches.
+#[derive(Debug, Clone, Default)]
+pub struct VersionReqSet {
+    reqs: Vec<VersionReq>,
+}
+
+impl VersionReqSet {
+    pub fn parse(raw: &str) -> Result<Self, AdvisoryRangeError> {
+        let mut reqs = vec![];
+        for part in raw.split(REQ_SEPARATOR) {
+            if is_empty_marker(part) {
+                continue;
+            }
+            let req = VersionReq::parse(part.trim()).map_err(|e| {
+                AdvisoryRangeError::InvalidRequirement {
+                    input: part.to_string(),
+                    reason: e.to_string(),
+                }
+            })?;
+            reqs.push(req);
+        }
+        Ok(VersionReqSet { reqs })
+    }
+
+    /// Unlike `VersionReq::matches`, pre-releases are compared by their
+    /// precedence: an advisory patched in `>= 1.2.0` also covers `1.3.0-rc.1`.
+    pub fn matches(&self, version: &Version) -> bool {
+        self.reqs.iter().any(|req| req_matches(req, version))
+    }
+}
+
+/// `patched` and `unaffected` lists of a RustSec advisory.
+#[derive(Debug, Clone, Default)]
+pub struct RustsecRange {
+    patched: VersionReqSet,
+    unaffected: VersionReqSet,
+}
+
+impl RustsecRange {
+    pub fn parse(patched: &str, unaffected: &str) -> Result<Self, AdvisoryRangeError> {
+        Ok(RustsecRange {
+            patched: VersionReqSet::parse(patched)?,
+            unaffected: VersionReqSet::parse(unaffected)?,
+        })
+    }
+
+    pub fn is_affected(&self, version: &Version) -> bool {
+        !self.patched.matches(version) && !self.unaffected.matches(version)
+    }
+}
+
+/// The inclusive `[start_version, end_version]` range of a CVE record.
+#[derive(Debug, Clone)]
+pub struct CveRange {
+    affected: VersionReqSet,
+}
+
+impl CveRange {
+    pub fn parse(
+        start_version: Option<&str>,
+        end_version: Option<&str>,
+    ) -> Result<Self, AdvisoryRangeError> {
+        let mut bounds = vec![];
+        if let Some(start) = start_version.filter(|s| !is_open_bound(s)) {
+            bounds.push(format!(">={}", cve_bound(start)?));
+        }
+        if let Some(end) = end_version.filter(|s| !is_open_bound(s)) {
+            bounds.push(format!("<={}", cve_bound(end)?));
+        }
+        // Without any bound every version is affected.
+        let req = if bounds.is_empty() {
+            VersionReq::STAR
+        } else {
+            VersionReq::parse(&bounds.join(", ")).map_err(|e| {
+                AdvisoryRangeError::InvalidRequirement {
+                    input: bounds.join(", "),
+                    reason: e.to_string(),
+                }
+            })?
+        };
+        Ok(CveRange {
+            affected: VersionReqSet { reqs: vec![req] },
+        })
+    }
+
+    pub fn is_affected(&self, version: &Version) -> bool {
+        self.affected.matches(version)
+    }
+}
+
+/// CVE bounds must be plain versions, a requirement like `^1.0` is rejected.
+fn cve_bound(raw: &str) -> Result<Version, AdvisoryRangeError> {
+    parse_version(raw).map_err(|e| AdvisoryRangeError::InvalidRequirement {
+        input: raw.to_string(),
+        reason: e.to_string(),
+    })
+}
+
+fn req_matches(req: &VersionReq, version: &Version) -> bool {
+    req.comparators
+        .iter()
+        .all(|cmp| comparator_matches(cmp, version))
+}
+
+/// Evaluates a single comparator the way Cargo does, except that
+/// pre-releases are ordered by precedence instead of being filtered out.
+fn comparator_matches(cmp: &Comparator, v: &Version) -> bool {
+    let lower = Version {
+        major: cmp.major,
+        minor: cmp.minor.unwrap_or(0),
+        patch: cmp.patch.unwrap_or(0),
+        pre: cmp.pre.clone(),
+        build: semver::BuildMetadata::EMPTY,
+    };
+    let v = &Version {
+        build: semver::BuildMetadata::EMPTY,
+        ..v.clone()
+    };
+    match cmp.op {
+        Op::Exact | Op::Wildcard => match (cmp.minor, cmp.patch) {
+            (None, _) => v.major == cmp.major,
+            (Some(minor), None) => v.major == cmp.major && v.minor == minor,
+            (Some(_), Some(_)) => *v == lower,
+        },
+        Op::Greater => match (cmp.minor, cmp.patch) {
+            (None, _) => v.major > cmp.major,
+            (Some(minor), None) => (v.major, v.minor) > (cmp.major, minor),
+            (Some(_), Some(_)) => *v > lower,
+        },
+        Op::GreaterEq => *v >= lower,
+        Op::Less => *v < lower,
+        Op::LessEq => match (cmp.minor, cmp.patch) {
+            (None, _) => v.major <= cmp.major,
+            (Some(minor), None) => (v.major, v.minor) <= (cmp.major, minor),
+            (Some(_), Some(_)) => *v <= lower,
+        },
+        Op::Tilde => {
+            let upper = match cmp.minor {
+                None => (cmp.major + 1, 0, 0),
+                Some(minor) => (cmp.major, minor + 1, 0),
+            };
+            *v >= lower && below(v, upper)
+        }
+        Op::Caret => {
+            let upper = match (cmp.major, cmp.minor, cmp.patch) {
+                (0, None, _) => (1, 0, 0),
+                (0, Some(0), None) => (0, 1, 0),
+                (0, Some(0), Some(patch)) => (0, 0, patch + 1),
+                (0, Some(minor), _) => (0, minor + 1, 0),
+                (major, _, _) => (major + 1, 0, 0),
+            };
+            *v >= lower && below(v, upper)
+        }
+        _ => false,
+    }
+}
+
+/// Exclusive upper bound which also excludes the pre-releases of `upper`,
+/// e.g. `^1.2` does not cover `2.0.0-alpha`.
+fn below(v: &Version, upper: (u64, u64, u64)) -> bool {
+    (v.major, v.minor, v.patch) < upper
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_version, AdvisoryRangeError, CveRange, RustsecRange, VersionReqSet};
+
+    fn v(raw: &str) -> semver::Version {
+        parse_version(raw).unwrap()
+    }
+
+    /// (advisory, patched, unaffected, affected versions, not affected versions)
+    #[allow(clippy::type_complexity)]
+    const RUSTSEC_CASES: &[(&str, &str, &str, &[&str], &[&str])] = &[
+        (
+            "RUSTSEC-2022-0013 regex",
+            ">= 1.5.5",
+            "",
+            &["1.5.4", "1.0.0", "0.2.11"],
+            &["1.5.5", "1.9.6"],
+        ),
+        (
+            "RUSTSEC-2021-0124 tokio",
+            ">= 1.8.4, < 1.9.0|>= 1.13.1",
+            "< 0.1.14",
+            &["0.1.14", "1.8.3", "1.9.0", "1.13.0"],
+            &["0.1.13", "1.8.4", "1.8.5", "1.13.1", "1.40.0"],
+        ),
+        (
+            "RUSTSEC-2023-0001 tokio",
+            ">= 1.18.4, < 1.19.0|>= 1.20.3, < 1.21.0|>= 1.23.1",
+            "< 0.2.0",
+            &["0.2.0", "1.18.3", "1.19.0", "1.20.2", "1.23.0"],
+            &["0.1.22", "1.18.4", "1.20.3", "1.23.1"],
+        ),
+        (
+            "RUSTSEC-2020-0071 time",
+            ">= 0.2.23",
+            "= 0.2.0|= 0.2.1|= 0.2.2|= 0.2.3|= 0.2.4|= 0.2.5|= 0.2.6",
+            &["0.1.45", "0.2.7", "0.2.22"],
+            &["0.2.0", "0.2.6", "0.2.23", "0.3.36"],
+        ),
+        (
+            "RUSTSEC-2021-0003 smallvec",
+            ">= 1.6.1",
+            "< 1.0.0",
+            &["1.0.0", "1.6.0"],
+            &["0.6.14", "1.6.1"],
+        ),
+        (
+            "caret patched list",
+            "^0.9.4|>= 1.0.1",
+            "",
+            &["0.9.3", "1.0.0", "0.8.9"],
+            &["0.9.4", "0.9.12", "1.0.1"],
+        ),
+        (
+            "tilde and wildcard",
+            "~1.2.3|2.*",
+            "<= 0.3",
+            &["1.3.0", "1.2.2", "3.0.0", "0.4.0"],
+            &["1.2.3", "1.2.9", "2.0.0", "2.7.1", "0.3.9"],
+        ),
+        (
+            "pre-releases",
+            ">= 1.2.0",
+            "",
+            &["1.2.0-alpha.1", "1.1.9"],
+            &["1.2.0", "1.3.0-rc.1", "2.0.0-beta"],
+        ),
+    ];
+
+    #[test]
+    fn test_rustsec_ranges() {
+        for (advisory, patched, unaffected, affected, not_affected) in RUSTSEC_CASES {
+            let range = RustsecRange::parse(patched, unaffected)
+                .unwrap_or_else(|e| panic!("{}: {}", advisory, e));
+            for version in *affected {
+                assert!(
+                    range.is_affected(&v(version)),
+                    "{}: {} should be affected",
+                    advisory,
+                    version
+                );
+            }
+            for version in *not_affected {
+                assert!(
+                    !range.is_affected(&v(version)),
+                    "{}: {} should not be affected",
+                    advisory,
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_caret_upper_bound_excludes_next_pre_release() {
+        let set = VersionReqSet::parse("^1.2").unwrap();
+        assert!(set.matches(&v("1.9.0")));
+        assert!(!set.matches(&v("2.0.0-alpha")));
+    }
+
+    #[test]
+    fn test_empty_lists_mean_every_version_is_affected() {
+        let range = RustsecRange::parse("", "Null").unwrap();
+        assert!(range.is_affected(&v("0.1.0")));
+        assert!(range.is_affected(&v("99.0.0")));
+    }
+
+    #[test]
+    fn test_star_matches_every_version() {
+        let patched = RustsecRange::parse("*", "").unwrap();
+        assert!(!patched.is_affected(&v("0.1.0")));
+        assert!(!patched.is_affected(&v("99.0.0")));
+        let unaffected = RustsecRange::parse(">= 1.0.0", "*").unwrap();
+        assert!(!unaffected.is_affected(&v("0.5.0")));
+        let cve = CveRange::parse(Some("1.0.0"), Some("*")).unwrap();
+        assert!(cve.is_affected(&v("7.0.0")));
+        assert!(!cve.is_affected(&v("0.9.0")));
+    }
+
+    #[test]
+    fn test_cve_ranges() {
+        // (start_version, end_version, affected, not affected)
+        #[allow(clippy::type_complexity)]
+        let cases: &[(Option<&str>, Option<&str>, &[&str], &[&str])] = &[
+            (
+                Some("0.1.0"),
+                Some("0.3.2"),
+                &["0.1.0", "0.2.5", "0.3.2"],
+                &["0.0.9", "0.3.3"],
+            ),
+            (None, Some("1.0.0"), &["0.1.0", "1.0.0"], &["1.0.1"]),
+            (Some("2.0.0"), Some(""), &["2.0.0", "9.9.9"], &["1.9.9"]),
+            (None, None, &["0.0.1", "5.0.0"], &[]),
+        ];
+        for (start, end, affected, not_affected) in cases {
+            let range = CveRange::parse(*start, *end).unwrap();
+            for version in *affected {
+                assert!(
+                    range.is_affected(&v(version)),
+                    "{:?}..{:?} {}",
+                    start,
+                    end,
+                    version
+                );
+            }
+            for version in *not_affected {
+                assert!(
+                    !range.is_affected(&v(version)),
+                    "{:?}..{:?} {}",
+                    start,
+                    end,
+                    version
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_failures_are_reported() {
+        assert!(matches!(
+            VersionReqSet::parse(">= 1.0.0|>= one.two"),
+            Err(AdvisoryRangeError::InvalidRequirement { .. })
+        ));
+        assert!(matches!(
+            RustsecRange::parse(">= 1.0.0", "< 0.1.0 ||| nonsense"),
+            Err(AdvisoryRangeError::InvalidRequirement { .. })
+        ));
+        assert!(matches!(
+            CveRange::parse(Some("^1.0"), None),
+            Err(AdvisoryRangeError::InvalidRequirement { .. })
+        ));
+        assert!(matches!(
+            parse_version("all"),
+            Err(AdvisoryRangeError::InvalidVersion { .. })
+        ));
+    }
+}
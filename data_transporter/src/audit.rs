@@ -0,0 +1,223 @@
+//! Offline audit of a `Cargo.lock`.
+//!
+//! Every registry or git package of the lockfile is looked up in the
+//! `rustsecs`/`cves` tables, its license is read from the `license` table and
+//! its presence in the TuGraph `program` graph is reported. Path packages are
+//! the project's own crates and are left out.
+
+use std::{collections::HashSet, error::Error, fmt};
+
+use serde::Deserialize;
+use toml::Value;
+
+use crate::{
+    data_reader::{DataReader, DataReaderTrait},
+    db::DBHandler,
+    handler::{AuditReport, AuditedPackage},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuditError {
+    Lockfile(String),
+    Manifest(String),
+    PackageName(String),
+}
+
+impl fmt::Display for AuditError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuditError::Lockfile(reason) => write!(f, "invalid Cargo.lock: {}", reason),
+            AuditError::Manifest(reason) => write!(f, "invalid Cargo.toml: {}", reason),
+            AuditError::PackageName(name) => write!(f, "invalid package name '{}'", name),
+        }
+    }
+}
+
+impl Error for AuditError {}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+/// Locked packages coming from a registry or a git repository.
+pub fn parse_lockfile(content: &str) -> Result<Vec<LockedPackage>, AuditError> {
+    let lockfile: Lockfile =
+        toml::from_str(content).map_err(|e| AuditError::Lockfile(e.to_string()))?;
+    let mut packages = vec![];
+    for package in lockfile.package {
+        if !is_valid_package_name(&package.name) {
+            return Err(AuditError::PackageName(package.name));
+        }
+        if package.source.is_some() {
+            packages.push(package);
+        }
+    }
+    Ok(packages)
+}
+
+/// Names of the crates a manifest depends on directly, including dev, build
+/// and target specific dependencies. Renamed dependencies are reported under
+/// their real package name.
+pub fn parse_manifest_dependencies(content: &str) -> Result<HashSet<String>, AuditError> {
+    let manifest: Value =
+        toml::from_str(content).map_err(|e| AuditError::Manifest(e.to_string()))?;
+    let mut tables = vec![&manifest];
+    if let Some(workspace) = manifest.get("workspace") {
+        tables.push(workspace);
+    }
+    if let Some(targets) = manifest.get("target").and_then(|t| t.as_table()) {
+        tables.extend(targets.values());
+    }
+    let mut names = HashSet::new();
+    for table in tables {
+        for kind in ["dependencies", "dev-dependencies", "build-dependencies"] {
+            let Some(deps) = table.get(kind).and_then(|d| d.as_table()) else {
+                continue;
+            };
+            for (key, spec) in deps {
+                let name = spec.get("package").and_then(|p| p.as_str()).unwrap_or(key);
+                names.insert(name.to_string());
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Cargo only accepts alphanumerics, `-` and `_` in package names. Anything
+/// else is refused before it reaches a query.
+pub(crate) fn is_valid_package_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub async fn audit_packages(
+    reader: &DataReader,
+    dbhandler: &DBHandler,
+    packages: Vec<LockedPackage>,
+    direct_dependencies: &HashSet<String>,
+) -> Result<AuditReport, Box<dyn Error>> {
+    let mut audited = vec![];
+    for package in packages {
+        let rustsecs = dbhandler
+            .get_direct_rustsec(&package.name, &package.version)
+            .await?;
+        let cves = dbhandler
+            .get_direct_cve_by_cratenameandversion(&package.name, &package.version)
+            .await?;
+        let namespaces = reader.get_program_namespaces(&package.name).await?;
+        let license = match namespaces.first() {
+            Some(namespace) => dbhandler
+                .get_license_by_name(namespace, &package.name)
+                .await?
+                .into_iter()
+                .next()
+                .filter(|l| l != "None"),
+            None => None,
+        };
+        audited.push(AuditedPackage {
+            direct: direct_dependencies.contains(&package.name),
+            name: package.name,
+            version: package.version,
+            source: package.source,
+            rustsecs,
+            cves,
+            license,
+            in_graph: !namespaces.is_empty(),
+        });
+    }
+    let vulnerable_count = audited
+        .iter()
+        .filter(|p| !p.rustsecs.is_empty() || !p.cves.is_empty())
+        .count();
+    Ok(AuditReport {
+        package_count: audited.len(),
+        vulnerable_count,
+        packages: audited,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_lockfile, parse_manifest_dependencies, AuditError};
+
+    const LOCKFILE: &str = r#"
+version = 3
+
+[[package]]
+name = "myapp"
+version = "0.1.0"
+dependencies = ["regex", "tokio"]
+
+[[package]]
+name = "regex"
+version = "1.5.4"
+source = "registry+https://github.com/rust-lang/crates.io-index"
+checksum = "d07a8629359eb56f1e2fb1652bb04212c072a87ba68546a04065d525673ac461"
+
+[[package]]
+name = "tokio"
+version = "1.17.0"
+source = "git+https://github.com/tokio-rs/tokio?branch=master#0123456789abcdef"
+"#;
+
+    #[test]
+    fn test_parse_lockfile() {
+        let packages = parse_lockfile(LOCKFILE).unwrap();
+        let names: Vec<(&str, &str)> = packages
+            .iter()
+            .map(|p| (p.name.as_str(), p.version.as_str()))
+            .collect();
+        assert_eq!(names, vec![("regex", "1.5.4"), ("tokio", "1.17.0")]);
+    }
+
+    #[test]
+    fn test_parse_lockfile_rejects_bad_input() {
+        assert!(matches!(
+            parse_lockfile("[[package]\nname ="),
+            Err(AuditError::Lockfile(_))
+        ));
+        let hostile = "[[package]]\nname = \"x'}) DETACH DELETE p //\"\nversion = \"1.0.0\"\nsource = \"registry+x\"\n";
+        assert!(matches!(
+            parse_lockfile(hostile),
+            Err(AuditError::PackageName(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_manifest_dependencies() {
+        let manifest = r#"
+[package]
+name = "myapp"
+
+[dependencies]
+regex = "1.5"
+rt = { package = "tokio", version = "1" }
+
+[dev-dependencies]
+criterion = "0.5"
+
+[target.'cfg(unix)'.build-dependencies]
+cc = "1"
+
+[workspace.dependencies]
+serde = "1"
+"#;
+        let mut names: Vec<String> = parse_manifest_dependencies(manifest)
+            .unwrap()
+            .into_iter()
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["cc", "criterion", "regex", "serde", "tokio"]);
+    }
+}
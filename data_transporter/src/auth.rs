@@ -0,0 +1,394 @@
+//! Session tokens of the upload and profile endpoints.
+//!
+//! The frontend records a login with `/api/submitUserinfo` and gets back a
+//! session token signed with `API_SESSION_SECRET`. Uploads and profile
+//! queries take the user from `Authorization: Bearer <token>` through the
+//! [`AuthenticatedUser`] extractor instead of trusting an email in the body.
+//!
+//! Users sign in with GitHub. The login carries the GitHub OAuth access token
+//! of the user in [`LOGIN_TOKEN_HEADER`], and [`LoginVerifier`] only lets a
+//! token be issued for an email GitHub reports as verified for that account.
+//! Nothing the browser could be given is trusted on its own.
+//!
+//! A token is `<payload>.<signature>`, both base64url without padding: the
+//! payload is the JSON of [`SessionClaims`], the signature its HMAC-SHA256.
+
+use std::{
+    env,
+    error::Error,
+    future::{ready, Ready},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use actix_web::{dev::Payload, http::header, web, FromRequest, HttpRequest};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{error::ApiError, state::AppState};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Header carrying the GitHub OAuth access token of the login on
+/// `/api/submitUserinfo`.
+pub const LOGIN_TOKEN_HEADER: &str = "X-GitHub-Token";
+
+/// GitHub API of [`LoginVerifier`] unless `API_LOGIN_GITHUB_API_URL` is set.
+const DEFAULT_GITHUB_API_URL: &str = "https://api.github.com";
+
+/// How long a login waits for GitHub.
+const LOGIN_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The payload of a session token.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SessionClaims {
+    /// Email of the logged in user, lower-cased.
+    pub sub: String,
+    /// Expiry, seconds since the Unix epoch.
+    pub exp: u64,
+}
+
+/// Response of `/api/submitUserinfo`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SessionToken {
+    pub token: String,
+    /// Expiry, seconds since the Unix epoch.
+    pub expires_at: u64,
+}
+
+/// Signs and verifies session tokens.
+#[derive(Clone)]
+pub struct SessionSigner {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl SessionSigner {
+    pub fn new(secret: impl Into<Vec<u8>>, ttl: Duration) -> Self {
+        SessionSigner {
+            secret: secret.into(),
+            ttl,
+        }
+    }
+
+    /// `API_SESSION_SECRET` and `API_SESSION_TTL_SECS` (one day by default).
+    /// A missing or empty session secret is refused, tokens could be forged.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Result<Self, Box<dyn Error>> {
+        let secret = var("API_SESSION_SECRET").unwrap_or_default();
+        if secret.is_empty() {
+            return Err("API_SESSION_SECRET is empty, session tokens cannot be signed".into());
+        }
+        if secret.len() < 32 {
+            tracing::warn!("API_SESSION_SECRET is shorter than 32 bytes");
+        }
+        let ttl = var("API_SESSION_TTL_SECS")
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(24 * 60 * 60);
+        Ok(Self::new(secret, Duration::from_secs(ttl)))
+    }
+
+    /// A token for `email`, valid for the configured TTL from `now`. The
+    /// email is lower-cased, logins check it ignoring case, so each user has
+    /// one identity however the email was typed.
+    pub fn issue(&self, email: &str, now: SystemTime) -> SessionToken {
+        let expires_at = unix_secs(now) + self.ttl.as_secs();
+        let claims = SessionClaims {
+            sub: email.to_ascii_lowercase(),
+            exp: expires_at,
+        };
+        let payload = URL_SAFE_NO_PAD.encode(serde_json::to_vec(&claims).unwrap());
+        let signature = URL_SAFE_NO_PAD.encode(self.mac(&payload).finalize().into_bytes());
+        SessionToken {
+            token: format!("{}.{}", payload, signature),
+            expires_at,
+        }
+    }
+
+    /// The claims of `token` if its signature is valid and it has not expired.
+    pub fn verify(&self, token: &str, now: SystemTime) -> Result<SessionClaims, ApiError> {
+        let invalid = || ApiError::unauthorized("invalid session token");
+        let (payload, signature) = token.split_once('.').ok_or_else(invalid)?;
+        let signature = URL_SAFE_NO_PAD.decode(signature).map_err(|_| invalid())?;
+        self.mac(payload)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+        let payload = URL_SAFE_NO_PAD.decode(payload).map_err(|_| invalid())?;
+        let claims: SessionClaims = serde_json::from_slice(&payload).map_err(|_| invalid())?;
+        if claims.exp <= unix_secs(now) {
+            return Err(ApiError::unauthorized("session token expired"));
+        }
+        Ok(claims)
+    }
+
+    fn mac(&self, payload: &str) -> HmacSha256 {
+        let mut mac =
+            HmacSha256::new_from_slice(&self.secret).expect("HMAC takes keys of any size");
+        mac.update(payload.as_bytes());
+        mac
+    }
+}
+
+/// An email of a GitHub account, from `GET /user/emails`.
+#[derive(Deserialize, Debug)]
+struct GithubEmail {
+    email: String,
+    verified: bool,
+}
+
+/// Checks a login against the GitHub account it was made with.
+#[derive(Clone)]
+pub struct LoginVerifier {
+    client: reqwest::Client,
+    api_url: String,
+}
+
+impl LoginVerifier {
+    pub fn new(api_url: &str) -> Result<Self, reqwest::Error> {
+        let client = reqwest::Client::builder()
+            .user_agent("crates-pro")
+            .timeout(LOGIN_CHECK_TIMEOUT)
+            .build()?;
+        Ok(LoginVerifier {
+            client,
+            api_url: api_url.trim_end_matches('/').to_string(),
+        })
+    }
+
+    /// `API_LOGIN_GITHUB_API_URL`, `https://api.github.com` by default. An
+    /// empty value is refused, logins could not be checked.
+    pub fn from_env() -> Result<Self, Box<dyn Error>> {
+        let api_url = env::var("API_LOGIN_GITHUB_API_URL")
+            .unwrap_or_else(|_| DEFAULT_GITHUB_API_URL.to_string());
+        if api_url.trim().is_empty() {
+            return Err("API_LOGIN_GITHUB_API_URL is empty, logins cannot be verified".into());
+        }
+        Ok(Self::new(api_url.trim())?)
+    }
+
+    /// Accepts the login of `email` if the GitHub account of `access_token`
+    /// has it as a verified email. The token needs the `user:email` scope.
+    pub async fn verify(&self, access_token: Option<&str>, email: &str) -> Result<(), ApiError> {
+        let access_token = access_token
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .ok_or_else(|| {
+                ApiError::unauthorized(format!("missing {} header", LOGIN_TOKEN_HEADER))
+            })?;
+        let response = self
+            .client
+            .get(format!("{}/user/emails", self.api_url))
+            .bearer_auth(access_token)
+            .header(reqwest::header::ACCEPT, "application/vnd.github+json")
+            .send()
+            .await
+            .map_err(|e| ApiError::internal(format!("cannot reach GitHub: {}", e)))?;
+        let status = response.status();
+        if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+            return Err(ApiError::unauthorized(
+                "GitHub rejected the access token of the login",
+            ));
+        }
+        let emails: Vec<GithubEmail> = response
+            .error_for_status()
+            .map_err(|e| ApiError::internal(format!("GitHub login check failed: {}", e)))?
+            .json()
+            .await
+            .map_err(|e| ApiError::internal(format!("unexpected GitHub response: {}", e)))?;
+        check_email(&emails, email)
+    }
+}
+
+fn check_email(emails: &[GithubEmail], email: &str) -> Result<(), ApiError> {
+    if emails
+        .iter()
+        .any(|known| known.verified && known.email.eq_ignore_ascii_case(email))
+    {
+        Ok(())
+    } else {
+        Err(ApiError::unauthorized(format!(
+            "'{}' is not a verified email of the GitHub account",
+            email
+        )))
+    }
+}
+
+/// The user of a request, from its bearer session token.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub email: String,
+}
+
+impl AuthenticatedUser {
+    fn from_request(req: &HttpRequest) -> Result<Self, ApiError> {
+        let state = req
+            .app_data::<web::Data<AppState>>()
+            .ok_or_else(|| ApiError::internal("app state not configured"))?;
+        let token = bearer_token(req)
+            .ok_or_else(|| ApiError::unauthorized("missing bearer session token"))?;
+        let claims = state.sessions.verify(token, SystemTime::now())?;
+        Ok(AuthenticatedUser { email: claims.sub })
+    }
+
+    /// Rejects a request that names another user than the authenticated one.
+    pub fn ensure_is(&self, email: &str) -> Result<(), ApiError> {
+        if email.is_empty() || email.eq_ignore_ascii_case(&self.email) {
+            Ok(())
+        } else {
+            Err(ApiError::forbidden("cannot act on behalf of another user"))
+        }
+    }
+}
+
+impl FromRequest for AuthenticatedUser {
+    type Error = ApiError;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(AuthenticatedUser::from_request(req))
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.split_once(' ')?;
+    scheme
+        .eq_ignore_ascii_case("bearer")
+        .then_some(token.trim())
+        .filter(|token| !token.is_empty())
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use actix_web::test::TestRequest;
+
+    use super::{
+        bearer_token, check_email, AuthenticatedUser, GithubEmail, LoginVerifier, SessionSigner,
+    };
+    use crate::error::ApiError;
+
+    fn signer() -> SessionSigner {
+        SessionSigner::new("0123456789abcdef0123456789abcdef", Duration::from_secs(60))
+    }
+
+    #[test]
+    fn test_issue_and_verify() {
+        let now = SystemTime::now();
+        let token = signer().issue("alice@example.com", now);
+        let claims = signer().verify(&token.token, now).unwrap();
+        assert_eq!(claims.sub, "alice@example.com");
+        assert_eq!(claims.exp, token.expires_at);
+
+        let later = now + Duration::from_secs(61);
+        assert_eq!(
+            signer().verify(&token.token, later),
+            Err(ApiError::unauthorized("session token expired"))
+        );
+    }
+
+    #[test]
+    fn test_one_identity_per_email() {
+        let now = SystemTime::now();
+        let token = signer().issue("Alice@Example.com", now);
+        let claims = signer().verify(&token.token, now).unwrap();
+        assert_eq!(claims.sub, "alice@example.com");
+
+        let user = AuthenticatedUser { email: claims.sub };
+        assert!(user.ensure_is("alice@example.com").is_ok());
+        assert!(user.ensure_is("ALICE@example.com").is_ok());
+        assert!(user.ensure_is("").is_ok());
+        assert!(user.ensure_is("mallory@example.com").is_err());
+    }
+
+    #[test]
+    fn test_session_secret_is_required() {
+        let vars = |secret: &'static str| {
+            move |key: &str| match key {
+                "API_SESSION_SECRET" => Some(secret.to_string()),
+                "API_SESSION_TTL_SECS" => Some("3600".to_string()),
+                _ => None,
+            }
+        };
+        assert!(SessionSigner::from_vars(vars("")).is_err());
+        assert!(SessionSigner::from_vars(|_: &str| None).is_err());
+        let signer = SessionSigner::from_vars(vars("0123456789abcdef0123456789abcdef")).unwrap();
+        assert_eq!(signer.ttl, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_rejects_forged_tokens() {
+        let now = SystemTime::now();
+        let token = signer().issue("alice@example.com", now).token;
+        let other = SessionSigner::new("another secret", Duration::from_secs(60));
+        assert!(other.verify(&token, now).is_err());
+
+        // swap in the payload of another user, keeping alice's signature
+        let mallory = signer().issue("mallory@example.com", now).token;
+        let (payload, _) = mallory.split_once('.').unwrap();
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{}", payload, signature);
+        assert!(signer().verify(&forged, now).is_err());
+
+        for garbage in ["", "no-dot", "a.b", "..."] {
+            assert!(signer().verify(garbage, now).is_err());
+        }
+    }
+
+    #[test]
+    fn test_check_email() {
+        let emails = [
+            GithubEmail {
+                email: "Alice@Example.com".to_string(),
+                verified: true,
+            },
+            GithubEmail {
+                email: "alice@unverified.example".to_string(),
+                verified: false,
+            },
+        ];
+        assert!(check_email(&emails, "alice@example.com").is_ok());
+        assert!(check_email(&emails, "alice@unverified.example").is_err());
+        assert!(check_email(&emails, "mallory@example.com").is_err());
+        assert!(check_email(&[], "alice@example.com").is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_login_needs_access_token() {
+        // refused before GitHub is asked
+        let verifier = LoginVerifier::new("http://127.0.0.1:9").unwrap();
+        for token in [None, Some(""), Some("  ")] {
+            assert!(matches!(
+                verifier.verify(token, "alice@example.com").await,
+                Err(ApiError::Unauthorized(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_bearer_token() {
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Bearer abc.def"))
+            .to_http_request();
+        assert_eq!(bearer_token(&req), Some("abc.def"));
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Basic abc"))
+            .to_http_request();
+        assert_eq!(bearer_token(&req), None);
+        assert_eq!(
+            bearer_token(&TestRequest::default().to_http_request()),
+            None
+        );
+    }
+}
@@ -0,0 +1,513 @@
+//! Bulk lookup of crate front info.
+//!
+//! The cached `crates_info` entries of all requested crates are read with a
+//! single `MGET`. The misses are checked against TuGraph with one query for
+//! their namespaces and one for their versions, then computed together:
+//! their URLs, licenses, direct dependencies and dependents take one query
+//! each for all misses, their dependency closures are walked one level per
+//! query and their advisories are read with a single query. An invalid or
+//! unknown crate only fails its own entry, a failing query fails the entries
+//! of all misses.
+
+use std::{
+    cmp::Ordering,
+    collections::{hash_map::Entry, HashMap, HashSet, VecDeque},
+};
+
+use model::general_model::DependencyKind;
+use semver::Version;
+
+use crate::{
+    audit::is_valid_package_name,
+    data_reader::DataReaderTrait,
+    error::ApiError,
+    handler::{BatchCrateQuery, BatchCrateResult, Crateinfo, DependencyCount, DependentCount},
+    state::AppState,
+};
+
+/// Crates accepted in one request.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Versions expanded at most while walking the dependency closures of the
+/// misses of one request.
+const MAX_CLOSURE_VERSIONS: usize = 20_000;
+
+/// Versions visited at most in the closure of one crate, as in a single
+/// lookup.
+const MAX_CRATE_CLOSURE: usize = 2000;
+
+/// A cache miss to compute.
+struct Miss {
+    key: String,
+    namespace: String,
+    name: String,
+    /// Every version of the crate, highest first.
+    versions: Vec<String>,
+    /// `name/version` of the looked up versions, all of them for `all`.
+    roots: Vec<String>,
+}
+
+fn is_valid_namespace(namespace: &str) -> bool {
+    let parts: Vec<&str> = namespace.split('/').collect();
+    parts.len() == 2
+        && parts.iter().all(|part| {
+            !part.is_empty()
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        })
+}
+
+/// Refuses an entry before any of its fields reaches a query.
+pub fn validate(query: &BatchCrateQuery) -> Result<(), ApiError> {
+    if !is_valid_package_name(&query.name) {
+        return Err(ApiError::bad_request(format!(
+            "invalid crate name '{}'",
+            query.name
+        )));
+    }
+    if query.version != "all" && semver::Version::parse(&query.version).is_err() {
+        return Err(ApiError::bad_request(format!(
+            "invalid version '{}'",
+            query.version
+        )));
+    }
+    if let Some(namespace) = &query.namespace {
+        if !is_valid_namespace(namespace) {
+            return Err(ApiError::bad_request(format!(
+                "invalid namespace '{}', expected 'nsfront/nsbehind'",
+                namespace
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn cache_key(namespace: &str, name: &str, version: &str) -> String {
+    format!("crates_info:{}:{}:{}", namespace, name, version)
+}
+
+/// Looks up every query, the results are in the order of `queries`.
+pub async fn lookup_crates(
+    state: &AppState,
+    queries: Vec<BatchCrateQuery>,
+) -> Result<Vec<BatchCrateResult>, ApiError> {
+    let mut namespaces: Vec<Result<Option<String>, ApiError>> = queries
+        .iter()
+        .map(|query| validate(query).map(|_| query.namespace.clone()))
+        .collect();
+
+    // 没有给出 namespace 的 crate 一次查询全部补齐
+    let mut unresolved: Vec<String> = queries
+        .iter()
+        .zip(&namespaces)
+        .filter(|(_, namespace)| matches!(namespace, Ok(None)))
+        .map(|(query, _)| query.name.clone())
+        .collect();
+    unresolved.sort();
+    unresolved.dedup();
+    let found = state
+        .reader
+        .get_namespaces_of_crates(&unresolved)
+        .await
+        .map_err(ApiError::tugraph)?;
+    for (query, namespace) in queries.iter().zip(namespaces.iter_mut()) {
+        if let Ok(None) = namespace {
+            *namespace = match found.get(&query.name).and_then(|list| list.first()) {
+                Some(resolved) => Ok(Some(resolved.clone())),
+                None => Err(ApiError::not_found(format!(
+                    "crate '{}' not found",
+                    query.name
+                ))),
+            };
+        }
+    }
+
+    let mut keys: Vec<String> = vec![];
+    let mut wanted: HashMap<String, (String, String, String)> = HashMap::new();
+    for (query, namespace) in queries.iter().zip(&namespaces) {
+        if let Ok(Some(namespace)) = namespace {
+            let key = cache_key(namespace, &query.name, &query.version);
+            if let Entry::Vacant(slot) = wanted.entry(key.clone()) {
+                keys.push(key);
+                slot.insert((namespace.clone(), query.name.clone(), query.version.clone()));
+            }
+        }
+    }
+
+    let mut redisconn = state.redis().await?;
+    let cached = redisconn.query_many_from_redis(&keys).await?;
+    let mut outcomes: HashMap<String, Result<Crateinfo, ApiError>> = HashMap::new();
+    let mut misses = vec![];
+    for (key, value) in keys.iter().zip(cached) {
+        match value.and_then(|value| serde_json::from_str::<Crateinfo>(&value).ok()) {
+            Some(info) => {
+                outcomes.insert(key.clone(), Ok(info));
+            }
+            None => misses.push(key.clone()),
+        }
+    }
+
+    let mut crates: Vec<(String, String)> = misses
+        .iter()
+        .map(|key| {
+            let (namespace, name, _) = &wanted[key];
+            (namespace.clone(), name.clone())
+        })
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    crates.sort();
+    let versions = state
+        .reader
+        .get_versions_of_crates(&crates)
+        .await
+        .map_err(ApiError::tugraph)?;
+    let mut to_compute = vec![];
+    for key in misses {
+        let (namespace, name, version) = wanted[&key].clone();
+        match versions.get(&(namespace.clone(), name.clone())) {
+            None => {
+                outcomes.insert(
+                    key,
+                    Err(ApiError::not_found(format!(
+                        "crate '{}' not found in namespace '{}'",
+                        name, namespace
+                    ))),
+                );
+            }
+            Some(known) if version != "all" && !known.contains(&version) => {
+                outcomes.insert(
+                    key,
+                    Err(ApiError::not_found(format!(
+                        "version '{}' of crate '{}' not found in namespace '{}'",
+                        version, name, namespace
+                    ))),
+                );
+            }
+            Some(known) => {
+                let mut versions = known.clone();
+                sort_versions(&mut versions);
+                let roots = if version == "all" {
+                    versions.iter().map(|v| format!("{}/{}", name, v)).collect()
+                } else {
+                    vec![format!("{}/{}", name, version)]
+                };
+                to_compute.push(Miss {
+                    key,
+                    namespace,
+                    name,
+                    versions,
+                    roots,
+                });
+            }
+        }
+    }
+
+    let keys: Vec<String> = to_compute.iter().map(|miss| miss.key.clone()).collect();
+    match compute_crate_infos(state, to_compute).await {
+        Ok(computed) => {
+            for (key, info) in computed {
+                let (namespace, name, version) = wanted[&key].clone();
+                let val = serde_json::to_string(&info)?;
+                // 写缓存失败不影响本次结果
+                if let Err(e) = redisconn
+                    .insert_crates_info_into_redis(namespace, name, version, val)
+                    .await
+                {
+                    tracing::warn!("failed to cache {}: {}", key, e);
+                }
+                outcomes.insert(key, Ok(info));
+            }
+        }
+        Err(e) => {
+            for key in keys {
+                outcomes.insert(key, Err(e.clone()));
+            }
+        }
+    }
+
+    Ok(queries
+        .into_iter()
+        .zip(namespaces)
+        .map(|(query, namespace)| {
+            let (namespace, outcome) = match namespace {
+                Ok(Some(namespace)) => {
+                    let key = cache_key(&namespace, &query.name, &query.version);
+                    let outcome = outcomes.get(&key).cloned().unwrap_or_else(|| {
+                        Err(ApiError::internal(format!("no result for {}", key)))
+                    });
+                    (Some(namespace), outcome)
+                }
+                Ok(None) => (None, Err(ApiError::internal("namespace not resolved"))),
+                Err(e) => (query.namespace.clone(), Err(e)),
+            };
+            let (info, error) = match outcome {
+                Ok(info) => (Some(info), None),
+                Err(e) => (None, Some((&e).into())),
+            };
+            BatchCrateResult {
+                namespace,
+                name: query.name,
+                version: query.version,
+                info,
+                error,
+            }
+        })
+        .collect())
+}
+
+/// Highest version first, versions that do not parse last.
+fn sort_versions(versions: &mut [String]) {
+    versions.sort_by(|a, b| match (Version::parse(a), Version::parse(b)) {
+        (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
+        (Ok(_), Err(_)) => Ordering::Less,
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => Ordering::Equal,
+    });
+}
+
+/// The versions reachable from `direct` in `graph`, `direct` included. At
+/// most `limit` versions are dequeued, as in `new_get_all_dependencies`.
+fn closure_of(
+    direct: &HashSet<String>,
+    graph: &HashMap<String, Vec<String>>,
+    limit: usize,
+) -> HashSet<String> {
+    let mut queue: VecDeque<&String> = direct.iter().collect();
+    let mut visited = HashSet::new();
+    let mut count = 0;
+    while let Some(current) = queue.pop_front() {
+        count += 1;
+        if count == limit {
+            break;
+        }
+        if visited.insert(current.clone()) {
+            queue.extend(graph.get(current).into_iter().flatten());
+        }
+    }
+    visited
+}
+
+/// Computes the front info of all misses, keyed by cache key.
+async fn compute_crate_infos(
+    state: &AppState,
+    misses: Vec<Miss>,
+) -> Result<Vec<(String, Crateinfo)>, ApiError> {
+    if misses.is_empty() {
+        return Ok(vec![]);
+    }
+    let reader = &state.reader;
+    let mut crates: Vec<(String, String)> = misses
+        .iter()
+        .map(|miss| (miss.namespace.clone(), miss.name.clone()))
+        .collect();
+    crates.sort();
+    crates.dedup();
+    let github_urls = reader
+        .get_github_urls_of_crates(&crates)
+        .await
+        .map_err(ApiError::tugraph)?;
+    let doc_urls = reader
+        .get_doc_urls_of_crates(&crates)
+        .await
+        .map_err(ApiError::tugraph)?;
+
+    let roots: Vec<String> = misses
+        .iter()
+        .flat_map(|miss| miss.roots.iter().cloned())
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let mut direct: HashMap<&str, Vec<String>> = HashMap::new();
+    let edges = reader
+        .get_dependency_edges_of_versions(&roots)
+        .await
+        .map_err(ApiError::tugraph)?;
+    for (root, dependency, _) in &edges {
+        direct
+            .entry(root.as_str())
+            .or_default()
+            .push(dependency.clone());
+    }
+    let mut dependents: HashMap<String, Vec<String>> = HashMap::new();
+    for (root, dependent) in reader
+        .get_dependent_edges_of_versions(&roots)
+        .await
+        .map_err(ApiError::tugraph)?
+    {
+        dependents.entry(root).or_default().push(dependent);
+    }
+
+    // 依赖闭包按层展开, 每层一次查询, dev 依赖不继续展开
+    let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+    let mut frontier: Vec<String> = direct
+        .values()
+        .flatten()
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    while !frontier.is_empty() && graph.len() < MAX_CLOSURE_VERSIONS {
+        for node in &frontier {
+            graph.insert(node.clone(), vec![]);
+        }
+        let mut next = HashSet::new();
+        for (node, dependency, kind) in reader
+            .get_dependency_edges_of_versions(&frontier)
+            .await
+            .map_err(ApiError::tugraph)?
+        {
+            if kind == Some(DependencyKind::Dev) {
+                continue;
+            }
+            if !graph.contains_key(&dependency) {
+                next.insert(dependency.clone());
+            }
+            graph.entry(node).or_default().push(dependency);
+        }
+        frontier = next.into_iter().collect();
+    }
+
+    let closures: Vec<(HashSet<String>, HashSet<String>)> = misses
+        .iter()
+        .map(|miss| {
+            let direct: HashSet<String> = miss
+                .roots
+                .iter()
+                .flat_map(|root| direct.get(root.as_str()).into_iter().flatten().cloned())
+                .collect();
+            let closure = closure_of(&direct, &graph, MAX_CRATE_CLOSURE);
+            (direct, closure)
+        })
+        .collect();
+    let advised: Vec<String> = roots
+        .iter()
+        .chain(closures.iter().flat_map(|(_, closure)| closure.iter()))
+        .cloned()
+        .collect::<HashSet<_>>()
+        .into_iter()
+        .collect();
+    let dbhandler = state.db().await?;
+    let advisories = dbhandler
+        .get_rustsec_batch(&advised)
+        .await
+        .map_err(ApiError::postgres)?;
+    let licenses = dbhandler
+        .get_licenses_of_programs(&crates)
+        .await
+        .map_err(ApiError::postgres)?;
+
+    let url = |urls: &HashMap<(String, String), String>, key: &(String, String)| {
+        urls.get(key)
+            .filter(|url| !matches!(url.as_str(), "null" | "None"))
+            .cloned()
+            .unwrap_or_default()
+    };
+    Ok(misses
+        .into_iter()
+        .zip(closures)
+        .map(|(miss, (direct, closure))| {
+            let program = (miss.namespace, miss.name);
+            let mut seen = HashSet::new();
+            let cves = miss
+                .roots
+                .iter()
+                .flat_map(|root| advisories.get(root).into_iter().flatten())
+                .filter(|advisory| seen.insert(*advisory))
+                .cloned()
+                .collect();
+            let dep_cves = closure
+                .iter()
+                .flat_map(|node| advisories.get(node).into_iter().flatten())
+                .cloned()
+                .collect::<HashSet<_>>()
+                .into_iter()
+                .collect();
+            let direct_dependents: HashSet<&String> = miss
+                .roots
+                .iter()
+                .flat_map(|root| dependents.get(root).into_iter().flatten())
+                .collect();
+            let info = Crateinfo {
+                crate_name: program.1.clone(),
+                description: "".to_string(),
+                dependencies: DependencyCount {
+                    direct: direct.len(),
+                    indirect: closure.difference(&direct).count(),
+                },
+                dependents: DependentCount {
+                    direct: direct_dependents.len(),
+                    indirect: 0,
+                },
+                cves,
+                dep_cves,
+                license: licenses
+                    .get(&program)
+                    .cloned()
+                    .unwrap_or_else(|| "None".to_string()),
+                github_url: url(&github_urls, &program),
+                doc_url: url(&doc_urls, &program),
+                versions: miss.versions,
+            };
+            (miss.key, info)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use super::{closure_of, sort_versions, validate};
+    use crate::handler::BatchCrateQuery;
+
+    fn query(namespace: Option<&str>, name: &str, version: &str) -> BatchCrateQuery {
+        BatchCrateQuery {
+            namespace: namespace.map(str::to_string),
+            name: name.to_string(),
+            version: version.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&query(None, "serde", "1.0.200")).is_ok());
+        assert!(validate(&query(Some("serde-rs/serde"), "serde_derive", "all")).is_ok());
+        assert!(validate(&query(None, "serde'}) DETACH DELETE (n", "1.0.0")).is_err());
+        assert!(validate(&query(None, "serde", "latest")).is_err());
+        assert!(validate(&query(Some("serde-rs"), "serde", "1.0.0")).is_err());
+        assert!(validate(&query(Some("a/b/c"), "serde", "1.0.0")).is_err());
+    }
+
+    #[test]
+    fn test_closure_of() {
+        let graph: HashMap<String, Vec<String>> = [
+            ("a/1", vec!["b/1", "c/1"]),
+            ("b/1", vec!["c/1"]),
+            ("c/1", vec!["a/1"]),
+        ]
+        .into_iter()
+        .map(|(node, edges)| {
+            (
+                node.to_string(),
+                edges.into_iter().map(str::to_string).collect(),
+            )
+        })
+        .collect();
+        let direct: HashSet<String> = ["b/1".to_string()].into();
+
+        let closure = closure_of(&direct, &graph, 2000);
+        let mut closure: Vec<&str> = closure.iter().map(String::as_str).collect();
+        closure.sort();
+        assert_eq!(closure, ["a/1", "b/1", "c/1"]);
+        assert_eq!(closure_of(&direct, &graph, 2).len(), 1);
+    }
+
+    #[test]
+    fn test_sort_versions() {
+        let mut versions = ["0.9.0", "bogus", "1.10.0", "1.2.0"].map(str::to_string);
+        sort_versions(&mut versions);
+        assert_eq!(versions, ["1.10.0", "1.2.0", "0.9.0", "bogus"]);
+    }
+}
@@ -0,0 +1,318 @@
+//! Drops cached API responses once a newly imported version is in TuGraph.
+//!
+//! `ImportDriver` announces every imported version as a `VersionWithTag` on
+//! `KAFKA_ANALYSIS_TOPIC`. The consumer here reads the same topic with its
+//! own group id. The message comes before the import files are loaded into
+//! TuGraph, and a request in between would cache the old data again, so the
+//! version is kept pending until its `version` vertex shows up, checked every
+//! `API_CACHE_INVALIDATION_POLL_SECS`. A version still missing after the
+//! longest `REDIS_TTL_*` is given up, whatever was cached before its load has
+//! expired by then.
+//!
+//! Keys are dropped by crate name, whatever their namespace and version:
+//!
+//! - every key of the imported crate, the version page and the listings now
+//!   have one more version;
+//! - the `dependent` keys of each crate some version of it depends on;
+//! - the `dependency`, `dependencygraph`, `vulnerabilities` and `crates_info`
+//!   keys of each crate depending on some version of it, their requirements
+//!   may now resolve to the new version.
+//!
+//! Graphs of transitive dependents are left to expire with their TTL.
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::time::{Duration, Instant};
+
+use futures_util::StreamExt;
+use model::general_model::VersionWithTag;
+use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::{ClientConfig, Message};
+
+use crate::data_reader::DataReaderTrait;
+use crate::redis_store::{CacheFamily, CacheTtls};
+use crate::state::{parse_var, AppState};
+
+/// Default of `API_CACHE_INVALIDATION_POLL_SECS`.
+const DEFAULT_POLL_SECS: u64 = 60;
+
+/// Families whose value depends on what a version resolves its requirements to.
+const DEPENDENT_FAMILIES: [CacheFamily; 4] = [
+    CacheFamily::CratesInfo,
+    CacheFamily::Dependency,
+    CacheFamily::DependencyGraph,
+    CacheFamily::Vulnerabilities,
+];
+
+/// The `(family prefix, crate name)` pairs whose keys are dropped after a
+/// version of `name` has been imported.
+pub fn invalidation_targets(
+    name: &str,
+    dependencies: &HashSet<String>,
+    dependents: &HashSet<String>,
+) -> HashSet<(&'static str, String)> {
+    let mut targets = HashSet::new();
+    for family in CacheFamily::ALL {
+        targets.insert((family.prefix(), name.to_string()));
+    }
+    for dependency in dependencies {
+        targets.insert((CacheFamily::Dependent.prefix(), dependency.clone()));
+    }
+    for dependent in dependents {
+        for family in DEPENDENT_FAMILIES {
+            targets.insert((family.prefix(), dependent.clone()));
+        }
+    }
+    targets
+}
+
+/// Whether `key`, `{prefix}:{namespace}:{name}[:...]`, is one of `targets`.
+pub fn is_target(key: &str, targets: &HashSet<(&'static str, String)>) -> bool {
+    let mut parts = key.splitn(4, ':');
+    let (Some(prefix), Some(_namespace), Some(name)) = (parts.next(), parts.next(), parts.next())
+    else {
+        return false;
+    };
+    CacheFamily::ALL
+        .iter()
+        .find(|family| family.prefix() == prefix)
+        .is_some_and(|family| targets.contains(&(family.prefix(), name.to_string())))
+}
+
+/// Deletes the keys affected by the import of `version`, in one pass over
+/// the keyspace.
+pub async fn invalidate(
+    state: &AppState,
+    version: &VersionWithTag,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let dependencies = state
+        .reader
+        .get_dependency_crate_names(&version.name)
+        .await?;
+    let dependents = state
+        .reader
+        .get_dependent_crate_names(&version.name)
+        .await?;
+    let targets = invalidation_targets(&version.name, &dependencies, &dependents);
+    let mut redis = state.redis().await?;
+    let deleted = redis.delete_where(|key| is_target(key, &targets)).await?;
+    Ok(deleted)
+}
+
+/// Imported versions waiting to be loaded into TuGraph, keyed by
+/// `name/version`.
+#[derive(Default)]
+pub struct PendingVersions {
+    versions: HashMap<String, (VersionWithTag, Instant)>,
+}
+
+impl PendingVersions {
+    /// Waits for `version`, announced at `now`.
+    pub fn add(&mut self, version: VersionWithTag, now: Instant) {
+        let key = format!("{}/{}", version.name, version.version);
+        self.versions.insert(key, (version, now));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.versions.is_empty()
+    }
+
+    pub fn name_and_versions(&self) -> Vec<String> {
+        self.versions.keys().cloned().collect()
+    }
+
+    /// Removes the versions in `loaded` and returns them.
+    pub fn take_loaded(&mut self, loaded: &HashSet<String>) -> Vec<VersionWithTag> {
+        loaded
+            .iter()
+            .filter_map(|key| self.versions.remove(key))
+            .map(|(version, _)| version)
+            .collect()
+    }
+
+    /// Gives up the versions announced more than `max_age` before `now`,
+    /// returns how many.
+    pub fn expire(&mut self, now: Instant, max_age: Duration) -> usize {
+        let before = self.versions.len();
+        self.versions
+            .retain(|_, (_, announced)| now.duration_since(*announced) <= max_age);
+        before - self.versions.len()
+    }
+}
+
+/// Drops the keys of the pending versions TuGraph has loaded since the last
+/// call.
+async fn invalidate_loaded(state: &AppState, pending: &mut PendingVersions, max_age: Duration) {
+    let expired = pending.expire(Instant::now(), max_age);
+    if expired > 0 {
+        tracing::warn!(
+            "cache invalidation gave up {} versions never loaded",
+            expired
+        );
+    }
+    if pending.is_empty() {
+        return;
+    }
+    let loaded = match state
+        .reader
+        .get_loaded_versions(&pending.name_and_versions())
+        .await
+    {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            tracing::warn!("cache invalidation failed to look up versions: {}", e);
+            return;
+        }
+    };
+    for version in pending.take_loaded(&loaded) {
+        match invalidate(state, &version).await {
+            Ok(deleted) => tracing::info!(
+                "invalidated {} cached keys for {}/{}",
+                deleted,
+                version.name,
+                version.version
+            ),
+            Err(e) => tracing::error!(
+                "cache invalidation for {}/{} failed: {}",
+                version.name,
+                version.version,
+                e
+            ),
+        }
+    }
+}
+
+/// Consumes `KAFKA_ANALYSIS_TOPIC` until the consumer fails to start, errors
+/// of a single message are logged and skipped.
+pub async fn run(state: &AppState) {
+    let broker = env::var("KAFKA_BROKER").unwrap_or_default();
+    let topic = env::var("KAFKA_ANALYSIS_TOPIC").unwrap_or_default();
+    let group_id = env::var("KAFKA_CACHE_INVALIDATION_GROUP_ID")
+        .unwrap_or_else(|_| "cache-invalidation-group".to_string());
+    let poll_secs = parse_var(
+        &|key: &str| env::var(key).ok(),
+        "API_CACHE_INVALIDATION_POLL_SECS",
+        DEFAULT_POLL_SECS,
+    );
+    let max_age = Duration::from_secs(CacheTtls::from_env().longest());
+    let consumer: StreamConsumer = match ClientConfig::new()
+        .set("group.id", &group_id)
+        .set("bootstrap.servers", &broker)
+        .set("enable.partition.eof", "false")
+        .set("session.timeout.ms", "10000")
+        .set("enable.auto.commit", "true")
+        .set("auto.offset.reset", "latest")
+        .create()
+    {
+        Ok(consumer) => consumer,
+        Err(e) => {
+            tracing::error!("cache invalidation consumer creation failed: {}", e);
+            return;
+        }
+    };
+    if let Err(e) = consumer.subscribe(&[&topic]) {
+        tracing::error!("cache invalidation can't subscribe to {}: {}", topic, e);
+        return;
+    }
+    tracing::info!("cache invalidation listening on {}", topic);
+
+    let mut pending = PendingVersions::default();
+    let mut poll = tokio::time::interval(Duration::from_secs(poll_secs.max(1)));
+    let mut stream = consumer.stream();
+    loop {
+        let message = tokio::select! {
+            message = stream.next() => message,
+            _ = poll.tick() => {
+                invalidate_loaded(state, &mut pending, max_age).await;
+                continue;
+            }
+        };
+        let Some(message) = message else {
+            break;
+        };
+        let message = match message {
+            Ok(message) => message,
+            Err(e) => {
+                tracing::warn!("cache invalidation failed to receive: {}", e);
+                continue;
+            }
+        };
+        let Some(payload) = message.payload() else {
+            continue;
+        };
+        match serde_json::from_slice::<VersionWithTag>(payload) {
+            Ok(version) => pending.add(version, Instant::now()),
+            Err(e) => {
+                tracing::warn!("cache invalidation failed to deserialize message: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::time::{Duration, Instant};
+
+    use model::general_model::VersionWithTag;
+
+    use super::{invalidation_targets, is_target, PendingVersions};
+
+    fn names(names: &[&str]) -> HashSet<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_invalidation_targets() {
+        let targets = invalidation_targets("serde", &names(&["serde_derive"]), &names(&["toml"]));
+        for key in [
+            "crates_info:crates/io:serde:1.0.0",
+            "versionpage:crates/io:serde",
+            "dependent:crates/io:serde:1.0.0:index",
+            "dependent:crates/io:serde_derive:1.0.200",
+            "dependent:crates/io:serde_derive:1.0.200:normal:page2",
+            "dependency:github/toml-rs:toml:0.8.0",
+            "dependencygraph:crates/io:toml:0.5.11:depth2",
+            "vulnerabilities:crates/io:toml:0.8.0",
+            "crates_info:crates/io:toml:0.8.0",
+        ] {
+            assert!(is_target(key, &targets), "{}", key);
+        }
+        for key in [
+            "dependent:crates/io:toml:0.8.0",
+            "dependency:crates/io:serde_derive:1.0.200",
+            "crates_info:crates/io:serde_json:1.0.0",
+            "unknown:crates/io:serde:1.0.0",
+            "versionpage:serde",
+        ] {
+            assert!(!is_target(key, &targets), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_pending_versions() {
+        let start = Instant::now();
+        let mut pending = PendingVersions::default();
+        pending.add(VersionWithTag::new("serde", "1.0.1", "", "v1.0.1"), start);
+        pending.add(VersionWithTag::new("toml", "0.8.1", "", "v0.8.1"), start);
+        let later = start + Duration::from_secs(30);
+        pending.add(VersionWithTag::new("toml", "0.8.2", "", "v0.8.2"), later);
+
+        // only what TuGraph has is invalidated, the rest keeps waiting
+        let taken = pending.take_loaded(&names(&["serde/1.0.1", "rand/0.9.0"]));
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].version, "1.0.1");
+        let mut waiting = pending.name_and_versions();
+        waiting.sort();
+        assert_eq!(waiting, ["toml/0.8.1", "toml/0.8.2"]);
+
+        assert_eq!(pending.expire(later, Duration::from_secs(60)), 0);
+        assert_eq!(
+            pending.expire(start + Duration::from_secs(61), Duration::from_secs(60)),
+            1
+        );
+        assert_eq!(pending.name_and_versions(), ["toml/0.8.2"]);
+        assert!(pending.take_loaded(&names(&["toml/0.8.1"])).is_empty());
+        assert!(!pending.is_empty());
+    }
+}
@@ -0,0 +1,425 @@
+//! Differences between two releases of a crate.
+//!
+//! Dependencies are matched on name, kind and target, a match whose
+//! requirement or resolved version moved is reported as changed. Advisories
+//! are those affecting the crate itself and its normal and build
+//! dependencies. MirChecker warnings are matched on their message and file,
+//! line numbers shift between releases. Sensleak scans the whole history of
+//! a repository, both releases share its findings.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use model::general_model::DependencyKind;
+
+use crate::{
+    data_reader::{DataReader, DataReaderTrait},
+    db::DBHandler,
+    handler::{
+        ChangedDependency, ComparedAdvisory, ComparedDependency, DependencyChanges, LicenseChange,
+        MircheckerDelta, NewRustsec, VersionComparison,
+    },
+    NameVersion,
+};
+
+/// A `depends_on` edge out of a version.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DependencyRequirement {
+    pub name: String,
+    pub kind: DependencyKind,
+    /// Empty for dependencies of every target.
+    pub target: String,
+    /// Empty for edges imported before requirements were recorded.
+    pub requirement: String,
+    pub resolved_version: String,
+}
+
+impl DependencyRequirement {
+    fn key(&self) -> (String, String, String) {
+        (
+            self.name.clone(),
+            self.kind.to_string(),
+            self.target.clone(),
+        )
+    }
+
+    fn to_compared(&self) -> ComparedDependency {
+        ComparedDependency {
+            name: self.name.clone(),
+            kind: self.kind.to_string(),
+            target: self.target.clone(),
+            requirement: self.requirement.clone(),
+            resolved_version: self.resolved_version.clone(),
+        }
+    }
+}
+
+pub fn diff_dependencies(
+    from: &[DependencyRequirement],
+    to: &[DependencyRequirement],
+) -> DependencyChanges {
+    let from: BTreeMap<_, _> = from.iter().map(|dep| (dep.key(), dep)).collect();
+    let to: BTreeMap<_, _> = to.iter().map(|dep| (dep.key(), dep)).collect();
+    let mut changes = DependencyChanges::default();
+    for (key, old) in &from {
+        match to.get(key) {
+            None => changes.removed.push(old.to_compared()),
+            Some(new)
+                if old.requirement != new.requirement
+                    || old.resolved_version != new.resolved_version =>
+            {
+                changes.changed.push(ChangedDependency {
+                    name: old.name.clone(),
+                    kind: old.kind.to_string(),
+                    target: old.target.clone(),
+                    from_requirement: old.requirement.clone(),
+                    to_requirement: new.requirement.clone(),
+                    from_version: old.resolved_version.clone(),
+                    to_version: new.resolved_version.clone(),
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, new) in &to {
+        if !from.contains_key(key) {
+            changes.added.push(new.to_compared());
+        }
+    }
+    changes
+}
+
+/// `(advisory id, package)` of the advisories affecting `name_and_versions`,
+/// with the affected version, read with a single query.
+async fn advisories_of(
+    dbhandler: &DBHandler,
+    name_and_versions: &[NameVersion],
+) -> Result<BTreeMap<(String, String), (String, NewRustsec)>, Box<dyn Error>> {
+    let keys: Vec<String> = name_and_versions
+        .iter()
+        .map(|nv| format!("{}/{}", nv.name, nv.version))
+        .collect();
+    let mut found = dbhandler.get_rustsec_batch(&keys).await?;
+    let mut advisories = BTreeMap::new();
+    for (nv, key) in name_and_versions.iter().zip(&keys) {
+        for advisory in found.remove(key).unwrap_or_default() {
+            advisories.insert(
+                (advisory.id.clone(), nv.name.clone()),
+                (nv.version.clone(), advisory),
+            );
+        }
+    }
+    Ok(advisories)
+}
+
+/// The advisories of `from` missing from `to` and the other way round.
+pub fn diff_advisories(
+    from: &BTreeMap<(String, String), (String, NewRustsec)>,
+    to: &BTreeMap<(String, String), (String, NewRustsec)>,
+) -> (Vec<ComparedAdvisory>, Vec<ComparedAdvisory>) {
+    let only_in = |a: &BTreeMap<(String, String), (String, NewRustsec)>,
+                   b: &BTreeMap<(String, String), (String, NewRustsec)>| {
+        a.iter()
+            .filter(|(key, _)| !b.contains_key(*key))
+            .map(|((id, package), (version, advisory))| ComparedAdvisory {
+                id: id.clone(),
+                package: package.clone(),
+                version: version.clone(),
+                subtitle: advisory.subtitle.clone(),
+            })
+            .collect()
+    };
+    (only_in(from, to), only_in(to, from))
+}
+
+/// The warning blocks of a stored MirChecker result.
+pub fn mirchecker_warnings(res: &str) -> Vec<String> {
+    let mut blocks: Vec<String> = vec![];
+    for line in res.lines() {
+        if line.starts_with("warning: [MirChecker]") {
+            blocks.push(String::new());
+        }
+        if let Some(block) = blocks.last_mut() {
+            block.push_str(line);
+            block.push('\n');
+        }
+    }
+    blocks
+}
+
+/// A warning without its line and column, e.g.
+/// `warning: [MirChecker] ...` and `src/lib.rs`.
+fn warning_key(block: &str) -> (String, String) {
+    let mut lines = block.lines();
+    let message = lines.next().unwrap_or_default().trim().to_string();
+    let file = lines
+        .find_map(|line| line.trim_start().strip_prefix("--> "))
+        .map(|location| location.split(':').next().unwrap_or_default().to_string())
+        .unwrap_or_default();
+    (message, file)
+}
+
+/// The warnings of `to` not in `from` and the other way round, a warning
+/// reported twice in `from` and once in `to` was fixed once.
+pub fn diff_warnings(from: &[String], to: &[String]) -> (Vec<String>, Vec<String>) {
+    let mut remaining: HashMap<(String, String), usize> = HashMap::new();
+    for block in from {
+        *remaining.entry(warning_key(block)).or_default() += 1;
+    }
+    let mut introduced = vec![];
+    for block in to {
+        match remaining.get_mut(&warning_key(block)) {
+            Some(count) if *count > 0 => *count -= 1,
+            _ => introduced.push(block.trim_end().to_string()),
+        }
+    }
+    let mut fixed = vec![];
+    for block in from.iter().rev() {
+        if let Some(count) = remaining.get_mut(&warning_key(block)) {
+            if *count > 0 {
+                *count -= 1;
+                fixed.push(block.trim_end().to_string());
+            }
+        }
+    }
+    fixed.reverse();
+    (fixed, introduced)
+}
+
+/// The license of each version, the one of crates.io if it knows the
+/// version and the program license otherwise.
+async fn licenses_of(
+    dbhandler: &DBHandler,
+    cratesio_dbhandler: &DBHandler,
+    namespace: &str,
+    name: &str,
+    versions: [&str; 2],
+) -> Result<[Option<String>; 2], Box<dyn Error>> {
+    let cratesio = cratesio_dbhandler
+        .get_cratesio_version_licenses(name, &versions)
+        .await?;
+    let program = dbhandler
+        .get_license_by_name(namespace, name)
+        .await?
+        .into_iter()
+        .find(|license| license != "None");
+    Ok(versions.map(|version| cratesio.get(version).cloned().or_else(|| program.clone())))
+}
+
+pub async fn build_version_comparison(
+    reader: &DataReader,
+    dbhandler: &DBHandler,
+    cratesio_dbhandler: &DBHandler,
+    namespace: &str,
+    name: &str,
+    from: &str,
+    to: &str,
+) -> Result<VersionComparison, Box<dyn Error>> {
+    let from_deps = reader
+        .get_dependency_requirements_from_tg(&format!("{}/{}", name, from))
+        .await?;
+    let to_deps = reader
+        .get_dependency_requirements_from_tg(&format!("{}/{}", name, to))
+        .await?;
+    let dependencies = diff_dependencies(&from_deps, &to_deps);
+
+    // dev dependencies don't ship with the crate
+    let affected = |version: &str, deps: &[DependencyRequirement]| -> Vec<NameVersion> {
+        let mut nodes = vec![NameVersion {
+            name: name.to_string(),
+            version: version.to_string(),
+        }];
+        nodes.extend(
+            deps.iter()
+                .filter(|dep| dep.kind != DependencyKind::Dev)
+                .map(|dep| NameVersion {
+                    name: dep.name.clone(),
+                    version: dep.resolved_version.clone(),
+                }),
+        );
+        nodes.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+        nodes.dedup();
+        nodes
+    };
+    let from_advisories = advisories_of(dbhandler, &affected(from, &from_deps)).await?;
+    let to_advisories = advisories_of(dbhandler, &affected(to, &to_deps)).await?;
+    let (advisories_fixed, advisories_introduced) =
+        diff_advisories(&from_advisories, &to_advisories);
+
+    let [from_license, to_license] =
+        licenses_of(dbhandler, cratesio_dbhandler, namespace, name, [from, to]).await?;
+
+    let mirchecker_id = |version: &str| format!("{}/{}/{}", namespace, name, version);
+    let mut mirchecker = MircheckerDelta::default();
+    let mut warnings = vec![];
+    for (version, failed) in [
+        (from, &mut mirchecker.from_failed),
+        (to, &mut mirchecker.to_failed),
+    ] {
+        let id = mirchecker_id(version);
+        *failed = !dbhandler
+            .get_mirchecker_run_state_from_pg(id.clone())
+            .await?;
+        warnings.push(mirchecker_warnings(
+            &dbhandler.get_mirchecker_from_pg(id).await?,
+        ));
+    }
+    (mirchecker.fixed, mirchecker.introduced) = diff_warnings(&warnings[0], &warnings[1]);
+
+    let sensleak = dbhandler
+        .get_senseleak_from_pg(namespace.to_string())
+        .await?;
+    let sensleak_findings = serde_json::from_str::<Vec<serde_json::Value>>(&sensleak)
+        .map(|findings| findings.len())
+        .unwrap_or(0);
+
+    Ok(VersionComparison {
+        namespace: namespace.to_string(),
+        name: name.to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+        dependencies,
+        license: LicenseChange {
+            changed: from_license != to_license,
+            from: from_license,
+            to: to_license,
+        },
+        advisories_fixed,
+        advisories_introduced,
+        mirchecker,
+        sensleak_findings,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use model::general_model::DependencyKind;
+
+    use super::{
+        diff_advisories, diff_dependencies, diff_warnings, mirchecker_warnings,
+        DependencyRequirement,
+    };
+    use crate::handler::NewRustsec;
+
+    fn dep(
+        name: &str,
+        kind: DependencyKind,
+        requirement: &str,
+        version: &str,
+    ) -> DependencyRequirement {
+        DependencyRequirement {
+            name: name.to_string(),
+            kind,
+            target: String::new(),
+            requirement: requirement.to_string(),
+            resolved_version: version.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_diff_dependencies() {
+        let from = vec![
+            dep("serde", DependencyKind::Normal, "1.0", "1.0.100"),
+            dep("log", DependencyKind::Normal, "0.4", "0.4.20"),
+            dep("tempfile", DependencyKind::Dev, "3", "3.8.0"),
+        ];
+        let to = vec![
+            dep("serde", DependencyKind::Normal, "1.0.150", "1.0.200"),
+            dep("tracing", DependencyKind::Normal, "0.1", "0.1.40"),
+            dep("tempfile", DependencyKind::Dev, "3", "3.8.0"),
+            // 同名但不同类型的依赖单独比较
+            dep("log", DependencyKind::Build, "0.4", "0.4.20"),
+        ];
+        let changes = diff_dependencies(&from, &to);
+        let names = |deps: &[crate::handler::ComparedDependency]| -> Vec<String> {
+            deps.iter()
+                .map(|d| format!("{}:{}", d.name, d.kind))
+                .collect()
+        };
+        assert_eq!(names(&changes.added), ["log:build", "tracing:normal"]);
+        assert_eq!(names(&changes.removed), ["log:normal"]);
+        assert_eq!(changes.changed.len(), 1);
+        let serde = &changes.changed[0];
+        assert_eq!(
+            (
+                serde.from_requirement.as_str(),
+                serde.to_requirement.as_str(),
+                serde.from_version.as_str(),
+                serde.to_version.as_str()
+            ),
+            ("1.0", "1.0.150", "1.0.100", "1.0.200")
+        );
+    }
+
+    #[test]
+    fn test_diff_advisories() {
+        let advisory = |id: &str| NewRustsec {
+            id: id.to_string(),
+            subtitle: format!("{} subtitle", id),
+            reported: String::new(),
+            issued: String::new(),
+            package: String::new(),
+            ttype: String::new(),
+            keywords: String::new(),
+            aliases: String::new(),
+            reference: String::new(),
+            patched: String::new(),
+            unaffected: String::new(),
+            description: String::new(),
+            url: String::new(),
+        };
+        let entry = |id: &str, package: &str, version: &str| {
+            (
+                (id.to_string(), package.to_string()),
+                (version.to_string(), advisory(id)),
+            )
+        };
+        let from = BTreeMap::from([
+            entry("RUSTSEC-2020-0001", "time", "0.1.0"),
+            entry("RUSTSEC-2021-0002", "smallvec", "1.0.0"),
+        ]);
+        let to = BTreeMap::from([
+            entry("RUSTSEC-2021-0002", "smallvec", "1.6.0"),
+            entry("RUSTSEC-2023-0003", "openssl", "0.10.0"),
+        ]);
+        let (fixed, introduced) = diff_advisories(&from, &to);
+        assert_eq!(fixed.len(), 1);
+        assert_eq!(
+            (fixed[0].id.as_str(), fixed[0].package.as_str()),
+            ("RUSTSEC-2020-0001", "time")
+        );
+        assert_eq!(introduced.len(), 1);
+        assert_eq!(introduced[0].version, "0.10.0");
+    }
+
+    #[test]
+    fn test_diff_mirchecker_warnings() {
+        let from = mirchecker_warnings(
+            "warning: [MirChecker] Possible integer overflow\n  --> src/lib.rs:10:5\n\
+             warning: [MirChecker] Possible integer overflow\n  --> src/lib.rs:40:9\n\
+             warning: [MirChecker] Unreachable code\n  --> src/main.rs:3:1\n",
+        );
+        assert_eq!(from.len(), 3);
+        let to = mirchecker_warnings(
+            "warning: [MirChecker] Possible integer overflow\n  --> src/lib.rs:12:5\n\
+             warning: [MirChecker] Possible division by zero\n  --> src/lib.rs:50:9\n",
+        );
+        let (fixed, introduced) = diff_warnings(&from, &to);
+        assert_eq!(
+            fixed,
+            [
+                "warning: [MirChecker] Possible integer overflow\n  --> src/lib.rs:40:9",
+                "warning: [MirChecker] Unreachable code\n  --> src/main.rs:3:1"
+            ]
+        );
+        assert_eq!(
+            introduced,
+            ["warning: [MirChecker] Possible division by zero\n  --> src/lib.rs:50:9"]
+        );
+        assert!(mirchecker_warnings("").is_empty());
+    }
+}
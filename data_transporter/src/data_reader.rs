@@ -1,26 +1,36 @@
-use model::tugraph_model::{
-    Application, ApplicationVersion, Library, LibraryVersion, Program, UProgram, UVersion,
+use model::{
+    general_model::DependencyKind,
+    tugraph_model::{
+        Application, ApplicationVersion, Library, LibraryVersion, Program, UProgram, UVersion,
+    },
 };
 use semver::Version;
-use serde_json::Value;
+use serde::Deserialize;
 use std::{
     cmp::Ordering,
-    collections::{HashSet, VecDeque},
+    collections::{HashMap, HashSet, VecDeque},
     error::Error,
     time::Instant,
 };
-use tokio_postgres::NoTls;
-use tudriver::tugraph_client::TuGraphClient;
+use tudriver::{
+    cypher::{CypherQuery, Node},
+    tugraph_client::TuGraphClient,
+};
 
 use crate::{
-    db::{db_connection_config_from_env, db_cratesio_connection_config_from_env, DBHandler},
-    handler::{
-        Crateinfo, DependencyCount, DependencyCrateInfo, DependencyInfo, DependentCount,
-        DependentData, DependentInfo, Deptree, Versionpage,
-    },
+    compare::DependencyRequirement,
+    db::DBHandler,
+    features::{FeatureEdge, FeatureNode},
+    handler::{Crateinfo, DependencyCount, DependentCount, Versionpage},
+    impact::RequirementEdge,
+    listing::{sort_entries, ListingEntry, ListingSort, Relation},
     NameVersion,
 };
 
+/// Upper bound of the versions in the graph of `get_dependency_graph`, each
+/// one costs a query.
+pub const MAX_DEPENDENCY_GRAPH_VERSIONS: usize = 2000;
+
 pub trait DataReaderTrait {
     async fn get_all_programs_id(&self) -> Vec<String>;
     async fn get_program(&self, program_id: &str) -> Result<Program, Box<dyn Error>>;
@@ -38,10 +48,60 @@ pub trait DataReaderTrait {
         &self,
         namespace: &str,
         nameversion: &str,
+        kind: Option<DependencyKind>,
     ) -> Result<Vec<crate::NameVersion>, Box<dyn Error>>;
+    /// Every version of the crate `name` in `namespace`, library or application.
+    async fn get_versions_in_namespace(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>>;
+    /// The versions of many crates in one query, keyed by `(namespace, name)`.
+    async fn get_versions_of_crates(
+        &self,
+        crates: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<String>>, Box<dyn Error>>;
+    /// The GitHub URLs of many programs in one query, keyed by
+    /// `(namespace, name)`.
+    async fn get_github_urls_of_crates(
+        &self,
+        crates: &[(String, String)],
+    ) -> Result<HashMap<(String, String), String>, Box<dyn Error>>;
+    /// The documentation URLs of many programs in one query, keyed by
+    /// `(namespace, name)`.
+    async fn get_doc_urls_of_crates(
+        &self,
+        crates: &[(String, String)],
+    ) -> Result<HashMap<(String, String), String>, Box<dyn Error>>;
+    /// The `depends_on` edges out of many versions in one query, as
+    /// `(name_and_version, dependency, kind)`.
+    async fn get_dependency_edges_of_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<Vec<(String, String, Option<DependencyKind>)>, Box<dyn Error>>;
+    /// The `depends_on` edges into many versions in one query, as
+    /// `(name_and_version, dependent)`.
+    async fn get_dependent_edges_of_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>>;
+    /// Which of `name_and_versions` have a `version` vertex, in one query.
+    async fn get_loaded_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<HashSet<String>, Box<dyn Error>>;
+    /// The namespaces of many crates in one query, keyed by name.
+    async fn get_namespaces_of_crates(
+        &self,
+        names: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, Box<dyn Error>>;
     #[allow(dead_code)]
     async fn get_program_by_name(&self, program_name: &str)
         -> Result<Vec<Program>, Box<dyn Error>>;
+    async fn get_program_namespaces(
+        &self,
+        program_name: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>>;
     async fn get_indirect_dependency_nodes(
         &self,
         nameversion: NameVersion,
@@ -55,14 +115,26 @@ pub trait DataReaderTrait {
         &self,
         namespace: &str,
         nameversion: &str,
+        kind: Option<DependencyKind>,
     ) -> Result<Vec<crate::NameVersion>, Box<dyn Error>>;
+    /// Names of the crates any version of the crate `name` depends on, of
+    /// any kind.
+    async fn get_dependency_crate_names(
+        &self,
+        name: &str,
+    ) -> Result<HashSet<String>, Box<dyn Error>>;
+    /// Names of the crates depending on any version of the crate `name`, with
+    /// any kind.
+    async fn get_dependent_crate_names(
+        &self,
+        name: &str,
+    ) -> Result<HashSet<String>, Box<dyn Error>>;
     async fn get_indirect_dependent_nodes(
         &self,
         nameversion: NameVersion,
     ) -> Result<Vec<crate::NameVersion>, Box<dyn Error>>;
 
     //async fn get_max_version(&self, name: String) -> Result<String, Box<dyn Error>>;
-    #[allow(dead_code)]
     async fn get_lib_version(&self, name: String) -> Result<Vec<String>, Box<dyn Error>>;
     async fn new_get_lib_version(
         &self,
@@ -85,6 +157,18 @@ pub trait DataReaderTrait {
         &self,
         nodes: Vec<NameVersion>,
     ) -> Result<HashSet<String>, Box<dyn Error>>;
+    async fn get_dependency_graph(
+        &self,
+        namespace: &str,
+        nameversion: &str,
+    ) -> Result<(HashMap<String, Vec<String>>, bool), Box<dyn Error>>;
+    /// The features and edges of a version, of the version of the crate in
+    /// `namespace` when one is given.
+    async fn get_feature_node(
+        &self,
+        namespace: Option<&str>,
+        name_and_version: &str,
+    ) -> Result<FeatureNode, Box<dyn Error>>;
     /*#[allow(dead_code)]
     async fn get_all_dependents(
         &self,
@@ -102,38 +186,80 @@ pub trait DataReaderTrait {
         name: String,
     ) -> Result<String, Box<dyn Error>>;
     async fn get_doc_url(&self, namespace: String, name: String) -> Result<String, Box<dyn Error>>;
-    async fn build_graph(
-        &self,
-        rootnode: &mut Deptree,
-        visited: &mut HashSet<String>,
-    ) -> Result<(), Box<dyn Error>>;
     async fn get_version_page_from_tg(
         &self,
+        cratesio_dbhandler: &DBHandler,
         nsfront: String,
         nsbehind: String,
         nname: String,
     ) -> Result<Vec<Versionpage>, Box<dyn Error>>;
     async fn get_crates_front_info_from_tg(
         &self,
+        dbhandler: &DBHandler,
         nname: String,
         nversion: String,
         nsfront: String,
         nsbehind: String,
     ) -> Result<Crateinfo, Box<dyn Error>>;
-    async fn get_dependency_from_tg(
+    /// The direct and indirect dependencies of a version, of every version for `all`.
+    async fn get_dependency_index_from_tg(
         &self,
-        name: String,
-        version: String,
-        nsfront: String,
-        nsbehind: String,
-    ) -> Result<DependencyInfo, Box<dyn Error>>;
-    async fn get_dependent_from_tg(
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+    ) -> Result<Vec<ListingEntry>, Box<dyn Error>>;
+    /// The direct dependents of a version, the indirect ones too when
+    /// `with_indirect`, they are expensive for popular crates.
+    async fn get_dependent_index_from_tg(
         &self,
-        name: String,
-        version: String,
-        nsfront: String,
-        nsbehind: String,
-    ) -> Result<DependentInfo, Box<dyn Error>>;
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        with_indirect: bool,
+    ) -> Result<Vec<ListingEntry>, Box<dyn Error>>;
+    /// One page of the direct dependencies of a version, ordered by name in
+    /// TuGraph, and the number of them over all pages.
+    async fn get_direct_dependency_page_from_tg(
+        &self,
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        skip: usize,
+        limit: usize,
+    ) -> Result<(Vec<ListingEntry>, usize), Box<dyn Error>>;
+    /// One page of the direct dependents of a version, ordered by name in
+    /// TuGraph, and the number of them over all pages.
+    async fn get_direct_dependent_page_from_tg(
+        &self,
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        skip: usize,
+        limit: usize,
+    ) -> Result<(Vec<ListingEntry>, usize), Box<dyn Error>>;
+    /// The `depends_on` edges into every version of a crate, with the
+    /// requirement each dependent declared.
+    async fn get_requirement_edges_from_tg(
+        &self,
+        namespace: &str,
+        name: &str,
+        kind: Option<DependencyKind>,
+    ) -> Result<Vec<RequirementEdge>, Box<dyn Error>>;
+    /// The `depends_on` edges out of a version, with their requirement.
+    async fn get_dependency_requirements_from_tg(
+        &self,
+        name_and_version: &str,
+    ) -> Result<Vec<DependencyRequirement>, Box<dyn Error>>;
+    /// The versions depending on any of `name_and_versions` through a normal
+    /// or build edge, in one query.
+    async fn get_runtime_dependents_of_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<Vec<String>, Box<dyn Error>>;
 }
 
 #[derive(Clone)]
@@ -150,223 +276,263 @@ impl DataReader {
         let client = TuGraphClient::new(uri, user, password, db).await?;
         Ok(DataReader { client })
     }
-}
-
-impl DataReaderTrait for DataReader {
-    async fn get_dependent_from_tg(
+    pub async fn with_max_connections(
+        uri: &str,
+        user: &str,
+        password: &str,
+        db: &str,
+        max_connections: usize,
+    ) -> Result<Self, Box<dyn Error>> {
+        let client =
+            TuGraphClient::with_max_connections(uri, user, password, db, max_connections).await?;
+        Ok(DataReader { client })
+    }
+    /// The direct dependencies or dependents of a version, or of every
+    /// version of the crate for `all`, in one query.
+    async fn get_direct_listing_nodes(
         &self,
-        name: String,
-        version: String,
-        nsfront: String,
-        nsbehind: String,
-    ) -> Result<DependentInfo, Box<dyn Error>> {
-        let namespace = nsfront.clone() + "/" + &nsbehind.clone();
-        let nameversion = name.clone() + "/" + &version.clone();
-        let mut direct_nodes = vec![];
-        if version.clone() == *"all" {
-            let lib_versions = self
-                .new_get_lib_version(namespace.clone(), name.clone())
-                .await
-                .unwrap();
-            let mut getversions = vec![];
-            for version in lib_versions {
-                getversions.push(version);
-            }
-            getversions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a), // 从高到低排序
-                    (Ok(_), Err(_)) => Ordering::Less,   // 无法解析的版本号认为更小
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            let mut visited = HashSet::new();
-            for nversion in &getversions {
-                let tmp_name_and_version = name.clone() + "/" + nversion;
-                let tmp_direct_nodes = self
-                    .new_get_direct_dependent_nodes(&namespace, &tmp_name_and_version)
-                    .await
-                    .unwrap();
-                for node in tmp_direct_nodes {
-                    if visited.insert(node.clone()) {
-                        direct_nodes.push(node.clone());
-                    }
-                }
-            }
-        } else {
-            direct_nodes = self
-                .new_get_direct_dependent_nodes(&namespace, &nameversion)
-                .await
-                .unwrap();
-        }
-        let getdirect_count = direct_nodes.len();
-        /*let all_dependent_nodes = self
-            .new_get_all_dependents(namespace.clone(), nameversion.clone())
-            .await
-            .unwrap();
-        let mut indirect_dependent = vec![];
-        for node in all_dependent_nodes {
-            let mut dr = false;
-            for node2 in direct_nodes.clone() {
-                let nv = node2.name.clone() + "/" + &node2.version.clone();
-                if node == nv {
-                    dr = true;
-                    break;
-                }
-            }
-            if !dr {
-                indirect_dependent.push(node);
-            }
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        direction: Direction,
+    ) -> Result<Vec<NameVersion>, Box<dyn Error>> {
+        let query = direct_listing_query(namespace, name, version, kind, direction);
+        let nodes = self
+            .client
+            .query_column_as::<String>(&query, "name_and_version")
+            .await?
+            .iter()
+            .filter_map(|nv| NameVersion::from_string(nv))
+            .collect();
+        Ok(nodes)
+    }
+    /// A page of [`Self::get_direct_listing_nodes`] read with `SKIP`/`LIMIT`,
+    /// and their count. TuGraph compares the versions of a crate as strings,
+    /// they are put back in semver order within the page.
+    #[allow(clippy::too_many_arguments)]
+    async fn get_direct_listing_page(
+        &self,
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        direction: Direction,
+        skip: usize,
+        limit: usize,
+    ) -> Result<(Vec<ListingEntry>, usize), Box<dyn Error>> {
+        let query =
+            direct_listing_page_query(namespace, name, version, kind, direction, skip, limit);
+        let mut entries: Vec<ListingEntry> = self
+            .client
+            .query_column_as::<String>(&query, "name_and_version")
+            .await?
+            .iter()
+            .filter_map(|nv| NameVersion::from_string(nv))
+            .map(|node| ListingEntry {
+                name: node.name,
+                version: node.version,
+                relation: Relation::Direct,
+            })
+            .collect();
+        sort_entries(&mut entries, ListingSort::Name, &HashMap::new());
+        let query = direct_listing_count_query(namespace, name, version, kind, direction);
+        let total = self
+            .client
+            .query_column_as::<i64>(&query, "total")
+            .await?
+            .first()
+            .copied()
+            .unwrap_or(0);
+        Ok((entries, usize::try_from(total)?))
+    }
+    /// The `column` of many programs in one query, keyed by
+    /// `(namespace, name)`. Programs without it are left out.
+    async fn program_urls_of_crates(
+        &self,
+        crates: &[(String, String)],
+        column: &'static str,
+    ) -> Result<HashMap<(String, String), String>, Box<dyn Error>> {
+        let mut urls = HashMap::new();
+        if crates.is_empty() {
+            return Ok(urls);
         }
-        let indirect_dependent_count = indirect_dependent.len();*/
-        let mut deps = vec![];
-        let mut count1 = 0;
-        for item in direct_nodes {
-            let dep = DependentData {
-                crate_name: item.clone().name,
-                version: item.clone().version,
-                relation: "Direct".to_string(),
+        let namespaces: Vec<&str> = crates.iter().map(|(ns, _)| ns.as_str()).collect();
+        let names: Vec<&str> = crates.iter().map(|(_, name)| name.as_str()).collect();
+        let query = CypherQuery::new(format!(
+            "
+            MATCH (p:program)
+WHERE p.namespace IN $namespaces AND p.name IN $names
+RETURN DISTINCT p.namespace AS namespace, p.name AS name, p.{} AS url",
+            column
+        ))
+        .param("namespaces", namespaces)
+        .param("names", names);
+        let wanted: HashSet<&(String, String)> = crates.iter().collect();
+        for row in self.client.query_as::<CrateUrlRow>(&query).await? {
+            let Some(url) = row.url else {
+                continue;
             };
-            deps.push(dep);
-            count1 += 1;
-            if count1 == 50 {
-                break;
+            let key = (row.namespace, row.name);
+            // the IN filters are independent, drop the cross pairs
+            if wanted.contains(&key) {
+                urls.insert(key, url);
             }
         }
-        /*let mut count2 = 0;
-        for item in indirect_dependent {
-            let parts: Vec<&str> = item.split('/').collect();
-            let dep = DependentData {
-                crate_name: parts[0].to_string(),
-                version: parts[1].to_string(),
-                relation: "Indirect".to_string(),
-            };
-            count2 += 1;
-            if count2 == 50 {
-                break;
-            }
-            deps.push(dep);
-        }*/
+        Ok(urls)
+    }
+    /// The crate names of the `name_and_version` column of `query`.
+    async fn crate_names_of(&self, query: &CypherQuery) -> Result<HashSet<String>, Box<dyn Error>> {
+        let names = self
+            .client
+            .query_column_as::<String>(query, "name_and_version")
+            .await?
+            .iter()
+            .filter_map(|nv| NameVersion::from_string(nv).map(|nv| nv.name))
+            .collect();
+        Ok(names)
+    }
+}
 
-        let res_deps = DependentInfo {
-            direct_count: getdirect_count,
-            indirect_count: 0,
-            data: deps,
-        };
-        Ok(res_deps)
+impl DataReaderTrait for DataReader {
+    async fn get_direct_dependency_page_from_tg(
+        &self,
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        skip: usize,
+        limit: usize,
+    ) -> Result<(Vec<ListingEntry>, usize), Box<dyn Error>> {
+        self.get_direct_listing_page(
+            namespace,
+            name,
+            version,
+            kind,
+            Direction::Dependencies,
+            skip,
+            limit,
+        )
+        .await
     }
-    async fn get_dependency_from_tg(
+    async fn get_direct_dependent_page_from_tg(
         &self,
-        name: String,
-        version: String,
-        nsfront: String,
-        nsbehind: String,
-    ) -> Result<DependencyInfo, Box<dyn Error>> {
-        let namespace = nsfront.clone() + "/" + &nsbehind.clone();
-        let nameversion = name.clone() + "/" + &version.clone();
-        tracing::info!("{} {}", namespace.clone(), nameversion.clone());
-        let mut direct_nodes = vec![];
-        if version.clone() == *"all" {
-            let lib_versions = self
-                .new_get_lib_version(namespace.clone(), name.clone())
-                .await
-                .unwrap();
-            let mut getversions = vec![];
-            for version in lib_versions {
-                getversions.push(version);
-            }
-            getversions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a), // 从高到低排序
-                    (Ok(_), Err(_)) => Ordering::Less,   // 无法解析的版本号认为更小
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        skip: usize,
+        limit: usize,
+    ) -> Result<(Vec<ListingEntry>, usize), Box<dyn Error>> {
+        self.get_direct_listing_page(
+            namespace,
+            name,
+            version,
+            kind,
+            Direction::Dependents,
+            skip,
+            limit,
+        )
+        .await
+    }
+    async fn get_dependent_index_from_tg(
+        &self,
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+        with_indirect: bool,
+    ) -> Result<Vec<ListingEntry>, Box<dyn Error>> {
+        let direct_nodes = self
+            .get_direct_listing_nodes(namespace, name, version, kind, Direction::Dependents)
+            .await?;
+        let mut entries: Vec<ListingEntry> = direct_nodes
+            .iter()
+            .map(|node| ListingEntry {
+                name: node.name.clone(),
+                version: node.version.clone(),
+                relation: Relation::Direct,
+            })
+            .collect();
+        if with_indirect {
+            let direct: HashSet<String> = direct_nodes
+                .iter()
+                .map(|node| format!("{}/{}", node.name, node.version))
+                .collect();
+            let mut queue: VecDeque<String> = direct.iter().cloned().collect();
             let mut visited = HashSet::new();
-            for nversion in &getversions {
-                let tmp_name_and_version = name.clone() + "/" + nversion;
-                let tmp_direct_nodes = self
-                    .new_get_direct_dependency_nodes(&namespace, &tmp_name_and_version)
-                    .await
-                    .unwrap();
-                for node in tmp_direct_nodes {
-                    if visited.insert(node.clone()) {
-                        direct_nodes.push(node.clone());
+            while let Some(current) = queue.pop_front() {
+                if visited.len() == MAX_INDIRECT_NODES {
+                    tracing::warn!(
+                        "indirect dependents of {}/{} cut at {}",
+                        name,
+                        version,
+                        MAX_INDIRECT_NODES
+                    );
+                    break;
+                }
+                if !visited.insert(current.clone()) {
+                    continue;
+                }
+                for node in self.get_direct_dependent_nodes(&current).await? {
+                    let nv = format!("{}/{}", node.name, node.version);
+                    if !visited.contains(&nv) {
+                        queue.push_back(nv);
+                    }
+                }
+                if !direct.contains(&current) {
+                    if let Some(node) = NameVersion::from_string(&current) {
+                        entries.push(ListingEntry {
+                            name: node.name,
+                            version: node.version,
+                            relation: Relation::Indirect,
+                        });
                     }
                 }
             }
-        } else {
-            direct_nodes = self
-                .new_get_direct_dependency_nodes(&namespace, &nameversion)
-                .await
-                .unwrap();
         }
-        let getdirect_count = direct_nodes.len();
-        let all_dependency_nodes = self
-            .new_get_all_dependencies(direct_nodes.clone())
-            .await
-            .unwrap();
-        let mut indirect_dependency = vec![];
+        Ok(entries)
+    }
+    async fn get_dependency_index_from_tg(
+        &self,
+        namespace: &str,
+        name: &str,
+        version: &str,
+        kind: Option<DependencyKind>,
+    ) -> Result<Vec<ListingEntry>, Box<dyn Error>> {
+        let direct_nodes = self
+            .get_direct_listing_nodes(namespace, name, version, kind, Direction::Dependencies)
+            .await?;
+        let all_dependency_nodes = self.new_get_all_dependencies(direct_nodes.clone()).await?;
+        let direct: HashSet<String> = direct_nodes
+            .iter()
+            .map(|node| format!("{}/{}", node.name, node.version))
+            .collect();
+        let mut entries: Vec<ListingEntry> = direct_nodes
+            .into_iter()
+            .map(|node| ListingEntry {
+                name: node.name,
+                version: node.version,
+                relation: Relation::Direct,
+            })
+            .collect();
         for node in all_dependency_nodes {
-            let mut dr = false;
-            for node2 in direct_nodes.clone() {
-                let nv = node2.name.clone() + "/" + &node2.version.clone();
-                if node == nv {
-                    dr = true;
-                    break;
-                }
+            if direct.contains(&node) {
+                continue;
             }
-            if !dr {
-                indirect_dependency.push(node);
+            if let Some(node) = NameVersion::from_string(&node) {
+                entries.push(ListingEntry {
+                    name: node.name,
+                    version: node.version,
+                    relation: Relation::Indirect,
+                });
             }
         }
-        let indirect_dependency_count = indirect_dependency.len();
-        let mut deps = vec![];
-        for item in direct_nodes {
-            let dep_count = self.count_dependencies(item.clone()).await.unwrap();
-            let dep = DependencyCrateInfo {
-                crate_name: item.clone().name,
-                version: item.clone().version,
-                relation: "Direct".to_string(),
-                license: "".to_string(),
-                dependencies: dep_count,
-            };
-            deps.push(dep);
-        }
-        for item in indirect_dependency {
-            let parts: Vec<&str> = item.split('/').collect();
-            let newitem = NameVersion {
-                name: parts[0].to_string(),
-                version: parts[1].to_string(),
-            };
-            let dep_count = self.count_dependencies(newitem.clone()).await.unwrap();
-
-            let dep = DependencyCrateInfo {
-                crate_name: parts[0].to_string(),
-                version: parts[1].to_string(),
-                relation: "Indirect".to_string(),
-                license: "".to_string(),
-                dependencies: dep_count,
-            };
-            deps.push(dep);
-        }
-
-        let res_deps = DependencyInfo {
-            direct_count: getdirect_count,
-            indirect_count: indirect_dependency_count,
-            data: deps,
-        };
-        Ok(res_deps)
+        Ok(entries)
     }
     async fn get_crates_front_info_from_tg(
         &self,
+        dbhandler: &DBHandler,
         nname: String,
         nversion: String,
         nsfront: String,
@@ -417,7 +583,7 @@ impl DataReaderTrait for DataReader {
             for version in &getversions {
                 let tmp_name_and_version = nname.clone() + "/" + version;
                 let tmp_direct_nodes = self
-                    .new_get_direct_dependency_nodes(&namespace, &tmp_name_and_version)
+                    .new_get_direct_dependency_nodes(&namespace, &tmp_name_and_version, None)
                     .await
                     .unwrap();
                 for node in tmp_direct_nodes {
@@ -428,7 +594,7 @@ impl DataReaderTrait for DataReader {
             }
         } else {
             direct_dependency_nodes = self
-                .new_get_direct_dependency_nodes(&namespace, &name_and_version)
+                .new_get_direct_dependency_nodes(&namespace, &name_and_version, None)
                 .await
                 .unwrap();
         }
@@ -469,7 +635,7 @@ impl DataReaderTrait for DataReader {
             for version in &getversions {
                 let tmp_name_and_version = nname.clone() + "/" + version;
                 let tmp_direct_nodes = self
-                    .new_get_direct_dependent_nodes(&namespace, &tmp_name_and_version)
+                    .new_get_direct_dependent_nodes(&namespace, &tmp_name_and_version, None)
                     .await
                     .unwrap();
                 for node in tmp_direct_nodes {
@@ -480,7 +646,7 @@ impl DataReaderTrait for DataReader {
             }
         } else {
             direct_dependent_nodes = self
-                .new_get_direct_dependent_nodes(&namespace, &name_and_version)
+                .new_get_direct_dependent_nodes(&namespace, &name_and_version, None)
                 .await
                 .unwrap();
         }
@@ -492,18 +658,6 @@ impl DataReaderTrait for DataReader {
         let get_dept_time = time1.elapsed();
         tracing::info!("get_dept_time:{:?}", get_dept_time);
         let time2 = Instant::now();
-        let db_connection_config = db_connection_config_from_env();
-        #[allow(unused_variables)]
-        let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-            .await
-            .unwrap();
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
-            }
-        });
-        tracing::info!("finish connect pg");
-        let dbhandler = DBHandler { client };
         let mut getcves = vec![];
         if nversion.clone() == *"all" {
             let mut visited = HashSet::new();
@@ -556,6 +710,7 @@ impl DataReaderTrait for DataReader {
     #[allow(unused_assignments)]
     async fn get_version_page_from_tg(
         &self,
+        cratesio_dbhandler: &DBHandler,
         nsfront: String,
         nsbehind: String,
         nname: String,
@@ -592,29 +747,17 @@ impl DataReaderTrait for DataReader {
             dts_count = all_dts.len();
             if all_dts.len() == 0 {
                 let direct_dts = self
-                    .new_get_direct_dependent_nodes(&namespace, &name_and_version)
+                    .new_get_direct_dependent_nodes(&namespace, &name_and_version, None)
                     .await
                     .unwrap();
                 dts_count = direct_dts.len();
             }*/
             let all_dts = self
-                .new_get_direct_dependent_nodes(&namespace, &name_and_version)
+                .new_get_direct_dependent_nodes(&namespace, &name_and_version, None)
                 .await
                 .unwrap();
             tracing::info!("finish get all dependents");
-            let _db_cratesio_connection_config = db_cratesio_connection_config_from_env();
-            #[allow(unused_variables)]
-            let (client2, connection2) =
-                tokio_postgres::connect(&_db_cratesio_connection_config, NoTls)
-                    .await
-                    .unwrap();
-            tokio::spawn(async move {
-                if let Err(e) = connection2.await {
-                    eprintln!("connection error: {}", e);
-                }
-            });
-            let dbhandler2 = DBHandler { client: client2 };
-            let res = dbhandler2
+            let res = cratesio_dbhandler
                 .get_dump_from_cratesio_pg(nname.clone(), version.clone())
                 .await
                 .unwrap();
@@ -634,98 +777,49 @@ impl DataReaderTrait for DataReader {
         }
         Ok(every_version)
     }
-    async fn build_graph(
-        &self,
-        rootnode: &mut Deptree,
-        visited: &mut HashSet<String>,
-    ) -> Result<(), Box<dyn Error>> {
-        let name_and_version = &rootnode.name_and_version;
-        let res = self
-            .get_direct_dependency_nodes(name_and_version)
-            .await
-            .unwrap();
-        tracing::info!("direct dep count:{}", res.len());
-        let db_connection_config = db_connection_config_from_env();
-        #[allow(unused_variables)]
-        let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-            .await
-            .unwrap();
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
-            }
-        });
-        let dbhandler = DBHandler { client };
-        for node in res {
-            let name = node.name.clone();
-            let version = node.version.clone();
-            let dep_nv = name.clone() + "/" + &version;
-            let rustcve = dbhandler.get_direct_rustsec(&name, &version).await.unwrap();
-            let mut dn = Deptree {
-                name_and_version: dep_nv.clone(),
-                cve_count: rustcve.len(),
-                direct_dependency: Vec::new(),
-            };
-            if visited.insert(dep_nv.clone()) {
-                Box::pin(self.build_graph(&mut dn, visited)).await?;
-                rootnode.direct_dependency.push(dn);
-            }
-        }
-        Ok(())
-    }
     async fn get_github_url(
         &self,
         namespace: String,
         name: String,
     ) -> Result<String, Box<dyn Error>> {
         tracing::info!("{}|{}", namespace.clone(), name.clone());
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (n:program {{namespace:'{}'}}) WHERE n.name='{}'
-RETURN n.github_url 
+            MATCH (n:program {namespace: $namespace}) WHERE n.name = $name
+RETURN n.github_url AS github_url
         ",
-            &namespace, &name
-        );
-        let results = self.client.exec_query(&query).await?;
+        )
+        .param("namespace", namespace.as_str())
+        .param("name", name.as_str());
+        let urls = self
+            .client
+            .query_column_as::<Option<String>>(&query, "github_url")
+            .await?;
         tracing::info!("finish get github_url");
-        let mut res = vec![];
-        for node in results {
-            res.push(node);
-        }
-        let unique_items: HashSet<String> = res.clone().into_iter().collect();
-        let mut nodes = vec![];
-        for res in unique_items {
-            let parsed: Value = serde_json::from_str(&res).unwrap();
-            if let Some(url) = parsed.get("n.github_url").and_then(|v| v.as_str()) {
-                nodes.push(url.to_string());
-            }
-        }
-        nodes.push("None".to_string());
-        Ok(nodes[0].clone())
+        Ok(urls
+            .into_iter()
+            .flatten()
+            .next()
+            .unwrap_or_else(|| "None".to_string()))
     }
     async fn get_doc_url(&self, namespace: String, name: String) -> Result<String, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (n:program {{namespace:'{}'}}) WHERE n.name='{}'
-RETURN n.doc_url 
+            MATCH (n:program {namespace: $namespace}) WHERE n.name = $name
+RETURN n.doc_url AS doc_url
         ",
-            &namespace, &name
-        );
-        let results = self.client.exec_query(&query).await?;
-        let mut res = vec![];
-        for node in results {
-            res.push(node);
-        }
-        let unique_items: HashSet<String> = res.clone().into_iter().collect();
-        let mut nodes = vec![];
-        for res in unique_items {
-            let parsed: Value = serde_json::from_str(&res).unwrap();
-            if let Some(url) = parsed.get("n.doc_url").and_then(|v| v.as_str()) {
-                nodes.push(url.to_string());
-            }
-        }
-        nodes.push("None".to_string());
-        Ok(nodes[0].clone())
+        )
+        .param("namespace", namespace.as_str())
+        .param("name", name.as_str());
+        let urls = self
+            .client
+            .query_column_as::<Option<String>>(&query, "doc_url")
+            .await?;
+        Ok(urls
+            .into_iter()
+            .flatten()
+            .next()
+            .unwrap_or_else(|| "None".to_string()))
     }
     async fn get_all_dependencies(
         &self,
@@ -777,6 +871,117 @@ RETURN n.doc_url
         }
         Ok(visited)
     }
+    /// Adjacency list of the dependency closure of `nameversion`, keyed by
+    /// `name/version`, and whether it was cut off at
+    /// [`MAX_DEPENDENCY_GRAPH_VERSIONS`]. The root's edges come from its
+    /// namespace, the rest are walked with `get_direct_dependency_nodes`.
+    async fn get_dependency_graph(
+        &self,
+        namespace: &str,
+        nameversion: &str,
+    ) -> Result<(HashMap<String, Vec<String>>, bool), Box<dyn Error>> {
+        let mut graph = HashMap::new();
+        let mut queue = VecDeque::new();
+        let root_deps: Vec<String> = self
+            .new_get_direct_dependency_nodes(namespace, nameversion, None)
+            .await?
+            .into_iter()
+            .map(|dep| dep.name + "/" + &dep.version)
+            .collect();
+        queue.extend(root_deps.clone());
+        graph.insert(nameversion.to_string(), root_deps);
+        while let Some(current) = queue.pop_front() {
+            if graph.contains_key(&current) {
+                continue;
+            }
+            if graph.len() >= MAX_DEPENDENCY_GRAPH_VERSIONS {
+                return Ok((graph, true));
+            }
+            let deps: Vec<String> = self
+                .get_direct_dependency_nodes(&current)
+                .await?
+                .into_iter()
+                .map(|dep| dep.name + "/" + &dep.version)
+                .collect();
+            queue.extend(deps.clone());
+            graph.insert(current, deps);
+        }
+        Ok((graph, false))
+    }
+    async fn get_feature_node(
+        &self,
+        namespace: Option<&str>,
+        name_and_version: &str,
+    ) -> Result<FeatureNode, Box<dyn Error>> {
+        let version = match namespace {
+            Some(_) => "(p:program {namespace: $namespace})-[:has_type]->(l)-[:has_version]->(lv {name_and_version: $name_and_version})-[:has_dep_version]->(n:version)",
+            None => "(n:version {name_and_version: $name_and_version})",
+        };
+        let scoped = |query: CypherQuery| match namespace {
+            Some(namespace) => query.param("namespace", namespace),
+            None => query,
+        };
+        let query = scoped(
+            CypherQuery::new(format!(
+                "
+                MATCH {}
+                RETURN n.features as features
+                ",
+                version
+            ))
+            .param("name_and_version", name_and_version),
+        );
+        let mut node = FeatureNode::default();
+        for features in self
+            .client
+            .query_column_as::<Option<String>>(&query, "features")
+            .await?
+            .into_iter()
+            .flatten()
+        {
+            node.features = serde_json::from_str(&features).unwrap_or_default();
+        }
+
+        let query = scoped(
+            CypherQuery::new(format!(
+                "
+                MATCH {}-[e:depends_on]->(m:version)
+                RETURN m.name_and_version as name_and_version, e.kind as kind, e.rename as rename, e.optional as optional, e.default_features as default_features, e.features as features
+                ",
+                version
+            ))
+            .param("name_and_version", name_and_version),
+        );
+        for row in self.client.query_as::<FeatureEdgeRow>(&query).await? {
+            let dependency = row.name_and_version;
+            let Some(name) = NameVersion::from_string(&dependency).map(|nv| nv.name) else {
+                continue;
+            };
+            // edges imported before these properties existed read as `null`
+            let key = match row.rename {
+                Some(rename) if !rename.is_empty() => rename,
+                _ => name,
+            };
+            node.edges.push(FeatureEdge {
+                dependency,
+                key,
+                kind: row
+                    .kind
+                    .and_then(|kind| kind.parse().ok())
+                    .unwrap_or_default(),
+                optional: row.optional.unwrap_or(false),
+                default_features: row.default_features.unwrap_or(true),
+                features: row
+                    .features
+                    .unwrap_or_default()
+                    .split(',')
+                    .filter(|f| !f.is_empty())
+                    .map(|f| f.to_string())
+                    .collect(),
+            });
+        }
+        Ok(node)
+    }
     /*async fn get_all_dependents(
         &self,
         nameversion: NameVersion,
@@ -806,7 +1011,7 @@ RETURN n.doc_url
         let mut queue = VecDeque::new();
         let mut visited = HashSet::new();
         for node in self
-            .new_get_direct_dependent_nodes(&namespace, &nameversion)
+            .new_get_direct_dependent_nodes(&namespace, &nameversion, None)
             .await
             .unwrap()
         {
@@ -832,62 +1037,53 @@ RETURN n.doc_url
             RETURN p 
         ";
 
-        let results = self.client.exec_query(query).await.unwrap();
-
-        let mut programs = vec![];
-        for result in results {
-            let programs_json: Value = serde_json::from_str(&result).unwrap();
-
-            let pro = programs_json["p"].clone();
-
-            let program: Program = serde_json::from_value(pro).unwrap();
-
-            programs.push(program.id);
-        }
+        let programs = self
+            .client
+            .query_column_as::<Program>(&CypherQuery::new(query), "p")
+            .await
+            .unwrap();
 
-        programs
+        programs.into_iter().map(|program| program.id).collect()
     }
 
     async fn get_program(&self, program_id: &str) -> Result<Program, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (p: program {{id: '{}'}})
-            RETURN p            
+            MATCH (p: program {id: $id})
+            RETURN p
             ",
-            program_id
-        );
-        let results = self.client.exec_query(&query).await?;
-        let programs_json: Value = serde_json::from_str(&results[0]).unwrap();
-        let pro = programs_json["p"].clone();
-        let program: Program = serde_json::from_value(pro).unwrap();
+        )
+        .param("id", program_id);
+        let program = self
+            .client
+            .query_column_as::<Program>(&query, "p")
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| format!("no program with id {}", program_id))?;
         Ok(program)
     }
 
     async fn get_type(&self, program_id: &str) -> Result<(UProgram, bool), Box<dyn Error>> {
         let mut islib = false;
 
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (p: program {{id: '{}'}})-[:has_type]->(o)
+            MATCH (p: program {id: $id})-[:has_type]->(o)
             RETURN o, label(o) as o_label
             ",
-            program_id
-        );
+        )
+        .param("id", program_id);
 
-        let results = self.client.exec_query(&query).await?;
+        let rows = self.client.query_as::<TypeRow>(&query).await?;
         let mut uprograms = vec![];
-        for result in results {
-            let result_json: Value = serde_json::from_str(&result).unwrap();
-
-            let label: String = serde_json::from_value(result_json["o_label"].clone()).unwrap();
-
-            let o = result_json["o"].clone();
-            if label.eq(&"library".to_string()) {
+        for row in rows {
+            if row.o_label == "library" {
                 islib = true;
-                let library: Library = serde_json::from_value(o).unwrap();
+                let library: Library = row.o.to()?;
                 uprograms.push(UProgram::Library(library));
-            } else if label.eq(&"application".to_string()) {
-                let application: Application = serde_json::from_value(o).unwrap();
+            } else if row.o_label == "application" {
+                let application: Application = row.o.to()?;
                 uprograms.push(UProgram::Application(application));
             }
         }
@@ -900,44 +1096,49 @@ RETURN n.doc_url
         is_lib: bool,
     ) -> Result<Vec<crate::VersionInfo>, Box<dyn Error>> {
         let query = if is_lib {
-            format!(
+            CypherQuery::new(
                 "
-                MATCH (l: library {{id: '{}'}})-[:has_version]->(o)
+                MATCH (l: library {id: $id})-[:has_version]->(o)
                 RETURN o
             ",
-                program_id
             )
         } else {
-            format!(
+            CypherQuery::new(
                 "
-                MATCH (l: application {{id: '{}'}})-[:has_version]->(o)
+                MATCH (l: application {id: $id})-[:has_version]->(o)
                 RETURN o
                 ",
-                program_id
             )
-        };
+        }
+        .param("id", program_id);
 
-        let results = self.client.exec_query(&query).await?;
+        let version_bases: Vec<(UVersion, String)> = if is_lib {
+            self.client
+                .query_column_as::<LibraryVersion>(&query, "o")
+                .await?
+                .into_iter()
+                .map(|library_version| {
+                    let name_version = library_version.name_and_version.clone();
+                    (UVersion::LibraryVersion(library_version), name_version)
+                })
+                .collect()
+        } else {
+            self.client
+                .query_column_as::<ApplicationVersion>(&query, "o")
+                .await?
+                .into_iter()
+                .map(|application_version| {
+                    let name_version = application_version.name_and_version.clone();
+                    (
+                        UVersion::ApplicationVersion(application_version),
+                        name_version,
+                    )
+                })
+                .collect()
+        };
 
         let mut versions: Vec<crate::VersionInfo> = vec![];
-        for result in results {
-            let result_json: Value = serde_json::from_str(&result).unwrap();
-
-            let o = result_json["o"].clone();
-
-            let (version_base, name_version) = if is_lib {
-                let library_version: LibraryVersion = serde_json::from_value(o).unwrap();
-                (
-                    UVersion::LibraryVersion(library_version.clone()),
-                    library_version.name_and_version.clone(),
-                )
-            } else {
-                let application_version: ApplicationVersion = serde_json::from_value(o).unwrap();
-                (
-                    UVersion::ApplicationVersion(application_version.clone()),
-                    application_version.name_and_version.clone(),
-                )
-            };
+        for (version_base, name_version) in version_bases {
             tracing::debug!("Read version for id {}: {:?}", program_id, version_base);
 
             // get dependencies
@@ -958,27 +1159,25 @@ RETURN n.doc_url
         &self,
         name_and_version: &str,
     ) -> Result<Vec<crate::NameVersion>, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-                MATCH (n:version {{name_and_version: '{}'}})-[:depends_on]->(m:version)
+                MATCH (n:version {name_and_version: $name_and_version})-[e:depends_on]->(m:version)
+                WHERE e.kind IS NULL OR e.kind <> 'dev'
                 RETURN m.name_and_version as name_and_version
                 ",
-            name_and_version
-        );
-
-        let results = self.client.exec_query(&query).await?;
-        let unique_items: HashSet<String> = results.clone().into_iter().collect();
-        let mut nodes = vec![];
+        )
+        .param("name_and_version", name_and_version);
 
-        for result in unique_items {
-            let result_json: Value = serde_json::from_str(&result).unwrap();
-            let name_version_str: String =
-                serde_json::from_value(result_json["name_and_version"].clone()).unwrap();
-
-            if let Some(name_version) = crate::NameVersion::from_string(&name_version_str) {
-                nodes.push(name_version);
-            }
-        }
+        let unique_items: HashSet<String> = self
+            .client
+            .query_column_as(&query, "name_and_version")
+            .await?
+            .into_iter()
+            .collect();
+        let nodes = unique_items
+            .iter()
+            .filter_map(|nv| crate::NameVersion::from_string(nv))
+            .collect();
 
         Ok(nodes)
     }
@@ -986,34 +1185,32 @@ RETURN n.doc_url
         &self,
         namespace: &str,
         nameversion: &str,
+        kind: Option<DependencyKind>,
     ) -> Result<Vec<crate::NameVersion>, Box<dyn Error>> {
         tracing::info!("enter get_direct_dependency_nodes");
-        let query1 = format!(
-            "
-                MATCH (p:program {{namespace: '{}'}})-[:has_type]->(l)-[:has_version]->(lv {{name_and_version: '{}'}})-[:has_dep_version]->(vs:version)-[:depends_on]->(m:version)
+        let query1 = with_kind(
+            CypherQuery::new(format!(
+                "
+                MATCH (p:program {{namespace: $namespace}})-[:has_type]->(l)-[:has_version]->(lv {{name_and_version: $name_and_version}})-[:has_dep_version]->(vs:version)-{}->(m:version)
 RETURN m.name_and_version as name_and_version
                 ",
-            namespace,
-            nameversion,
-        );
-        let results1 = self.client.exec_query(&query1).await?;
+                depends_on_edge("", kind),
+            )),
+            kind,
+        )
+        .param("namespace", namespace)
+        .param("name_and_version", nameversion);
+        let unique_items: HashSet<String> = self
+            .client
+            .query_column_as(&query1, "name_and_version")
+            .await?
+            .into_iter()
+            .collect();
         tracing::info!("finish get_direct_dep");
-        let mut res = vec![];
-        for node in results1 {
-            res.push(node);
-        }
-        let unique_items: HashSet<String> = res.clone().into_iter().collect();
-        let mut nodes = vec![];
-
-        for result in unique_items {
-            let result_json: Value = serde_json::from_str(&result).unwrap();
-            let name_version_str: String =
-                serde_json::from_value(result_json["name_and_version"].clone()).unwrap();
-
-            if let Some(name_version) = crate::NameVersion::from_string(&name_version_str) {
-                nodes.push(name_version);
-            }
-        }
+        let nodes = unique_items
+            .iter()
+            .filter_map(|nv| crate::NameVersion::from_string(nv))
+            .collect();
 
         Ok(nodes)
     }
@@ -1042,24 +1239,39 @@ RETURN m.name_and_version as name_and_version
         &self,
         program_name: &str,
     ) -> Result<Vec<Program>, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
             MATCH (p:program)
-            WHERE p.name CONTAINS '{}'
+            WHERE p.name CONTAINS $name
             RETURN p
             ",
-            program_name
-        );
-        let results = self.client.exec_query(&query).await?;
-        let mut programs = vec![];
-        for result in results {
-            let programs_json: Value = serde_json::from_str(&result).unwrap();
-            let pro = programs_json["p"].clone();
-            let program: Program = serde_json::from_value(pro).unwrap();
-            programs.push(program);
-        }
+        )
+        .param("name", program_name);
+        let programs = self.client.query_column_as(&query, "p").await?;
         Ok(programs)
     }
+    async fn get_program_namespaces(
+        &self,
+        program_name: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let query = CypherQuery::new(
+            "
+            MATCH (p:program {name: $name})
+            RETURN p.namespace AS namespace
+            ",
+        )
+        .param("name", program_name);
+        let mut namespaces: Vec<String> = self
+            .client
+            .query_column_as::<Option<String>>(&query, "namespace")
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        namespaces.sort();
+        namespaces.dedup();
+        Ok(namespaces)
+    }
     async fn count_dependencies(&self, nameversion: NameVersion) -> Result<usize, Box<dyn Error>> {
         let all_nodes = self.get_all_dependencies(nameversion).await.unwrap();
         Ok(all_nodes.len())
@@ -1068,61 +1280,103 @@ RETURN m.name_and_version as name_and_version
         &self,
         name_and_version: &str,
     ) -> Result<Vec<crate::NameVersion>, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-                MATCH (n:version {{name_and_version: '{}'}})<-[:depends_on]-(m:version)
+                MATCH (n:version {name_and_version: $name_and_version})<-[e:depends_on]-(m:version)
+                WHERE e.kind IS NULL OR e.kind <> 'dev'
                 RETURN m.name_and_version as name_and_version
                 ",
-            name_and_version
-        );
-
-        let results = self.client.exec_query(&query).await?;
-        let unique_items: HashSet<String> = results.clone().into_iter().collect();
-        let mut nodes = vec![];
+        )
+        .param("name_and_version", name_and_version);
 
-        for result in unique_items {
-            let result_json: Value = serde_json::from_str(&result).unwrap();
-            let name_version_str: String =
-                serde_json::from_value(result_json["name_and_version"].clone()).unwrap();
-
-            if let Some(name_version) = crate::NameVersion::from_string(&name_version_str) {
-                nodes.push(name_version);
-            }
-        }
+        let unique_items: HashSet<String> = self
+            .client
+            .query_column_as(&query, "name_and_version")
+            .await?
+            .into_iter()
+            .collect();
+        let nodes = unique_items
+            .iter()
+            .filter_map(|nv| crate::NameVersion::from_string(nv))
+            .collect();
 
         Ok(nodes)
     }
+    async fn get_loaded_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<HashSet<String>, Box<dyn Error>> {
+        if name_and_versions.is_empty() {
+            return Ok(HashSet::new());
+        }
+        let name_and_versions: Vec<&str> = name_and_versions.iter().map(String::as_str).collect();
+        let query = CypherQuery::new(
+            "
+                MATCH (v:version) WHERE v.name_and_version IN $name_and_versions
+                RETURN DISTINCT v.name_and_version AS name_and_version
+                ",
+        )
+        .param("name_and_versions", name_and_versions);
+        let loaded = self
+            .client
+            .query_column_as::<String>(&query, "name_and_version")
+            .await?;
+        Ok(loaded.into_iter().collect())
+    }
+    async fn get_dependency_crate_names(
+        &self,
+        name: &str,
+    ) -> Result<HashSet<String>, Box<dyn Error>> {
+        let query = CypherQuery::new(
+            "
+                MATCH (lv {name: $name})-[:has_dep_version]->(n:version)-[:depends_on]->(m:version)
+                RETURN DISTINCT m.name_and_version as name_and_version
+                ",
+        )
+        .param("name", name);
+        self.crate_names_of(&query).await
+    }
+    async fn get_dependent_crate_names(
+        &self,
+        name: &str,
+    ) -> Result<HashSet<String>, Box<dyn Error>> {
+        let query = CypherQuery::new(
+            "
+                MATCH (lv {name: $name})-[:has_dep_version]->(n:version)<-[:depends_on]-(m:version)
+                RETURN DISTINCT m.name_and_version as name_and_version
+                ",
+        )
+        .param("name", name);
+        self.crate_names_of(&query).await
+    }
     async fn new_get_direct_dependent_nodes(
         &self,
         namespace: &str,
         nameversion: &str,
+        kind: Option<DependencyKind>,
     ) -> Result<Vec<crate::NameVersion>, Box<dyn Error>> {
-        let query1 = format!(
-            "
-                MATCH (p:program {{namespace: '{}'}})-[:has_type]->(l)-[:has_version]->(lv {{name_and_version:'{}'}})-[:has_dep_version]->(vs:version)<-[:depends_on]-(m:version)
+        let query1 = with_kind(
+            CypherQuery::new(format!(
+                "
+                MATCH (p:program {{namespace: $namespace}})-[:has_type]->(l)-[:has_version]->(lv {{name_and_version: $name_and_version}})-[:has_dep_version]->(vs:version)<-{}-(m:version)
 RETURN m.name_and_version as name_and_version
                 ",
-            namespace,
-            nameversion
-        );
-        let results1 = self.client.exec_query(&query1).await?;
-        let mut res = vec![];
-        for node in results1 {
-            res.push(node);
-        }
-        let unique_items: HashSet<String> = res.clone().into_iter().collect();
-
-        let mut nodes = vec![];
-
-        for result in unique_items {
-            let result_json: Value = serde_json::from_str(&result).unwrap();
-            let name_version_str: String =
-                serde_json::from_value(result_json["name_and_version"].clone()).unwrap();
-
-            if let Some(name_version) = crate::NameVersion::from_string(&name_version_str) {
-                nodes.push(name_version);
-            }
-        }
+                depends_on_edge("", kind),
+            )),
+            kind,
+        )
+        .param("namespace", namespace)
+        .param("name_and_version", nameversion);
+        let unique_items: HashSet<String> = self
+            .client
+            .query_column_as(&query1, "name_and_version")
+            .await?
+            .into_iter()
+            .collect();
+        let nodes = unique_items
+            .iter()
+            .filter_map(|nv| crate::NameVersion::from_string(nv))
+            .collect();
 
         Ok(nodes)
     }
@@ -1147,68 +1401,276 @@ RETURN m.name_and_version as name_and_version
     }
 
     async fn get_lib_version(&self, name: String) -> Result<Vec<String>, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (n:library_version {{name: '{}'}}) RETURN n.version LIMIT 100",
-            name
-        );
+            MATCH (n:library_version {name: $name}) RETURN n.version AS version LIMIT 100",
+        )
+        .param("name", name);
 
-        let results = self.client.exec_query(&query).await.unwrap();
-
-        let mut realres = vec![];
+        let realres = self
+            .client
+            .query_column_as::<Option<String>>(&query, "version")
+            .await
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
 
-        for res in results {
-            let parsed: Value = serde_json::from_str(&res).unwrap();
-            if let Some(version) = parsed.get("n.version").and_then(|v| v.as_str()) {
-                realres.push(version.to_string());
+        Ok(realres)
+    }
+    async fn get_versions_in_namespace(
+        &self,
+        namespace: &str,
+        name: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let query = CypherQuery::new(
+            "
+            MATCH (p:program {namespace: $namespace})-[:has_type]->()-[:has_version]->(v {name: $name})
+RETURN DISTINCT v.version AS version",
+        )
+        .param("namespace", namespace)
+        .param("name", name);
+        let versions = self
+            .client
+            .query_column_as::<Option<String>>(&query, "version")
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        Ok(versions)
+    }
+    async fn get_versions_of_crates(
+        &self,
+        crates: &[(String, String)],
+    ) -> Result<HashMap<(String, String), Vec<String>>, Box<dyn Error>> {
+        let mut versions: HashMap<(String, String), Vec<String>> = HashMap::new();
+        if crates.is_empty() {
+            return Ok(versions);
+        }
+        let namespaces: Vec<&str> = crates.iter().map(|(ns, _)| ns.as_str()).collect();
+        let names: Vec<&str> = crates.iter().map(|(_, name)| name.as_str()).collect();
+        let query = CypherQuery::new(
+            "
+            MATCH (p:program)-[:has_type]->()-[:has_version]->(v)
+WHERE p.namespace IN $namespaces AND v.name IN $names
+RETURN DISTINCT p.namespace AS namespace, v.name AS name, v.version AS version",
+        )
+        .param("namespaces", namespaces)
+        .param("names", names);
+        let wanted: HashSet<&(String, String)> = crates.iter().collect();
+        for row in self.client.query_as::<CrateVersionRow>(&query).await? {
+            let key = (row.namespace, row.name);
+            // the IN filters are independent, drop the cross pairs
+            if wanted.contains(&key) {
+                versions.entry(key).or_default().push(row.version);
             }
         }
-
-        Ok(realres)
+        Ok(versions)
+    }
+    async fn get_requirement_edges_from_tg(
+        &self,
+        namespace: &str,
+        name: &str,
+        kind: Option<DependencyKind>,
+    ) -> Result<Vec<RequirementEdge>, Box<dyn Error>> {
+        let query = requirement_edges_query(namespace, name, kind);
+        let mut edges = vec![];
+        for row in self.client.query_as::<RequirementEdgeRow>(&query).await? {
+            // edges imported before these properties existed read as `null`
+            edges.push(RequirementEdge {
+                dependent: row.dependent,
+                requirement: row.requirement,
+                kind: row
+                    .kind
+                    .and_then(|kind| kind.parse().ok())
+                    .unwrap_or_default(),
+                source: row.source.unwrap_or_default(),
+            });
+        }
+        Ok(edges)
+    }
+    async fn get_dependency_requirements_from_tg(
+        &self,
+        name_and_version: &str,
+    ) -> Result<Vec<DependencyRequirement>, Box<dyn Error>> {
+        let query = CypherQuery::new(
+            "
+                MATCH (n:version {name_and_version: $name_and_version})-[e:depends_on]->(m:version)
+                RETURN m.name_and_version as name_and_version, e.kind as kind, e.target as target, e.requirement as requirement
+                ",
+        )
+        .param("name_and_version", name_and_version);
+        let mut requirements = vec![];
+        for row in self
+            .client
+            .query_as::<DependencyRequirementRow>(&query)
+            .await?
+        {
+            let Some(dependency) = NameVersion::from_string(&row.name_and_version) else {
+                continue;
+            };
+            // edges imported before these properties existed read as `null`
+            requirements.push(DependencyRequirement {
+                name: dependency.name,
+                kind: row
+                    .kind
+                    .and_then(|kind| kind.parse().ok())
+                    .unwrap_or_default(),
+                target: row.target.unwrap_or_default(),
+                requirement: row.requirement.unwrap_or_default(),
+                resolved_version: dependency.version,
+            });
+        }
+        Ok(requirements)
+    }
+    async fn get_runtime_dependents_of_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        if name_and_versions.is_empty() {
+            return Ok(vec![]);
+        }
+        let name_and_versions: Vec<&str> = name_and_versions.iter().map(String::as_str).collect();
+        let query = CypherQuery::new(
+            "
+            MATCH (m:version)-[e:depends_on]->(n:version)
+WHERE n.name_and_version IN $name_and_versions AND (e.kind IS NULL OR e.kind <> 'dev')
+RETURN DISTINCT m.name_and_version as name_and_version",
+        )
+        .param("name_and_versions", name_and_versions);
+        let dependents = self
+            .client
+            .query_column_as(&query, "name_and_version")
+            .await?;
+        Ok(dependents)
+    }
+    async fn get_github_urls_of_crates(
+        &self,
+        crates: &[(String, String)],
+    ) -> Result<HashMap<(String, String), String>, Box<dyn Error>> {
+        self.program_urls_of_crates(crates, "github_url").await
+    }
+    async fn get_doc_urls_of_crates(
+        &self,
+        crates: &[(String, String)],
+    ) -> Result<HashMap<(String, String), String>, Box<dyn Error>> {
+        self.program_urls_of_crates(crates, "doc_url").await
+    }
+    async fn get_dependency_edges_of_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<Vec<(String, String, Option<DependencyKind>)>, Box<dyn Error>> {
+        if name_and_versions.is_empty() {
+            return Ok(vec![]);
+        }
+        let name_and_versions: Vec<&str> = name_and_versions.iter().map(String::as_str).collect();
+        let query = CypherQuery::new(
+            "
+            MATCH (n:version)-[e:depends_on]->(m:version)
+WHERE n.name_and_version IN $name_and_versions
+RETURN DISTINCT n.name_and_version AS name_and_version, m.name_and_version AS other, e.kind AS kind",
+        )
+        .param("name_and_versions", name_and_versions);
+        Ok(self
+            .client
+            .query_as::<VersionEdgeRow>(&query)
+            .await?
+            .into_iter()
+            // edges imported before kinds were recorded read as `null`
+            .map(|row| {
+                let kind = row.kind.and_then(|kind| kind.parse().ok());
+                (row.name_and_version, row.other, kind)
+            })
+            .collect())
+    }
+    async fn get_dependent_edges_of_versions(
+        &self,
+        name_and_versions: &[String],
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        if name_and_versions.is_empty() {
+            return Ok(vec![]);
+        }
+        let name_and_versions: Vec<&str> = name_and_versions.iter().map(String::as_str).collect();
+        let query = CypherQuery::new(
+            "
+            MATCH (m:version)-[e:depends_on]->(n:version)
+WHERE n.name_and_version IN $name_and_versions
+RETURN DISTINCT n.name_and_version AS name_and_version, m.name_and_version AS other, e.kind AS kind",
+        )
+        .param("name_and_versions", name_and_versions);
+        Ok(self
+            .client
+            .query_as::<VersionEdgeRow>(&query)
+            .await?
+            .into_iter()
+            .map(|row| (row.name_and_version, row.other))
+            .collect())
+    }
+    async fn get_namespaces_of_crates(
+        &self,
+        names: &[String],
+    ) -> Result<HashMap<String, Vec<String>>, Box<dyn Error>> {
+        let mut namespaces: HashMap<String, Vec<String>> = HashMap::new();
+        if names.is_empty() {
+            return Ok(namespaces);
+        }
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let query = CypherQuery::new(
+            "
+            MATCH (p:program)
+WHERE p.name IN $names
+RETURN p.name AS name, p.namespace AS namespace",
+        )
+        .param("names", names);
+        for row in self.client.query_as::<CrateNamespaceRow>(&query).await? {
+            namespaces.entry(row.name).or_default().push(row.namespace);
+        }
+        for list in namespaces.values_mut() {
+            list.sort();
+            list.dedup();
+        }
+        Ok(namespaces)
     }
     async fn new_get_lib_version(
         &self,
         namespace: String,
         name: String,
     ) -> Result<Vec<String>, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (p:program {{namespace: '{}'}})-[:has_type]->(l)-[:has_version]->(lv {{name:'{}'}})
-RETURN lv.version",
-            namespace,
-            name,
-        );
+            MATCH (p:program {namespace: $namespace})-[:has_type]->(l)-[:has_version]->(lv {name: $name})
+RETURN lv.version AS version",
+        )
+        .param("namespace", namespace)
+        .param("name", name);
         let time1 = Instant::now();
-        let results = self.client.exec_query(&query).await.unwrap();
+        let unique_items: HashSet<String> = self
+            .client
+            .query_column_as::<Option<String>>(&query, "version")
+            .await
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
         let query_time = time1.elapsed();
         tracing::info!("query_statement_need_time:{:?}", query_time);
-        let unique_items: HashSet<String> = results.clone().into_iter().collect();
 
-        let mut realres = vec![];
-
-        for res in unique_items {
-            let parsed: Value = serde_json::from_str(&res).unwrap();
-            if let Some(version) = parsed.get("lv.version").and_then(|v| v.as_str()) {
-                realres.push(version.to_string());
-            }
-        }
-
-        Ok(realres)
+        Ok(unique_items.into_iter().collect())
     }
     async fn get_app_version(&self, name: String) -> Result<Vec<String>, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (n:application_version {{name: '{}'}}) RETURN n.version LIMIT 100",
-            name
-        );
-        let results = self.client.exec_query(&query).await.unwrap();
-        let mut realres = vec![];
-        for res in results {
-            let parsed: Value = serde_json::from_str(&res).unwrap();
-            if let Some(version) = parsed.get("n.version").and_then(|v| v.as_str()) {
-                realres.push(version.to_string());
-            }
-        }
+            MATCH (n:application_version {name: $name}) RETURN n.version AS version LIMIT 100",
+        )
+        .param("name", name);
+        let realres = self
+            .client
+            .query_column_as::<Option<String>>(&query, "version")
+            .await
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
         Ok(realres)
     }
     async fn new_get_app_version(
@@ -1216,26 +1678,358 @@ RETURN lv.version",
         namespace: String,
         name: String,
     ) -> Result<Vec<String>, Box<dyn Error>> {
-        let query = format!(
+        let query = CypherQuery::new(
             "
-            MATCH (p:program {{namespace: '{}'}})-[:has_type]->(a:application)-[:has_version]->(av:application_version {{name:'{}'}})
-RETURN av.version",
-            namespace,
-            name,
+            MATCH (p:program {namespace: $namespace})-[:has_type]->(a:application)-[:has_version]->(av:application_version {name: $name})
+RETURN av.version AS version",
+        )
+        .param("namespace", namespace)
+        .param("name", name);
+
+        let unique_items: HashSet<String> = self
+            .client
+            .query_column_as::<Option<String>>(&query, "version")
+            .await
+            .unwrap()
+            .into_iter()
+            .flatten()
+            .collect();
+
+        Ok(unique_items.into_iter().collect())
+    }
+}
+
+/// A `depends_on` edge with the properties the feature resolver reads.
+#[derive(Deserialize)]
+struct FeatureEdgeRow {
+    name_and_version: String,
+    kind: Option<String>,
+    rename: Option<String>,
+    optional: Option<bool>,
+    default_features: Option<bool>,
+    features: Option<String>,
+}
+
+/// The type vertex of a program and its label.
+#[derive(Deserialize)]
+struct TypeRow {
+    o: Node,
+    o_label: String,
+}
+
+#[derive(Deserialize)]
+struct CrateVersionRow {
+    namespace: String,
+    name: String,
+    version: String,
+}
+
+#[derive(Deserialize)]
+struct CrateUrlRow {
+    namespace: String,
+    name: String,
+    url: Option<String>,
+}
+
+/// A `depends_on` edge of `name_and_version`, `other` is the version at its
+/// other end.
+#[derive(Deserialize)]
+struct VersionEdgeRow {
+    name_and_version: String,
+    other: String,
+    kind: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CrateNamespaceRow {
+    name: String,
+    namespace: String,
+}
+
+#[derive(Deserialize)]
+struct RequirementEdgeRow {
+    dependent: String,
+    requirement: Option<String>,
+    kind: Option<String>,
+    source: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DependencyRequirementRow {
+    name_and_version: String,
+    kind: Option<String>,
+    target: Option<String>,
+    requirement: Option<String>,
+}
+
+/// The `depends_on` pattern of a query, bound to the edge variable `var`
+/// and restricted to the kind in `$kind` if given, see [`with_kind`].
+fn depends_on_edge(var: &str, kind: Option<DependencyKind>) -> String {
+    match kind {
+        Some(_) => format!("[{}:depends_on {{kind: $kind}}]", var),
+        None => format!("[{}:depends_on]", var),
+    }
+}
+
+/// Binds the `$kind` of [`depends_on_edge`].
+fn with_kind(query: CypherQuery, kind: Option<DependencyKind>) -> CypherQuery {
+    match kind {
+        Some(kind) => query.param("kind", kind.to_string()),
+        None => query,
+    }
+}
+
+/// Indirect dependents visited at most per listing.
+const MAX_INDIRECT_NODES: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    Dependencies,
+    Dependents,
+}
+
+fn direct_listing_query(
+    namespace: &str,
+    name: &str,
+    version: &str,
+    kind: Option<DependencyKind>,
+    direction: Direction,
+) -> CypherQuery {
+    direct_listing_match(
+        namespace,
+        name,
+        version,
+        kind,
+        direction,
+        "RETURN DISTINCT m.name_and_version as name_and_version",
+    )
+}
+
+/// One page of [`direct_listing_query`], ordered by crate name and, within a
+/// crate, by the version string descending.
+fn direct_listing_page_query(
+    namespace: &str,
+    name: &str,
+    version: &str,
+    kind: Option<DependencyKind>,
+    direction: Direction,
+    skip: usize,
+    limit: usize,
+) -> CypherQuery {
+    direct_listing_match(
+        namespace,
+        name,
+        version,
+        kind,
+        direction,
+        "RETURN DISTINCT m.name_and_version as name_and_version, m.name as name
+ORDER BY name ASC, name_and_version DESC
+SKIP $skip LIMIT $limit",
+    )
+    .param("skip", i64::try_from(skip).unwrap_or(i64::MAX))
+    .param("limit", i64::try_from(limit).unwrap_or(i64::MAX))
+}
+
+/// The number of entries of [`direct_listing_query`].
+fn direct_listing_count_query(
+    namespace: &str,
+    name: &str,
+    version: &str,
+    kind: Option<DependencyKind>,
+    direction: Direction,
+) -> CypherQuery {
+    direct_listing_match(
+        namespace,
+        name,
+        version,
+        kind,
+        direction,
+        "RETURN count(DISTINCT m.name_and_version) as total",
+    )
+}
+
+/// The direct listing pattern of a version, `m` being the listed versions,
+/// followed by `tail`.
+fn direct_listing_match(
+    namespace: &str,
+    name: &str,
+    version: &str,
+    kind: Option<DependencyKind>,
+    direction: Direction,
+    tail: &str,
+) -> CypherQuery {
+    let version_filter = if version == "all" {
+        "{name: $name}"
+    } else {
+        "{name_and_version: $name_and_version}"
+    };
+    let edge = match direction {
+        Direction::Dependencies => format!("-{}->", depends_on_edge("", kind)),
+        Direction::Dependents => format!("<-{}-", depends_on_edge("", kind)),
+    };
+    let query = CypherQuery::new(format!(
+        "
+                MATCH (p:program {{namespace: $namespace}})-[:has_type]->(l)-[:has_version]->(lv {})-[:has_dep_version]->(vs:version){}(m:version)
+{}
+                ",
+        version_filter, edge, tail,
+    ))
+    .param("namespace", namespace);
+    let query = if version == "all" {
+        query.param("name", name)
+    } else {
+        query.param("name_and_version", format!("{}/{}", name, version))
+    };
+    with_kind(query, kind)
+}
+
+/// The `depends_on` edges into every version of `name` in `namespace`.
+fn requirement_edges_query(
+    namespace: &str,
+    name: &str,
+    kind: Option<DependencyKind>,
+) -> CypherQuery {
+    let query = CypherQuery::new(format!(
+        "
+                MATCH (p:program {{namespace: $namespace}})-[:has_type]->(l)-[:has_version]->(lv {{name: $name}})-[:has_dep_version]->(vs:version)<-{}-(m:version)
+RETURN m.name_and_version as dependent, e.requirement as requirement, e.kind as kind, e.source as source
+                ",
+        depends_on_edge("e", kind),
+    ))
+    .param("namespace", namespace)
+    .param("name", name);
+    with_kind(query, kind)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        direct_listing_count_query, direct_listing_page_query, direct_listing_query,
+        requirement_edges_query, Direction,
+    };
+    use model::general_model::DependencyKind;
+    use tudriver::cypher::BoltType;
+
+    /// Names that rewrite a query when spliced into its text.
+    const HOSTILE: &[&str] = &[
+        "it's",
+        r"back\slash",
+        "'}) DETACH DELETE n //",
+        "x'})-[:has_type]->(l) RETURN l //",
+        "\"}) MATCH (n) DETACH DELETE n //",
+    ];
+
+    fn string(value: &str) -> Option<BoltType> {
+        Some(BoltType::from(value))
+    }
+
+    #[test]
+    fn test_direct_listing_query() {
+        let query = direct_listing_query(
+            "serde-rs/serde",
+            "serde",
+            "all",
+            None,
+            Direction::Dependents,
+        );
+        assert!(query.text().contains("(lv {name: $name})"));
+        assert!(query
+            .text()
+            .contains("(vs:version)<-[:depends_on]-(m:version)"));
+        assert_eq!(query.get_param("name").cloned(), string("serde"));
+        assert_eq!(query.get_param("kind"), None);
+        let query = direct_listing_query(
+            "serde-rs/serde",
+            "serde",
+            "1.0.200",
+            Some(DependencyKind::Dev),
+            Direction::Dependencies,
         );
+        assert!(query
+            .text()
+            .contains("(lv {name_and_version: $name_and_version})"));
+        assert!(query
+            .text()
+            .contains("(vs:version)-[:depends_on {kind: $kind}]->(m:version)"));
+        assert_eq!(
+            query.get_param("name_and_version").cloned(),
+            string("serde/1.0.200")
+        );
+        assert_eq!(query.get_param("kind").cloned(), string("dev"));
+    }
 
-        let results = self.client.exec_query(&query).await.unwrap();
-        let unique_items: HashSet<String> = results.clone().into_iter().collect();
+    #[test]
+    fn test_direct_listing_page_query() {
+        let query = direct_listing_page_query(
+            "serde-rs/serde",
+            "serde",
+            "1.0.200",
+            None,
+            Direction::Dependents,
+            40,
+            20,
+        );
+        assert!(query
+            .text()
+            .contains("(vs:version)<-[:depends_on]-(m:version)"));
+        assert!(query
+            .text()
+            .contains("ORDER BY name ASC, name_and_version DESC\nSKIP $skip LIMIT $limit"));
+        assert_eq!(
+            query.get_param("skip").cloned(),
+            Some(BoltType::from(40i64))
+        );
+        assert_eq!(
+            query.get_param("limit").cloned(),
+            Some(BoltType::from(20i64))
+        );
+        let query = direct_listing_count_query(
+            "serde-rs/serde",
+            "serde",
+            "1.0.200",
+            Some(DependencyKind::Normal),
+            Direction::Dependencies,
+        );
+        assert!(query
+            .text()
+            .contains("RETURN count(DISTINCT m.name_and_version) as total"));
+        assert_eq!(query.get_param("kind").cloned(), string("normal"));
+    }
 
-        let mut realres = vec![];
+    #[test]
+    fn test_requirement_edges_query() {
+        let query = requirement_edges_query("serde-rs/serde", "serde", None);
+        assert!(query
+            .text()
+            .contains("(vs:version)<-[e:depends_on]-(m:version)"));
+        assert!(query.text().contains("e.requirement as requirement"));
+        let query = requirement_edges_query("serde-rs/serde", "serde", Some(DependencyKind::Build));
+        assert!(query.text().contains("<-[e:depends_on {kind: $kind}]-"));
+        assert_eq!(query.get_param("kind").cloned(), string("build"));
+    }
 
-        for res in unique_items {
-            let parsed: Value = serde_json::from_str(&res).unwrap();
-            if let Some(version) = parsed.get("av.version").and_then(|v| v.as_str()) {
-                realres.push(version.to_string());
-            }
-        }
+    #[test]
+    fn test_hostile_names_are_bound() {
+        let reference = direct_listing_query("a/b", "c", "1.0.0", None, Direction::Dependents);
+        for hostile in HOSTILE {
+            let query = direct_listing_query(hostile, hostile, "all", None, Direction::Dependents);
+            assert!(!query.text().contains(hostile));
+            assert_eq!(query.get_param("namespace").cloned(), string(hostile));
+            assert_eq!(query.get_param("name").cloned(), string(hostile));
 
-        Ok(realres)
+            let query =
+                direct_listing_query(hostile, hostile, hostile, None, Direction::Dependents);
+            // the text only depends on the shape of the listing
+            assert_eq!(query.text(), reference.text());
+            assert_eq!(
+                query.get_param("name_and_version").cloned(),
+                string(&format!("{}/{}", hostile, hostile))
+            );
+
+            let query = requirement_edges_query(hostile, hostile, Some(DependencyKind::Normal));
+            assert!(!query.text().contains(hostile));
+            assert_eq!(query.get_param("namespace").cloned(), string(hostile));
+            assert_eq!(query.get_param("name").cloned(), string(hostile));
+        }
     }
 }
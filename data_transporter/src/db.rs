@@ -1,20 +1,49 @@
-use std::{cmp::Ordering, collections::HashSet, env};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    ops::Deref,
+    time::Duration,
+};
 
 use crate::{
+    advisory_range::{parse_version, CveRange, RustsecRange},
     handler::{
         Crateinfo, DependencyCount, DependencyCrateInfo, DependencyInfo, DependentCount,
-        DependentData, DependentInfo, NewRustsec, RustSec, Versionpage,
+        DependentData, DependentInfo, NewRustsec, RustSec, UploadRecord, Versionpage,
     },
     UploadedCrate, Userinfo,
 };
 use chrono::NaiveDateTime;
+use deadpool_postgres::{Manager, Object, Pool, PoolError, Runtime};
+use model::repo_sync_model::UploadStatus;
 use model::tugraph_model::{Program, UProgram};
 use semver::Version;
 use serde::{Deserialize, Serialize};
 use tokio_postgres::{Error, NoTls};
 use utoipa::ToSchema;
 pub struct DBHandler {
-    pub client: tokio_postgres::Client,
+    pub client: PgClient,
+}
+/// A Postgres client either owned by its handler or borrowed from a pool,
+/// going back to the pool when the handler is dropped.
+pub enum PgClient {
+    Owned(tokio_postgres::Client),
+    Pooled(Object),
+}
+impl Deref for PgClient {
+    type Target = tokio_postgres::Client;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            PgClient::Owned(client) => client,
+            PgClient::Pooled(object) => object,
+        }
+    }
+}
+impl From<tokio_postgres::Client> for PgClient {
+    fn from(client: tokio_postgres::Client) -> Self {
+        PgClient::Owned(client)
+    }
 }
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct CveInfo {
@@ -51,7 +80,30 @@ pub fn db_cratesio_connection_config_from_env() -> String {
     )
 }
 
+/// A pool of at most `max_size` connections to `config`, acquiring one waits
+/// at most `timeout`.
+pub fn build_pg_pool(
+    config: &str,
+    max_size: usize,
+    timeout: Duration,
+) -> Result<Pool, Box<dyn std::error::Error>> {
+    let manager = Manager::new(config.parse::<tokio_postgres::Config>()?, NoTls);
+    let pool = Pool::builder(manager)
+        .max_size(max_size)
+        .wait_timeout(Some(timeout))
+        .create_timeout(Some(timeout))
+        .runtime(Runtime::Tokio1)
+        .build()?;
+    Ok(pool)
+}
+
 impl DBHandler {
+    pub async fn from_pool(pool: &Pool) -> Result<Self, PoolError> {
+        Ok(DBHandler {
+            client: PgClient::Pooled(pool.get().await?),
+        })
+    }
+
     pub async fn connect() -> Result<Self, Error> {
         let db_connection_config = db_connection_config_from_env();
         let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls).await?;
@@ -86,7 +138,9 @@ impl DBHandler {
             }
         });
 
-        Ok(DBHandler { client })
+        Ok(DBHandler {
+            client: client.into(),
+        })
     }
 
     pub async fn clear_database(&self) -> Result<(), Error> {
@@ -314,489 +368,356 @@ impl DBHandler {
 
         Ok(res)
     }
-    pub async fn process_closed_interval_of_match_version(
+    pub async fn get_direct_rustsec(
         &self,
-        oneline_patched: String,
-        version: String,
-    ) -> Result<bool, Error> {
-        let mut matched = false;
-        let mut two_versions = vec![];
-        let newparts: Vec<&str> = oneline_patched.split(',').collect();
-        for part in newparts {
-            let one_version = part.to_string();
-            let res_one_version = one_version.trim();
-            two_versions.push(res_one_version.to_string());
-        }
-        let mut left = "".to_string();
-        let mut right = "".to_string();
-        if two_versions.len() == 2 {
-            if two_versions[0].clone().starts_with(">") || two_versions[0].clone().starts_with(">=")
-            {
-                left = two_versions[0].clone();
-                right = two_versions[1].clone();
-            } else if two_versions[0].clone().starts_with("<")
-                || two_versions[0].clone().starts_with("<=")
-            {
-                left = two_versions[1].clone();
-                right = two_versions[0].clone();
-            }
-        }
-        if (left.starts_with(">") && !left.starts_with(">="))
-            && (right.starts_with("<") && !right.starts_with("<="))
-        {
-            //> <
-            let mut versions = vec![];
-            let tmp_left = &left[1..];
-            let left_version = tmp_left.to_string();
-            let tmp_right = &right[1..];
-            let right_version = tmp_right.to_string();
-            versions.push(version.clone());
-            versions.push(left_version.clone());
-            versions.push(right_version.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if version.clone() == versions[1].clone()
-                && (versions[0].clone() != version.clone()
-                    && versions[2].clone() != version.clone())
-            {
-                matched = true;
-            }
-        } else if (left.starts_with(">") && !left.starts_with(">=")) && right.starts_with("<=") {
-            //> <=
-            let mut versions = vec![];
-            let tmp_left = &left[1..];
-            let left_version = tmp_left.to_string();
-            let tmp_right = &right[2..];
-            let right_version = tmp_right.to_string();
-            versions.push(version.clone());
-            versions.push(left_version.clone());
-            versions.push(right_version.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if version.clone() == versions[1].clone() && (versions[2].clone() != version.clone()) {
-                matched = true;
-            }
-        } else if left.starts_with(">=") && (right.starts_with("<") && !right.starts_with("<=")) {
-            //>= <
-            let mut versions = vec![];
-            let tmp_left = &left[2..];
-            let left_version = tmp_left.to_string();
-            let tmp_right = &right[1..];
-            let right_version = tmp_right.to_string();
-            versions.push(version.clone());
-            versions.push(left_version.clone());
-            versions.push(right_version.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if version.clone() == versions[1].clone() && (versions[0].clone() != version.clone()) {
-                matched = true;
-            }
-        } else if left.starts_with(">=") && right.starts_with("<=") {
-            //>= <=
-            let mut versions = vec![];
-            let tmp_left = &left[2..];
-            let left_version = tmp_left.to_string();
-            let tmp_right = &right[2..];
-            let right_version = tmp_right.to_string();
-            versions.push(version.clone());
-            versions.push(left_version.clone());
-            versions.push(right_version.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if version.clone() == versions[1].clone() {
-                matched = true;
-            }
-        }
-        Ok(matched)
+        cname: &str,
+        version: &str,
+    ) -> Result<Vec<NewRustsec>, Error> {
+        tracing::info!("enter get direct_rustsec");
+        let getres = self.get_affecting_rustsec(cname, version).await?;
+        tracing::info!("finish get direct_rustsec");
+        Ok(getres)
     }
-    pub async fn process_open_interval_of_match_version(
+    pub async fn get_dependency_rustsec(
         &self,
-        oneline_patched: String,
-        version: String,
-    ) -> Result<bool, Error> {
-        let mut matched = false;
-        if oneline_patched.starts_with(">") && !oneline_patched.starts_with(">=") {
-            let mut versions = vec![];
-            let trimmed = &oneline_patched[1..];
-            let res = trimmed.to_string();
-            versions.push(version.clone());
-            versions.push(res.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if versions[0].clone() == version.clone() && res.clone() != version.clone() {
-                matched = true;
-            }
-        } else if let Some(trimmed) = oneline_patched.strip_prefix(">=") {
-            let mut versions = vec![];
-            let res = trimmed.to_string();
-            versions.push(version.clone());
-            versions.push(res.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if versions[0].clone() == version.clone() {
-                matched = true;
-            }
-        } else if oneline_patched.starts_with("<") && !oneline_patched.starts_with("<=") {
-            let mut versions = vec![];
-            let trimmed = &oneline_patched[1..];
-            let res = trimmed.to_string();
-            versions.push(version.clone());
-            versions.push(res.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if versions[1].clone() == version.clone() && res.clone() != version.clone() {
-                matched = true;
-            }
-        } else if let Some(trimmed) = oneline_patched.strip_prefix("<=") {
-            let mut versions = vec![];
-            let res = trimmed.to_string();
-            versions.push(version.clone());
-            versions.push(res.clone());
-            versions.sort_by(|a, b| {
-                let version_a = Version::parse(a);
-                let version_b = Version::parse(b);
-                match (version_a, version_b) {
-                    (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
-                    (Ok(_), Err(_)) => Ordering::Less,
-                    (Err(_), Ok(_)) => Ordering::Greater,
-                    (Err(_), Err(_)) => Ordering::Equal,
-                }
-            });
-            if versions[1].clone() == version.clone() {
-                matched = true;
+        nameversion: HashSet<String>,
+    ) -> Result<Vec<NewRustsec>, Error> {
+        let mut getres = vec![];
+        for nv in nameversion {
+            if let Some((cname, version)) = nv.split_once('/') {
+                getres.extend(self.get_affecting_rustsec(cname, version).await?);
             }
         }
-        Ok(matched)
+        let unique: Vec<NewRustsec> = getres
+            .into_iter()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        Ok(unique)
     }
-    pub async fn match_version(&self, patched: String, version: String) -> Result<bool, Error> {
-        let mut matched = false;
-        let mut part_petched = vec![];
-        let parts: Vec<&str> = patched.split('|').collect();
-        for part in parts {
-            part_petched.push(part);
+    /// Downloads of many crates in one query, the highest over their namespaces.
+    pub async fn get_downloads_batch(
+        &self,
+        names: &[String],
+    ) -> Result<HashMap<String, i64>, Error> {
+        let rows = self
+            .client
+            .query(
+                "SELECT name, MAX(downloads) AS downloads FROM programs \
+                 WHERE name = ANY($1) GROUP BY name;",
+                &[&names],
+            )
+            .await?;
+        let mut downloads = HashMap::new();
+        for row in rows {
+            let name: String = row.get("name");
+            let count: Option<i64> = row.get("downloads");
+            downloads.insert(name, count.unwrap_or(0));
         }
-        for np in part_petched {
-            let oneline_patched = np.to_string();
-            if oneline_patched.clone().contains(",") {
-                //closed interval
-                matched = matched
-                    || self
-                        .process_closed_interval_of_match_version(
-                            oneline_patched.clone(),
-                            version.clone(),
-                        )
-                        .await
-                        .unwrap();
-            } else if oneline_patched.clone().contains("^") {
-                //specific version
-                if let Some(trimmed) = oneline_patched.strip_prefix("^") {
-                    let res = trimmed.to_string();
-                    if version == res {
-                        matched = true;
-                    }
-                }
-            } else {
-                //open interval
-                matched = matched
-                    || self
-                        .process_open_interval_of_match_version(
-                            oneline_patched.clone(),
-                            version.clone(),
-                        )
-                        .await?;
+        Ok(downloads)
+    }
+    /// Downloads of many crates on crates.io, read from `crate_downloads` of
+    /// the crates.io dump. Crates missing from it are left out.
+    pub async fn get_cratesio_downloads_batch(
+        &self,
+        names: &[String],
+    ) -> Result<HashMap<String, i64>, Error> {
+        let rows = self
+            .client
+            .query(
+                "SELECT c.name, d.downloads FROM crates c \
+                 JOIN crate_downloads d ON d.crate_id = c.id \
+                 WHERE c.name = ANY($1);",
+                &[&names],
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| (row.get("name"), row.get("downloads")))
+            .collect())
+    }
+    /// The license of many programs in one query, keyed by
+    /// `(namespace, name)`.
+    pub async fn get_licenses_of_programs(
+        &self,
+        programs: &[(String, String)],
+    ) -> Result<HashMap<(String, String), String>, Error> {
+        let namespaces: Vec<&str> = programs.iter().map(|(ns, _)| ns.as_str()).collect();
+        let names: Vec<&str> = programs.iter().map(|(_, name)| name.as_str()).collect();
+        let rows = self
+            .client
+            .query(
+                "SELECT p.namespace, p.name, l.license \
+                 FROM unnest($1::text[], $2::text[]) AS p(namespace, name) \
+                 JOIN license l ON l.program_namespace = p.namespace AND l.program_name = p.name;",
+                &[&namespaces, &names],
+            )
+            .await?;
+        Ok(rows
+            .into_iter()
+            .map(|row| ((row.get("namespace"), row.get("name")), row.get("license")))
+            .collect())
+    }
+    /// The crates.io license of each of `versions` of a crate, versions
+    /// without one are left out.
+    pub async fn get_cratesio_version_licenses(
+        &self,
+        name: &str,
+        versions: &[&str],
+    ) -> Result<HashMap<String, String>, Error> {
+        let rows = self
+            .client
+            .query(
+                "SELECT v.num, v.license FROM versions v \
+                 JOIN crates c ON c.id = v.crate_id \
+                 WHERE c.name = $1 AND v.num = ANY($2);",
+                &[&name, &versions],
+            )
+            .await?;
+        let mut licenses = HashMap::new();
+        for row in rows {
+            let license: Option<String> = row.get("license");
+            if let Some(license) = license.filter(|l| !l.is_empty()) {
+                licenses.insert(row.get("num"), license);
             }
         }
-        Ok(matched)
+        Ok(licenses)
     }
-    pub async fn get_direct_rustsec(
+    /// Number of RustSec advisories affecting each `name/version`, read with a
+    /// single query for all of them.
+    pub async fn count_rustsec_batch(
         &self,
-        cname: &str,
-        version: &str,
-    ) -> Result<Vec<NewRustsec>, Error> {
-        tracing::info!("enter get direct_rustsec");
+        nameversions: &[String],
+    ) -> Result<HashMap<String, usize>, Error> {
+        let names: Vec<&str> = nameversions
+            .iter()
+            .filter_map(|nv| nv.split_once('/').map(|(name, _)| name))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
         let rows = self
             .client
-            .query("SELECT * FROM rustsecs WHERE cratename=$1;", &[&cname])
-            .await
-            .unwrap();
-        let mut get_direct_rust_sec = vec![];
+            .query(
+                "SELECT r.id, r.cratename, r.patched, r.aliases, r.small_desc, i.unaffected \
+                 FROM rustsecs r JOIN rustsec_info i ON i.id = r.id \
+                 WHERE r.cratename = ANY($1);",
+                &[&names],
+            )
+            .await?;
+        let mut advisories: HashMap<String, Vec<(RustSec, String)>> = HashMap::new();
         for row in rows {
             let t_aliases: String = row.get("aliases");
-            let parts: Vec<&str> = t_aliases.split(';').collect();
-            let mut real_aliases = vec![];
-            for part in parts {
-                real_aliases.push(part.to_string());
-            }
             let rs = RustSec {
                 id: row.get("id"),
                 cratename: row.get("cratename"),
                 patched: row.get("patched"),
-                aliases: real_aliases.clone(),
+                aliases: t_aliases.split(';').map(|s| s.to_string()).collect(),
                 small_desc: row.get("small_desc"),
             };
-            get_direct_rust_sec.push(rs.clone());
+            let unaffected: String = row.get("unaffected");
+            advisories
+                .entry(rs.cratename.clone())
+                .or_default()
+                .push((rs, unaffected));
         }
-        let mut getres = vec![];
-        for rc in get_direct_rust_sec {
-            if rc.cratename.clone() == *cname {
-                let matched = self
-                    .match_version(rc.clone().patched, version.to_string())
-                    .await
-                    .unwrap();
-                if !matched {
-                    let rows2 = self
-                        .client
-                        .query("SELECT * FROM rustsec_info WHERE id=$1;", &[&rc.clone().id])
-                        .await
-                        .unwrap();
-                    for row in rows2 {
-                        let tmp_id: String = row.get("id");
-                        let rs_url =
-                            "https://rustsec.org/advisories/".to_string() + &tmp_id + ".html";
-                        let nrs = NewRustsec {
-                            id: row.get("id"),
-                            subtitle: row.get("subtitle"),
-                            reported: row.get("reported"),
-                            issued: row.get("issued"),
-                            package: row.get("package"),
-                            ttype: row.get("type"),
-                            keywords: row.get("keywords"),
-                            aliases: row.get("aliases"),
-                            reference: row.get("reference"),
-                            patched: row.get("patched"),
-                            unaffected: row.get("unaffected"),
-                            description: row.get("description"),
-                            url: rs_url.clone(),
-                        };
-                        getres.push(nrs.clone());
-                    }
-                }
-            }
+
+        let mut counts = HashMap::new();
+        for nv in nameversions {
+            let Some((cname, version)) = nv.split_once('/') else {
+                continue;
+            };
+            let parsed_version = parse_version(version).ok();
+            let count = advisories
+                .get(cname)
+                .into_iter()
+                .flatten()
+                .filter(|(rs, unaffected)| rustsec_affects(rs, unaffected, parsed_version.as_ref()))
+                .count();
+            counts.insert(nv.clone(), count);
         }
-        tracing::info!("finish get direct_rustsec");
-        Ok(getres)
+        Ok(counts)
     }
-    pub async fn get_dependency_rustsec(
+    /// RustSec advisories affecting each `name/version`, read with a single
+    /// query for all of them. Every entry of `nameversions` is in the result.
+    pub async fn get_rustsec_batch(
         &self,
-        nameversion: HashSet<String>,
+        nameversions: &[String],
+    ) -> Result<HashMap<String, Vec<NewRustsec>>, Error> {
+        let names: Vec<&str> = nameversions
+            .iter()
+            .filter_map(|nv| nv.split_once('/').map(|(name, _)| name))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        let rows = self
+            .client
+            .query(
+                "SELECT r.cratename, r.patched AS range_patched, r.aliases AS range_aliases, \
+                 r.small_desc, i.* \
+                 FROM rustsecs r JOIN rustsec_info i ON i.id = r.id \
+                 WHERE r.cratename = ANY($1);",
+                &[&names],
+            )
+            .await?;
+        let mut advisories: HashMap<String, Vec<(RustSec, NewRustsec)>> = HashMap::new();
+        for row in rows {
+            let t_aliases: String = row.get("range_aliases");
+            let rs = RustSec {
+                id: row.get("id"),
+                cratename: row.get("cratename"),
+                patched: row.get("range_patched"),
+                aliases: t_aliases.split(';').map(|s| s.to_string()).collect(),
+                small_desc: row.get("small_desc"),
+            };
+            let nrs = NewRustsec {
+                id: row.get("id"),
+                subtitle: row.get("subtitle"),
+                reported: row.get("reported"),
+                issued: row.get("issued"),
+                package: row.get("package"),
+                ttype: row.get("type"),
+                keywords: row.get("keywords"),
+                aliases: row.get("aliases"),
+                reference: row.get("reference"),
+                patched: row.get("patched"),
+                unaffected: row.get("unaffected"),
+                description: row.get("description"),
+                url: "https://rustsec.org/advisories/".to_string() + &rs.id + ".html",
+            };
+            advisories
+                .entry(rs.cratename.clone())
+                .or_default()
+                .push((rs, nrs));
+        }
+
+        let mut affecting = HashMap::new();
+        for nv in nameversions {
+            let Some((cname, version)) = nv.split_once('/') else {
+                continue;
+            };
+            let parsed_version = parse_version(version).ok();
+            let found = advisories
+                .get(cname)
+                .into_iter()
+                .flatten()
+                .filter(|(rs, nrs)| rustsec_affects(rs, &nrs.unaffected, parsed_version.as_ref()))
+                .map(|(_, nrs)| nrs.clone())
+                .collect();
+            affecting.insert(nv.clone(), found);
+        }
+        Ok(affecting)
+    }
+    /// RustSec advisories of `cname` whose `patched` and `unaffected` ranges
+    /// do not cover `version`.
+    async fn get_affecting_rustsec(
+        &self,
+        cname: &str,
+        version: &str,
     ) -> Result<Vec<NewRustsec>, Error> {
+        let rows = self
+            .client
+            .query("SELECT * FROM rustsecs WHERE cratename=$1;", &[&cname])
+            .await?;
+        let parsed_version = match parse_version(version) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                tracing::warn!("{}, reporting every advisory of {}", e, cname);
+                None
+            }
+        };
         let mut getres = vec![];
-        for nv in nameversion {
-            let parts: Vec<&str> = nv.split('/').collect();
-            let cname = parts[0].to_string();
-            let version = parts[1].to_string();
-            let rows = self
+        for row in rows {
+            let t_aliases: String = row.get("aliases");
+            let rs = RustSec {
+                id: row.get("id"),
+                cratename: row.get("cratename"),
+                patched: row.get("patched"),
+                aliases: t_aliases.split(';').map(|s| s.to_string()).collect(),
+                small_desc: row.get("small_desc"),
+            };
+            let rows2 = self
                 .client
-                .query("SELECT * FROM rustsecs where cratename=$1;", &[&cname])
-                .await
-                .unwrap();
-            let mut get_all_rust_sec = vec![];
-            for row in rows {
-                let t_aliases: String = row.get("aliases");
-                let parts: Vec<&str> = t_aliases.split(';').collect();
-                let mut real_aliases = vec![];
-                for part in parts {
-                    real_aliases.push(part.to_string());
+                .query("SELECT * FROM rustsec_info WHERE id=$1;", &[&rs.id])
+                .await?;
+            for row in rows2 {
+                let unaffected: String = row.get("unaffected");
+                if !rustsec_affects(&rs, &unaffected, parsed_version.as_ref()) {
+                    continue;
                 }
-                let rs = RustSec {
+                let rs_url = "https://rustsec.org/advisories/".to_string() + &rs.id + ".html";
+                let nrs = NewRustsec {
                     id: row.get("id"),
-                    cratename: row.get("cratename"),
+                    subtitle: row.get("subtitle"),
+                    reported: row.get("reported"),
+                    issued: row.get("issued"),
+                    package: row.get("package"),
+                    ttype: row.get("type"),
+                    keywords: row.get("keywords"),
+                    aliases: row.get("aliases"),
+                    reference: row.get("reference"),
                     patched: row.get("patched"),
-                    aliases: real_aliases.clone(),
-                    small_desc: row.get("small_desc"),
+                    unaffected,
+                    description: row.get("description"),
+                    url: rs_url,
                 };
-                get_all_rust_sec.push(rs.clone());
-            }
-            for rc in get_all_rust_sec.clone() {
-                if rc.cratename.clone() == cname.clone() {
-                    let matched = self
-                        .match_version(rc.clone().patched, version.to_string())
-                        .await
-                        .unwrap();
-                    if !matched {
-                        let rows2 = self
-                            .client
-                            .query("SELECT * FROM rustsec_info WHERE id=$1;", &[&rc.clone().id])
-                            .await
-                            .unwrap();
-                        for row in rows2 {
-                            let tmp_id: String = row.get("id");
-                            let rs_url =
-                                "https://rustsec.org/advisories/".to_string() + &tmp_id + ".html";
-                            let nrs = NewRustsec {
-                                id: row.get("id"),
-                                subtitle: row.get("subtitle"),
-                                reported: row.get("reported"),
-                                issued: row.get("issued"),
-                                package: row.get("package"),
-                                ttype: row.get("type"),
-                                keywords: row.get("keywords"),
-                                aliases: row.get("aliases"),
-                                reference: row.get("reference"),
-                                patched: row.get("patched"),
-                                unaffected: row.get("unaffected"),
-                                description: row.get("description"),
-                                url: rs_url.clone(),
-                            };
-                            getres.push(nrs.clone());
-                        }
-                        //getres.push(rc.clone());
-                    }
-                }
+                getres.push(nrs);
             }
         }
-        let unique: Vec<NewRustsec> = getres
-            .into_iter()
-            .collect::<HashSet<_>>()
-            .into_iter()
-            .collect();
-        Ok(unique)
+        Ok(getres)
     }
-    /*#[allow(dead_code)]
+    /// Ids of the CVEs whose `[start_version, end_version]` covers the given version.
     pub async fn get_direct_cve_by_cratenameandversion(
         &self,
         cratename: &str,
         version: &str,
     ) -> Result<Vec<String>, Error> {
-        let rows = self.client.query("SELECT * FROM cves;", &[]).await.unwrap();
-        let mut getallcves = vec![];
-        for row in rows {
-            let cveinfo = CveInfo {
-                cve_id: row.get("cve_id"),
-                url: "".to_string(),
-                description: row.get("description"),
-                crate_name: row.get("name"),
-                start_version: row.get("start_version"),
-                end_version: row.get("end_version"),
-            };
-            getallcves.push(cveinfo);
-        }
+        let rows = self
+            .client
+            .query(
+                "SELECT cve_id, start_version, end_version FROM cves WHERE name=$1;",
+                &[&cratename],
+            )
+            .await?;
+        let parsed_version = match parse_version(version) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                tracing::warn!("{}, reporting every cve of {}", e, cratename);
+                None
+            }
+        };
         let mut getres = vec![];
-        for cveinfo in getallcves {
-            let mut version3 = vec![];
-            if cveinfo.crate_name.clone() == *cratename {
-                version3.push(cveinfo.start_version.clone());
-                version3.push(cveinfo.end_version.clone());
-                version3.push(version.to_string());
-                version3.sort_by(|a, b| {
-                    let version_a = Version::parse(a);
-                    let version_b = Version::parse(b);
-
-                    match (version_a, version_b) {
-                        (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a), // 从高到低排序
-                        (Ok(_), Err(_)) => Ordering::Less,   // 无法解析的版本号认为更小
-                        (Err(_), Ok(_)) => Ordering::Greater,
-                        (Err(_), Err(_)) => Ordering::Equal,
-                    }
-                });
-                if version3[1].clone() == *version {
-                    getres.push(cveinfo.cve_id.clone());
+        for row in rows {
+            let cve_id: String = row.get("cve_id");
+            let start_version: Option<String> = row.get("start_version");
+            let end_version: Option<String> = row.get("end_version");
+            let affected = match (
+                parsed_version.as_ref(),
+                CveRange::parse(start_version.as_deref(), end_version.as_deref()),
+            ) {
+                (None, _) => true,
+                (Some(v), Ok(range)) => range.is_affected(v),
+                (Some(_), Err(e)) => {
+                    tracing::warn!("{}: {}, treating it as affecting", cve_id, e);
+                    true
                 }
+            };
+            if affected {
+                getres.push(cve_id);
             }
         }
-
         Ok(getres)
     }
-    #[allow(dead_code)]
     pub async fn get_dependency_cve_by_cratenameandversion(
         &self,
         nameversion: HashSet<String>,
     ) -> Result<Vec<String>, Error> {
-        let rows = self.client.query("SELECT * FROM cves;", &[]).await.unwrap();
-        let mut getallcves = vec![];
-        for row in rows {
-            let cveinfo = CveInfo {
-                cve_id: row.get("cve_id"),
-                url: "".to_string(),
-                description: row.get("description"),
-                crate_name: row.get("name"),
-                start_version: row.get("start_version"),
-                end_version: row.get("end_version"),
-            };
-            getallcves.push(cveinfo);
-        }
         let mut getres = vec![];
         for nv in nameversion {
-            let parts: Vec<&str> = nv.split('/').collect();
-            let cratename = parts[0].to_string();
-            let crateversion = parts[1].to_string();
-            for cveinfo in getallcves.clone() {
-                let mut version3 = vec![];
-                if cveinfo.crate_name.clone() == *cratename {
-                    version3.push(cveinfo.start_version.clone());
-                    version3.push(cveinfo.end_version.clone());
-                    version3.push(crateversion.to_string());
-                    version3.sort_by(|a, b| {
-                        let version_a = Version::parse(a);
-                        let version_b = Version::parse(b);
-
-                        match (version_a, version_b) {
-                            (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a), // 从高到低排序
-                            (Ok(_), Err(_)) => Ordering::Less,   // 无法解析的版本号认为更小
-                            (Err(_), Ok(_)) => Ordering::Greater,
-                            (Err(_), Err(_)) => Ordering::Equal,
-                        }
-                    });
-                    if version3[1].clone() == *crateversion {
-                        getres.push(cveinfo.cve_id.clone());
-                    }
-                }
+            if let Some((cratename, crateversion)) = nv.split_once('/') {
+                getres.extend(
+                    self.get_direct_cve_by_cratenameandversion(cratename, crateversion)
+                        .await?,
+                );
             }
         }
         let unique: Vec<String> = getres
@@ -805,7 +726,7 @@ impl DBHandler {
             .into_iter()
             .collect();
         Ok(unique)
-    }*/
+    }
     pub async fn get_license_by_name(
         &self,
         namespace: &str,
@@ -1225,6 +1146,9 @@ impl DBHandler {
             let real_res = DependencyInfo {
                 direct_count: direct as usize,
                 indirect_count: indirect as usize,
+                total: deps.len(),
+                page: 1,
+                per_page: deps.len(),
                 data: deps,
             };
             res.push(real_res);
@@ -1266,6 +1190,9 @@ impl DBHandler {
             let real_res = DependentInfo {
                 direct_count: direct as usize,
                 indirect_count: indirect as usize,
+                total: deps.len(),
+                page: 1,
+                per_page: deps.len(),
                 data: deps,
             };
             res.push(real_res);
@@ -1383,7 +1310,13 @@ impl DBHandler {
         for row in rows {
             let name: String = row.get("filename");
             let time: String = row.get("uploadtime");
-            let tmp_res = UploadedCrate { name, time };
+            let tmp_res = UploadedCrate {
+                name,
+                time,
+                id: None,
+                status: None,
+                message: None,
+            };
             res.push(tmp_res);
         }
         Ok(res)
@@ -1401,11 +1334,100 @@ impl DBHandler {
         for row in rows {
             let name: String = row.get("githuburl");
             let time: String = row.get("uploadtime");
-            let tmp_res = UploadedCrate { name, time };
+            let tmp_res = UploadedCrate {
+                name,
+                time,
+                id: None,
+                status: None,
+                message: None,
+            };
             res.push(tmp_res);
         }
         Ok(res)
     }
+    /// The `upload_status` table of `/api/submitCrate`, created at startup.
+    pub async fn create_upload_tables(&self) -> Result<(), Error> {
+        self.client
+            .batch_execute(
+                "
+            CREATE TABLE IF NOT EXISTS upload_status (
+                id TEXT PRIMARY KEY,
+                email TEXT NOT NULL,
+                source TEXT NOT NULL,
+                crate_name TEXT,
+                status TEXT NOT NULL,
+                message TEXT,
+                created_at TIMESTAMPTZ DEFAULT NOW(),
+                updated_at TIMESTAMPTZ DEFAULT NOW()
+            );
+            ",
+            )
+            .await
+    }
+    /// Records a new upload of `email`, `source` is its file name or link.
+    pub async fn insert_upload_status(
+        &self,
+        id: &str,
+        email: &str,
+        source: &str,
+        status: UploadStatus,
+    ) -> Result<(), Error> {
+        self.client
+            .execute(
+                "INSERT INTO upload_status(id, email, source, status) VALUES ($1, $2, $3, $4)",
+                &[&id, &email, &source, &status.as_str()],
+            )
+            .await?;
+        Ok(())
+    }
+    /// Moves an upload to `status`, keeping the crate name it already has
+    /// when `crate_name` is `None`. Nothing changes unless the upload is in
+    /// one of [`UploadStatus::previous`].
+    pub async fn update_upload_status(
+        &self,
+        id: &str,
+        status: UploadStatus,
+        crate_name: Option<&str>,
+        message: Option<&str>,
+    ) -> Result<(), Error> {
+        let previous: Vec<&str> = status.previous().iter().map(UploadStatus::as_str).collect();
+        self.client
+            .execute(
+                "UPDATE upload_status
+                SET status = $2, crate_name = COALESCE($3, crate_name), message = $4,
+                    updated_at = NOW()
+                WHERE id = $1 AND status = ANY($5)",
+                &[&id, &status.as_str(), &crate_name, &message, &previous],
+            )
+            .await?;
+        Ok(())
+    }
+    /// Upload `id` of `email`, `None` when it does not exist or is someone else's.
+    pub async fn get_upload_status(
+        &self,
+        id: &str,
+        email: &str,
+    ) -> Result<Option<UploadRecord>, Error> {
+        let row = self
+            .client
+            .query_opt(
+                "SELECT * FROM upload_status WHERE id = $1 AND email = $2",
+                &[&id, &email],
+            )
+            .await?;
+        Ok(row.as_ref().map(upload_record_of_row))
+    }
+    /// Uploads of `email`, newest first.
+    pub async fn get_upload_statuses(&self, email: &str) -> Result<Vec<UploadRecord>, Error> {
+        let rows = self
+            .client
+            .query(
+                "SELECT * FROM upload_status WHERE email = $1 ORDER BY created_at DESC",
+                &[&email],
+            )
+            .await?;
+        Ok(rows.iter().map(upload_record_of_row).collect())
+    }
     pub async fn insert_sensleak_result_into_pg(
         &self,
         id: String,
@@ -1520,3 +1542,32 @@ impl DBHandler {
         Ok(real_res)
     }
 }
+
+fn upload_record_of_row(row: &tokio_postgres::Row) -> UploadRecord {
+    let created_at: chrono::DateTime<chrono::Utc> = row.get("created_at");
+    let updated_at: chrono::DateTime<chrono::Utc> = row.get("updated_at");
+    UploadRecord {
+        id: row.get("id"),
+        source: row.get("source"),
+        crate_name: row.get("crate_name"),
+        status: row.get("status"),
+        message: row.get("message"),
+        created_at: created_at.to_rfc3339(),
+        updated_at: updated_at.to_rfc3339(),
+    }
+}
+
+/// Whether a RustSec advisory applies to `version`. Advisories whose ranges
+/// cannot be parsed, or checked against an unparseable version, are kept.
+fn rustsec_affects(rs: &RustSec, unaffected: &str, version: Option<&Version>) -> bool {
+    let Some(version) = version else {
+        return true;
+    };
+    match RustsecRange::parse(&rs.patched, unaffected) {
+        Ok(range) => range.is_affected(version),
+        Err(e) => {
+            tracing::warn!("{}: {}, treating it as affecting {}", rs.id, e, version);
+            true
+        }
+    }
+}
@@ -0,0 +1,238 @@
+//! The dependency closure of a version as a DAG.
+//!
+//! Every version appears once as a node, at its shortest distance from the
+//! root, and every `depends_on` edge between two nodes of the closure is kept,
+//! so a crate reached through several paths shows all of them.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    error::Error,
+    fmt::Write,
+};
+
+use crate::{
+    data_reader::{DataReader, DataReaderTrait},
+    db::DBHandler,
+    handler::{DependencyGraph, GraphEdge, GraphNode},
+    NameVersion,
+};
+
+/// Upper bound of the nodes of one graph, the same as the vulnerability graph.
+const MAX_GRAPH_NODES: usize = 2000;
+
+/// Output formats of `/dependencies/graphpage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    Json,
+    Dot,
+    Mermaid,
+}
+
+impl std::str::FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(GraphFormat::Json),
+            "dot" => Ok(GraphFormat::Dot),
+            "mermaid" => Ok(GraphFormat::Mermaid),
+            _ => Err(format!("unknown graph format '{}'", s)),
+        }
+    }
+}
+
+pub async fn build_dependency_graph(
+    reader: &DataReader,
+    dbhandler: &DBHandler,
+    name: &str,
+    version: &str,
+    max_depth: Option<usize>,
+) -> Result<DependencyGraph, Box<dyn Error>> {
+    let root = name.to_string() + "/" + version;
+    let mut depths = HashMap::from([(root.clone(), 0)]);
+    let mut order = vec![root.clone()];
+    let mut edges = vec![];
+    let mut queue = VecDeque::from([root.clone()]);
+    while let Some(current) = queue.pop_front() {
+        let depth = depths[&current];
+        if max_depth.is_some_and(|max| depth >= max) {
+            continue;
+        }
+        let mut deps: Vec<String> = reader
+            .get_direct_dependency_nodes(&current)
+            .await?
+            .into_iter()
+            .map(|dep| dep.name + "/" + &dep.version)
+            .collect();
+        deps.sort();
+        for dep in deps {
+            if !depths.contains_key(&dep) {
+                if depths.len() == MAX_GRAPH_NODES {
+                    continue;
+                }
+                depths.insert(dep.clone(), depth + 1);
+                order.push(dep.clone());
+                queue.push_back(dep.clone());
+            }
+            edges.push(GraphEdge {
+                from: current.clone(),
+                to: dep,
+            });
+        }
+    }
+
+    let cve_counts = dbhandler.count_rustsec_batch(&order).await?;
+    let nodes = order
+        .into_iter()
+        .filter_map(|id| {
+            let nv = NameVersion::from_string(&id)?;
+            Some(GraphNode {
+                depth: depths[&id],
+                cve_count: cve_counts.get(&id).copied().unwrap_or(0),
+                crate_name: nv.name,
+                version: nv.version,
+                id,
+            })
+        })
+        .collect();
+    Ok(DependencyGraph {
+        root,
+        max_depth,
+        nodes,
+        edges,
+    })
+}
+
+/// Graphviz DOT, versions with advisories are drawn in red.
+pub fn to_dot(graph: &DependencyGraph) -> String {
+    let mut dot = String::from("digraph dependencies {\n    node [shape=box];\n");
+    for node in &graph.nodes {
+        let color = if node.cve_count > 0 {
+            ", color=red"
+        } else {
+            ""
+        };
+        writeln!(
+            dot,
+            "    \"{}\" [label=\"{}\\n{} CVEs\"{}];",
+            node.id, node.id, node.cve_count, color
+        )
+        .unwrap();
+    }
+    for edge in &graph.edges {
+        writeln!(dot, "    \"{}\" -> \"{}\";", edge.from, edge.to).unwrap();
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// A Mermaid flowchart. Mermaid ids cannot hold `/` or `.`, so nodes are
+/// numbered and labelled with their `name/version`.
+pub fn to_mermaid(graph: &DependencyGraph) -> String {
+    let ids: HashMap<&str, usize> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.id.as_str(), i))
+        .collect();
+    let mut mermaid = String::from("graph TD\n");
+    for (i, node) in graph.nodes.iter().enumerate() {
+        writeln!(
+            mermaid,
+            "    n{}[\"{}<br/>{} CVEs\"]",
+            i, node.id, node.cve_count
+        )
+        .unwrap();
+    }
+    for edge in &graph.edges {
+        if let (Some(from), Some(to)) = (ids.get(edge.from.as_str()), ids.get(edge.to.as_str())) {
+            writeln!(mermaid, "    n{} --> n{}", from, to).unwrap();
+        }
+    }
+    let vulnerable: Vec<String> = graph
+        .nodes
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| node.cve_count > 0)
+        .map(|(i, _)| format!("n{}", i))
+        .collect();
+    if !vulnerable.is_empty() {
+        mermaid.push_str("    classDef vulnerable stroke:#d00\n");
+        writeln!(mermaid, "    class {} vulnerable", vulnerable.join(",")).unwrap();
+    }
+    mermaid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_dot, to_mermaid, GraphFormat};
+    use crate::handler::{DependencyGraph, GraphEdge, GraphNode};
+
+    fn diamond() -> DependencyGraph {
+        let node = |id: &str, depth, cve_count| {
+            let (name, version) = id.split_once('/').unwrap();
+            GraphNode {
+                id: id.to_string(),
+                crate_name: name.to_string(),
+                version: version.to_string(),
+                depth,
+                cve_count,
+            }
+        };
+        let edge = |from: &str, to: &str| GraphEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+        };
+        DependencyGraph {
+            root: "app/1.0.0".to_string(),
+            max_depth: None,
+            nodes: vec![
+                node("app/1.0.0", 0, 0),
+                node("a/1.0.0", 1, 0),
+                node("b/1.0.0", 1, 0),
+                node("vuln/0.1.0", 2, 2),
+            ],
+            edges: vec![
+                edge("app/1.0.0", "a/1.0.0"),
+                edge("app/1.0.0", "b/1.0.0"),
+                edge("a/1.0.0", "vuln/0.1.0"),
+                edge("b/1.0.0", "vuln/0.1.0"),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_dot_keeps_every_edge() {
+        let dot = to_dot(&diamond());
+        assert!(dot.starts_with("digraph dependencies {"));
+        assert!(dot.contains("\"vuln/0.1.0\" [label=\"vuln/0.1.0\\n2 CVEs\", color=red];"));
+        assert!(dot.contains("\"a/1.0.0\" -> \"vuln/0.1.0\";"));
+        assert!(dot.contains("\"b/1.0.0\" -> \"vuln/0.1.0\";"));
+        assert!(dot.trim_end().ends_with('}'));
+    }
+
+    #[test]
+    fn test_mermaid() {
+        let mermaid = to_mermaid(&diamond());
+        let expected = "graph TD
+    n0[\"app/1.0.0<br/>0 CVEs\"]
+    n1[\"a/1.0.0<br/>0 CVEs\"]
+    n2[\"b/1.0.0<br/>0 CVEs\"]
+    n3[\"vuln/0.1.0<br/>2 CVEs\"]
+    n0 --> n1
+    n0 --> n2
+    n1 --> n3
+    n2 --> n3
+    classDef vulnerable stroke:#d00
+    class n3 vulnerable
+";
+        assert_eq!(mermaid, expected);
+    }
+
+    #[test]
+    fn test_graph_format() {
+        assert_eq!("dot".parse(), Ok(GraphFormat::Dot));
+        assert_eq!("mermaid".parse(), Ok(GraphFormat::Mermaid));
+        assert!("svg".parse::<GraphFormat>().is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! Errors of the API handlers.
+//!
+//! Every handler returns `Result<HttpResponse, ApiError>`, an error is
+//! answered with its status code and an [`ErrorResponse`] body instead of
+//! panicking the worker.
+
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    /// The crate, namespace or version does not exist.
+    NotFound(String),
+    /// The path, query or body of the request is invalid.
+    BadRequest(String),
+    /// The request carries no valid session token.
+    Unauthorized(String),
+    /// The session token belongs to another user.
+    Forbidden(String),
+    /// A query to TuGraph failed.
+    TuGraph(String),
+    /// A query to Postgres failed or no connection was available.
+    Postgres(String),
+    /// A Redis command failed or no connection was available.
+    Redis(String),
+    Internal(String),
+}
+
+/// The body of every error response.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct ErrorResponse {
+    /// HTTP status code.
+    pub code: u16,
+    /// `not_found`, `bad_request`, `unauthorized`, `forbidden`,
+    /// `tugraph_error`, `postgres_error`, `redis_error` or `internal_error`.
+    pub error: String,
+    pub message: String,
+}
+
+impl ApiError {
+    pub fn not_found(message: impl fmt::Display) -> Self {
+        ApiError::NotFound(message.to_string())
+    }
+
+    pub fn bad_request(message: impl fmt::Display) -> Self {
+        ApiError::BadRequest(message.to_string())
+    }
+
+    pub fn unauthorized(message: impl fmt::Display) -> Self {
+        ApiError::Unauthorized(message.to_string())
+    }
+
+    pub fn forbidden(message: impl fmt::Display) -> Self {
+        ApiError::Forbidden(message.to_string())
+    }
+
+    pub fn tugraph(e: impl fmt::Display) -> Self {
+        ApiError::TuGraph(e.to_string())
+    }
+
+    pub fn postgres(e: impl fmt::Display) -> Self {
+        ApiError::Postgres(e.to_string())
+    }
+
+    pub fn redis(e: impl fmt::Display) -> Self {
+        ApiError::Redis(e.to_string())
+    }
+
+    pub fn internal(e: impl fmt::Display) -> Self {
+        ApiError::Internal(e.to_string())
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::TuGraph(_) => "tugraph_error",
+            ApiError::Postgres(_) => "postgres_error",
+            ApiError::Redis(_) => "redis_error",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    fn message(&self) -> &str {
+        match self {
+            ApiError::NotFound(message)
+            | ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::TuGraph(message)
+            | ApiError::Postgres(message)
+            | ApiError::Redis(message)
+            | ApiError::Internal(message) => message,
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::TuGraph(_) | ApiError::Postgres(_) | ApiError::Redis(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        if status.is_server_error() {
+            tracing::error!("{}", self);
+        }
+        HttpResponse::build(status).json(ErrorResponse::from(self))
+    }
+}
+
+impl From<&ApiError> for ErrorResponse {
+    fn from(e: &ApiError) -> Self {
+        ErrorResponse {
+            code: e.status_code().as_u16(),
+            error: e.kind().to_string(),
+            message: e.message().to_string(),
+        }
+    }
+}
+
+impl From<tokio_postgres::Error> for ApiError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        ApiError::postgres(e)
+    }
+}
+
+impl From<deadpool_postgres::PoolError> for ApiError {
+    fn from(e: deadpool_postgres::PoolError) -> Self {
+        ApiError::postgres(e)
+    }
+}
+
+impl From<redis::RedisError> for ApiError {
+    fn from(e: redis::RedisError) -> Self {
+        ApiError::redis(e)
+    }
+}
+
+impl From<serde_json::Error> for ApiError {
+    fn from(e: serde_json::Error) -> Self {
+        ApiError::internal(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{body::to_bytes, http::StatusCode, ResponseError};
+
+    use super::{ApiError, ErrorResponse};
+
+    #[actix_web::test]
+    async fn test_error_envelope() {
+        let error = ApiError::not_found("crate 'foo' not found in 'a/b'");
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+        let body = to_bytes(error.error_response().into_body()).await.unwrap();
+        let body: ErrorResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            body,
+            ErrorResponse {
+                code: 404,
+                error: "not_found".to_string(),
+                message: "crate 'foo' not found in 'a/b'".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_upstream_failures_are_bad_gateway() {
+        for error in [
+            ApiError::tugraph("bolt connection reset"),
+            ApiError::postgres("timed out"),
+            ApiError::redis("connection refused"),
+        ] {
+            assert_eq!(error.status_code(), StatusCode::BAD_GATEWAY);
+        }
+        assert_eq!(
+            ApiError::bad_request("invalid kind").to_string(),
+            "bad_request: invalid kind"
+        );
+    }
+}
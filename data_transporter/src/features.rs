@@ -0,0 +1,357 @@
+//! Feature-aware dependency closure.
+//!
+//! Optional dependencies are only part of the closure once a feature enables
+//! them, and the features activated on a dependency are the union of what
+//! every dependent requests, as Cargo's feature unification does.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    error::Error,
+};
+
+use model::general_model::DependencyKind;
+
+use crate::{
+    data_reader::{DataReader, DataReaderTrait},
+    handler::{FeatureClosure, FeatureClosureNode},
+    NameVersion,
+};
+
+/// Upper bound of the versions loaded for one closure, the same as the
+/// vulnerability graph.
+const MAX_LOADED_VERSIONS: usize = 2000;
+
+/// A `depends_on` edge with the properties needed to resolve features.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureEdge {
+    /// `name/version` of the dependency.
+    pub dependency: String,
+    /// The name the dependent uses in its features, the renamed key if any.
+    pub key: String,
+    pub kind: DependencyKind,
+    pub optional: bool,
+    pub default_features: bool,
+    pub features: Vec<String>,
+}
+
+/// A version with its `[features]` table and outgoing edges.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureNode {
+    pub features: BTreeMap<String, Vec<String>>,
+    pub edges: Vec<FeatureEdge>,
+}
+
+pub async fn build_feature_closure(
+    reader: &DataReader,
+    namespace: &str,
+    name: &str,
+    version: &str,
+    features: &[String],
+    default_features: bool,
+) -> Result<FeatureClosure, Box<dyn Error>> {
+    let root = name.to_string() + "/" + version;
+    let graph = load_feature_graph(reader, namespace, &root).await?;
+    let activated = resolve_features(&graph, &root, features, default_features);
+    let mut nodes = vec![];
+    for (node, features) in activated {
+        if node == root {
+            continue;
+        }
+        let Some(nv) = NameVersion::from_string(&node) else {
+            continue;
+        };
+        nodes.push(FeatureClosureNode {
+            crate_name: nv.name,
+            version: nv.version,
+            features: features.into_iter().collect(),
+        });
+    }
+    Ok(FeatureClosure {
+        crate_name: name.to_string(),
+        version: version.to_string(),
+        dependency_count: nodes.len(),
+        dependencies: nodes,
+    })
+}
+
+/// Every version reachable from `root` through non-dev edges, optional or
+/// not, so the resolver can pick what is actually enabled. Only `root` is
+/// looked up in `namespace`, as the other dependency closures do.
+async fn load_feature_graph(
+    reader: &DataReader,
+    namespace: &str,
+    root: &str,
+) -> Result<HashMap<String, FeatureNode>, Box<dyn Error>> {
+    let mut graph = HashMap::new();
+    let mut queue = VecDeque::from([root.to_string()]);
+    while let Some(current) = queue.pop_front() {
+        if graph.len() == MAX_LOADED_VERSIONS {
+            break;
+        }
+        if graph.contains_key(&current) {
+            continue;
+        }
+        let scope = (current == root).then_some(namespace);
+        let node = reader.get_feature_node(scope, &current).await?;
+        queue.extend(
+            node.edges
+                .iter()
+                .filter(|e| e.kind != DependencyKind::Dev)
+                .map(|e| e.dependency.clone()),
+        );
+        graph.insert(current, node);
+    }
+    Ok(graph)
+}
+
+enum Work<'a> {
+    Visit(&'a str),
+    Feature(&'a str, &'a str),
+    EnableDependency(&'a str, &'a str),
+}
+
+/// Activated features of every version in the closure of `root`, the root
+/// included. Dev dependencies are left out, features a version does not
+/// declare are dropped, and versions missing from `graph` are reported with
+/// the features requested on them.
+pub fn resolve_features(
+    graph: &HashMap<String, FeatureNode>,
+    root: &str,
+    features: &[String],
+    default_features: bool,
+) -> BTreeMap<String, BTreeSet<String>> {
+    let empty = FeatureNode::default();
+    let node_of = |name: &str| graph.get(name).unwrap_or(&empty);
+
+    let mut activated: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut visited: HashSet<&str> = HashSet::new();
+    let mut enabled: HashMap<&str, HashSet<&str>> = HashMap::new();
+    // `dep?/feature` values waiting for `dep` to be enabled
+    let mut weak: HashMap<(&str, &str), Vec<&str>> = HashMap::new();
+
+    let mut queue = VecDeque::from([Work::Visit(root)]);
+    if default_features {
+        queue.push_back(Work::Feature(root, "default"));
+    }
+    queue.extend(features.iter().map(|f| Work::Feature(root, f.as_str())));
+
+    while let Some(work) = queue.pop_front() {
+        match work {
+            Work::Visit(node) => {
+                if !visited.insert(node) {
+                    continue;
+                }
+                activated.entry(node.to_string()).or_default();
+                for edge in &node_of(node).edges {
+                    if edge.kind != DependencyKind::Dev && !edge.optional {
+                        enabled.entry(node).or_default().insert(&edge.key);
+                        use_edge(edge, &mut queue);
+                    }
+                }
+            }
+            Work::Feature(node, feature) => {
+                queue.push_back(Work::Visit(node));
+                let info = node_of(node);
+                let values = info.features.get(feature);
+                // an optional dependency without `dep:` is an implicit feature
+                let implicit = info.edges.iter().any(|e| e.key == feature && e.optional);
+                let known = values.is_some() || implicit || !graph.contains_key(node);
+                if !known
+                    || !activated
+                        .entry(node.to_string())
+                        .or_default()
+                        .insert(feature.to_string())
+                {
+                    continue;
+                }
+                let Some(values) = values else {
+                    queue.push_back(Work::EnableDependency(node, feature));
+                    continue;
+                };
+                for value in values {
+                    if let Some(dep) = value.strip_prefix("dep:") {
+                        queue.push_back(Work::EnableDependency(node, dep));
+                    } else if let Some((dep, dep_feature)) = value.split_once('/') {
+                        if let Some(dep) = dep.strip_suffix('?') {
+                            if enabled.get(node).is_some_and(|e| e.contains(dep)) {
+                                feature_on_dependency(info, dep, dep_feature, &mut queue);
+                            } else {
+                                weak.entry((node, dep)).or_default().push(dep_feature);
+                            }
+                        } else {
+                            queue.push_back(Work::EnableDependency(node, dep));
+                            feature_on_dependency(info, dep, dep_feature, &mut queue);
+                        }
+                    } else {
+                        queue.push_back(Work::Feature(node, value));
+                    }
+                }
+            }
+            Work::EnableDependency(node, dep) => {
+                let info = node_of(node);
+                let optional: Vec<&FeatureEdge> = info
+                    .edges
+                    .iter()
+                    .filter(|e| e.key == dep && e.optional && e.kind != DependencyKind::Dev)
+                    .collect();
+                if optional.is_empty() || !enabled.entry(node).or_default().insert(dep) {
+                    continue;
+                }
+                for edge in optional {
+                    use_edge(edge, &mut queue);
+                }
+                for dep_feature in weak.remove(&(node, dep)).unwrap_or_default() {
+                    feature_on_dependency(info, dep, dep_feature, &mut queue);
+                }
+            }
+        }
+    }
+    activated
+}
+
+fn use_edge<'a>(edge: &'a FeatureEdge, queue: &mut VecDeque<Work<'a>>) {
+    queue.push_back(Work::Visit(&edge.dependency));
+    if edge.default_features {
+        queue.push_back(Work::Feature(&edge.dependency, "default"));
+    }
+    for feature in &edge.features {
+        queue.push_back(Work::Feature(&edge.dependency, feature));
+    }
+}
+
+fn feature_on_dependency<'a>(
+    node: &'a FeatureNode,
+    dep: &str,
+    feature: &'a str,
+    queue: &mut VecDeque<Work<'a>>,
+) {
+    for edge in node
+        .edges
+        .iter()
+        .filter(|e| e.key == dep && e.kind != DependencyKind::Dev)
+    {
+        queue.push_back(Work::Feature(&edge.dependency, feature));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+    use model::general_model::DependencyKind;
+
+    use super::{resolve_features, FeatureEdge, FeatureNode};
+
+    fn edge(dependency: &str, key: &str, optional: bool, features: &[&str]) -> FeatureEdge {
+        FeatureEdge {
+            dependency: dependency.to_string(),
+            key: key.to_string(),
+            kind: DependencyKind::Normal,
+            optional,
+            default_features: true,
+            features: features.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    fn node(features: &[(&str, &[&str])], edges: Vec<FeatureEdge>) -> FeatureNode {
+        FeatureNode {
+            features: features
+                .iter()
+                .map(|(f, values)| {
+                    (
+                        f.to_string(),
+                        values.iter().map(|v| v.to_string()).collect(),
+                    )
+                })
+                .collect(),
+            edges,
+        }
+    }
+
+    fn set(features: &[&str]) -> BTreeSet<String> {
+        features.iter().map(|f| f.to_string()).collect()
+    }
+
+    fn demo_graph() -> HashMap<String, FeatureNode> {
+        let mut graph = HashMap::new();
+        graph.insert(
+            "app/1.0.0".to_string(),
+            node(
+                &[
+                    ("default", &["std"]),
+                    ("std", &["serde?/std"]),
+                    ("async", &["dep:rt", "log/kv"]),
+                    ("ser", &["dep:serde"]),
+                ],
+                vec![
+                    edge("tokio/1.0.0", "rt", true, &["macros"]),
+                    edge("serde/1.0.0", "serde", true, &[]),
+                    edge("log/0.4.0", "log", false, &[]),
+                    FeatureEdge {
+                        kind: DependencyKind::Dev,
+                        ..edge("criterion/0.5.0", "criterion", false, &[])
+                    },
+                ],
+            ),
+        );
+        graph.insert(
+            "tokio/1.0.0".to_string(),
+            node(
+                &[("macros", &["tokio-macros"]), ("default", &[])],
+                vec![edge("tokio-macros/2.0.0", "tokio-macros", true, &[])],
+            ),
+        );
+        graph.insert(
+            "serde/1.0.0".to_string(),
+            node(&[("default", &["std"]), ("std", &[])], vec![]),
+        );
+        graph.insert(
+            "log/0.4.0".to_string(),
+            node(&[("kv", &[]), ("std", &[])], vec![]),
+        );
+        graph
+    }
+
+    #[test]
+    fn test_default_features_leave_optional_dependencies_out() {
+        let activated = resolve_features(&demo_graph(), "app/1.0.0", &[], true);
+        let expected: BTreeMap<String, BTreeSet<String>> = [
+            ("app/1.0.0".to_string(), set(&["default", "std"])),
+            ("log/0.4.0".to_string(), set(&[])),
+        ]
+        .into_iter()
+        .collect();
+        assert_eq!(activated, expected);
+    }
+
+    #[test]
+    fn test_features_enable_renamed_dependencies_transitively() {
+        let activated = resolve_features(
+            &demo_graph(),
+            "app/1.0.0",
+            &["async".to_string(), "ser".to_string()],
+            true,
+        );
+        assert_eq!(
+            activated["app/1.0.0"],
+            set(&["async", "default", "ser", "std"])
+        );
+        assert_eq!(
+            activated["tokio/1.0.0"],
+            set(&["default", "macros", "tokio-macros"])
+        );
+        // not loaded, so what was requested on it is kept as is
+        assert_eq!(activated["tokio-macros/2.0.0"], set(&["default"]));
+        assert_eq!(activated["log/0.4.0"], set(&["kv"]));
+        // `serde?/std` applies once `ser` enables serde
+        assert_eq!(activated["serde/1.0.0"], set(&["default", "std"]));
+        assert!(!activated.contains_key("criterion/0.5.0"));
+    }
+
+    #[test]
+    fn test_no_default_features() {
+        let activated = resolve_features(&demo_graph(), "app/1.0.0", &[], false);
+        assert_eq!(activated["app/1.0.0"], set(&[]));
+        assert_eq!(activated.len(), 2);
+    }
+}
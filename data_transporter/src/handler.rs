@@ -1,47 +1,53 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 #[allow(unused_imports)]
 use std::env;
-use std::error::Error;
 //use std::error::Error;
+use std::future::Future;
 use std::time::Instant;
 
-use crate::data_reader::{DataReader, DataReaderTrait};
-use crate::db::{db_connection_config_from_env, DBHandler};
-use crate::redis_store::{get_redis_connection, RedisHandler};
-use crate::{get_tugraph_api_handler, NameVersion, Userinfo};
-use crate::{Query, VersionInfo};
+use crate::audit::{audit_packages, parse_lockfile, parse_manifest_dependencies};
+use crate::auth::AuthenticatedUser;
+use crate::batch::{lookup_crates, MAX_BATCH_SIZE};
+use crate::compare::build_version_comparison;
+use crate::data_reader::DataReaderTrait;
+use crate::db::DBHandler;
+use crate::dependency_graph::{build_dependency_graph, to_dot, to_mermaid, GraphFormat};
+use crate::error::{ApiError, ErrorResponse};
+use crate::features::build_feature_closure;
+use crate::impact::{analyse_impact, ImpactClass};
+use crate::listing::{
+    filter_entries, sort_entries, ListingEntry, ListingParams, ListingSort, Relation,
+};
+use crate::redis_store::{depth_key_suffix, kind_key_suffix, CacheFamily, RedisHandler};
+use crate::sbom::{build_sbom, to_cyclonedx, to_spdx, SbomFormat};
+use crate::state::AppState;
+use crate::upload::{
+    extract_archive, find_crate_name, parse_github_link, upload_location, upload_root,
+    user_import_message, ArchiveKind, UploadError,
+};
+use crate::vulnerability::build_vulnerability_report;
+use crate::{ListingQuery, Query, UploadedCrate, VersionInfo};
+use crate::{NameVersion, Userinfo};
 use actix_multipart::{Field, Multipart};
 use actix_web::http::header::ContentDisposition;
-use actix_web::{web, HttpResponse, Responder};
+use actix_web::{web, HttpResponse};
 use futures_util::StreamExt;
 //use model::repo_sync_model;
 //use model::repo_sync_model::CrateType;
+use model::general_model::DependencyKind;
+use model::repo_sync_model::{MessageModel, UploadStatus};
 use model::tugraph_model::{Program, UProgram};
 //use repo_import::ImportDriver;
 use sanitize_filename::sanitize;
 use search::crates_search::RecommendCrate;
 //use search::crates_search::RecommendCrate;
 use search::crates_search::SearchModule;
-use search::crates_search::SearchSortCriteria;
+use search::crates_search::{ProgramType, SearchFilters, SearchSortCriteria};
 use serde::Deserialize;
 use serde::Serialize;
-use std::io::Cursor;
-use std::io::Read;
 //use std::time::Instant;
 //use semver::Version;
-use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
-use tokio_postgres::NoTls;
 use utoipa::ToSchema;
-use zip::ZipArchive;
-pub struct ApiHandler {
-    reader: DataReader,
-}
-impl ApiHandler {
-    pub async fn new(reader: DataReader) -> Self {
-        Self { reader }
-    }
-}
 
 #[derive(Serialize, Deserialize, Debug, Default, Clone, ToSchema)]
 pub struct QueryCratesInfo {
@@ -52,6 +58,8 @@ pub struct QueryCratesInfo {
 #[derive(Serialize, Deserialize, Debug, Default, Clone, ToSchema)]
 pub struct QueryData {
     total_page: usize,
+    /// Hits over all pages.
+    total: usize,
     items: Vec<QueryItem>,
 }
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
@@ -73,13 +81,30 @@ pub struct DependencyCrateInfo {
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct DependencyInfo {
     pub direct_count: usize,
+    /// Not counted for `sort=name&relation=direct`, those pages are read
+    /// from TuGraph without the dependency closure.
     pub indirect_count: usize,
+    /// Entries matching the `relation` filter, over all pages.
+    #[serde(default)]
+    pub total: usize,
+    #[serde(default)]
+    pub page: usize,
+    #[serde(default)]
+    pub per_page: usize,
     pub data: Vec<DependencyCrateInfo>,
 }
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct DependentInfo {
     pub direct_count: usize,
+    /// Only counted when `relation=indirect` is requested.
     pub indirect_count: usize,
+    /// Entries matching the `relation` filter, over all pages.
+    #[serde(default)]
+    pub total: usize,
+    #[serde(default)]
+    pub page: usize,
+    #[serde(default)]
+    pub per_page: usize,
     pub data: Vec<DependentData>,
 }
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
@@ -136,11 +161,28 @@ pub struct NewRustsec {
     pub url: String,
 }
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
-pub struct Deptree {
-    pub name_and_version: String,
+pub struct DependencyGraph {
+    /// `name/version` of the root.
+    pub root: String,
+    pub max_depth: Option<usize>,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct GraphNode {
+    /// `name/version`, referred to by the edges.
+    pub id: String,
+    pub crate_name: String,
+    pub version: String,
+    /// Length of the shortest path from the root.
+    pub depth: usize,
+    /// Number of RustSec advisories affecting this version.
     pub cve_count: usize,
-    #[schema(value_type = Vec<Deptree>)]
-    pub direct_dependency: Vec<Deptree>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
 }
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct Versionpage {
@@ -150,6 +192,197 @@ pub struct Versionpage {
     pub dependents: usize,
 }
 #[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct VulnerabilityReport {
+    pub crate_name: String,
+    pub version: String,
+    pub vulnerable_count: usize,
+    pub vulnerabilities: Vec<VulnerableDependency>,
+    /// Whether the dependency graph was cut off at its size limit, the
+    /// dependencies past it are not checked.
+    #[serde(default)]
+    pub graph_truncated: bool,
+    /// Whether some dependency has more paths than the ones listed.
+    #[serde(default)]
+    pub paths_truncated: bool,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct VulnerableDependency {
+    pub crate_name: String,
+    pub version: String,
+    pub advisories: Vec<NewRustsec>,
+    /// Number of edges between the root and this dependency.
+    pub shortest_path_len: usize,
+    /// `name/version` chains starting at the root, shortest first.
+    pub paths: Vec<Vec<String>>,
+    pub upgrades: Vec<DirectUpgrade>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct DirectUpgrade {
+    pub crate_name: String,
+    pub current_version: String,
+    /// `None` if no known newer version drops the advisory.
+    pub minimal_version: Option<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct FeatureClosure {
+    pub crate_name: String,
+    pub version: String,
+    pub dependency_count: usize,
+    pub dependencies: Vec<FeatureClosureNode>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct FeatureClosureNode {
+    pub crate_name: String,
+    pub version: String,
+    /// Features activated on this version, unified over all its dependents.
+    pub features: Vec<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ImpactReport {
+    pub namespace: String,
+    pub name: String,
+    pub proposed_version: String,
+    /// One entry per class: picks_up, pinned_out and unresolvable.
+    pub summary: Vec<ImpactSummary>,
+    /// Direct dependents, the most downloaded first.
+    pub dependents: Vec<ImpactDependent>,
+    /// Whether the transitive roll-up stopped before reaching every crate.
+    pub truncated: bool,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ImpactSummary {
+    pub class: ImpactClass,
+    pub direct_crates: usize,
+    pub direct_downloads: i64,
+    /// Crates depending on the direct dependents of this class, the direct
+    /// dependents themselves not included.
+    pub transitive_crates: usize,
+    pub transitive_downloads: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ImpactDependent {
+    pub name: String,
+    /// The newest version of the dependent depending on the crate.
+    pub version: String,
+    pub requirement: String,
+    pub kind: String,
+    pub class: ImpactClass,
+    /// Why the requirement could not be evaluated.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    pub downloads: i64,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct VersionComparison {
+    pub namespace: String,
+    pub name: String,
+    pub from: String,
+    pub to: String,
+    pub dependencies: DependencyChanges,
+    pub license: LicenseChange,
+    /// Advisories of the crate or a normal or build dependency gone in `to`.
+    pub advisories_fixed: Vec<ComparedAdvisory>,
+    pub advisories_introduced: Vec<ComparedAdvisory>,
+    pub mirchecker: MircheckerDelta,
+    /// Sensleak scans the whole repository history, both versions share it.
+    pub sensleak_findings: usize,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct DependencyChanges {
+    pub added: Vec<ComparedDependency>,
+    pub removed: Vec<ComparedDependency>,
+    pub changed: Vec<ChangedDependency>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ComparedDependency {
+    pub name: String,
+    pub kind: String,
+    pub target: String,
+    /// Empty if the edge was imported before requirements were recorded.
+    pub requirement: String,
+    pub resolved_version: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ChangedDependency {
+    pub name: String,
+    pub kind: String,
+    pub target: String,
+    pub from_requirement: String,
+    pub to_requirement: String,
+    pub from_version: String,
+    pub to_version: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct LicenseChange {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub changed: bool,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct ComparedAdvisory {
+    pub id: String,
+    /// The crate the advisory is about, the compared crate or a dependency.
+    pub package: String,
+    /// The affected version of `package`.
+    pub version: String,
+    pub subtitle: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, Default, ToSchema)]
+pub struct MircheckerDelta {
+    pub from_failed: bool,
+    pub to_failed: bool,
+    /// Warning blocks of `from` gone in `to`, matched on message and file.
+    pub fixed: Vec<String>,
+    pub introduced: Vec<String>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct AuditReport {
+    pub package_count: usize,
+    pub vulnerable_count: usize,
+    pub packages: Vec<AuditedPackage>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct AuditedPackage {
+    pub name: String,
+    pub version: String,
+    pub source: Option<String>,
+    /// Whether the uploaded Cargo.toml depends on this package directly.
+    pub direct: bool,
+    pub rustsecs: Vec<NewRustsec>,
+    pub cves: Vec<String>,
+    pub license: Option<String>,
+    /// Whether the package is known in the TuGraph `program` graph.
+    pub in_graph: bool,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BatchCratesRequest {
+    pub crates: Vec<BatchCrateQuery>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, ToSchema)]
+pub struct BatchCrateQuery {
+    /// `nsfront/nsbehind`, the first namespace of the crate when omitted.
+    #[serde(default)]
+    pub namespace: Option<String>,
+    pub name: String,
+    /// A version or `all`.
+    pub version: String,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BatchCratesResponse {
+    /// One result per requested crate, in request order.
+    pub results: Vec<BatchCrateResult>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct BatchCrateResult {
+    /// The requested or resolved namespace.
+    pub namespace: Option<String>,
+    pub name: String,
+    pub version: String,
+    pub info: Option<Crateinfo>,
+    /// Why `info` is missing.
+    pub error: Option<ErrorResponse>,
+}
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
 pub struct SenseleakRes {
     pub exist: bool,
     pub res: String,
@@ -167,28 +400,15 @@ pub struct MircheckerRes {
     path = "/api/cvelist",
     responses(
         (status = 200, description = "成功获取crate信息", body = crate::db::Allcve),
-        (status = 404, description = "未找到crate信息")
+        (status = 502, description = "Postgres 查询失败", body = ErrorResponse)
     ),
     tag = "security"
 )]
-pub async fn get_cves() -> impl Responder {
-    let _handler = get_tugraph_api_handler().await;
+pub async fn get_cves(state: &AppState) -> Result<HttpResponse, ApiError> {
+    let dbhd = state.db().await?;
+    let cves = dbhd.get_all_cvelist().await?;
 
-    let db_connection_config = db_connection_config_from_env();
-    #[allow(unused_variables)]
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-    let dbhd = DBHandler { client };
-    let cves = dbhd.get_all_cvelist().await.unwrap();
-
-    HttpResponse::Ok().json(cves)
+    Ok(HttpResponse::Ok().json(cves))
 }
 
 /// 获取所有crates
@@ -197,24 +417,27 @@ pub async fn get_cves() -> impl Responder {
     path = "/api/crates",
     responses(
         (status = 200, description = "成功获取所有crate的id", body = Vec<model::tugraph_model::Program>),
-        (status = 404, description = "未找到crate id")
+        (status = 502, description = "TuGraph 查询失败", body = ErrorResponse)
     ),
     tag = "crates"
 )]
-pub async fn get_all_crates() -> impl Responder {
+pub async fn get_all_crates(state: &AppState) -> Result<HttpResponse, ApiError> {
     tracing::info!("get all crates func run");
-    let handler = get_tugraph_api_handler().await;
-    let ids = handler.reader.get_all_programs_id().await;
+    let ids = state.reader.get_all_programs_id().await;
 
     let mut programs = vec![];
     for id in &ids {
-        let program = handler.reader.get_program(id).await.unwrap();
+        let program = state
+            .reader
+            .get_program(id)
+            .await
+            .map_err(ApiError::tugraph)?;
         programs.push(program);
     }
 
     tracing::info!("finish get all crates func");
 
-    HttpResponse::Ok().json(programs) // 返回 JSON 格式
+    Ok(HttpResponse::Ok().json(programs)) // 返回 JSON 格式
 }
 
 /// 获取crate详细信息,ok
@@ -226,32 +449,31 @@ pub async fn get_all_crates() -> impl Responder {
     ),
     responses(
         (status = 200, description = "成功获取crate详细信息", body= (Program, UProgram, Vec<VersionInfo>)),
-        (status = 404, description = "未找到crate"),
-        (status = 500, description = "服务器内部错误")
+        (status = 404, description = "未找到crate", body = ErrorResponse),
+        (status = 502, description = "TuGraph 查询失败", body = ErrorResponse)
     ),
     tag = "crates"
 )]
-pub async fn get_crate_details(crate_name: web::Path<String>) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
-    match handler.reader.get_program(&crate_name).await {
-        Ok(program) => {
-            match handler.reader.get_type(&crate_name).await {
-                Ok((uprogram, islib)) => {
-                    match handler.reader.get_versions(&crate_name, islib).await {
-                        Ok(versions) => {
-                            HttpResponse::Ok().json((program, uprogram, versions))
-                            // 返回 JSON 格式
-                        }
-                        Err(_) => {
-                            HttpResponse::InternalServerError().body("Failed to get versions.")
-                        }
-                    }
-                }
-                Err(_) => HttpResponse::InternalServerError().body("Failed to get type."),
-            }
-        }
-        Err(_) => HttpResponse::NotFound().body("Crate not found."),
-    }
+pub async fn get_crate_details(
+    state: &AppState,
+    crate_name: web::Path<String>,
+) -> Result<HttpResponse, ApiError> {
+    let program = state
+        .reader
+        .get_program(&crate_name)
+        .await
+        .map_err(|_| ApiError::not_found(format!("crate '{}' not found", crate_name)))?;
+    let (uprogram, islib) = state
+        .reader
+        .get_type(&crate_name)
+        .await
+        .map_err(ApiError::tugraph)?;
+    let versions = state
+        .reader
+        .get_versions(&crate_name, islib)
+        .await
+        .map_err(ApiError::tugraph)?;
+    Ok(HttpResponse::Ok().json((program, uprogram, versions))) // 返回 JSON 格式
 }
 
 /// 获取直接依赖关系图,ok
@@ -264,23 +486,25 @@ pub async fn get_crate_details(crate_name: web::Path<String>) -> impl Responder
     ),
     responses(
         (status = 200, description = "成功获取依赖关系图", body = Vec<NameVersion>),
-        (status = 404, description = "未找到依赖关系图")
+        (status = 502, description = "TuGraph 查询失败", body = ErrorResponse)
     ),
     tag = "dependencies"
 )]
-pub async fn get_direct_dep_for_graph(nname: String, nversion: String) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
+pub async fn get_direct_dep_for_graph(
+    state: &AppState,
+    nname: String,
+    nversion: String,
+) -> Result<HttpResponse, ApiError> {
     let name_and_version = nname + "/" + &nversion;
-    let res = handler
+    let res = state
         .reader
         .get_direct_dependency_nodes(&name_and_version)
         .await
-        .unwrap();
-    HttpResponse::Ok().json(res)
+        .map_err(ApiError::tugraph)?;
+    Ok(HttpResponse::Ok().json(res))
 }
 
 /*pub async fn get_max_version(versions: Vec<String>) -> Result<String, Box<dyn Error>> {
-    let _handler = get_tugraph_api_handler().await;
     let res = versions
         .into_iter()
         .max_by(|a, b| {
@@ -321,18 +545,17 @@ pub async fn get_direct_dep_for_graph(nname: String, nversion: String) -> impl R
     nsfront: String,
     nsbehind: String,
 ) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
 
     let namespace = nsfront.clone() + "/" + &nsbehind.clone();
     let nameversion = name.clone() + "/" + &version.clone();
     tracing::info!("{} {}", namespace.clone(), nameversion.clone());
-    let direct_nodes = handler
+    let direct_nodes = state
         .reader
         .new_get_direct_dependency_nodes(&namespace, &nameversion)
         .await
         .unwrap();
     let getdirect_count = direct_nodes.len();
-    let all_dependency_nodes = handler
+    let all_dependency_nodes = state
         .reader
         .new_get_all_dependencies(namespace.clone(), nameversion.clone())
         .await
@@ -354,7 +577,7 @@ pub async fn get_direct_dep_for_graph(nname: String, nversion: String) -> impl R
     let indirect_dependency_count = indirect_dependency.len();
     let mut deps = vec![];
     for item in direct_nodes {
-        let dep_count = handler
+        let dep_count = state
             .reader
             .count_dependencies(item.clone())
             .await
@@ -374,7 +597,7 @@ pub async fn get_direct_dep_for_graph(nname: String, nversion: String) -> impl R
             name: parts[0].to_string(),
             version: parts[1].to_string(),
         };
-        let dep_count = handler
+        let dep_count = state
             .reader
             .count_dependencies(newitem.clone())
             .await
@@ -404,11 +627,10 @@ pub async fn get_direct_dep_for_graph(nname: String, nversion: String) -> impl R
     nsfront: String,
     nsbehind: String,
 ) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
 
     let namespace = nsfront.clone() + "/" + &nsbehind.clone();
     let nameversion = name.clone() + "/" + &version.clone();
-    let direct_nodes = handler
+    let direct_nodes = state
         .reader
         .new_get_direct_dependent_nodes(&namespace, &nameversion)
         .await
@@ -438,6 +660,9 @@ pub async fn get_direct_dep_for_graph(nname: String, nversion: String) -> impl R
     HttpResponse::Ok().json(res_deps)
 }*/
 
+/// Hits returned per search page at most.
+const MAX_SEARCH_PER_PAGE: usize = 100;
+
 /// 查询 crates
 #[utoipa::path(
     post,
@@ -445,33 +670,53 @@ pub async fn get_direct_dep_for_graph(nname: String, nversion: String) -> impl R
     request_body = Query,
     responses(
         (status = 200, description = "查询成功", body = QueryCratesInfo),
-        (status = 400, description = "无效的查询参数")
+        (status = 400, description = "无效的查询参数", body = ErrorResponse),
+        (status = 502, description = "Postgres 查询失败", body = ErrorResponse)
     ),
     tag = "search"
 )]
-pub async fn query_crates(q: Query) -> impl Responder {
-    let _handler = get_tugraph_api_handler().await;
+pub async fn query_crates(state: &AppState, q: Query) -> Result<HttpResponse, ApiError> {
     //add yj's search module
     let name = q.query;
     let page = q.pagination.page;
     let per_page = q.pagination.per_page;
     tracing::info!("name:{},page:{},per_page:{}", name, page, per_page);
-    let db_connection_config = db_connection_config_from_env();
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
+    if page == 0 || per_page == 0 {
+        return Err(ApiError::bad_request("page and per_page start from 1"));
+    }
+    if per_page > MAX_SEARCH_PER_PAGE {
+        return Err(ApiError::bad_request(format!(
+            "per_page must not exceed {}",
+            MAX_SEARCH_PER_PAGE
+        )));
+    }
+    let sort = q
+        .sort
+        .as_deref()
+        .map(str::parse::<SearchSortCriteria>)
+        .transpose()
+        .map_err(ApiError::bad_request)?
+        .unwrap_or(SearchSortCriteria::Relavance);
+    let filters = SearchFilters {
+        program_type: q
+            .filters
+            .program_type
+            .as_deref()
+            .map(str::parse::<ProgramType>)
+            .transpose()
+            .map_err(ApiError::bad_request)?,
+        license: q.filters.license.filter(|l| !l.trim().is_empty()),
+        has_advisories: q.filters.has_advisories,
+        in_cratesio: q.filters.in_cratesio,
+    };
+    let dbhandler = state.db().await?;
     let start_time2 = Instant::now();
     let question = name.clone();
-    let search_module = SearchModule::new(&client).await;
+    let search_module = SearchModule::new(&dbhandler.client).await;
     let res = search_module
-        .search_crate(&question, SearchSortCriteria::Relavance)
+        .search_crate(&question, sort, &filters)
         .await
-        .unwrap();
+        .map_err(ApiError::postgres)?;
     tracing::trace!("search need time:{:?}", start_time2.elapsed());
     let mut seen = HashSet::new();
     let uniq_res: Vec<RecommendCrate> = res
@@ -479,84 +724,79 @@ pub async fn query_crates(q: Query) -> impl Responder {
         .filter(|x| seen.insert((x.name.clone(), x.namespace.clone())))
         .collect();
     tracing::trace!("total programs: {}", uniq_res.len());
-    let mut gettotal_page = uniq_res.len() / per_page;
-    if uniq_res.is_empty() || uniq_res.len() % per_page != 0 {
-        gettotal_page += 1;
-    }
-    let mut getitems = vec![];
-    for i in (page - 1) * 20..(page - 1) * 20 + 20 {
-        if i >= uniq_res.len() {
-            break;
-        }
-        let mut mv = vec![];
-        let program_name = uniq_res[i].clone().name;
-        let getnamespace = uniq_res[i].clone().namespace;
-        let parts: Vec<&str> = getnamespace.split('/').collect();
-        let nsf = parts[0].to_string();
-        let nsb = parts[1].to_string();
-
-        mv.push(uniq_res[i].clone().max_version);
-
-        if mv[0].clone() == *"null" {
-            mv[0] = "0.0.0".to_string();
-        }
-        let query_item = QueryItem {
-            name: program_name.clone(),
-            version: mv[0].clone(),
-            date: "".to_string(),
-            nsfront: nsf,
-            nsbehind: nsb,
-        };
-        getitems.push(query_item);
-    }
+    let gettotal_page = uniq_res.len().div_ceil(per_page).max(1);
+    let getitems = uniq_res
+        .iter()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|program| {
+            // 没有 '/' 的命名空间整体作为前半部分
+            let (nsf, nsb) = program
+                .namespace
+                .split_once('/')
+                .unwrap_or((program.namespace.as_str(), ""));
+            let version = if program.max_version == "null" {
+                "0.0.0".to_string()
+            } else {
+                program.max_version.clone()
+            };
+            QueryItem {
+                name: program.name.clone(),
+                version,
+                date: "".to_string(),
+                nsfront: nsf.to_string(),
+                nsbehind: nsb.to_string(),
+            }
+        })
+        .collect();
     let response = QueryCratesInfo {
         code: 200,
         message: "成功".to_string(),
         data: QueryData {
             total_page: gettotal_page,
+            total: uniq_res.len(),
             items: getitems,
         },
     };
 
-    HttpResponse::Ok().json(response)
+    Ok(HttpResponse::Ok().json(response))
 }
-//post of upload
-#[allow(clippy::let_unit_value)]
-pub async fn upload_crate(mut payload: Multipart) -> impl Responder {
+//post of upload, recorded under the user of the session token
+pub async fn upload_crate(
+    state: &AppState,
+    user: AuthenticatedUser,
+    mut payload: Multipart,
+) -> Result<HttpResponse, ApiError> {
     tracing::info!("enter upload crate");
     use futures_util::StreamExt as _;
-    let mut upload_time: Option<String> = None;
-    let mut user_email: Option<String> = None;
     let mut github_link: Option<String> = None;
-    let mut file_name: Option<String> = None;
-    while let Some(Ok(mut field)) = payload.next().await {
-        tracing::info!("enter while");
+    let mut file: Option<(String, Vec<u8>)> = None;
+    while let Some(field) = payload.next().await {
+        let mut field = field.map_err(ApiError::bad_request)?;
         if let Some(content_disposition) = field.content_disposition().cloned() {
-            tracing::info!("enter first if");
             if let Some(name) = content_disposition.get_name() {
-                tracing::info!("enter second if");
                 match name {
                     "file" => {
                         tracing::info!("enter match file");
-                        file_name = process_file_of_upload_crate(&content_disposition, &mut field)
-                            .await
-                            .unwrap();
-                        // analyze
+                        file = Some(
+                            process_file_of_upload_crate(
+                                &content_disposition,
+                                &mut field,
+                                state.upload_limits.max_upload_bytes,
+                            )
+                            .await?,
+                        );
                     }
                     "githubLink" => {
                         github_link = process_githublink_of_upload_crate(&mut field)
-                            .await
-                            .unwrap();
-                    }
-                    "uploadTime" => {
-                        tracing::info!("enter match uploadtime");
-                        upload_time = process_uploadtime_of_upload_crate(&mut field)
-                            .await
-                            .unwrap();
+                            .await?
+                            .filter(|link| !link.trim().is_empty());
                     }
                     "user_email" => {
                         tracing::info!("enter match user_email");
-                        user_email = process_useremail_of_upload_crate(&mut field).await.unwrap();
+                        // kept for older clients, it must name the session's user
+                        let email = process_useremail_of_upload_crate(&mut field).await?;
+                        user.ensure_is(email.as_deref().unwrap_or_default())?;
                     }
                     _ => {
                         tracing::info!("enter match nothing");
@@ -565,290 +805,357 @@ pub async fn upload_crate(mut payload: Multipart) -> impl Responder {
             }
         }
     }
-    let _ = process_insertintopg_of_upload_crate(file_name, upload_time, github_link, user_email)
-        .await
-        .unwrap();
-    HttpResponse::Ok().json(())
-}
-pub async fn process_insertintopg_of_upload_crate(
-    file_name: Option<String>,
-    upload_time: Option<String>,
-    github_link: Option<String>,
-    user_email: Option<String>,
-) -> Result<(), Box<dyn Error>> {
-    if let Some(filename) = file_name {
-        tracing::info!("enter 1/2 if let");
-        let db_connection_config = db_connection_config_from_env();
-        #[allow(unused_variables)]
-        let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-            .await
-            .unwrap();
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
-            }
-        });
-        let dbhandler = DBHandler { client };
-        if let Some(uploadtime) = upload_time.clone() {
-            tracing::info!("enter upload time:{}", uploadtime.clone());
-            if let Some(useremail) = user_email.clone() {
-                tracing::info!("enter user email:{}", useremail.clone());
-                dbhandler
-                    .client
-                    .execute(
-                        "INSERT INTO uploadedcrate(email,filename,uploadtime) VALUES ($1, $2,$3);",
-                        &[&useremail.clone(), &filename.clone(), &uploadtime.clone()],
-                    )
-                    .await
-                    .unwrap();
-            }
+    let mut receipts = vec![];
+    if let Some((name, data)) = file {
+        receipts.push(accept_uploaded_archive(state, &user, &name, data).await?);
+    }
+    if let Some(link) = &github_link {
+        receipts.push(accept_uploaded_link(state, &user, link.trim()).await?);
+    }
+    if receipts.is_empty() {
+        return Err(ApiError::bad_request(
+            "Missing an archive in field 'file' or a link in field 'githubLink'.",
+        ));
+    }
+    Ok(HttpResponse::Ok().json(receipts))
+}
+/// An accepted upload, followed with `upload_status`.
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct UploadReceipt {
+    pub id: String,
+    /// File name or GitHub link of the upload.
+    pub source: String,
+    pub crate_name: String,
+    /// `queued`, or `failed` when it could not be published for import.
+    pub status: String,
+}
+async fn accept_uploaded_archive(
+    state: &AppState,
+    user: &AuthenticatedUser,
+    file_name: &str,
+    data: Vec<u8>,
+) -> Result<UploadReceipt, ApiError> {
+    let kind = ArchiveKind::from_file_name(file_name)
+        .ok_or_else(|| UploadError::UnsupportedFormat(file_name.to_string()))?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let dbhandler = state.db().await?;
+    dbhandler
+        .insert_upload_status(&id, &user.email, file_name, UploadStatus::Received)
+        .await?;
+    let dest = upload_root().join(&id);
+    let limits = state.upload_limits.clone();
+    let extract_dest = dest.clone();
+    let extracted = tokio::task::spawn_blocking(move || {
+        let result = extract_archive(kind, &data, &extract_dest, &limits)
+            .map(|_| find_crate_name(&extract_dest));
+        if result.is_err() {
+            let _ = std::fs::remove_dir_all(&extract_dest);
+        }
+        result
+    })
+    .await
+    .map_err(ApiError::internal)?;
+    let crate_name = match extracted {
+        Ok(name) => name.unwrap_or_else(|| {
+            std::path::Path::new(file_name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        }),
+        Err(e) => {
+            let message = e.to_string();
+            dbhandler
+                .update_upload_status(&id, UploadStatus::Failed, None, Some(&message))
+                .await?;
+            return Err(match ApiError::from(e) {
+                ApiError::Internal(_) => ApiError::internal(format!("upload {}: {}", id, message)),
+                _ => ApiError::bad_request(format!("upload {}: {}", id, message)),
+            });
         }
     };
-    if let Some(githublink) = github_link {
-        tracing::info!("enter 2/2 if let");
-        let db_connection_config = db_connection_config_from_env();
-        #[allow(unused_variables)]
-        let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-            .await
-            .unwrap();
-        tokio::spawn(async move {
-            if let Err(e) = connection.await {
-                eprintln!("connection error: {}", e);
+    let message = user_import_message(&id, &crate_name, &upload_location(&id), None);
+    let status = queue_upload(state, &dbhandler, &id, &message).await?;
+    Ok(UploadReceipt {
+        id,
+        source: file_name.to_string(),
+        crate_name,
+        status: status.as_str().to_string(),
+    })
+}
+async fn accept_uploaded_link(
+    state: &AppState,
+    user: &AuthenticatedUser,
+    link: &str,
+) -> Result<UploadReceipt, ApiError> {
+    let (_, repo) = parse_github_link(link).ok_or_else(|| {
+        ApiError::bad_request(format!(
+            "Invalid GitHub link '{}', expected https://github.com/<owner>/<repo>.",
+            link
+        ))
+    })?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let dbhandler = state.db().await?;
+    dbhandler
+        .insert_upload_status(&id, &user.email, link, UploadStatus::Received)
+        .await?;
+    let message = user_import_message(&id, &repo, link, Some(link.to_string()));
+    let status = queue_upload(state, &dbhandler, &id, &message).await?;
+    Ok(UploadReceipt {
+        id,
+        source: link.to_string(),
+        crate_name: repo,
+        status: status.as_str().to_string(),
+    })
+}
+/// Publishes an upload for import, a failure is recorded rather than returned.
+async fn queue_upload(
+    state: &AppState,
+    dbhandler: &DBHandler,
+    id: &str,
+    message: &MessageModel,
+) -> Result<UploadStatus, ApiError> {
+    let crate_name = Some(message.db_model.crate_name.as_str());
+    match state.upload_queue.publish(message).await {
+        Ok(()) => {
+            dbhandler
+                .update_upload_status(id, UploadStatus::Queued, crate_name, None)
+                .await?;
+            Ok(UploadStatus::Queued)
+        }
+        Err(e) => {
+            tracing::error!("failed to publish upload {}: {}", id, e);
+            dbhandler
+                .update_upload_status(id, UploadStatus::Failed, crate_name, Some(&e))
+                .await?;
+            Ok(UploadStatus::Failed)
+        }
+    }
+}
+//post of Cargo.lock audit
+pub async fn audit_lockfile(
+    state: &AppState,
+    mut payload: Multipart,
+) -> Result<HttpResponse, ApiError> {
+    tracing::info!("enter audit lockfile");
+    let mut lockfile: Option<String> = None;
+    let mut manifest: Option<String> = None;
+    while let Some(Ok(mut field)) = payload.next().await {
+        let name = field
+            .content_disposition()
+            .and_then(|cd| cd.get_name())
+            .map(|name| name.to_string());
+        match name.as_deref() {
+            Some("lockfile") => {
+                lockfile = Some(read_text_field(&mut field, MAX_MANIFEST_FIELD_BYTES).await?);
             }
-        });
-        let dbhandler = DBHandler { client };
-        if let Some(uploadtime) = upload_time.clone() {
-            if let Some(useremail) = user_email.clone() {
-                dbhandler
-                    .client
-                    .execute(
-                        "INSERT INTO uploadedurl(email,githuburl,uploadtime) VALUES ($1, $2,$3);",
-                        &[&useremail.clone(), &githublink.clone(), &uploadtime.clone()],
-                    )
-                    .await
-                    .unwrap();
+            Some("manifest") => {
+                manifest = Some(read_text_field(&mut field, MAX_MANIFEST_FIELD_BYTES).await?);
+            }
+            _ => {
+                tracing::info!("ignore field {:?}", name);
             }
         }
     }
-    Ok(())
+    let Some(lockfile) = lockfile else {
+        return Err(ApiError::bad_request(
+            "Missing Cargo.lock in field 'lockfile'.",
+        ));
+    };
+    let packages = parse_lockfile(&lockfile).map_err(ApiError::bad_request)?;
+    let direct_dependencies = match manifest.as_deref().map(parse_manifest_dependencies) {
+        Some(names) => names.map_err(ApiError::bad_request)?,
+        None => HashSet::new(),
+    };
+    let dbhandler = state.db().await?;
+    let report = audit_packages(&state.reader, &dbhandler, packages, &direct_dependencies)
+        .await
+        .map_err(ApiError::tugraph)?;
+    Ok(HttpResponse::Ok().json(report))
 }
-#[allow(unused_assignments)]
-pub async fn process_useremail_of_upload_crate(
-    field: &mut Field,
-) -> Result<Option<String>, Box<dyn Error>> {
-    let mut user_email: Option<String> = None;
-    let mut email_data = Vec::new();
-
+/// Largest `Cargo.lock` or `Cargo.toml` accepted by the lockfile audit.
+const MAX_MANIFEST_FIELD_BYTES: usize = 2 * 1024 * 1024;
+/// Largest email or link field of an upload.
+const MAX_SHORT_FIELD_BYTES: usize = 4 * 1024;
+/// Reads a text field, refused before reading it all when it is larger than
+/// `max_bytes`.
+async fn read_text_field(field: &mut Field, max_bytes: usize) -> Result<String, ApiError> {
+    let mut data = Vec::new();
     while let Some(chunk) = field.next().await {
-        let data = chunk.unwrap();
-        email_data.extend_from_slice(&data);
+        let chunk = chunk.map_err(ApiError::bad_request)?;
+        if data.len() + chunk.len() > max_bytes {
+            return Err(ApiError::bad_request(format!(
+                "Field '{}' is larger than {} bytes.",
+                field.name().unwrap_or_default(),
+                max_bytes
+            )));
+        }
+        data.extend_from_slice(&chunk);
     }
-    user_email = Some(String::from_utf8(email_data).unwrap_or_default());
-    tracing::info!("user_email:{:?}", user_email);
-    Ok(user_email)
+    String::from_utf8(data).map_err(ApiError::bad_request)
 }
-#[allow(unused_assignments)]
-pub async fn process_uploadtime_of_upload_crate(
+pub async fn process_useremail_of_upload_crate(
     field: &mut Field,
-) -> Result<Option<String>, Box<dyn Error>> {
-    let mut upload_time: Option<String> = None;
-    let mut time_data = Vec::new();
-
-    while let Some(chunk) = field.next().await {
-        let data = chunk.unwrap();
-        time_data.extend_from_slice(&data);
-    }
-    upload_time = Some(String::from_utf8(time_data).unwrap_or_default());
-    tracing::info!("uploadtime:{:?}", upload_time);
-    Ok(upload_time)
+) -> Result<Option<String>, ApiError> {
+    let user_email = read_text_field(field, MAX_SHORT_FIELD_BYTES).await?;
+    tracing::info!("user_email:{:?}", user_email);
+    Ok(Some(user_email))
 }
-#[allow(unused_assignments)]
 pub async fn process_githublink_of_upload_crate(
     field: &mut Field,
-) -> Result<Option<String>, Box<dyn Error>> {
-    let mut github_link: Option<String> = None;
-    let mut url_data = Vec::new();
-    while let Some(chunk) = field.next().await {
-        let data = chunk.unwrap();
-        url_data.extend_from_slice(&data);
-    }
-    github_link = Some(String::from_utf8(url_data).unwrap_or_default());
-    Ok(github_link)
+) -> Result<Option<String>, ApiError> {
+    let github_link = read_text_field(field, MAX_SHORT_FIELD_BYTES).await?;
+    Ok(Some(github_link))
 }
-#[allow(unused_assignments)]
+/// The sanitized file name and content of an uploaded archive, refused
+/// before reading it all when it is not an archive or larger than `max_bytes`.
 pub async fn process_file_of_upload_crate(
     content_disposition: &ContentDisposition,
     field: &mut Field,
-) -> Result<Option<String>, Box<dyn Error>> {
-    tracing::info!("enter match file");
-    let mut file_name: Option<String> = None;
-    let filename = if let Some(file_name) = content_disposition.get_filename() {
-        file_name.to_string()
-    } else {
-        "default.zip".to_string()
-    };
-    tracing::info!("filename:{}", filename.clone());
-    let sanitized_filename = sanitize(filename.clone());
-    file_name = Some(filename.clone());
-    tracing::info!("file_name:{:?}", file_name.clone());
-    if sanitized_filename.ends_with(".zip") {
-        tracing::info!("enter file zip");
-        let zip_filepath = format!("target/zip/upload/{}", sanitized_filename);
-        let _ = tokio::fs::create_dir_all("target/zip/upload/").await;
-        let mut f = tokio::fs::File::create(&zip_filepath).await.unwrap();
-        while let Some(chunk) = field.next().await {
-            let data = chunk.unwrap();
-            f.write_all(&data).await.unwrap();
-        }
-        let parts: Vec<&str> = sanitized_filename.split('.').collect();
-        let mut filename = "".to_string();
-        if parts.len() >= 2 {
-            filename = parts[0].to_string();
-            tracing::info!("filename without zip: {}", filename);
-        }
-        let mut zip_file = tokio::fs::File::open(&zip_filepath).await.unwrap();
-        let mut buffer = Vec::new();
-        zip_file.read_to_end(&mut buffer).await.unwrap();
-        let reader = Cursor::new(buffer.clone());
-        let mut archive = ZipArchive::new(reader).unwrap();
-        for i in 0..archive.len() {
-            let mut file = archive.by_index(i).unwrap();
-            let outpath = match file.enclosed_name() {
-                Some(path) => {
-                    format!("target/www/uploads/{}/{}", filename, path.display())
-                }
-                None => continue,
-            };
-
-            if file.name().ends_with('/') {
-                // This is a directory, create it
-                tokio::fs::create_dir_all(&outpath).await.unwrap();
-            } else {
-                // Ensure the parent directory exists
-                if let Some(parent) = std::path::Path::new(&outpath).parent() {
-                    if !parent.exists() {
-                        tokio::fs::create_dir_all(&parent).await.unwrap();
-                    }
-                }
-
-                // Write the file
-                let mut outfile = tokio::fs::File::create(&outpath).await.unwrap();
-                while let Ok(bytes_read) = file.read(&mut buffer) {
-                    if bytes_read == 0 {
-                        break;
-                    }
-                    outfile.write_all(&buffer[..bytes_read]).await.unwrap();
-                }
-            }
-        }
-        tracing::info!("finish match file");
-        //send message
-    } else {
-        tracing::info!("enter else");
-        let filepath = format!("/home/rust/output/www/uploads/{}", sanitized_filename);
-        let mut f = tokio::fs::File::create(&filepath).await.unwrap();
-
-        while let Some(chunk) = field.next().await {
-            let data = chunk.unwrap();
-            f.write_all(&data).await.unwrap();
+    max_bytes: u64,
+) -> Result<(String, Vec<u8>), ApiError> {
+    let file_name = sanitize(content_disposition.get_filename().unwrap_or("default.zip"));
+    tracing::info!("file_name:{}", file_name);
+    if ArchiveKind::from_file_name(&file_name).is_none() {
+        return Err(UploadError::UnsupportedFormat(file_name).into());
+    }
+    let mut data = Vec::new();
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(ApiError::bad_request)?;
+        if (data.len() + chunk.len()) as u64 > max_bytes {
+            return Err(UploadError::TooLarge(max_bytes).into());
         }
+        data.extend_from_slice(&chunk);
     }
-    Ok(file_name)
+    Ok((file_name, data))
 }
-//post of log in
-pub async fn submituserinfo(info: Userinfo) -> impl Responder {
-    let db_connection_config = db_connection_config_from_env();
-    #[allow(unused_variables)]
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-    let dbhandler = DBHandler { client };
+//post of log in, answered with the session token of the user
+pub async fn submituserinfo(
+    state: &AppState,
+    access_token: Option<&str>,
+    info: Userinfo,
+) -> Result<HttpResponse, ApiError> {
+    if info.user.email.is_empty() {
+        return Err(ApiError::bad_request("Missing email of the user."));
+    }
+    state.logins.verify(access_token, &info.user.email).await?;
+    let dbhandler = state.db().await?;
     tracing::info!("enter submituserinfo and set db client");
-    #[allow(clippy::let_unit_value)]
-    let _ = dbhandler
+    dbhandler
         .insert_userinfo_into_pg(info.clone())
         .await
-        .unwrap();
-    HttpResponse::Ok().json(())
+        .map_err(ApiError::postgres)?;
+    let token = state
+        .sessions
+        .issue(&info.user.email, std::time::SystemTime::now());
+    Ok(HttpResponse::Ok().json(token))
 }
-pub async fn query_upload_crate(email: String) -> impl Responder {
-    let db_connection_config = db_connection_config_from_env();
-    #[allow(unused_variables)]
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-    let dbhandler = DBHandler { client };
-    let mut real_res = vec![];
+//uploads of the user of the session token, `email` may only name that user
+pub async fn query_upload_crate(
+    state: &AppState,
+    user: AuthenticatedUser,
+    email: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    user.ensure_is(email.as_deref().unwrap_or_default())?;
+    let email = user.email;
+    let dbhandler = state.db().await?;
+    let mut real_res: Vec<UploadedCrate> = dbhandler
+        .get_upload_statuses(&email)
+        .await?
+        .into_iter()
+        .map(UploadedCrate::from)
+        .collect();
+    // uploads made before `upload_status`
     let res = dbhandler
         .query_uploaded_crates_from_pg(email.clone())
         .await
-        .unwrap();
+        .map_err(ApiError::postgres)?;
     for row in res {
         real_res.push(row);
     }
     let res2 = dbhandler
         .query_uploaded_url_from_pg(email.clone())
         .await
-        .unwrap();
+        .map_err(ApiError::postgres)?;
     for row in res2 {
         real_res.push(row);
     }
-    HttpResponse::Ok().json(real_res)
+    Ok(HttpResponse::Ok().json(real_res))
 }
-pub async fn get_senseleak(nsfront: String, nsbehind: String) -> impl Responder {
-    let db_connection_config = db_connection_config_from_env();
-    #[allow(unused_variables)]
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-    let dbhandler = DBHandler { client };
+/// 上传的导入与分析状态
+#[derive(Serialize, Deserialize, Debug, Clone, ToSchema)]
+pub struct UploadRecord {
+    pub id: String,
+    /// 上传的文件名或 GitHub 链接
+    pub source: String,
+    pub crate_name: Option<String>,
+    /// received、queued、imported、analysing、analysed 或 failed
+    pub status: String,
+    /// 失败原因
+    pub message: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+}
+/// 查询当前用户的一次上传的状态
+#[utoipa::path(
+    get,
+    path = "/api/uploads/{id}",
+    params(
+        ("id" = String, Path, description = "上传 id")
+    ),
+    responses(
+        (status = 200, description = "上传的状态", body = UploadRecord),
+        (status = 401, description = "缺少或无效的会话令牌", body = ErrorResponse),
+        (status = 404, description = "当前用户没有该上传", body = ErrorResponse),
+        (status = 502, description = "Postgres 查询失败", body = ErrorResponse)
+    ),
+    tag = "upload"
+)]
+pub async fn get_upload_status(
+    state: &AppState,
+    user: AuthenticatedUser,
+    id: String,
+) -> Result<HttpResponse, ApiError> {
+    let dbhandler = state.db().await?;
+    match dbhandler.get_upload_status(&id, &user.email).await? {
+        Some(record) => Ok(HttpResponse::Ok().json(record)),
+        None => Err(ApiError::not_found(format!("upload '{}' not found", id))),
+    }
+}
+pub async fn get_senseleak(
+    state: &AppState,
+    nsfront: String,
+    nsbehind: String,
+) -> Result<HttpResponse, ApiError> {
+    let dbhandler = state.db().await?;
     let id = nsfront.clone() + "/" + &nsbehind;
-    let res = dbhandler.get_senseleak_from_pg(id).await.unwrap();
+    let res = dbhandler
+        .get_senseleak_from_pg(id)
+        .await
+        .map_err(ApiError::postgres)?;
     let mut exist = true;
     if res.clone() == *"[]" {
         exist = false;
     }
     let return_val = SenseleakRes { exist, res };
-    HttpResponse::Ok().json(return_val)
+    Ok(HttpResponse::Ok().json(return_val))
 }
 pub async fn get_mirchecker(
+    state: &AppState,
     nsfront: String,
     nsbehind: String,
     name: String,
     version: String,
-) -> impl Responder {
-    let db_connection_config = db_connection_config_from_env();
-    #[allow(unused_variables)]
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-    let dbhandler = DBHandler { client };
+) -> Result<HttpResponse, ApiError> {
+    let dbhandler = state.db().await?;
     let id = nsfront.clone() + "/" + &nsbehind + "/" + &name + "/" + &version;
     let run_state = dbhandler
         .get_mirchecker_run_state_from_pg(id.clone())
         .await
-        .unwrap();
-    let res = dbhandler.get_mirchecker_from_pg(id.clone()).await.unwrap();
+        .map_err(ApiError::postgres)?;
+    let res = dbhandler
+        .get_mirchecker_from_pg(id.clone())
+        .await
+        .map_err(ApiError::postgres)?;
     let mut exist = false;
     if res.contains("warning: [MirChecker]") {
         exist = true;
@@ -858,37 +1165,72 @@ pub async fn get_mirchecker(
         exist,
         res,
     };
-    HttpResponse::Ok().json(return_val)
+    Ok(HttpResponse::Ok().json(return_val))
+}
+
+/// 批量获取 crate 信息
+#[utoipa::path(
+    post,
+    path = "/api/crates/batch",
+    request_body = BatchCratesRequest,
+    responses(
+        (status = 200, description = "每个 crate 的信息或错误", body = BatchCratesResponse),
+        (status = 400, description = "请求为空或超过 100 个 crate", body = ErrorResponse),
+        (status = 502, description = "TuGraph 或 Redis 查询失败", body = ErrorResponse)
+    ),
+    tag = "crates"
+)]
+pub async fn get_crates_batch(
+    state: &AppState,
+    request: BatchCratesRequest,
+) -> Result<HttpResponse, ApiError> {
+    if request.crates.is_empty() {
+        return Err(ApiError::bad_request("'crates' must not be empty"));
+    }
+    if request.crates.len() > MAX_BATCH_SIZE {
+        return Err(ApiError::bad_request(format!(
+            "at most {} crates per request, got {}",
+            MAX_BATCH_SIZE,
+            request.crates.len()
+        )));
+    }
+    let results = lookup_crates(state, request.crates).await?;
+    Ok(HttpResponse::Ok().json(BatchCratesResponse { results }))
 }
 
 pub async fn new_get_crates_front_info_from_redis(
+    state: &AppState,
     nname: String,
     nversion: String,
     nsfront: String,
     nsbehind: String,
-) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
+) -> Result<HttpResponse, ApiError> {
     let namespace = nsfront.clone() + "/" + &nsbehind.clone();
 
-    let conn = get_redis_connection().await.unwrap();
-    let mut redisconn = RedisHandler { connection: conn };
+    let mut redisconn = state.redis().await?;
     let qid = format!("crates_info:{}:{}:{}", namespace, nname, nversion);
-    let qres = redisconn.query_from_redis(qid).await.unwrap();
+    let qres = redisconn
+        .query_from_redis(qid)
+        .await
+        .map_err(ApiError::redis)?;
     println!("finish query crates from reids");
     if qres.is_empty() {
         println!("qres is empty");
-        let res = handler
+        ensure_version_exists(state, &namespace, &nname, &nversion).await?;
+        let dbhandler = state.db().await?;
+        let res = state
             .reader
             .get_crates_front_info_from_tg(
+                &dbhandler,
                 nname.clone(),
                 nversion.clone(),
                 nsfront.clone(),
                 nsbehind.clone(),
             )
             .await
-            .unwrap();
+            .map_err(ApiError::tugraph)?;
         println!("finish get crates_info from tugraph");
-        let val = serde_json::to_string(&res).unwrap();
+        let val = serde_json::to_string(&res)?;
         redisconn
             .insert_crates_info_into_redis(
                 namespace.clone(),
@@ -897,176 +1239,684 @@ pub async fn new_get_crates_front_info_from_redis(
                 val.clone(),
             )
             .await
-            .unwrap();
-        HttpResponse::Ok().json(res)
+            .map_err(ApiError::redis)?;
+        Ok(HttpResponse::Ok().json(res))
     } else {
-        let res: Crateinfo = serde_json::from_str(&qres).unwrap();
-        HttpResponse::Ok().json(res.clone())
+        let res: Crateinfo = serde_json::from_str(&qres)?;
+        Ok(HttpResponse::Ok().json(res.clone()))
     }
 }
 pub async fn dependency_redis_cache(
+    state: &AppState,
     name: String,
     version: String,
     nsfront: String,
     nsbehind: String,
-) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
-    let conn = get_redis_connection().await.unwrap();
-    let mut redisconn = RedisHandler { connection: conn };
+    query: ListingQuery,
+) -> Result<HttpResponse, ApiError> {
+    let kind: Option<DependencyKind> = query
+        .kind
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(ApiError::bad_request)?;
+    let params = ListingParams::parse(
+        query.page,
+        query.per_page,
+        query.sort.as_deref(),
+        query.relation.as_deref(),
+    )?;
+    let mut redisconn = state.redis().await?;
     let namespace = nsfront.clone() + "/" + &nsbehind.clone();
-    let qid = format!("dependency:{}:{}:{}", namespace, name, version);
-    let res = redisconn.query_from_redis(qid.clone()).await.unwrap();
-    if res.is_empty() {
-        let res_deps = handler
+    let base_key = format!(
+        "dependency:{}:{}:{}{}",
+        namespace,
+        name,
+        version,
+        kind_key_suffix(kind)
+    );
+    let page_suffix = kind_key_suffix(kind) + &params.key_suffix();
+    let res = redisconn
+        .query_from_redis(format!("{}{}", base_key, params.key_suffix()))
+        .await
+        .map_err(ApiError::redis)?;
+    if !res.is_empty() {
+        let res_deps: DependencyInfo = serde_json::from_str(&res)?;
+        return Ok(HttpResponse::Ok().json(res_deps));
+    }
+    let res_deps = if params.pages_in_cypher() {
+        ensure_version_exists(state, &namespace, &name, &version).await?;
+        let (entries, total) = state
             .reader
-            .get_dependency_from_tg(
-                name.clone(),
-                version.clone(),
-                nsfront.clone(),
-                nsbehind.clone(),
-            )
-            .await
-            .unwrap();
-        let val = serde_json::to_string(&res_deps).unwrap();
-        redisconn
-            .insert_dependency_into_redis(
-                namespace.clone(),
-                name.clone(),
-                version.clone(),
-                val.clone(),
+            .get_direct_dependency_page_from_tg(
+                &namespace,
+                &name,
+                &version,
+                kind,
+                params.skip(),
+                params.per_page,
             )
             .await
-            .unwrap();
-        HttpResponse::Ok().json(res_deps.clone())
+            .map_err(ApiError::tugraph)?;
+        DependencyInfo {
+            direct_count: total,
+            indirect_count: 0,
+            total,
+            page: params.page,
+            per_page: params.per_page,
+            data: dependency_rows(state, &entries).await?,
+        }
     } else {
-        let res_deps: DependencyInfo = serde_json::from_str(&res).unwrap();
-        HttpResponse::Ok().json(res_deps.clone())
+        let index = cached_listing_index(
+            &mut redisconn,
+            CacheFamily::Dependency,
+            format!("{}:index", base_key),
+            async {
+                ensure_version_exists(state, &namespace, &name, &version).await?;
+                state
+                    .reader
+                    .get_dependency_index_from_tg(&namespace, &name, &version, kind)
+                    .await
+                    .map_err(ApiError::tugraph)
+            },
+        )
+        .await?;
+        let entries = sorted_listing(state, &index, &params).await?;
+        DependencyInfo {
+            direct_count: count_relation(&index, Relation::Direct),
+            indirect_count: count_relation(&index, Relation::Indirect),
+            total: entries.len(),
+            page: params.page,
+            per_page: params.per_page,
+            data: dependency_rows(state, params.window(&entries)).await?,
+        }
+    };
+    let val = serde_json::to_string(&res_deps)?;
+    redisconn
+        .insert_dependency_into_redis(namespace, name, version, page_suffix, val)
+        .await
+        .map_err(ApiError::redis)?;
+    Ok(HttpResponse::Ok().json(res_deps))
+}
+
+/// The rows of a dependency page, with the size of each entry's closure.
+async fn dependency_rows(
+    state: &AppState,
+    entries: &[ListingEntry],
+) -> Result<Vec<DependencyCrateInfo>, ApiError> {
+    let mut data = vec![];
+    for entry in entries {
+        let dep_count = state
+            .reader
+            .count_dependencies(NameVersion {
+                name: entry.name.clone(),
+                version: entry.version.clone(),
+            })
+            .await
+            .map_err(ApiError::tugraph)?;
+        data.push(DependencyCrateInfo {
+            crate_name: entry.name.clone(),
+            version: entry.version.clone(),
+            relation: entry.relation.to_string(),
+            license: "".to_string(),
+            dependencies: dep_count,
+        });
     }
+    Ok(data)
+}
+
+/// The listing index cached under `key`, built by `build` on a miss.
+async fn cached_listing_index(
+    redisconn: &mut RedisHandler,
+    family: CacheFamily,
+    key: String,
+    build: impl Future<Output = Result<Vec<ListingEntry>, ApiError>>,
+) -> Result<Vec<ListingEntry>, ApiError> {
+    let cached = redisconn
+        .query_from_redis(key.clone())
+        .await
+        .map_err(ApiError::redis)?;
+    if let Ok(index) = serde_json::from_str::<Vec<ListingEntry>>(&cached) {
+        return Ok(index);
+    }
+    let index = build.await?;
+    redisconn
+        .set_with_ttl(family, &key, &serde_json::to_string(&index)?)
+        .await
+        .map_err(ApiError::redis)?;
+    Ok(index)
+}
+
+/// The entries of `index` matching `params`, sorted, downloads are only
+/// fetched when sorting by them.
+async fn sorted_listing(
+    state: &AppState,
+    index: &[ListingEntry],
+    params: &ListingParams,
+) -> Result<Vec<ListingEntry>, ApiError> {
+    let mut entries = filter_entries(index, params.relation);
+    let downloads = if params.sort == ListingSort::Downloads {
+        let names: Vec<String> = entries
+            .iter()
+            .map(|entry| entry.name.clone())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        state.db().await?.get_downloads_batch(&names).await?
+    } else {
+        HashMap::new()
+    };
+    sort_entries(&mut entries, params.sort, &downloads);
+    Ok(entries)
+}
+
+fn count_relation(index: &[ListingEntry], relation: Relation) -> usize {
+    index
+        .iter()
+        .filter(|entry| entry.relation == relation)
+        .count()
 }
+/// 获取依赖图，节点与边的列表
+#[utoipa::path(
+    get,
+    path = "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependencies/graphpage",
+    params(
+        ("nsfront" = String, Path, description = "命名空间前半部分"),
+        ("nsbehind" = String, Path, description = "命名空间后半部分"),
+        ("cratename" = String, Path, description = "crate 名称"),
+        ("version" = String, Path, description = "版本号"),
+        ("max_depth" = Option<usize>, Query, description = "最大深度，不填则为整个依赖闭包"),
+        ("format" = Option<String>, Query, description = "json（默认）、dot 或 mermaid")
+    ),
+    responses(
+        (status = 200, description = "成功获取依赖图", body = DependencyGraph),
+        (status = 400, description = "未知的输出格式", body = ErrorResponse),
+        (status = 404, description = "未找到crate或版本", body = ErrorResponse),
+        (status = 502, description = "TuGraph、Postgres 或 Redis 查询失败", body = ErrorResponse)
+    ),
+    tag = "dependencies"
+)]
 pub async fn new_get_graph(
+    state: &AppState,
     nsfront: String,
     nsbehind: String,
     nname: String,
     nversion: String,
-) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
-    let db_connection_config = db_connection_config_from_env();
-    #[allow(unused_variables)]
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-    let dbhandler = DBHandler { client };
-    let conn = get_redis_connection().await.unwrap();
-    let mut redisconn = RedisHandler { connection: conn };
+    max_depth: Option<usize>,
+    format: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let format = format
+        .as_deref()
+        .unwrap_or("json")
+        .parse::<GraphFormat>()
+        .map_err(ApiError::bad_request)?;
+    let mut redisconn = state.redis().await?;
     let namespace = nsfront.clone() + "/" + &nsbehind.clone();
-    let qid = format!("dependencygraph:{}:{}:{}", namespace, nname, nversion);
-    let qres = redisconn.query_from_redis(qid.clone()).await.unwrap();
-    if qres.is_empty() {
-        tracing::info!("first time");
-        let nav = nname.clone() + "/" + &nversion;
-        let rustcve = dbhandler
-            .get_direct_rustsec(&nname, &nversion)
-            .await
-            .unwrap();
-        let mut res = Deptree {
-            name_and_version: nav.clone(),
-            cve_count: rustcve.len(),
-            direct_dependency: Vec::new(),
-        };
-        let mut visited = HashSet::new();
-        visited.insert(nav.clone());
-        handler
-            .reader
-            .build_graph(&mut res, &mut visited)
+    let qid = format!(
+        "dependencygraph:{}:{}:{}{}",
+        namespace,
+        nname,
+        nversion,
+        depth_key_suffix(max_depth)
+    );
+    let qres = redisconn
+        .query_from_redis(qid.clone())
+        .await
+        .map_err(ApiError::redis)?;
+    let graph = if qres.is_empty() {
+        ensure_version_exists(state, &namespace, &nname, &nversion).await?;
+        let dbhandler = state.db().await?;
+        let graph = build_dependency_graph(&state.reader, &dbhandler, &nname, &nversion, max_depth)
             .await
-            .unwrap();
-        let graph = serde_json::to_string(&res).unwrap();
+            .map_err(ApiError::tugraph)?;
+        let val = serde_json::to_string(&graph)?;
         redisconn
             .insert_dependency_graph_into_redis(
                 namespace.clone(),
                 nname.clone(),
                 nversion.clone(),
-                graph.clone(),
+                max_depth,
+                val,
             )
             .await
-            .unwrap();
-        HttpResponse::Ok().json(res)
+            .map_err(ApiError::redis)?;
+        graph
     } else {
-        tracing::info!("second time");
-        let res_tree: Deptree = serde_json::from_str(&qres).unwrap();
-        HttpResponse::Ok().json(res_tree)
-    }
+        serde_json::from_str::<DependencyGraph>(&qres)?
+    };
+    Ok(match format {
+        GraphFormat::Json => HttpResponse::Ok().json(graph),
+        GraphFormat::Dot => HttpResponse::Ok()
+            .content_type("text/vnd.graphviz; charset=utf-8")
+            .body(to_dot(&graph)),
+        GraphFormat::Mermaid => HttpResponse::Ok()
+            .content_type("text/plain; charset=utf-8")
+            .body(to_mermaid(&graph)),
+    })
 }
 pub async fn dependent_redis_cache(
+    state: &AppState,
     name: String,
     version: String,
     nsfront: String,
     nsbehind: String,
-) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
-    let conn = get_redis_connection().await.unwrap();
-    let mut redisconn = RedisHandler { connection: conn };
+    query: ListingQuery,
+) -> Result<HttpResponse, ApiError> {
+    let kind: Option<DependencyKind> = query
+        .kind
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(ApiError::bad_request)?;
+    let params = ListingParams::parse(
+        query.page,
+        query.per_page,
+        query.sort.as_deref(),
+        query.relation.as_deref(),
+    )?;
+    let with_indirect = params.relation == Some(Relation::Indirect);
+    let mut redisconn = state.redis().await?;
     let namespace = nsfront.clone() + "/" + &nsbehind.clone();
-    let qid = format!("dependent:{}:{}:{}", namespace, name, version);
-    let qres = redisconn.query_from_redis(qid.clone()).await.unwrap();
-    if qres.is_empty() {
-        let res_deps = handler
+    let base_key = format!(
+        "dependent:{}:{}:{}{}",
+        namespace,
+        name,
+        version,
+        kind_key_suffix(kind)
+    );
+    let page_suffix = kind_key_suffix(kind) + &params.key_suffix();
+    let qres = redisconn
+        .query_from_redis(format!("{}{}", base_key, params.key_suffix()))
+        .await
+        .map_err(ApiError::redis)?;
+    if !qres.is_empty() {
+        let res: DependentInfo = serde_json::from_str(&qres)?;
+        return Ok(HttpResponse::Ok().json(res));
+    }
+    let (page, total, direct_count, indirect_count) = if params.pages_in_cypher() {
+        ensure_version_exists(state, &namespace, &name, &version).await?;
+        let (entries, total) = state
             .reader
-            .get_dependent_from_tg(
-                name.clone(),
-                version.clone(),
-                nsfront.clone(),
-                nsbehind.clone(),
+            .get_direct_dependent_page_from_tg(
+                &namespace,
+                &name,
+                &version,
+                kind,
+                params.skip(),
+                params.per_page,
             )
             .await
-            .unwrap();
-        let val = serde_json::to_string(&res_deps).unwrap();
-        redisconn
-            .insert_dependent_into_redis(
-                namespace.clone(),
-                name.clone(),
-                version.clone(),
-                val.clone(),
-            )
-            .await
-            .unwrap();
-        HttpResponse::Ok().json(res_deps)
+            .map_err(ApiError::tugraph)?;
+        (entries, total, total, 0)
     } else {
-        let res: DependentInfo = serde_json::from_str(&qres).unwrap();
-        HttpResponse::Ok().json(res.clone())
-    }
+        let index_key = if with_indirect {
+            format!("{}:index:indirect", base_key)
+        } else {
+            format!("{}:index", base_key)
+        };
+        let index =
+            cached_listing_index(&mut redisconn, CacheFamily::Dependent, index_key, async {
+                ensure_version_exists(state, &namespace, &name, &version).await?;
+                state
+                    .reader
+                    .get_dependent_index_from_tg(&namespace, &name, &version, kind, with_indirect)
+                    .await
+                    .map_err(ApiError::tugraph)
+            })
+            .await?;
+        let entries = sorted_listing(state, &index, &params).await?;
+        (
+            params.window(&entries).to_vec(),
+            entries.len(),
+            count_relation(&index, Relation::Direct),
+            count_relation(&index, Relation::Indirect),
+        )
+    };
+    let res_deps = DependentInfo {
+        direct_count,
+        indirect_count,
+        total,
+        page: params.page,
+        per_page: params.per_page,
+        data: page
+            .iter()
+            .map(|entry| DependentData {
+                crate_name: entry.name.clone(),
+                version: entry.version.clone(),
+                relation: entry.relation.to_string(),
+            })
+            .collect(),
+    };
+    let val = serde_json::to_string(&res_deps)?;
+    redisconn
+        .insert_dependent_into_redis(namespace, name, version, page_suffix, val)
+        .await
+        .map_err(ApiError::redis)?;
+    Ok(HttpResponse::Ok().json(res_deps))
 }
 pub async fn new_get_version_page(
+    state: &AppState,
     nsfront: String,
     nsbehind: String,
     nname: String,
     _nversion: String,
-) -> impl Responder {
-    let handler = get_tugraph_api_handler().await;
-    let conn = get_redis_connection().await.unwrap();
-    let mut redisconn = RedisHandler { connection: conn };
+) -> Result<HttpResponse, ApiError> {
+    let mut redisconn = state.redis().await?;
     let namespace = nsfront.clone() + "/" + &nsbehind.clone();
     let qid = format!("versionpage:{}:{}", namespace, nname);
-    let res = redisconn.query_from_redis(qid.clone()).await.unwrap();
+    let res = redisconn
+        .query_from_redis(qid.clone())
+        .await
+        .map_err(ApiError::redis)?;
     if res.is_empty() {
-        let every_version = handler
+        ensure_version_exists(state, &namespace, &nname, "all").await?;
+        let cratesio_dbhandler = state.cratesio_db().await?;
+        let every_version = state
             .reader
-            .get_version_page_from_tg(nsfront.clone(), nsbehind.clone(), nname.clone())
+            .get_version_page_from_tg(
+                &cratesio_dbhandler,
+                nsfront.clone(),
+                nsbehind.clone(),
+                nname.clone(),
+            )
             .await
-            .unwrap();
-        let val = serde_json::to_string(&every_version).unwrap();
+            .map_err(ApiError::tugraph)?;
+        let val = serde_json::to_string(&every_version)?;
         redisconn
             .insert_versionpage_into_redis(namespace, nname.clone(), val.clone())
             .await
-            .unwrap();
-        HttpResponse::Ok().json(every_version)
+            .map_err(ApiError::redis)?;
+        Ok(HttpResponse::Ok().json(every_version))
+    } else {
+        let every_version: Vec<Versionpage> = serde_json::from_str(&res)?;
+        Ok(HttpResponse::Ok().json(every_version))
+    }
+}
+
+/// 获取依赖中漏洞的传播路径
+#[utoipa::path(
+    get,
+    path = "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/vulnerabilities",
+    params(
+        ("nsfront" = String, Path, description = "命名空间前半部分"),
+        ("nsbehind" = String, Path, description = "命名空间后半部分"),
+        ("cratename" = String, Path, description = "crate 名称"),
+        ("version" = String, Path, description = "版本号")
+    ),
+    responses(
+        (status = 200, description = "成功获取漏洞传播路径", body = VulnerabilityReport),
+        (status = 404, description = "未找到crate或版本", body = ErrorResponse),
+        (status = 502, description = "TuGraph、Postgres 或 Redis 查询失败", body = ErrorResponse)
+    ),
+    tag = "security"
+)]
+pub async fn get_vulnerabilities(
+    state: &AppState,
+    nsfront: String,
+    nsbehind: String,
+    nname: String,
+    nversion: String,
+) -> Result<HttpResponse, ApiError> {
+    let mut redisconn = state.redis().await?;
+    let namespace = nsfront.clone() + "/" + &nsbehind.clone();
+    let qid = format!("vulnerabilities:{}:{}:{}", namespace, nname, nversion);
+    let qres = redisconn
+        .query_from_redis(qid.clone())
+        .await
+        .map_err(ApiError::redis)?;
+    if qres.is_empty() {
+        ensure_version_exists(state, &namespace, &nname, &nversion).await?;
+        let dbhandler = state.db().await?;
+        let report =
+            build_vulnerability_report(&state.reader, &dbhandler, &namespace, &nname, &nversion)
+                .await
+                .map_err(ApiError::tugraph)?;
+        let val = serde_json::to_string(&report)?;
+        redisconn
+            .insert_vulnerabilities_into_redis(
+                namespace.clone(),
+                nname.clone(),
+                nversion.clone(),
+                val.clone(),
+            )
+            .await
+            .map_err(ApiError::redis)?;
+        Ok(HttpResponse::Ok().json(report))
     } else {
-        let every_version: Vec<Versionpage> = serde_json::from_str(&res).unwrap();
-        HttpResponse::Ok().json(every_version)
+        let report: VulnerabilityReport = serde_json::from_str(&qres)?;
+        Ok(HttpResponse::Ok().json(report))
+    }
+}
+
+/// 获取指定 feature 组合下的依赖闭包
+#[utoipa::path(
+    get,
+    path = "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependencies/features",
+    params(
+        ("nsfront" = String, Path, description = "命名空间前半部分"),
+        ("nsbehind" = String, Path, description = "命名空间后半部分"),
+        ("cratename" = String, Path, description = "crate 名称"),
+        ("version" = String, Path, description = "版本号"),
+        ("features" = Option<String>, Query, description = "启用的 feature，以逗号分隔"),
+        ("default_features" = Option<bool>, Query, description = "是否启用 default feature，默认为 true")
+    ),
+    responses(
+        (status = 200, description = "成功获取依赖闭包", body = FeatureClosure),
+        (status = 404, description = "未找到crate或版本", body = ErrorResponse),
+        (status = 502, description = "TuGraph 查询失败", body = ErrorResponse)
+    ),
+    tag = "dependencies"
+)]
+pub async fn get_feature_dependencies(
+    state: &AppState,
+    nsfront: String,
+    nsbehind: String,
+    nname: String,
+    nversion: String,
+    features: Option<String>,
+    default_features: Option<bool>,
+) -> Result<HttpResponse, ApiError> {
+    let namespace = nsfront + "/" + &nsbehind;
+    ensure_version_exists(state, &namespace, &nname, &nversion).await?;
+    let features: Vec<String> = features
+        .unwrap_or_default()
+        .split(',')
+        .map(|f| f.trim().to_string())
+        .filter(|f| !f.is_empty())
+        .collect();
+    let closure = build_feature_closure(
+        &state.reader,
+        &namespace,
+        &nname,
+        &nversion,
+        &features,
+        default_features.unwrap_or(true),
+    )
+    .await
+    .map_err(ApiError::tugraph)?;
+    Ok(HttpResponse::Ok().json(closure))
+}
+
+/// 导出依赖闭包的 SBOM
+#[utoipa::path(
+    get,
+    path = "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/sbom/{format}",
+    params(
+        ("nsfront" = String, Path, description = "命名空间前半部分"),
+        ("nsbehind" = String, Path, description = "命名空间后半部分"),
+        ("cratename" = String, Path, description = "crate 名称"),
+        ("version" = String, Path, description = "版本号，不能为 all"),
+        ("format" = String, Path, description = "cyclonedx（CycloneDX 1.5）或 spdx（SPDX 2.3）")
+    ),
+    responses(
+        (status = 200, description = "JSON 格式的 SBOM 文档"),
+        (status = 400, description = "未知的 SBOM 格式或版本号为 all", body = ErrorResponse),
+        (status = 404, description = "未找到crate或版本", body = ErrorResponse),
+        (status = 502, description = "TuGraph 或 Postgres 查询失败", body = ErrorResponse)
+    ),
+    tag = "dependencies"
+)]
+pub async fn get_sbom(
+    state: &AppState,
+    nsfront: String,
+    nsbehind: String,
+    nname: String,
+    nversion: String,
+    format: String,
+) -> Result<HttpResponse, ApiError> {
+    let format = format
+        .parse::<SbomFormat>()
+        .map_err(ApiError::bad_request)?;
+    refuse_all_versions("version", &nversion)?;
+    let namespace = nsfront + "/" + &nsbehind;
+    ensure_version_exists(state, &namespace, &nname, &nversion).await?;
+    let dbhandler = state.db().await?;
+    let sbom = build_sbom(&state.reader, &dbhandler, &namespace, &nname, &nversion)
+        .await
+        .map_err(ApiError::tugraph)?;
+    let serial = uuid::Uuid::new_v4().to_string();
+    let timestamp = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true);
+    let document = match format {
+        SbomFormat::CycloneDx => to_cyclonedx(&sbom, &serial, &timestamp),
+        SbomFormat::Spdx => to_spdx(&sbom, &serial, &timestamp),
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .body(serde_json::to_string(&document)?))
+}
+
+/// 比较同一 crate 的两个版本
+#[utoipa::path(
+    get,
+    path = "/api/crates/{nsfront}/{nsbehind}/{cratename}/compare/{from}/{to}",
+    params(
+        ("nsfront" = String, Path, description = "命名空间前半部分"),
+        ("nsbehind" = String, Path, description = "命名空间后半部分"),
+        ("cratename" = String, Path, description = "crate 名称"),
+        ("from" = String, Path, description = "旧版本号"),
+        ("to" = String, Path, description = "新版本号")
+    ),
+    responses(
+        (status = 200, description = "成功比较两个版本", body = VersionComparison),
+        (status = 400, description = "from 或 to 不是单个版本号", body = ErrorResponse),
+        (status = 404, description = "未找到crate或版本", body = ErrorResponse),
+        (status = 502, description = "TuGraph 或 Postgres 查询失败", body = ErrorResponse)
+    ),
+    tag = "crates"
+)]
+pub async fn compare_versions(
+    state: &AppState,
+    nsfront: String,
+    nsbehind: String,
+    nname: String,
+    from: String,
+    to: String,
+) -> Result<HttpResponse, ApiError> {
+    refuse_all_versions("from", &from)?;
+    refuse_all_versions("to", &to)?;
+    let namespace = nsfront + "/" + &nsbehind;
+    ensure_version_exists(state, &namespace, &nname, &from).await?;
+    ensure_version_exists(state, &namespace, &nname, &to).await?;
+    let dbhandler = state.db().await?;
+    let cratesio_dbhandler = state.cratesio_db().await?;
+    let comparison = build_version_comparison(
+        &state.reader,
+        &dbhandler,
+        &cratesio_dbhandler,
+        &namespace,
+        &nname,
+        &from,
+        &to,
+    )
+    .await
+    .map_err(ApiError::tugraph)?;
+    Ok(HttpResponse::Ok().json(comparison))
+}
+
+/// 评估拟发布的新版本对下游 crate 的影响
+#[utoipa::path(
+    get,
+    path = "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/impact",
+    params(
+        ("nsfront" = String, Path, description = "命名空间前半部分"),
+        ("nsbehind" = String, Path, description = "命名空间后半部分"),
+        ("cratename" = String, Path, description = "crate 名称"),
+        ("version" = String, Path, description = "拟发布的新版本号，不必已存在"),
+        ("kind" = Option<String>, Query, description = "只统计该类直接依赖：normal、dev 或 build")
+    ),
+    responses(
+        (status = 200, description = "成功评估影响", body = ImpactReport),
+        (status = 400, description = "版本号或依赖类型无效", body = ErrorResponse),
+        (status = 404, description = "未找到crate", body = ErrorResponse),
+        (status = 502, description = "TuGraph 或 Postgres 查询失败", body = ErrorResponse)
+    ),
+    tag = "dependents"
+)]
+pub async fn get_release_impact(
+    state: &AppState,
+    nsfront: String,
+    nsbehind: String,
+    nname: String,
+    nversion: String,
+    kind: Option<String>,
+) -> Result<HttpResponse, ApiError> {
+    let proposed = semver::Version::parse(&nversion)
+        .map_err(|e| ApiError::bad_request(format!("invalid version '{}': {}", nversion, e)))?;
+    let kind: Option<DependencyKind> = kind
+        .as_deref()
+        .map(str::parse)
+        .transpose()
+        .map_err(ApiError::bad_request)?;
+    let namespace = nsfront + "/" + &nsbehind;
+    ensure_version_exists(state, &namespace, &nname, "all").await?;
+    let analysis = analyse_impact(&state.reader, &namespace, &nname, &proposed, kind)
+        .await
+        .map_err(ApiError::tugraph)?;
+    let downloads = state
+        .cratesio_db()
+        .await?
+        .get_cratesio_downloads_batch(&analysis.crate_names())
+        .await
+        .map_err(ApiError::postgres)?;
+    let report = analysis.into_report(&namespace, &nname, &proposed, &downloads);
+    Ok(HttpResponse::Ok().json(report))
+}
+
+/// `NotFound` unless `namespace` holds the crate `name` and, except for
+/// `all`, its `version`.
+/// Refuses the `all` pseudo-version where an endpoint needs a single version.
+fn refuse_all_versions(param: &str, version: &str) -> Result<(), ApiError> {
+    if version == "all" {
+        return Err(ApiError::bad_request(format!(
+            "'{}' must be a single version, not 'all'",
+            param
+        )));
     }
+    Ok(())
+}
+
+async fn ensure_version_exists(
+    state: &AppState,
+    namespace: &str,
+    name: &str,
+    version: &str,
+) -> Result<(), ApiError> {
+    let versions = state
+        .reader
+        .get_versions_in_namespace(namespace, name)
+        .await
+        .map_err(ApiError::tugraph)?;
+    if versions.is_empty() {
+        return Err(ApiError::not_found(format!(
+            "crate '{}' not found in namespace '{}'",
+            name, namespace
+        )));
+    }
+    if version != "all" && !versions.iter().any(|v| v == version) {
+        return Err(ApiError::not_found(format!(
+            "version '{}' of crate '{}' not found in namespace '{}'",
+            version, name, namespace
+        )));
+    }
+    Ok(())
 }
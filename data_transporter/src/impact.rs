@@ -0,0 +1,427 @@
+//! Impact of a proposed release on the crates depending on a crate.
+//!
+//! Every `depends_on` edge keeps the requirement its dependent declared. A
+//! dependent crate is judged by the newest of its versions with an edge into
+//! the crate:
+//!
+//! - `picks_up`: a requirement matches the proposed version, the next
+//!   `cargo update` of the dependent moves to it;
+//! - `pinned_out`: every requirement excludes it, the dependent stays on the
+//!   current releases until it bumps its requirement;
+//! - `unresolvable`: a requirement can't be evaluated, it was not recorded,
+//!   does not parse or names a path or git source.
+//!
+//! The direct dependents picking the release up and the unresolvable ones are
+//! then followed through their own dependents, dev edges left out as they
+//! don't reach the users of a crate. Counts are weighted by the downloads of
+//! the crates.io `crate_downloads` table.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    error::Error,
+};
+
+use model::general_model::DependencyKind;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use utoipa::ToSchema;
+
+use crate::{
+    data_reader::{DataReader, DataReaderTrait},
+    handler::{ImpactDependent, ImpactReport, ImpactSummary},
+    NameVersion,
+};
+
+/// Crates reached at most per class by the transitive roll-up.
+const MAX_TRANSITIVE_CRATES: usize = 2000;
+
+/// Versions looked up per query of the transitive roll-up.
+const QUERY_CHUNK: usize = 500;
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize, ToSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ImpactClass {
+    PicksUp,
+    PinnedOut,
+    Unresolvable,
+}
+
+impl ImpactClass {
+    /// A version with several edges into the crate takes its worst class.
+    fn severity(&self) -> u8 {
+        match self {
+            ImpactClass::PinnedOut => 0,
+            ImpactClass::Unresolvable => 1,
+            ImpactClass::PicksUp => 2,
+        }
+    }
+}
+
+/// A `depends_on` edge into a version of the analysed crate.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RequirementEdge {
+    /// `name/version` of the dependent.
+    pub dependent: String,
+    /// `None` or empty for edges imported before requirements were recorded
+    /// and for path dependencies without a `version`.
+    pub requirement: Option<String>,
+    pub kind: DependencyKind,
+    /// `registry`, `path` or `git`, empty if not recorded.
+    pub source: String,
+}
+
+/// The class of `edge` for `proposed`, with the reason if unresolvable.
+pub fn classify(edge: &RequirementEdge, proposed: &Version) -> (ImpactClass, Option<String>) {
+    if matches!(edge.source.as_str(), "path" | "git") {
+        return (
+            ImpactClass::Unresolvable,
+            Some(format!(
+                "{} dependency, not resolved from crates.io",
+                edge.source
+            )),
+        );
+    }
+    let Some(requirement) = edge.requirement.as_deref().filter(|r| !r.trim().is_empty()) else {
+        return (
+            ImpactClass::Unresolvable,
+            Some("requirement not recorded, the dependent needs a re-import".to_string()),
+        );
+    };
+    match VersionReq::parse(requirement) {
+        Ok(req) if req.matches(proposed) => (ImpactClass::PicksUp, None),
+        Ok(_) => (ImpactClass::PinnedOut, None),
+        Err(e) => (
+            ImpactClass::Unresolvable,
+            Some(format!("invalid requirement '{}': {}", requirement, e)),
+        ),
+    }
+}
+
+fn is_newer(a: &str, b: &str) -> bool {
+    match (Version::parse(a), Version::parse(b)) {
+        (Ok(v_a), Ok(v_b)) => v_a > v_b,
+        (Ok(_), Err(_)) => true, // 无法解析的版本号视为更旧
+        (Err(_), Ok(_)) => false,
+        (Err(_), Err(_)) => a > b,
+    }
+}
+
+/// The newest version of each crate among `name_and_versions`.
+fn newest_by_crate<'a>(name_and_versions: impl IntoIterator<Item = &'a str>) -> Vec<NameVersion> {
+    let mut newest: BTreeMap<String, String> = BTreeMap::new();
+    for nv in name_and_versions
+        .into_iter()
+        .filter_map(NameVersion::from_string)
+    {
+        match newest.get(&nv.name) {
+            Some(version) if !is_newer(&nv.version, version) => {}
+            _ => {
+                newest.insert(nv.name, nv.version);
+            }
+        }
+    }
+    newest
+        .into_iter()
+        .map(|(name, version)| NameVersion { name, version })
+        .collect()
+}
+
+/// One entry per dependent crate, judged by its newest version with an edge
+/// into the crate. Downloads are filled in later.
+pub fn classify_dependents(edges: &[RequirementEdge], proposed: &Version) -> Vec<ImpactDependent> {
+    let newest = newest_by_crate(edges.iter().map(|edge| edge.dependent.as_str()));
+    let mut dependents = vec![];
+    for nv in newest {
+        let dependent = format!("{}/{}", nv.name, nv.version);
+        let worst = edges
+            .iter()
+            .filter(|edge| edge.dependent == dependent)
+            .map(|edge| (edge, classify(edge, proposed)))
+            .max_by_key(|(_, (class, _))| class.severity());
+        if let Some((edge, (class, reason))) = worst {
+            dependents.push(ImpactDependent {
+                name: nv.name,
+                version: nv.version,
+                requirement: edge.requirement.clone().unwrap_or_default(),
+                kind: edge.kind.to_string(),
+                class,
+                reason,
+                downloads: 0,
+            });
+        }
+    }
+    dependents
+}
+
+/// Crates depending on `start` through normal or build edges, directly or
+/// not, that are not in `known`. `true` if the walk was cut.
+async fn roll_up(
+    reader: &DataReader,
+    start: Vec<String>,
+    known: &HashSet<String>,
+) -> Result<(HashSet<String>, bool), Box<dyn Error>> {
+    let mut reached = HashSet::new();
+    let mut frontier = start;
+    while !frontier.is_empty() {
+        let mut dependents = vec![];
+        for chunk in frontier.chunks(QUERY_CHUNK) {
+            dependents.extend(reader.get_runtime_dependents_of_versions(chunk).await?);
+        }
+        let mut next = vec![];
+        for nv in newest_by_crate(dependents.iter().map(String::as_str)) {
+            if known.contains(&nv.name) || reached.contains(&nv.name) {
+                continue;
+            }
+            if reached.len() == MAX_TRANSITIVE_CRATES {
+                return Ok((reached, true));
+            }
+            next.push(format!("{}/{}", nv.name, nv.version));
+            reached.insert(nv.name);
+        }
+        frontier = next;
+    }
+    Ok((reached, false))
+}
+
+/// The classified direct dependents and the crates reached from each class.
+pub struct ImpactAnalysis {
+    pub dependents: Vec<ImpactDependent>,
+    pub transitive: BTreeMap<ImpactClass, HashSet<String>>,
+    pub truncated: bool,
+}
+
+pub async fn analyse_impact(
+    reader: &DataReader,
+    namespace: &str,
+    name: &str,
+    proposed: &Version,
+    kind: Option<DependencyKind>,
+) -> Result<ImpactAnalysis, Box<dyn Error>> {
+    let edges = reader
+        .get_requirement_edges_from_tg(namespace, name, kind)
+        .await?;
+    let dependents = classify_dependents(&edges, proposed);
+    let mut known: HashSet<String> = dependents.iter().map(|d| d.name.clone()).collect();
+    known.insert(name.to_string());
+
+    let mut transitive = BTreeMap::new();
+    let mut truncated = false;
+    for class in [ImpactClass::PicksUp, ImpactClass::Unresolvable] {
+        let start: Vec<String> = dependents
+            .iter()
+            .filter(|d| d.class == class)
+            .map(|d| format!("{}/{}", d.name, d.version))
+            .collect();
+        let (reached, cut) = roll_up(reader, start, &known).await?;
+        if cut {
+            tracing::warn!(
+                "transitive impact of {}/{} cut at {} crates",
+                name,
+                proposed,
+                MAX_TRANSITIVE_CRATES
+            );
+        }
+        truncated |= cut;
+        transitive.insert(class, reached);
+    }
+    Ok(ImpactAnalysis {
+        dependents,
+        transitive,
+        truncated,
+    })
+}
+
+impl ImpactAnalysis {
+    /// Every crate whose downloads the report needs.
+    pub fn crate_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .dependents
+            .iter()
+            .map(|d| d.name.clone())
+            .chain(self.transitive.values().flatten().cloned())
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// The report with every count weighted by `downloads`, a crate missing
+    /// from it counts as never downloaded.
+    pub fn into_report(
+        self,
+        namespace: &str,
+        name: &str,
+        proposed: &Version,
+        downloads: &HashMap<String, i64>,
+    ) -> ImpactReport {
+        let downloads_of = |name: &str| downloads.get(name).copied().unwrap_or(0);
+        let mut dependents = self.dependents;
+        for dependent in &mut dependents {
+            dependent.downloads = downloads_of(&dependent.name);
+        }
+        dependents.sort_by(|a, b| {
+            b.downloads
+                .cmp(&a.downloads)
+                .then_with(|| a.name.cmp(&b.name))
+        });
+        let summary = [
+            ImpactClass::PicksUp,
+            ImpactClass::PinnedOut,
+            ImpactClass::Unresolvable,
+        ]
+        .into_iter()
+        .map(|class| {
+            let direct: Vec<&ImpactDependent> =
+                dependents.iter().filter(|d| d.class == class).collect();
+            let transitive = self.transitive.get(&class);
+            ImpactSummary {
+                class,
+                direct_crates: direct.len(),
+                direct_downloads: direct.iter().map(|d| d.downloads).sum(),
+                transitive_crates: transitive.map_or(0, HashSet::len),
+                transitive_downloads: transitive
+                    .map_or(0, |names| names.iter().map(|n| downloads_of(n)).sum()),
+            }
+        })
+        .collect();
+        ImpactReport {
+            namespace: namespace.to_string(),
+            name: name.to_string(),
+            proposed_version: proposed.to_string(),
+            summary,
+            dependents,
+            truncated: self.truncated,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, HashMap, HashSet};
+
+    use model::general_model::DependencyKind;
+    use semver::Version;
+
+    use super::{classify, classify_dependents, ImpactAnalysis, ImpactClass, RequirementEdge};
+
+    fn edge(dependent: &str, requirement: Option<&str>, source: &str) -> RequirementEdge {
+        RequirementEdge {
+            dependent: dependent.to_string(),
+            requirement: requirement.map(str::to_string),
+            kind: DependencyKind::Normal,
+            source: source.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let proposed = Version::parse("2.0.0").unwrap();
+        let class = |e: RequirementEdge| classify(&e, &proposed).0;
+        assert_eq!(
+            class(edge("a/1.0.0", Some("*"), "registry")),
+            ImpactClass::PicksUp
+        );
+        assert_eq!(
+            class(edge("a/1.0.0", Some(">=1.2"), "registry")),
+            ImpactClass::PicksUp
+        );
+        assert_eq!(
+            class(edge("a/1.0.0", Some("1.4"), "registry")),
+            ImpactClass::PinnedOut
+        );
+        assert_eq!(
+            class(edge("a/1.0.0", Some("=1.4.2"), "")),
+            ImpactClass::PinnedOut
+        );
+        assert_eq!(
+            class(edge("a/1.0.0", Some("1.x.y"), "registry")),
+            ImpactClass::Unresolvable
+        );
+        assert_eq!(
+            class(edge("a/1.0.0", None, "registry")),
+            ImpactClass::Unresolvable
+        );
+        let (impact, reason) = classify(&edge("a/1.0.0", Some(""), ""), &proposed);
+        assert_eq!(impact, ImpactClass::Unresolvable);
+        assert!(reason.unwrap().starts_with("requirement not recorded"));
+        assert_eq!(
+            class(edge("a/1.0.0", Some("*"), "git")),
+            ImpactClass::Unresolvable
+        );
+    }
+
+    #[test]
+    fn test_newest_version_and_worst_edge_decide() {
+        let proposed = Version::parse("2.0.0").unwrap();
+        let edges = vec![
+            // 旧版本不影响结果
+            edge("old/0.9.0", Some("*"), "registry"),
+            edge("old/1.10.0", Some("1"), "registry"),
+            // 同一版本的多条边取最坏情况
+            edge("multi/0.1.0", Some("1"), "registry"),
+            edge("multi/0.1.0", Some(">=1"), "registry"),
+            edge("legacy/3.0.0", None, "registry"),
+        ];
+        let dependents = classify_dependents(&edges, &proposed);
+        let classes: Vec<(&str, &str, ImpactClass)> = dependents
+            .iter()
+            .map(|d| (d.name.as_str(), d.version.as_str(), d.class))
+            .collect();
+        assert_eq!(
+            classes,
+            [
+                ("legacy", "3.0.0", ImpactClass::Unresolvable),
+                ("multi", "0.1.0", ImpactClass::PicksUp),
+                ("old", "1.10.0", ImpactClass::PinnedOut),
+            ]
+        );
+        assert_eq!(dependents[1].requirement, ">=1");
+    }
+
+    #[test]
+    fn test_report_is_download_weighted() {
+        let proposed = Version::parse("2.0.0").unwrap();
+        let edges = vec![
+            edge("a/1.0.0", Some(">=1"), "registry"),
+            edge("b/1.0.0", Some("1"), "registry"),
+            edge("c/1.0.0", Some("*"), "registry"),
+        ];
+        let analysis = ImpactAnalysis {
+            dependents: classify_dependents(&edges, &proposed),
+            transitive: BTreeMap::from([
+                (
+                    ImpactClass::PicksUp,
+                    HashSet::from(["d".to_string(), "e".to_string()]),
+                ),
+                (ImpactClass::Unresolvable, HashSet::new()),
+            ]),
+            truncated: false,
+        };
+        assert_eq!(analysis.crate_names(), ["a", "b", "c", "d", "e"]);
+        let downloads = HashMap::from([
+            ("a".to_string(), 10),
+            ("b".to_string(), 1000),
+            ("c".to_string(), 5),
+            ("d".to_string(), 7),
+        ]);
+        let report = analysis.into_report("x/y", "lib", &proposed, &downloads);
+        let names: Vec<&str> = report.dependents.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["b", "a", "c"]);
+        let picks_up = &report.summary[0];
+        assert_eq!(picks_up.class, ImpactClass::PicksUp);
+        assert_eq!((picks_up.direct_crates, picks_up.direct_downloads), (2, 15));
+        assert_eq!(
+            (picks_up.transitive_crates, picks_up.transitive_downloads),
+            (2, 7)
+        );
+        let pinned_out = &report.summary[1];
+        assert_eq!(
+            (pinned_out.direct_crates, pinned_out.direct_downloads),
+            (1, 1000)
+        );
+        assert_eq!(report.summary[2].direct_crates, 0);
+    }
+}
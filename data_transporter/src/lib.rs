@@ -1,22 +1,38 @@
+mod advisory_range;
+mod audit;
+mod auth;
+mod batch;
+mod cache_invalidation;
+mod compare;
 mod data_packer;
 mod data_reader;
 pub mod db;
+mod dependency_graph;
+mod error;
+mod features;
 mod handler;
+mod impact;
+mod listing;
 mod redis_store;
+mod sbom;
+mod state;
 mod transporter;
+mod upload;
+mod vulnerability;
 
 use model::tugraph_model::UVersion;
 use search::search_prepare;
 use serde::{Deserialize, Serialize};
-use tokio_postgres::NoTls;
 pub use transporter::Transporter;
 
-use crate::data_reader::DataReader; // 确保导入你的 DataReader
-use crate::db::db_connection_config_from_env;
-use crate::handler::ApiHandler;
+use crate::auth::AuthenticatedUser;
+use crate::error::ApiError;
+use crate::state::{AppState, PoolConfig};
+
+use std::env;
 
 use actix_multipart::Multipart;
-use actix_web::{web, App, HttpResponse, HttpServer};
+use actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
 use utoipa::{OpenApi, ToSchema};
 use utoipa_swagger_ui::SwaggerUi;
 
@@ -24,6 +40,20 @@ use utoipa_swagger_ui::SwaggerUi;
 pub struct Query {
     query: String,
     pagination: Pagination,
+    /// `relevance` (default), `downloads`, `comprehensive` or `recently_updated`.
+    #[serde(default)]
+    sort: Option<String>,
+    #[serde(default)]
+    filters: SearchFilterQuery,
+}
+#[derive(Deserialize, Debug, Default, ToSchema)]
+pub struct SearchFilterQuery {
+    /// `library` or `application`.
+    program_type: Option<String>,
+    /// Part of the license expression, e.g. `MIT`.
+    license: Option<String>,
+    has_advisories: Option<bool>,
+    in_cratesio: Option<bool>,
 }
 #[derive(Deserialize, Debug, ToSchema)]
 pub struct Pagination {
@@ -45,6 +75,53 @@ pub struct Userinfo {
 pub struct UploadedCrate {
     name: String,
     time: String,
+    /// Id of `/api/uploads/{id}`, absent for uploads made before status tracking.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+impl From<handler::UploadRecord> for UploadedCrate {
+    fn from(record: handler::UploadRecord) -> Self {
+        UploadedCrate {
+            name: record.source,
+            time: record.created_at,
+            id: Some(record.id),
+            status: Some(record.status),
+            message: record.message,
+        }
+    }
+}
+/// `?features=a,b&default_features=false` of the feature-aware dependency closure.
+#[derive(Deserialize, Debug)]
+pub struct FeatureQuery {
+    features: Option<String>,
+    default_features: Option<bool>,
+}
+/// `?kind=normal|dev|build` of the release impact analysis.
+#[derive(Deserialize, Debug)]
+pub struct ImpactQuery {
+    kind: Option<String>,
+}
+/// `?max_depth=2&format=json|dot|mermaid` of the dependency graph.
+#[derive(Deserialize, Debug)]
+pub struct GraphQuery {
+    max_depth: Option<usize>,
+    format: Option<String>,
+}
+/// Query of the dependency and dependent listings: `kind=normal|dev|build`,
+/// `page` from 1, `per_page` up to 200, `sort=name|downloads|version` and
+/// `relation=direct|indirect`. Paging only limits the response, the listing
+/// is read whole once and cached, see the `listing` module.
+#[derive(Deserialize, Debug)]
+pub struct ListingQuery {
+    kind: Option<String>,
+    page: Option<usize>,
+    per_page: Option<usize>,
+    sort: Option<String>,
+    relation: Option<String>,
 }
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
@@ -59,24 +136,10 @@ struct RequestBody {
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
 struct RequestBody2 {
-    requestBody: String,
-}
-async fn get_tugraph_api_handler() -> ApiHandler {
-    let tugraph_bolt_url = &std::env::var("TUGRAPH_BOLT_URL").unwrap();
-    let tugraph_user_name = &std::env::var("TUGRAPH_USER_NAME").unwrap();
-    let tugraph_user_password = &std::env::var("TUGRAPH_USER_PASSWORD").unwrap();
-    let tugraph_cratespro_db = &std::env::var("TUGRAPH_CRATESPRO_DB").unwrap();
-    let reader = DataReader::new(
-        tugraph_bolt_url,
-        tugraph_user_name,
-        tugraph_user_password,
-        tugraph_cratespro_db,
-    )
-    .await
-    .unwrap();
-    ApiHandler::new(reader).await
+    /// Optional, must be the email of the session token.
+    #[serde(default)]
+    requestBody: Option<String>,
 }
-
 #[derive(OpenApi)]
 #[openapi(
     paths(
@@ -84,7 +147,15 @@ async fn get_tugraph_api_handler() -> ApiHandler {
         handler::get_all_crates,
         //handler::get_graph,
         handler::get_crate_details,
+        handler::get_crates_batch,
         handler::query_crates,
+        handler::get_vulnerabilities,
+        handler::get_feature_dependencies,
+        handler::get_release_impact,
+        handler::compare_versions,
+        handler::get_sbom,
+        handler::get_upload_status,
+        handler::new_get_graph,
         //handler::get_graph,
         //route::get_version_page,
         // route::get_graph,
@@ -102,17 +173,42 @@ async fn get_tugraph_api_handler() -> ApiHandler {
         schemas(
             model::tugraph_model::Program,
             db::Allcve,
+            error::ErrorResponse,
             handler::Versionpage,
-            //handler::Deptree,
             //handler::Crateinfo,
             handler::DependencyInfo,
             handler::DependentInfo,
             handler::Crateinfo,
+            handler::BatchCratesRequest,
+            handler::BatchCrateQuery,
+            handler::BatchCratesResponse,
+            handler::BatchCrateResult,
             model::tugraph_model::UProgram,
             VersionInfo,
             Query,
+            SearchFilterQuery,
             handler::QueryCratesInfo,
-            //handler::Deptree,
+            handler::VulnerabilityReport,
+            handler::VulnerableDependency,
+            handler::DirectUpgrade,
+            handler::DependencyGraph,
+            handler::GraphNode,
+            handler::GraphEdge,
+            handler::FeatureClosure,
+            handler::FeatureClosureNode,
+            handler::ImpactReport,
+            handler::ImpactSummary,
+            handler::ImpactDependent,
+            impact::ImpactClass,
+            handler::VersionComparison,
+            handler::DependencyChanges,
+            handler::ComparedDependency,
+            handler::ChangedDependency,
+            handler::LicenseChange,
+            handler::ComparedAdvisory,
+            handler::MircheckerDelta,
+            handler::UploadRecord,
+            handler::NewRustsec,
             // Query, 
             // Pagination,
             // route::QueryCratesInfo,
@@ -126,7 +222,6 @@ async fn get_tugraph_api_handler() -> ApiHandler {
             // route::Versionpage,
             // route::NewRustsec,
             // NameVersion,
-            // route::Deptree,
         )
     ),
     tags(
@@ -135,116 +230,205 @@ async fn get_tugraph_api_handler() -> ApiHandler {
         (name = "search", description = "Search API"),
         (name = "security", description = "Security API"),
         (name = "versions", description = "Version API"),
-        //(name = "upload", description = "Upload API"),
+        (name = "upload", description = "Upload API"),
     )
 )]
 struct ApiDoc;
 
 pub async fn run_api_server() -> std::io::Result<()> {
     tracing::info!("Start run_api_server");
-    let db_connection_config = db_connection_config_from_env();
-    let (client, connection) = tokio_postgres::connect(&db_connection_config, NoTls)
-        .await
-        .unwrap();
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            eprintln!("connection error: {}", e);
-        }
-    });
-    let pre_search = search_prepare::SearchPrepare::new(&client).await;
+    let pool_config = PoolConfig::from_env();
+    tracing::info!("pool config: {:?}", pool_config);
+    let state = web::Data::new(AppState::new(&pool_config).await.unwrap());
+    let dbhandler = state.db().await.unwrap();
+    let pre_search = search_prepare::SearchPrepare::new(&dbhandler.client).await;
     pre_search.prepare_tsv().await.unwrap();
+    dbhandler.create_upload_tables().await.unwrap();
+    drop(dbhandler);
+    if env::var("API_CACHE_INVALIDATION").is_ok_and(|v| v == "1") {
+        let state = state.clone();
+        tokio::spawn(async move { cache_invalidation::run(&state).await });
+    }
     HttpServer::new(move || {
         tracing::info!("start route");
         App::new()
+            .app_data(state.clone())
+            .app_data(
+                web::JsonConfig::default().error_handler(|e, _| ApiError::bad_request(e).into()),
+            )
+            .app_data(
+                web::QueryConfig::default().error_handler(|e, _| ApiError::bad_request(e).into()),
+            )
+            .app_data(
+                web::PathConfig::default().error_handler(|e, _| ApiError::bad_request(e).into()),
+            )
             .service(
                 SwaggerUi::new("/swagger-ui/{_:.*}")
                     .url("/api-docs/openapi.json", ApiDoc::openapi()),
             )
             .route(
                 "/api/cvelist",
-                web::get().to(|| async move { handler::get_cves().await }),
+                web::get().to(|state: web::Data<AppState>| async move {
+                    handler::get_cves(&state).await
+                }),
             )
             .route(
                 "/api/crates",
-                web::get().to(|| async move { handler::get_all_crates().await }),
+                web::get().to(|state: web::Data<AppState>| async move {
+                    handler::get_all_crates(&state).await
+                }),
             )
             .route(
                 "/api/crates/{cratename}",
-                web::get().to(|name: web::Path<String>| async move {
-                    handler::get_crate_details(name.into_inner().into()).await
-                }),
+                web::get().to(
+                    |state: web::Data<AppState>, name: web::Path<String>| async move {
+                        handler::get_crate_details(&state, name.into_inner().into()).await
+                    },
+                ),
             )
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/versions",
                 web::get().to(
-                    |path: web::Path<(String, String, String, String)>| async move {
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>| async move {
                         let (nsfront, nsbehind, cratename, version) = path.into_inner();
-                        handler::new_get_version_page(nsfront, nsbehind, cratename, version).await
+                        handler::new_get_version_page(&state, nsfront, nsbehind, cratename, version)
+                            .await
                     },
                 ),
             )
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependencies/graphpage",
                 web::get().to(
-                    |path: web::Path<(String, String, String, String)>| async move {
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>,
+                     query: web::Query<GraphQuery>| async move {
                         let (nsfront, nsbehind, cratename, version) = path.into_inner();
-                        handler::new_get_graph(nsfront, nsbehind, cratename, version).await
+                        let query = query.into_inner();
+                        handler::new_get_graph(
+                            &state,
+                            nsfront,
+                            nsbehind,
+                            cratename,
+                            version,
+                            query.max_depth,
+                            query.format,
+                        )
+                        .await
                     },
                 ),
             )
             .route(
                 "/api/submit",
-                web::post()
-                    .to(|payload: Multipart| async move { handler::upload_crate(payload).await }),
+                web::post().to(
+                    |state: web::Data<AppState>, user: AuthenticatedUser, payload: Multipart| async move {
+                        handler::upload_crate(&state, user, payload).await
+                    },
+                ),
             )
             .route(
                 "/api/submitCrate",
-                web::post().to(|payload: Multipart| async move {
-                    tracing::info!("enter submitcrate");
-                    handler::upload_crate(payload).await
-                }),
+                web::post().to(
+                    |state: web::Data<AppState>, user: AuthenticatedUser, payload: Multipart| async move {
+                        tracing::info!("enter submitcrate");
+                        handler::upload_crate(&state, user, payload).await
+                    },
+                ),
+            )
+            .route(
+                "/api/auditLockfile",
+                web::post().to(
+                    |state: web::Data<AppState>, payload: Multipart| async move {
+                        tracing::info!("enter auditLockfile");
+                        handler::audit_lockfile(&state, payload).await
+                    },
+                ),
             )
             .route(
                 "/api/submitUserinfo",
-                web::post().to(|payload: String| async move {
-                    //web::Json<Userinfo>
-                    tracing::info!("enter submitUserinfo");
-                    tracing::info!("payload:{}", payload.clone());
-                    let query: Root = serde_json::from_str(&payload).unwrap();
-                    tracing::info!("userinfo {:?}", query);
-                    handler::submituserinfo(query.requestBody.session).await
-                }),
+                web::post().to(
+                    |state: web::Data<AppState>, req: HttpRequest, payload: String| async move {
+                        //web::Json<Userinfo>
+                        tracing::info!("enter submitUserinfo");
+                        let query: Root =
+                            serde_json::from_str(&payload).map_err(ApiError::bad_request)?;
+                        tracing::info!("userinfo {:?}", query.requestBody.session.user.email);
+                        let access_token = req
+                            .headers()
+                            .get(auth::LOGIN_TOKEN_HEADER)
+                            .and_then(|v| v.to_str().ok());
+                        handler::submituserinfo(&state, access_token, query.requestBody.session)
+                            .await
+                    },
+                ),
             )
             .route(
                 "/api/profile",
-                web::post().to(|payload: String| async move {
-                    tracing::info!("enter profile");
-                    tracing::info!("payload:{}", payload.clone());
-                    let query: RequestBody2 = serde_json::from_str(&payload).unwrap();
-                    tracing::info!("profile email:{}", query.requestBody.clone());
-                    handler::query_upload_crate(query.requestBody).await
-                }),
+                web::post().to(
+                    |state: web::Data<AppState>, user: AuthenticatedUser, payload: String| async move {
+                        tracing::info!("enter profile");
+                        let email = if payload.trim().is_empty() {
+                            None
+                        } else {
+                            let query: RequestBody2 =
+                                serde_json::from_str(&payload).map_err(ApiError::bad_request)?;
+                            query.requestBody
+                        };
+                        tracing::info!("profile email:{}", user.email);
+                        handler::query_upload_crate(&state, user, email).await
+                    },
+                ),
+            )
+            .route(
+                "/api/uploads/{id}",
+                web::get().to(
+                    |state: web::Data<AppState>, user: AuthenticatedUser, id: web::Path<String>| async move {
+                        handler::get_upload_status(&state, user, id.into_inner()).await
+                    },
+                ),
+            )
+            .route(
+                "/api/crates/batch",
+                web::post().to(
+                    |state: web::Data<AppState>,
+                     payload: web::Json<handler::BatchCratesRequest>| async move {
+                        handler::get_crates_batch(&state, payload.into_inner()).await
+                    },
+                ),
             )
             .route(
                 "/api/search",
-                web::post().to(|payload: web::Json<Query>| async move {
-                    let query = payload.into_inner();
-                    handler::query_crates(query).await
-                }),
+                web::post().to(
+                    |state: web::Data<AppState>, payload: web::Json<Query>| async move {
+                        let query = payload.into_inner();
+                        handler::query_crates(&state, query).await
+                    },
+                ),
             )
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependencies",
                 web::get().to(
-                    |path: web::Path<(String, String, String, String)>| async move {
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>,
+                     query: web::Query<ListingQuery>| async move {
                         let (nsfront, nsbehind, cratename, version) = path.into_inner();
-                        handler::dependency_redis_cache(cratename, version, nsfront, nsbehind).await
+                        handler::dependency_redis_cache(
+                            &state,
+                            cratename,
+                            version,
+                            nsfront,
+                            nsbehind,
+                            query.into_inner(),
+                        )
+                        .await
                     },
                 ),
             )
             /* .route("/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependencycache",
-            web::get().to(|path: web::Path<(String, String,String,String)>|async move{
+            web::get().to(|state: web::Data<AppState>,
+                     path: web::Path<(String, String,String,String)>|async move{
                 let (nsfront,nsbehind,cratename, version) = path.into_inner();
-                handler::new_get_dependency(cratename,version,nsfront,nsbehind).await
+                handler::new_get_dependency(&state, cratename,version,nsfront,nsbehind).await
             }))*/
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependencies/graph",
@@ -257,24 +441,36 @@ pub async fn run_api_server() -> std::io::Result<()> {
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependents",
                 web::get().to(
-                    |path: web::Path<(String, String, String, String)>| async move {
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>,
+                     query: web::Query<ListingQuery>| async move {
                         let (nsfront, nsbehind, cratename, version) = path.into_inner();
-                        handler::dependent_redis_cache(cratename, version, nsfront, nsbehind).await
+                        handler::dependent_redis_cache(
+                            &state,
+                            cratename,
+                            version,
+                            nsfront,
+                            nsbehind,
+                            query.into_inner(),
+                        )
+                        .await
                     },
                 ),
             )
             /* .route("/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependentcache",
-            web::get().to(|path: web::Path<(String, String,String,String)>|async move{
+            web::get().to(|state: web::Data<AppState>,
+                     path: web::Path<(String, String,String,String)>|async move{
                 let (nsfront,nsbehind,cratename, version) = path.into_inner();
-                handler::new_get_dependent(cratename,version,nsfront,nsbehind).await
+                handler::new_get_dependent(&state, cratename,version,nsfront,nsbehind).await
             }))*/
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}",
                 web::get().to(
-                    |path: web::Path<(String, String, String, String)>| async move {
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>| async move {
                         let (nsfront, nsbehind, cratename, version) = path.into_inner();
                         handler::new_get_crates_front_info_from_redis(
-                            cratename, version, nsfront, nsbehind,
+                            &state, cratename, version, nsfront, nsbehind,
                         )
                         .await
                     },
@@ -283,25 +479,102 @@ pub async fn run_api_server() -> std::io::Result<()> {
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/senseleak",
                 web::get().to(
-                    |path: web::Path<(String, String, String, String)>| async move {
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>| async move {
                         let (nsfront, nsbehind, _cratename, _versionn) = path.into_inner();
-                        handler::get_senseleak(nsfront, nsbehind).await
+                        handler::get_senseleak(&state, nsfront, nsbehind).await
+                    },
+                ),
+            )
+            .route(
+                "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/vulnerabilities",
+                web::get().to(
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>| async move {
+                        let (nsfront, nsbehind, cratename, version) = path.into_inner();
+                        handler::get_vulnerabilities(&state, nsfront, nsbehind, cratename, version)
+                            .await
+                    },
+                ),
+            )
+            .route(
+                "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/dependencies/features",
+                web::get().to(
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>,
+                     query: web::Query<FeatureQuery>| async move {
+                        let (nsfront, nsbehind, cratename, version) = path.into_inner();
+                        let query = query.into_inner();
+                        handler::get_feature_dependencies(
+                            &state,
+                            nsfront,
+                            nsbehind,
+                            cratename,
+                            version,
+                            query.features,
+                            query.default_features,
+                        )
+                        .await
+                    },
+                ),
+            )
+            .route(
+                "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/sbom/{format}",
+                web::get().to(
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String, String)>| async move {
+                        let (nsfront, nsbehind, cratename, version, format) = path.into_inner();
+                        handler::get_sbom(&state, nsfront, nsbehind, cratename, version, format)
+                            .await
+                    },
+                ),
+            )
+            .route(
+                "/api/crates/{nsfront}/{nsbehind}/{cratename}/compare/{from}/{to}",
+                web::get().to(
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String, String)>| async move {
+                        let (nsfront, nsbehind, cratename, from, to) = path.into_inner();
+                        handler::compare_versions(&state, nsfront, nsbehind, cratename, from, to)
+                            .await
+                    },
+                ),
+            )
+            .route(
+                "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/impact",
+                web::get().to(
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>,
+                     query: web::Query<ImpactQuery>| async move {
+                        let (nsfront, nsbehind, cratename, version) = path.into_inner();
+                        handler::get_release_impact(
+                            &state,
+                            nsfront,
+                            nsbehind,
+                            cratename,
+                            version,
+                            query.into_inner().kind,
+                        )
+                        .await
                     },
                 ),
             )
             .route(
                 "/api/graph/{cratename}/{version}/direct",
-                web::get().to(|path: web::Path<(String, String)>| async move {
-                    let (cratename, version) = path.into_inner();
-                    handler::get_direct_dep_for_graph(cratename, version).await
-                }),
+                web::get().to(
+                    |state: web::Data<AppState>, path: web::Path<(String, String)>| async move {
+                        let (cratename, version) = path.into_inner();
+                        handler::get_direct_dep_for_graph(&state, cratename, version).await
+                    },
+                ),
             )
             .route(
                 "/api/crates/{nsfront}/{nsbehind}/{cratename}/{version}/mirchecker",
                 web::get().to(
-                    |path: web::Path<(String, String, String, String)>| async move {
+                    |state: web::Data<AppState>,
+                     path: web::Path<(String, String, String, String)>| async move {
                         let (nsfront, nsbehind, cratename, version) = path.into_inner();
-                        handler::get_mirchecker(nsfront, nsbehind, cratename, version).await
+                        handler::get_mirchecker(&state, nsfront, nsbehind, cratename, version).await
                     },
                 ),
             )
@@ -0,0 +1,322 @@
+//! Paging, sorting and filtering of the dependency and dependent listings.
+//!
+//! `sort=name&relation=direct` is paged by TuGraph: a page is read with
+//! `ORDER BY name SKIP/LIMIT` and the total with a `count` query, so a page
+//! of a popular crate never reads its whole listing. TuGraph compares the
+//! versions of one crate as strings, they are put back in semver order
+//! within the page. Those pages carry no indirect count.
+//!
+//! Every other sort and relation is only paged in the response. On a cache
+//! miss the whole listing of a version, with the whole dependency closure
+//! for its indirect entries, is read into an index of `name/version/relation`
+//! entries and cached on its own. Every page of that version is then served
+//! from the cached index: a request filters it by relation, sorts it and
+//! only computes the details of the requested page, which is cached under a
+//! key of its parameters. Those sorts are not pushed down since TuGraph
+//! cannot evaluate them: versions are compared as semver, and downloads come
+//! from Postgres. Indirect entries need the closure walked in full anyway.
+
+use std::{cmp::Ordering, collections::HashMap, fmt, str::FromStr};
+
+use semver::Version;
+use serde::{Deserialize, Serialize};
+
+use crate::error::ApiError;
+
+pub const DEFAULT_PER_PAGE: usize = 50;
+pub const MAX_PER_PAGE: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Relation {
+    Direct,
+    Indirect,
+}
+
+impl fmt::Display for Relation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Relation::Direct => write!(f, "Direct"),
+            Relation::Indirect => write!(f, "Indirect"),
+        }
+    }
+}
+
+impl FromStr for Relation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "direct" => Ok(Relation::Direct),
+            "indirect" => Ok(Relation::Indirect),
+            _ => Err(format!(
+                "unknown relation '{}', expected direct or indirect",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ListingSort {
+    /// By crate name, newest version first.
+    #[default]
+    Name,
+    /// Most downloaded crates first.
+    Downloads,
+    /// Newest version first.
+    Version,
+}
+
+impl fmt::Display for ListingSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListingSort::Name => write!(f, "name"),
+            ListingSort::Downloads => write!(f, "downloads"),
+            ListingSort::Version => write!(f, "version"),
+        }
+    }
+}
+
+impl FromStr for ListingSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "name" => Ok(ListingSort::Name),
+            "downloads" => Ok(ListingSort::Downloads),
+            "version" => Ok(ListingSort::Version),
+            _ => Err(format!(
+                "unknown sort '{}', expected name, downloads or version",
+                s
+            )),
+        }
+    }
+}
+
+/// One crate of a listing index.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListingEntry {
+    pub name: String,
+    pub version: String,
+    pub relation: Relation,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingParams {
+    /// 1-based.
+    pub page: usize,
+    pub per_page: usize,
+    pub sort: ListingSort,
+    /// Both relations when `None`.
+    pub relation: Option<Relation>,
+}
+
+impl ListingParams {
+    pub fn parse(
+        page: Option<usize>,
+        per_page: Option<usize>,
+        sort: Option<&str>,
+        relation: Option<&str>,
+    ) -> Result<Self, ApiError> {
+        let page = page.unwrap_or(1);
+        let per_page = per_page.unwrap_or(DEFAULT_PER_PAGE);
+        if page == 0 {
+            return Err(ApiError::bad_request("'page' starts at 1"));
+        }
+        if per_page == 0 || per_page > MAX_PER_PAGE {
+            return Err(ApiError::bad_request(format!(
+                "'per_page' must be between 1 and {}",
+                MAX_PER_PAGE
+            )));
+        }
+        Ok(ListingParams {
+            page,
+            per_page,
+            sort: sort
+                .map(str::parse)
+                .transpose()
+                .map_err(ApiError::bad_request)?
+                .unwrap_or_default(),
+            relation: relation
+                .map(str::parse)
+                .transpose()
+                .map_err(ApiError::bad_request)?,
+        })
+    }
+
+    /// Suffix of the cache key of one page.
+    pub fn key_suffix(&self) -> String {
+        let relation = match self.relation {
+            Some(Relation::Direct) => "direct",
+            Some(Relation::Indirect) => "indirect",
+            None => "all",
+        };
+        format!(
+            ":{}:{}:p{}x{}",
+            relation, self.sort, self.page, self.per_page
+        )
+    }
+
+    /// Whether the page is read from TuGraph with `SKIP`/`LIMIT` rather
+    /// than cut from the cached index.
+    pub fn pages_in_cypher(&self) -> bool {
+        self.sort == ListingSort::Name && self.relation == Some(Relation::Direct)
+    }
+
+    /// The number of entries before the requested page.
+    pub fn skip(&self) -> usize {
+        (self.page - 1).saturating_mul(self.per_page)
+    }
+
+    /// The entries of the requested page, `entries` already filtered and sorted.
+    pub fn window<'a, T>(&self, entries: &'a [T]) -> &'a [T] {
+        let start = self.skip();
+        if start >= entries.len() {
+            return &[];
+        }
+        let end = start.saturating_add(self.per_page).min(entries.len());
+        &entries[start..end]
+    }
+}
+
+fn cmp_versions_desc(a: &str, b: &str) -> Ordering {
+    match (Version::parse(a), Version::parse(b)) {
+        (Ok(v_a), Ok(v_b)) => v_b.cmp(&v_a),
+        (Ok(_), Err(_)) => Ordering::Less, // 无法解析的版本号排在后面
+        (Err(_), Ok(_)) => Ordering::Greater,
+        (Err(_), Err(_)) => a.cmp(b),
+    }
+}
+
+/// Sorts `entries`, `downloads` is only read for [`ListingSort::Downloads`],
+/// a crate missing from it counts as never downloaded.
+pub fn sort_entries(
+    entries: &mut [ListingEntry],
+    sort: ListingSort,
+    downloads: &HashMap<String, i64>,
+) {
+    entries.sort_by(|a, b| {
+        let by_name = || {
+            a.name
+                .cmp(&b.name)
+                .then_with(|| cmp_versions_desc(&a.version, &b.version))
+        };
+        match sort {
+            ListingSort::Name => by_name(),
+            ListingSort::Version => {
+                cmp_versions_desc(&a.version, &b.version).then_with(|| a.name.cmp(&b.name))
+            }
+            ListingSort::Downloads => {
+                let d_a = downloads.get(&a.name).copied().unwrap_or(0);
+                let d_b = downloads.get(&b.name).copied().unwrap_or(0);
+                d_b.cmp(&d_a).then_with(by_name)
+            }
+        }
+    });
+}
+
+/// The entries of `relation`, all of them when `None`.
+pub fn filter_entries(entries: &[ListingEntry], relation: Option<Relation>) -> Vec<ListingEntry> {
+    entries
+        .iter()
+        .filter(|entry| relation.is_none_or(|r| entry.relation == r))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{filter_entries, sort_entries, ListingEntry, ListingParams, ListingSort, Relation};
+
+    fn entry(name: &str, version: &str, relation: Relation) -> ListingEntry {
+        ListingEntry {
+            name: name.to_string(),
+            version: version.to_string(),
+            relation,
+        }
+    }
+
+    fn names(entries: &[ListingEntry]) -> Vec<String> {
+        entries
+            .iter()
+            .map(|e| format!("{}/{}", e.name, e.version))
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_params() {
+        let params = ListingParams::parse(None, None, None, None).unwrap();
+        assert_eq!((params.page, params.per_page), (1, 50));
+        assert_eq!(params.key_suffix(), ":all:name:p1x50");
+        let params =
+            ListingParams::parse(Some(3), Some(10), Some("Downloads"), Some("indirect")).unwrap();
+        assert_eq!(params.key_suffix(), ":indirect:downloads:p3x10");
+        assert!(ListingParams::parse(Some(0), None, None, None).is_err());
+        assert!(ListingParams::parse(None, Some(1000), None, None).is_err());
+        assert!(ListingParams::parse(None, None, Some("stars"), None).is_err());
+        assert!(ListingParams::parse(None, None, None, Some("dev")).is_err());
+    }
+
+    #[test]
+    fn test_sort_and_window() {
+        let mut entries = vec![
+            entry("tokio", "1.0.0", Relation::Direct),
+            entry("serde", "1.0.100", Relation::Indirect),
+            entry("serde", "1.0.200", Relation::Direct),
+            entry("anyhow", "1.0.50", Relation::Indirect),
+        ];
+        let downloads = HashMap::from([("serde".to_string(), 100), ("tokio".to_string(), 50)]);
+
+        sort_entries(&mut entries, ListingSort::Name, &downloads);
+        assert_eq!(
+            names(&entries),
+            [
+                "anyhow/1.0.50",
+                "serde/1.0.200",
+                "serde/1.0.100",
+                "tokio/1.0.0"
+            ]
+        );
+        sort_entries(&mut entries, ListingSort::Version, &downloads);
+        assert_eq!(
+            names(&entries),
+            [
+                "serde/1.0.200",
+                "serde/1.0.100",
+                "anyhow/1.0.50",
+                "tokio/1.0.0"
+            ]
+        );
+        sort_entries(&mut entries, ListingSort::Downloads, &downloads);
+        assert_eq!(
+            names(&entries),
+            [
+                "serde/1.0.200",
+                "serde/1.0.100",
+                "tokio/1.0.0",
+                "anyhow/1.0.50"
+            ]
+        );
+
+        let direct = filter_entries(&entries, Some(Relation::Direct));
+        assert_eq!(names(&direct), ["serde/1.0.200", "tokio/1.0.0"]);
+
+        let params = ListingParams::parse(Some(2), Some(3), None, None).unwrap();
+        assert_eq!(names(params.window(&entries)), ["anyhow/1.0.50"]);
+        let params = ListingParams::parse(Some(3), Some(3), None, None).unwrap();
+        assert!(params.window(&entries).is_empty());
+    }
+
+    #[test]
+    fn test_pages_in_cypher() {
+        let params = ListingParams::parse(Some(3), Some(20), None, Some("direct")).unwrap();
+        assert!(params.pages_in_cypher());
+        assert_eq!(params.skip(), 40);
+        let params = ListingParams::parse(None, None, Some("name"), None).unwrap();
+        assert!(!params.pages_in_cypher());
+        let params = ListingParams::parse(None, None, Some("version"), Some("direct")).unwrap();
+        assert!(!params.pages_in_cypher());
+    }
+}
@@ -1,37 +1,177 @@
+use std::collections::HashMap;
 use std::env;
+use std::time::Duration;
 
-use redis::{Commands, Connection};
+use model::general_model::DependencyKind;
+use redis::{aio::ConnectionManager, AsyncCommands, Client, RedisError};
+use tokio::sync::OnceCell;
+
+use crate::state::parse_var;
 
 pub struct RedisHandler {
-    pub connection: Connection,
+    pub connection: ConnectionManager,
+    pub ttls: CacheTtls,
+}
+
+/// The families of cached API responses, each key is
+/// `{prefix}:{namespace}:{name}[:{version}][:{suffix}]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CacheFamily {
+    CratesInfo,
+    Dependency,
+    Dependent,
+    DependencyGraph,
+    VersionPage,
+    Vulnerabilities,
+}
+
+impl CacheFamily {
+    pub const ALL: [CacheFamily; 6] = [
+        CacheFamily::CratesInfo,
+        CacheFamily::Dependency,
+        CacheFamily::Dependent,
+        CacheFamily::DependencyGraph,
+        CacheFamily::VersionPage,
+        CacheFamily::Vulnerabilities,
+    ];
+
+    pub fn prefix(&self) -> &'static str {
+        match self {
+            CacheFamily::CratesInfo => "crates_info",
+            CacheFamily::Dependency => "dependency",
+            CacheFamily::Dependent => "dependent",
+            CacheFamily::DependencyGraph => "dependencygraph",
+            CacheFamily::VersionPage => "versionpage",
+            CacheFamily::Vulnerabilities => "vulnerabilities",
+        }
+    }
+
+    /// The variable overriding the TTL of the family, e.g. `REDIS_TTL_DEPENDENCY_SECS`.
+    fn ttl_var(&self) -> String {
+        format!("REDIS_TTL_{}_SECS", self.prefix().to_uppercase())
+    }
+}
+
+/// Seconds a cached response of each family lives, one week unless
+/// overridden by `REDIS_TTL_{FAMILY}_SECS`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheTtls {
+    ttls: HashMap<CacheFamily, u64>,
+}
+
+/// Keys asked for by each SCAN of `delete_where`.
+const SCAN_BATCH: usize = 1000;
+
+const DEFAULT_TTL_SECS: u64 = 7 * 24 * 60 * 60; // 一周的秒数
+
+impl CacheTtls {
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let ttls = CacheFamily::ALL
+            .into_iter()
+            .map(|family| {
+                let key = family.ttl_var();
+                let ttl = match parse_var(&var, &key, DEFAULT_TTL_SECS) {
+                    0 => {
+                        tracing::warn!("invalid {}: 0, using {}", key, DEFAULT_TTL_SECS);
+                        DEFAULT_TTL_SECS
+                    }
+                    ttl => ttl,
+                };
+                (family, ttl)
+            })
+            .collect();
+        CacheTtls { ttls }
+    }
+
+    pub fn get(&self, family: CacheFamily) -> u64 {
+        self.ttls.get(&family).copied().unwrap_or(DEFAULT_TTL_SECS)
+    }
+
+    /// The TTL of the family kept the longest.
+    pub fn longest(&self) -> u64 {
+        CacheFamily::ALL
+            .into_iter()
+            .map(|family| self.get(family))
+            .max()
+            .unwrap_or(DEFAULT_TTL_SECS)
+    }
 }
 
-pub async fn get_redis_connection() -> Result<Connection, Box<dyn std::error::Error>> {
-    let host = env::var("REDIS_HOST").unwrap_or_else(|_| "172.17.0.1".to_string());
-    let password = env::var("REDIS_PASSWORD").unwrap_or_else(|_| "".to_string());
-    println!("host:{}", host);
-    // 构建连接字符串
-    let conn_string = format!("redis://:{}@{}:6379/", password, host);
-    println!("conn_string:{}", conn_string);
-    println!("尝试连接 Redis: {}", conn_string);
-
-    // 创建客户端
-    let client = redis::Client::open(&*conn_string).unwrap();
-    println!("finish get client");
-    // 获取连接
-    let conn = client.get_connection().unwrap();
-    Ok(conn)
+/// The multiplexed Redis connection shared by every request. It is opened,
+/// in at most `timeout`, by the first request using Redis, and reopened by
+/// `ConnectionManager` when it breaks.
+pub struct RedisConnector {
+    client: Client,
+    timeout: Duration,
+    connection: OnceCell<ConnectionManager>,
+}
+
+impl RedisConnector {
+    pub fn from_env(timeout: Duration) -> Result<Self, RedisError> {
+        let host = env::var("REDIS_HOST").unwrap_or_else(|_| "172.17.0.1".to_string());
+        let password = env::var("REDIS_PASSWORD").unwrap_or_else(|_| "".to_string());
+        println!("host:{}", host);
+        // 构建连接字符串
+        let conn_string = format!("redis://:{}@{}:6379/", password, host);
+        Ok(RedisConnector {
+            client: Client::open(&*conn_string)?,
+            timeout,
+            connection: OnceCell::new(),
+        })
+    }
+
+    pub async fn connect(&self) -> Result<ConnectionManager, RedisError> {
+        self.connection
+            .get_or_try_init(|| async {
+                tokio::time::timeout(self.timeout, ConnectionManager::new(self.client.clone()))
+                    .await
+                    .unwrap_or_else(|_| {
+                        Err(RedisError::from((
+                            redis::ErrorKind::IoError,
+                            "timed out connecting to Redis",
+                        )))
+                    })
+            })
+            .await
+            .cloned()
+    }
+}
+
+/// Listings filtered by dependency kind are cached under their own key.
+pub fn kind_key_suffix(kind: Option<DependencyKind>) -> String {
+    match kind {
+        Some(kind) => format!(":{}", kind),
+        None => "".to_string(),
+    }
+}
+
+/// Suffix of the dependency graph key for a `max_depth`, empty for the whole closure.
+pub fn depth_key_suffix(max_depth: Option<usize>) -> String {
+    match max_depth {
+        Some(depth) => format!(":depth{}", depth),
+        None => "".to_string(),
+    }
 }
 
 impl RedisHandler {
-    pub fn get_connection_mut(&mut self) -> &mut Connection {
+    pub async fn connect(connector: &RedisConnector, ttls: CacheTtls) -> Result<Self, RedisError> {
+        Ok(RedisHandler {
+            connection: connector.connect().await?,
+            ttls,
+        })
+    }
+    pub fn get_connection_mut(&mut self) -> &mut ConnectionManager {
         &mut self.connection
     }
     pub async fn query_from_redis(
         &mut self,
         qid: String,
     ) -> Result<String, Box<dyn std::error::Error>> {
-        match self.get_connection_mut().get::<_, String>(&qid) {
+        match self.get_connection_mut().get::<_, String>(&qid).await {
             Ok(value) => Ok(value),
             Err(e) => {
                 if e.kind() == redis::ErrorKind::TypeError {
@@ -43,6 +183,68 @@ impl RedisHandler {
         }
     }
 
+    /// The values of `keys` in one round trip, `None` for a missing key.
+    pub async fn query_many_from_redis(
+        &mut self,
+        keys: &[String],
+    ) -> Result<Vec<Option<String>>, redis::RedisError> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        // `AsyncCommands::get` sends GET for a single key, keep the reply a list
+        redis::cmd("MGET")
+            .arg(keys)
+            .query_async(&mut self.connection)
+            .await
+    }
+
+    /// Sets `key` with the TTL of its family.
+    pub async fn set_with_ttl(
+        &mut self,
+        family: CacheFamily,
+        key: &str,
+        value: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // 使用管道设置值和过期时间
+        let _: () = redis::pipe()
+            .cmd("SET")
+            .arg(key)
+            .arg(value)
+            .cmd("EXPIRE")
+            .arg(key)
+            .arg(self.ttls.get(family))
+            .query_async(&mut self.connection)
+            .await?;
+        Ok(())
+    }
+
+    /// Deletes every key `matches` accepts in one pass over the keyspace,
+    /// returns how many. Keys are deleted a SCAN batch at a time.
+    pub async fn delete_where(
+        &mut self,
+        matches: impl Fn(&str) -> bool,
+    ) -> Result<usize, redis::RedisError> {
+        let mut deleted = 0;
+        let mut cursor: u64 = 0;
+        loop {
+            let (next, keys): (u64, Vec<String>) = redis::cmd("SCAN")
+                .cursor_arg(cursor)
+                .arg("COUNT")
+                .arg(SCAN_BATCH)
+                .query_async(&mut self.connection)
+                .await?;
+            let keys: Vec<String> = keys.into_iter().filter(|key| matches(key)).collect();
+            if !keys.is_empty() {
+                let _: () = self.connection.del(&keys).await?;
+                deleted += keys.len();
+            }
+            if next == 0 {
+                return Ok(deleted);
+            }
+            cursor = next;
+        }
+    }
+
     pub async fn insert_crates_info_into_redis(
         &mut self,
         namespace: String,
@@ -52,38 +254,24 @@ impl RedisHandler {
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("start insert crates_info");
         let key = format!("crates_info:{}:{}:{}", namespace, name, version);
-
-        // 使用管道设置值和过期时间
-        let _: () = redis::pipe()
-            .cmd("SET")
-            .arg(&key)
-            .arg(&value)
-            .cmd("EXPIRE")
-            .arg(&key)
-            .arg(7 * 24 * 60 * 60) // 一周的秒数
-            .query(&mut self.connection)?;
+        self.set_with_ttl(CacheFamily::CratesInfo, &key, &value)
+            .await?;
         println!("finish insert crates_info");
         Ok(())
     }
+    /// `suffix` tells the kind and page of the listing apart.
     pub async fn insert_dependency_into_redis(
         &mut self,
         namespace: String,
         name: String,
         version: String,
+        suffix: String,
         value: String,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("start insert dependency");
-        let key = format!("dependency:{}:{}:{}", namespace, name, version);
-
-        // 使用管道设置值和过期时间
-        let _: () = redis::pipe()
-            .cmd("SET")
-            .arg(&key)
-            .arg(&value)
-            .cmd("EXPIRE")
-            .arg(&key)
-            .arg(7 * 24 * 60 * 60) // 一周的秒数
-            .query(&mut self.connection)?;
+        let key = format!("dependency:{}:{}:{}{}", namespace, name, version, suffix);
+        self.set_with_ttl(CacheFamily::Dependency, &key, &value)
+            .await?;
         println!("finish insert dependency");
         Ok(())
     }
@@ -92,42 +280,35 @@ impl RedisHandler {
         namespace: String,
         name: String,
         version: String,
+        max_depth: Option<usize>,
         value: String,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("start insert dependencygraph");
-        let key = format!("dependencygraph:{}:{}:{}", namespace, name, version);
-
-        // 使用管道设置值和过期时间
-        let _: () = redis::pipe()
-            .cmd("SET")
-            .arg(&key)
-            .arg(&value)
-            .cmd("EXPIRE")
-            .arg(&key)
-            .arg(7 * 24 * 60 * 60) // 一周的秒数
-            .query(&mut self.connection)?;
+        let key = format!(
+            "dependencygraph:{}:{}:{}{}",
+            namespace,
+            name,
+            version,
+            depth_key_suffix(max_depth)
+        );
+        self.set_with_ttl(CacheFamily::DependencyGraph, &key, &value)
+            .await?;
         println!("finish insert dependencygraph");
         Ok(())
     }
+    /// `suffix` tells the kind and page of the listing apart.
     pub async fn insert_dependent_into_redis(
         &mut self,
         namespace: String,
         name: String,
         version: String,
+        suffix: String,
         value: String,
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("start insert dependent");
-        let key = format!("dependent:{}:{}:{}", namespace, name, version);
-
-        // 使用管道设置值和过期时间
-        let _: () = redis::pipe()
-            .cmd("SET")
-            .arg(&key)
-            .arg(&value)
-            .cmd("EXPIRE")
-            .arg(&key)
-            .arg(7 * 24 * 60 * 60) // 一周的秒数
-            .query(&mut self.connection)?;
+        let key = format!("dependent:{}:{}:{}{}", namespace, name, version, suffix);
+        self.set_with_ttl(CacheFamily::Dependent, &key, &value)
+            .await?;
         println!("finish insert dependent");
         Ok(())
     }
@@ -139,17 +320,44 @@ impl RedisHandler {
     ) -> Result<(), Box<dyn std::error::Error>> {
         println!("start insert versionpage");
         let key = format!("versionpage:{}:{}", namespace, name);
-
-        // 使用管道设置值和过期时间
-        let _: () = redis::pipe()
-            .cmd("SET")
-            .arg(&key)
-            .arg(&value)
-            .cmd("EXPIRE")
-            .arg(&key)
-            .arg(7 * 24 * 60 * 60) // 一周的秒数
-            .query(&mut self.connection)?;
+        self.set_with_ttl(CacheFamily::VersionPage, &key, &value)
+            .await?;
         println!("finish insert versionpage");
         Ok(())
     }
+    pub async fn insert_vulnerabilities_into_redis(
+        &mut self,
+        namespace: String,
+        name: String,
+        version: String,
+        value: String,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        println!("start insert vulnerabilities");
+        let key = format!("vulnerabilities:{}:{}:{}", namespace, name, version);
+        self.set_with_ttl(CacheFamily::Vulnerabilities, &key, &value)
+            .await?;
+        println!("finish insert vulnerabilities");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{CacheFamily, CacheTtls, DEFAULT_TTL_SECS};
+
+    #[test]
+    fn test_ttls_per_family() {
+        let vars = HashMap::from([
+            ("REDIS_TTL_DEPENDENT_SECS", "3600"),
+            ("REDIS_TTL_VERSIONPAGE_SECS", "0"),
+            ("REDIS_TTL_DEPENDENCYGRAPH_SECS", "soon"),
+        ]);
+        let ttls = CacheTtls::from_vars(|key| vars.get(key).map(|v| v.to_string()));
+        assert_eq!(ttls.get(CacheFamily::Dependent), 3600);
+        assert_eq!(ttls.get(CacheFamily::VersionPage), DEFAULT_TTL_SECS);
+        assert_eq!(ttls.get(CacheFamily::DependencyGraph), DEFAULT_TTL_SECS);
+        assert_eq!(ttls.get(CacheFamily::CratesInfo), DEFAULT_TTL_SECS);
+    }
 }
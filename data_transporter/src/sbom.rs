@@ -0,0 +1,432 @@
+//! CycloneDX 1.5 and SPDX 2.3 documents of a dependency closure.
+//!
+//! The components are the closure of the normal and build dependencies of a
+//! version, as walked by `new_get_all_dependencies`. Each one carries its
+//! `pkg:cargo` package URL, the license of its program, the GitHub URL of
+//! the program and the RustSec advisories affecting it. Only the edges of
+//! the root are listed, the closure does not keep the others.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+};
+
+use model::general_model::DependencyKind;
+use serde_json::{json, Value};
+
+use crate::{
+    data_reader::{DataReader, DataReaderTrait},
+    db::DBHandler,
+    handler::NewRustsec,
+    NameVersion,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SbomFormat {
+    CycloneDx,
+    Spdx,
+}
+
+impl std::str::FromStr for SbomFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cyclonedx" => Ok(SbomFormat::CycloneDx),
+            "spdx" => Ok(SbomFormat::Spdx),
+            _ => Err(format!(
+                "unknown SBOM format '{}', expected cyclonedx or spdx",
+                s
+            )),
+        }
+    }
+}
+
+impl SbomFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            SbomFormat::CycloneDx => "application/vnd.cyclonedx+json",
+            SbomFormat::Spdx => "application/spdx+json",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SbomPackage {
+    pub name: String,
+    pub version: String,
+    /// An SPDX license expression, `None` if the program has none.
+    pub license: Option<String>,
+    pub repository: Option<String>,
+    pub advisories: Vec<NewRustsec>,
+}
+
+impl SbomPackage {
+    pub fn purl(&self) -> String {
+        format!("pkg:cargo/{}@{}", self.name, self.version)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sbom {
+    pub root: SbomPackage,
+    /// Sorted by name and version.
+    pub dependencies: Vec<SbomPackage>,
+    /// Indexes into `dependencies` of the direct dependencies of the root.
+    pub direct: Vec<usize>,
+}
+
+/// Crates still use the `/` separator of the old Cargo manifests,
+/// e.g. `MIT/Apache-2.0`.
+fn license_expression(license: &str) -> Option<String> {
+    let license = license.trim();
+    if license.is_empty() || license == "None" {
+        return None;
+    }
+    Some(
+        license
+            .split('/')
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" OR "),
+    )
+}
+
+pub async fn build_sbom(
+    reader: &DataReader,
+    dbhandler: &DBHandler,
+    namespace: &str,
+    name: &str,
+    version: &str,
+) -> Result<Sbom, Box<dyn Error>> {
+    let root = format!("{}/{}", name, version);
+    let mut direct_nodes = vec![];
+    for kind in [DependencyKind::Normal, DependencyKind::Build] {
+        direct_nodes.extend(
+            reader
+                .new_get_direct_dependency_nodes(namespace, &root, Some(kind))
+                .await?,
+        );
+    }
+    let direct: Vec<String> = direct_nodes
+        .iter()
+        .map(|node| format!("{}/{}", node.name, node.version))
+        .collect();
+    let mut closure: Vec<NameVersion> = reader
+        .new_get_all_dependencies(direct_nodes)
+        .await?
+        .iter()
+        .filter(|node| **node != root)
+        .filter_map(|node| NameVersion::from_string(node))
+        .collect();
+    closure.sort_by(|a, b| (&a.name, &a.version).cmp(&(&b.name, &b.version)));
+
+    // 依赖只知道名字，取其第一个命名空间
+    let mut names: Vec<String> = closure.iter().map(|node| node.name.clone()).collect();
+    names.dedup();
+    let found = reader.get_namespaces_of_crates(&names).await?;
+    let mut programs: BTreeMap<String, String> = names
+        .iter()
+        .filter_map(|n| {
+            let namespace = found.get(n)?.first()?;
+            Some((n.clone(), namespace.clone()))
+        })
+        .collect();
+    programs.insert(name.to_string(), namespace.to_string());
+    let programs: Vec<(String, String)> = programs
+        .into_iter()
+        .map(|(name, namespace)| (namespace, name))
+        .collect();
+    let licenses = dbhandler.get_licenses_of_programs(&programs).await?;
+    let repositories = reader.get_github_urls_of_crates(&programs).await?;
+    let program_of: HashMap<&str, &(String, String)> = programs
+        .iter()
+        .map(|program| (program.1.as_str(), program))
+        .collect();
+
+    let package = |node: &NameVersion| {
+        let program = program_of.get(node.name.as_str());
+        SbomPackage {
+            name: node.name.clone(),
+            version: node.version.clone(),
+            license: program
+                .and_then(|p| licenses.get(*p))
+                .and_then(|l| license_expression(l)),
+            repository: program
+                .and_then(|p| repositories.get(*p))
+                .filter(|url| !url.is_empty() && *url != "None")
+                .cloned(),
+            advisories: vec![],
+        }
+    };
+    let mut root = package(&NameVersion {
+        name: name.to_string(),
+        version: version.to_string(),
+    });
+    let mut dependencies: Vec<SbomPackage> = closure.iter().map(package).collect();
+    let packages: Vec<String> = std::iter::once(&root)
+        .chain(&dependencies)
+        .map(|package| format!("{}/{}", package.name, package.version))
+        .collect();
+    let mut advisories = dbhandler.get_rustsec_batch(&packages).await?;
+    for (package, key) in std::iter::once(&mut root)
+        .chain(dependencies.iter_mut())
+        .zip(&packages)
+    {
+        package.advisories = advisories.remove(key).unwrap_or_default();
+    }
+    let direct = closure
+        .iter()
+        .enumerate()
+        .filter(|(_, node)| direct.contains(&format!("{}/{}", node.name, node.version)))
+        .map(|(i, _)| i)
+        .collect();
+    Ok(Sbom {
+        root,
+        dependencies,
+        direct,
+    })
+}
+
+fn advisory_url(id: &str) -> String {
+    format!("https://rustsec.org/advisories/{}.html", id)
+}
+
+fn cyclonedx_component(package: &SbomPackage) -> Value {
+    let mut component = json!({
+        "type": "library",
+        "bom-ref": package.purl(),
+        "name": package.name,
+        "version": package.version,
+        "purl": package.purl(),
+    });
+    if let Some(license) = &package.license {
+        component["licenses"] = json!([{ "expression": license }]);
+    }
+    if let Some(repository) = &package.repository {
+        component["externalReferences"] = json!([{ "type": "vcs", "url": repository }]);
+    }
+    component
+}
+
+/// A CycloneDX 1.5 JSON document, `serial` is a UUID and `timestamp` an
+/// RFC 3339 date.
+pub fn to_cyclonedx(sbom: &Sbom, serial: &str, timestamp: &str) -> Value {
+    let packages = std::iter::once(&sbom.root).chain(&sbom.dependencies);
+    // 同一公告影响多个包时合并为一条
+    let mut vulnerabilities: BTreeMap<&str, (&NewRustsec, Vec<String>)> = BTreeMap::new();
+    for package in packages {
+        for advisory in &package.advisories {
+            vulnerabilities
+                .entry(advisory.id.as_str())
+                .or_insert_with(|| (advisory, vec![]))
+                .1
+                .push(package.purl());
+        }
+    }
+    json!({
+        "bomFormat": "CycloneDX",
+        "specVersion": "1.5",
+        "serialNumber": format!("urn:uuid:{}", serial),
+        "version": 1,
+        "metadata": {
+            "timestamp": timestamp,
+            "tools": { "components": [{ "type": "application", "name": "crates-pro" }] },
+            "component": cyclonedx_component(&sbom.root),
+        },
+        "components": sbom.dependencies.iter().map(cyclonedx_component).collect::<Vec<_>>(),
+        "dependencies": [{
+            "ref": sbom.root.purl(),
+            "dependsOn": sbom.direct.iter().map(|i| sbom.dependencies[*i].purl()).collect::<Vec<_>>(),
+        }],
+        "vulnerabilities": vulnerabilities.into_iter().map(|(id, (advisory, affected))| json!({
+            "bom-ref": id,
+            "id": id,
+            "source": { "name": "RustSec", "url": advisory_url(id) },
+            "description": advisory.subtitle,
+            "affects": affected.into_iter().map(|purl| json!({ "ref": purl })).collect::<Vec<_>>(),
+        })).collect::<Vec<_>>(),
+    })
+}
+
+/// SPDX identifiers only allow letters, digits, `.` and `-`, the index keeps
+/// `a_b` and `a-b` apart.
+fn spdx_id(index: usize, package: &SbomPackage) -> String {
+    let sanitized: String = format!("{}-{}", package.name, package.version)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '-'
+            }
+        })
+        .collect();
+    format!("SPDXRef-Package-{}-{}", index, sanitized)
+}
+
+fn spdx_package(index: usize, package: &SbomPackage) -> Value {
+    let mut external_refs = vec![json!({
+        "referenceCategory": "PACKAGE-MANAGER",
+        "referenceType": "purl",
+        "referenceLocator": package.purl(),
+    })];
+    for advisory in &package.advisories {
+        external_refs.push(json!({
+            "referenceCategory": "SECURITY",
+            "referenceType": "advisory",
+            "referenceLocator": advisory_url(&advisory.id),
+            "comment": format!("{}: {}", advisory.id, advisory.subtitle),
+        }));
+    }
+    json!({
+        "name": package.name,
+        "SPDXID": spdx_id(index, package),
+        "versionInfo": package.version,
+        "downloadLocation": package.repository.as_deref().unwrap_or("NOASSERTION"),
+        "filesAnalyzed": false,
+        "licenseConcluded": "NOASSERTION",
+        "licenseDeclared": package.license.as_deref().unwrap_or("NOASSERTION"),
+        "copyrightText": "NOASSERTION",
+        "externalRefs": external_refs,
+    })
+}
+
+/// An SPDX 2.3 JSON document, `serial` is a UUID making the document
+/// namespace unique and `timestamp` an RFC 3339 date.
+pub fn to_spdx(sbom: &Sbom, serial: &str, timestamp: &str) -> Value {
+    let root_id = spdx_id(0, &sbom.root);
+    let mut packages = vec![spdx_package(0, &sbom.root)];
+    packages.extend(
+        sbom.dependencies
+            .iter()
+            .enumerate()
+            .map(|(i, package)| spdx_package(i + 1, package)),
+    );
+    let mut relationships = vec![json!({
+        "spdxElementId": "SPDXRef-DOCUMENT",
+        "relationshipType": "DESCRIBES",
+        "relatedSpdxElement": root_id,
+    })];
+    for i in &sbom.direct {
+        relationships.push(json!({
+            "spdxElementId": root_id,
+            "relationshipType": "DEPENDS_ON",
+            "relatedSpdxElement": spdx_id(i + 1, &sbom.dependencies[*i]),
+        }));
+    }
+    let document_name = format!("{}-{}", sbom.root.name, sbom.root.version);
+    json!({
+        "spdxVersion": "SPDX-2.3",
+        "dataLicense": "CC0-1.0",
+        "SPDXID": "SPDXRef-DOCUMENT",
+        "name": document_name,
+        "documentNamespace": format!("https://spdx.org/spdxdocs/{}-{}", document_name, serial),
+        "creationInfo": {
+            "created": timestamp,
+            "creators": ["Tool: crates-pro"],
+        },
+        "packages": packages,
+        "relationships": relationships,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{license_expression, to_cyclonedx, to_spdx, Sbom, SbomPackage};
+    use crate::handler::NewRustsec;
+
+    fn package(name: &str, version: &str, license: Option<&str>) -> SbomPackage {
+        SbomPackage {
+            name: name.to_string(),
+            version: version.to_string(),
+            license: license.map(str::to_string),
+            repository: None,
+            advisories: vec![],
+        }
+    }
+
+    fn sample() -> Sbom {
+        let mut time = package("time", "0.1.43", Some("MIT OR Apache-2.0"));
+        time.advisories.push(NewRustsec {
+            id: "RUSTSEC-2020-0071".to_string(),
+            subtitle: "Potential segfault in the time crate".to_string(),
+            reported: String::new(),
+            issued: String::new(),
+            package: "time".to_string(),
+            ttype: String::new(),
+            keywords: String::new(),
+            aliases: String::new(),
+            reference: String::new(),
+            patched: String::new(),
+            unaffected: String::new(),
+            description: String::new(),
+            url: String::new(),
+        });
+        let mut root = package("app", "1.0.0", Some("MIT"));
+        root.repository = Some("https://github.com/example/app".to_string());
+        Sbom {
+            root,
+            dependencies: vec![package("libc", "0.2.150", None), time],
+            direct: vec![1],
+        }
+    }
+
+    #[test]
+    fn test_license_expression() {
+        assert_eq!(
+            license_expression("MIT/Apache-2.0").as_deref(),
+            Some("MIT OR Apache-2.0")
+        );
+        assert_eq!(license_expression("None"), None);
+        assert_eq!(license_expression(" "), None);
+    }
+
+    #[test]
+    fn test_cyclonedx() {
+        let bom = to_cyclonedx(&sample(), "0000", "2024-01-01T00:00:00Z");
+        assert_eq!(bom["specVersion"], "1.5");
+        assert_eq!(bom["serialNumber"], "urn:uuid:0000");
+        assert_eq!(bom["metadata"]["component"]["purl"], "pkg:cargo/app@1.0.0");
+        assert_eq!(
+            bom["metadata"]["component"]["externalReferences"][0]["url"],
+            "https://github.com/example/app"
+        );
+        assert_eq!(bom["components"].as_array().unwrap().len(), 2);
+        assert!(bom["components"][0].get("licenses").is_none());
+        assert_eq!(
+            bom["components"][1]["licenses"][0]["expression"],
+            "MIT OR Apache-2.0"
+        );
+        assert_eq!(
+            bom["dependencies"][0]["dependsOn"],
+            serde_json::json!(["pkg:cargo/time@0.1.43"])
+        );
+        assert_eq!(bom["vulnerabilities"][0]["id"], "RUSTSEC-2020-0071");
+        assert_eq!(
+            bom["vulnerabilities"][0]["affects"][0]["ref"],
+            "pkg:cargo/time@0.1.43"
+        );
+    }
+
+    #[test]
+    fn test_spdx() {
+        let doc = to_spdx(&sample(), "0000", "2024-01-01T00:00:00Z");
+        assert_eq!(doc["spdxVersion"], "SPDX-2.3");
+        let packages = doc["packages"].as_array().unwrap();
+        assert_eq!(packages.len(), 3);
+        assert_eq!(packages[0]["SPDXID"], "SPDXRef-Package-0-app-1.0.0");
+        assert_eq!(packages[1]["licenseDeclared"], "NOASSERTION");
+        assert_eq!(packages[1]["downloadLocation"], "NOASSERTION");
+        assert_eq!(
+            packages[2]["externalRefs"][1]["referenceCategory"],
+            "SECURITY"
+        );
+        let relationships = doc["relationships"].as_array().unwrap();
+        assert_eq!(relationships[0]["relationshipType"], "DESCRIBES");
+        assert_eq!(
+            relationships[1]["relatedSpdxElement"],
+            "SPDXRef-Package-2-time-0.1.43"
+        );
+    }
+}
@@ -0,0 +1,162 @@
+//! Connections shared by every request of the API server.
+//!
+//! `run_api_server` builds the Postgres pools, the Redis connection and the
+//! TuGraph client once and hands them to the handlers as `web::Data<AppState>`.
+//! A handler borrows a connection for the length of the request, so the
+//! number of database connections stays bounded by the pool sizes however
+//! many requests come in. Redis commands of every request are multiplexed
+//! over a single connection.
+
+use std::{env, error::Error, fmt::Display, str::FromStr, time::Duration};
+
+use deadpool_postgres::Pool;
+
+use crate::{
+    auth::{LoginVerifier, SessionSigner},
+    data_reader::DataReader,
+    db::{
+        build_pg_pool, db_connection_config_from_env, db_cratesio_connection_config_from_env,
+        DBHandler,
+    },
+    redis_store::{CacheTtls, RedisConnector, RedisHandler},
+    upload::{UploadLimits, UploadQueue},
+};
+
+/// Sizes and acquire timeout of the pools, read from the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PoolConfig {
+    /// `API_PG_POOL_SIZE`, per database.
+    pub pg_pool_size: usize,
+    /// `API_TUGRAPH_POOL_SIZE`, bolt connections of the TuGraph client.
+    pub tugraph_pool_size: usize,
+    /// `API_POOL_TIMEOUT_SECS`, how long a request waits for a free connection,
+    /// or for the Redis connection to open.
+    pub acquire_timeout: Duration,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            pg_pool_size: 16,
+            tugraph_pool_size: 64,
+            acquire_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+impl PoolConfig {
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let default = PoolConfig::default();
+        PoolConfig {
+            pg_pool_size: parse_var(&var, "API_PG_POOL_SIZE", default.pg_pool_size),
+            tugraph_pool_size: parse_var(&var, "API_TUGRAPH_POOL_SIZE", default.tugraph_pool_size),
+            acquire_timeout: Duration::from_secs(parse_var(
+                &var,
+                "API_POOL_TIMEOUT_SECS",
+                default.acquire_timeout.as_secs(),
+            )),
+        }
+    }
+}
+
+/// The value of `key`, `default` when it is unset or not a valid `T`.
+pub(crate) fn parse_var<T: FromStr + Display>(
+    var: &impl Fn(&str) -> Option<String>,
+    key: &str,
+    default: T,
+) -> T {
+    match var(key) {
+        Some(value) => value.trim().parse().unwrap_or_else(|_| {
+            tracing::warn!("invalid {}: {}, using {}", key, value, default);
+            default
+        }),
+        None => default,
+    }
+}
+
+pub struct AppState {
+    pub reader: DataReader,
+    pub sessions: SessionSigner,
+    pub logins: LoginVerifier,
+    pub upload_limits: UploadLimits,
+    pub upload_queue: UploadQueue,
+    pg: Pool,
+    cratesio_pg: Pool,
+    redis: RedisConnector,
+    ttls: CacheTtls,
+}
+
+impl AppState {
+    pub async fn new(config: &PoolConfig) -> Result<Self, Box<dyn Error>> {
+        let reader = DataReader::with_max_connections(
+            &env::var("TUGRAPH_BOLT_URL")?,
+            &env::var("TUGRAPH_USER_NAME")?,
+            &env::var("TUGRAPH_USER_PASSWORD")?,
+            &env::var("TUGRAPH_CRATESPRO_DB")?,
+            config.tugraph_pool_size,
+        )
+        .await?;
+        Ok(AppState {
+            reader,
+            sessions: SessionSigner::from_env()?,
+            logins: LoginVerifier::from_env()?,
+            upload_limits: UploadLimits::from_env(),
+            upload_queue: UploadQueue::from_env(),
+            pg: build_pg_pool(
+                &db_connection_config_from_env(),
+                config.pg_pool_size,
+                config.acquire_timeout,
+            )?,
+            cratesio_pg: build_pg_pool(
+                &db_cratesio_connection_config_from_env(),
+                config.pg_pool_size,
+                config.acquire_timeout,
+            )?,
+            redis: RedisConnector::from_env(config.acquire_timeout)?,
+            ttls: CacheTtls::from_env(),
+        })
+    }
+
+    /// A connection to the cratespro database.
+    pub async fn db(&self) -> Result<DBHandler, deadpool_postgres::PoolError> {
+        DBHandler::from_pool(&self.pg).await
+    }
+
+    /// A connection to the crates.io dump.
+    pub async fn cratesio_db(&self) -> Result<DBHandler, deadpool_postgres::PoolError> {
+        DBHandler::from_pool(&self.cratesio_pg).await
+    }
+
+    pub async fn redis(&self) -> Result<RedisHandler, redis::RedisError> {
+        RedisHandler::connect(&self.redis, self.ttls.clone()).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, time::Duration};
+
+    use super::PoolConfig;
+
+    #[test]
+    fn test_pool_config_from_vars() {
+        let vars = HashMap::from([
+            ("API_PG_POOL_SIZE", "32"),
+            ("API_TUGRAPH_POOL_SIZE", "not a number"),
+            ("API_POOL_TIMEOUT_SECS", " 10 "),
+        ]);
+        let config = PoolConfig::from_vars(|key| vars.get(key).map(|v| v.to_string()));
+        assert_eq!(
+            config,
+            PoolConfig {
+                pg_pool_size: 32,
+                tugraph_pool_size: 64,
+                acquire_timeout: Duration::from_secs(10),
+            }
+        );
+    }
+}
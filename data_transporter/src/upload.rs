@@ -0,0 +1,643 @@
+//! Uploads of `/api/submitCrate`.
+//!
+//! An uploaded `.zip` or `.crate` (a gzipped tarball, as published on
+//! crates.io) gets an upload id and is extracted to `UPLOAD_DIR/<id>/`, which
+//! must be storage the importer reads under the same `UPLOAD_DIR`. The
+//! extraction refuses:
+//!
+//! - archives larger than `API_UPLOAD_MAX_BYTES`;
+//! - more than `API_UPLOAD_MAX_ENTRIES` entries or more than
+//!   `API_UPLOAD_MAX_UNPACKED_BYTES` unpacked, counted on the bytes actually
+//!   written rather than on the sizes the archive declares;
+//! - archives unpacking to more than `API_UPLOAD_MAX_RATIO` times their size;
+//! - absolute paths, `..` components and links.
+//!
+//! Accepted uploads and GitHub links are published as a `MessageModel` of
+//! kind `User` on `KAFKA_USER_IMPORT_TOPIC`, which `ImportDriver` reads
+//! before the Mega topic. The `upload_status` table follows every upload
+//! from `received` to `queued` or `failed`, `ImportDriver` and the analysis
+//! workers move it on to `imported`, `analysing` and `analysed`.
+
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io::{self, Cursor, Read},
+    path::{Component, Path, PathBuf},
+    time::Duration,
+};
+
+use chrono::Utc;
+use flate2::read::GzDecoder;
+pub use model::repo_sync_model::upload_location;
+use model::repo_sync_model::{
+    CrateType, MessageKind, MessageModel, Model, RepoSyncStatus, SourceOfData,
+};
+use rdkafka::{
+    producer::{FutureProducer, FutureRecord},
+    ClientConfig,
+};
+use tar::EntryType;
+use zip::ZipArchive;
+
+use crate::{error::ApiError, state::parse_var};
+
+/// Limits of a single upload, read from the environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadLimits {
+    /// `API_UPLOAD_MAX_BYTES`, size of the uploaded archive.
+    pub max_upload_bytes: u64,
+    /// `API_UPLOAD_MAX_UNPACKED_BYTES`, bytes written by the extraction.
+    pub max_unpacked_bytes: u64,
+    /// `API_UPLOAD_MAX_ENTRIES`, files and directories of the archive.
+    pub max_entries: usize,
+    /// `API_UPLOAD_MAX_RATIO`, unpacked bytes per byte of archive.
+    pub max_ratio: u64,
+}
+
+impl Default for UploadLimits {
+    fn default() -> Self {
+        UploadLimits {
+            max_upload_bytes: 50 * 1024 * 1024,
+            max_unpacked_bytes: 500 * 1024 * 1024,
+            max_entries: 10_000,
+            max_ratio: 100,
+        }
+    }
+}
+
+impl UploadLimits {
+    pub fn from_env() -> Self {
+        Self::from_vars(|key| env::var(key).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Self {
+        let default = UploadLimits::default();
+        UploadLimits {
+            max_upload_bytes: parse_var(&var, "API_UPLOAD_MAX_BYTES", default.max_upload_bytes),
+            max_unpacked_bytes: parse_var(
+                &var,
+                "API_UPLOAD_MAX_UNPACKED_BYTES",
+                default.max_unpacked_bytes,
+            ),
+            max_entries: parse_var(&var, "API_UPLOAD_MAX_ENTRIES", default.max_entries),
+            max_ratio: parse_var(&var, "API_UPLOAD_MAX_RATIO", default.max_ratio),
+        }
+    }
+}
+
+/// Formats accepted by `/api/submitCrate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveKind {
+    Zip,
+    /// `.crate`, `.tar.gz` or `.tgz`.
+    Crate,
+}
+
+impl ArchiveKind {
+    pub fn from_file_name(file_name: &str) -> Option<Self> {
+        let lower = file_name.to_ascii_lowercase();
+        if lower.ends_with(".zip") {
+            Some(ArchiveKind::Zip)
+        } else if [".crate", ".tar.gz", ".tgz"]
+            .iter()
+            .any(|suffix| lower.ends_with(suffix))
+        {
+            Some(ArchiveKind::Crate)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum UploadError {
+    UnsupportedFormat(String),
+    TooLarge(u64),
+    TooManyEntries(usize),
+    /// Unpacks to more than the allowed ratio of its size.
+    SuspiciousRatio(u64),
+    UnsafePath(String),
+    Archive(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for UploadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UploadError::UnsupportedFormat(name) => write!(
+                f,
+                "unsupported upload '{}', expected a .zip or .crate archive",
+                name
+            ),
+            UploadError::TooLarge(limit) => write!(f, "upload exceeds {} bytes", limit),
+            UploadError::TooManyEntries(limit) => {
+                write!(f, "archive has more than {} entries", limit)
+            }
+            UploadError::SuspiciousRatio(ratio) => {
+                write!(f, "archive unpacks to more than {} times its size", ratio)
+            }
+            UploadError::UnsafePath(path) => write!(f, "unsafe path in archive: {}", path),
+            UploadError::Archive(e) => write!(f, "invalid archive: {}", e),
+            UploadError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for UploadError {}
+
+impl From<io::Error> for UploadError {
+    fn from(e: io::Error) -> Self {
+        UploadError::Io(e)
+    }
+}
+
+impl From<zip::result::ZipError> for UploadError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(e) => UploadError::Io(e),
+            e => UploadError::Archive(e.to_string()),
+        }
+    }
+}
+
+impl From<UploadError> for ApiError {
+    fn from(e: UploadError) -> Self {
+        match e {
+            UploadError::Io(e) => ApiError::internal(e),
+            e => ApiError::bad_request(e),
+        }
+    }
+}
+
+/// `UPLOAD_DIR`, `target/www/uploads` by default.
+pub fn upload_root() -> PathBuf {
+    env::var("UPLOAD_DIR")
+        .unwrap_or_else(|_| "target/www/uploads".to_string())
+        .into()
+}
+
+/// Extracts `data` into `dest`, returns the number of bytes written.
+pub fn extract_archive(
+    kind: ArchiveKind,
+    data: &[u8],
+    dest: &Path,
+    limits: &UploadLimits,
+) -> Result<u64, UploadError> {
+    if data.len() as u64 > limits.max_upload_bytes {
+        return Err(UploadError::TooLarge(limits.max_upload_bytes));
+    }
+    let mut budget = UnpackBudget::new(data.len() as u64, limits);
+    fs::create_dir_all(dest)?;
+    match kind {
+        ArchiveKind::Zip => extract_zip(data, dest, &mut budget)?,
+        ArchiveKind::Crate => extract_crate(data, dest, &mut budget)?,
+    }
+    Ok(budget.written)
+}
+
+/// What the extraction may still write.
+struct UnpackBudget {
+    written: u64,
+    entries: usize,
+    max_bytes: u64,
+    ratio_bytes: u64,
+    limits: UploadLimits,
+}
+
+impl UnpackBudget {
+    fn new(archive_len: u64, limits: &UploadLimits) -> Self {
+        UnpackBudget {
+            written: 0,
+            entries: 0,
+            max_bytes: limits.max_unpacked_bytes,
+            ratio_bytes: archive_len.max(1).saturating_mul(limits.max_ratio),
+            limits: limits.clone(),
+        }
+    }
+
+    fn add_entry(&mut self) -> Result<(), UploadError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(UploadError::TooManyEntries(self.limits.max_entries));
+        }
+        Ok(())
+    }
+
+    fn remaining(&self) -> u64 {
+        self.max_bytes.min(self.ratio_bytes) - self.written
+    }
+
+    /// The error of unpacking `total` bytes, past one of the limits.
+    fn exceeded(&self, total: u64) -> UploadError {
+        if total > self.max_bytes {
+            UploadError::TooLarge(self.max_bytes)
+        } else {
+            UploadError::SuspiciousRatio(self.limits.max_ratio)
+        }
+    }
+
+    /// Copies at most the remaining budget, fails if `reader` has more.
+    fn copy(&mut self, reader: &mut impl Read, path: &Path) -> Result<(), UploadError> {
+        let remaining = self.remaining();
+        let mut out = File::create(path)?;
+        let copied = io::copy(&mut reader.take(remaining + 1), &mut out)?;
+        if copied > remaining {
+            drop(out);
+            let _ = fs::remove_file(path);
+            return Err(self.exceeded(self.written + copied));
+        }
+        self.written += copied;
+        Ok(())
+    }
+}
+
+/// `path` below `dest`, `None` if it is absolute or leaves `dest`.
+fn safe_join(dest: &Path, path: &Path) -> Option<PathBuf> {
+    let mut joined = dest.to_path_buf();
+    let mut depth = 0;
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => {
+                joined.push(part);
+                depth += 1;
+            }
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    (depth > 0).then_some(joined)
+}
+
+fn extract_zip(data: &[u8], dest: &Path, budget: &mut UnpackBudget) -> Result<(), UploadError> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+    if archive.len() > budget.limits.max_entries {
+        return Err(UploadError::TooManyEntries(budget.limits.max_entries));
+    }
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i)?;
+        budget.add_entry()?;
+        let outpath = file
+            .enclosed_name()
+            .and_then(|name| safe_join(dest, &name))
+            .ok_or_else(|| UploadError::UnsafePath(file.name().to_string()))?;
+        if file.is_symlink() {
+            return Err(UploadError::UnsafePath(file.name().to_string()));
+        }
+        if file.is_dir() {
+            fs::create_dir_all(&outpath)?;
+            continue;
+        }
+        // the declared size may lie, `copy` counts what is actually written
+        if file.size() > budget.remaining() {
+            return Err(budget.exceeded(budget.written + file.size()));
+        }
+        if let Some(parent) = outpath.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        budget.copy(&mut file, &outpath)?;
+    }
+    Ok(())
+}
+
+fn extract_crate(data: &[u8], dest: &Path, budget: &mut UnpackBudget) -> Result<(), UploadError> {
+    let mut archive = tar::Archive::new(GzDecoder::new(data));
+    for entry in archive
+        .entries()
+        .map_err(|e| UploadError::Archive(e.to_string()))?
+    {
+        let mut entry = entry.map_err(|e| UploadError::Archive(e.to_string()))?;
+        let path = entry
+            .path()
+            .map_err(|e| UploadError::Archive(e.to_string()))?
+            .into_owned();
+        let entry_type = entry.header().entry_type();
+        if matches!(
+            entry_type,
+            EntryType::XGlobalHeader | EntryType::XHeader | EntryType::GNULongName
+        ) {
+            continue;
+        }
+        budget.add_entry()?;
+        let outpath = safe_join(dest, &path)
+            .ok_or_else(|| UploadError::UnsafePath(path.display().to_string()))?;
+        match entry_type {
+            EntryType::Directory => fs::create_dir_all(&outpath)?,
+            EntryType::Regular | EntryType::Continuous => {
+                if let Some(parent) = outpath.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                budget.copy(&mut entry, &outpath)?;
+            }
+            _ => return Err(UploadError::UnsafePath(path.display().to_string())),
+        }
+    }
+    Ok(())
+}
+
+/// `package.name` of the `Cargo.toml` at the root of `dir` or one level
+/// below, where `.crate` archives put it.
+pub fn find_crate_name(dir: &Path) -> Option<String> {
+    let mut manifests = vec![dir.join("Cargo.toml")];
+    let mut subdirs: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .collect();
+    subdirs.sort();
+    manifests.extend(subdirs.into_iter().map(|dir| dir.join("Cargo.toml")));
+    manifests.into_iter().find_map(|manifest| {
+        let content = fs::read_to_string(manifest).ok()?;
+        let value: toml::Value = toml::from_str(&content).ok()?;
+        value
+            .get("package")?
+            .get("name")?
+            .as_str()
+            .map(|name| name.to_string())
+    })
+}
+
+/// `(owner, repo)` of a `https://github.com/<owner>/<repo>` link.
+pub fn parse_github_link(link: &str) -> Option<(String, String)> {
+    let path = link
+        .strip_prefix("https://github.com/")?
+        .trim_end_matches('/')
+        .trim_end_matches(".git");
+    let (owner, repo) = path.split_once('/')?;
+    let valid = |part: &str| {
+        !part.is_empty()
+            && part != "."
+            && part != ".."
+            && part
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    };
+    (valid(owner) && valid(repo)).then(|| (owner.to_string(), repo.to_string()))
+}
+
+/// The message asking `ImportDriver` to import an upload. `mega_url` is the
+/// [`upload_location`] of an extracted archive or the GitHub link,
+/// `extra_field` the upload id.
+pub fn user_import_message(
+    upload_id: &str,
+    crate_name: &str,
+    location: &str,
+    github_url: Option<String>,
+) -> MessageModel {
+    MessageModel {
+        db_model: Model {
+            id: 0,
+            crate_name: crate_name.to_string(),
+            github_url,
+            mega_url: location.to_string(),
+            crate_type: CrateType::Lib,
+            status: RepoSyncStatus::Syncing,
+            err_message: None,
+        },
+        message_kind: MessageKind::User,
+        source_of_data: SourceOfData::Github,
+        timestamp: Utc::now(),
+        extra_field: upload_id.to_string(),
+    }
+}
+
+/// Producer of `KAFKA_USER_IMPORT_TOPIC`.
+pub struct UploadQueue {
+    producer: Option<FutureProducer>,
+    topic: String,
+}
+
+impl UploadQueue {
+    pub fn from_env() -> Self {
+        let broker = env::var("KAFKA_BROKER").unwrap_or_default();
+        let topic = env::var("KAFKA_USER_IMPORT_TOPIC").unwrap_or_default();
+        let producer = match ClientConfig::new()
+            .set("bootstrap.servers", &broker)
+            .set("message.timeout.ms", "5000")
+            .create()
+        {
+            Ok(producer) => Some(producer),
+            Err(e) => {
+                tracing::error!("upload producer creation failed: {}", e);
+                None
+            }
+        };
+        UploadQueue { producer, topic }
+    }
+
+    pub async fn publish(&self, message: &MessageModel) -> Result<(), String> {
+        let producer = self
+            .producer
+            .as_ref()
+            .ok_or("no Kafka producer for uploads")?;
+        if self.topic.is_empty() {
+            return Err("KAFKA_USER_IMPORT_TOPIC not set".to_string());
+        }
+        let payload = serde_json::to_string(message).map_err(|e| e.to_string())?;
+        producer
+            .send(
+                FutureRecord::to(&self.topic)
+                    .key(&message.extra_field)
+                    .payload(&payload),
+                Duration::from_secs(5),
+            )
+            .await
+            .map(|_| ())
+            .map_err(|(e, _)| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+    use std::path::{Path, PathBuf};
+
+    use flate2::{write::GzEncoder, Compression};
+    use zip::{write::SimpleFileOptions, ZipWriter};
+
+    use super::{
+        extract_archive, find_crate_name, parse_github_link, safe_join, upload_location,
+        user_import_message, ArchiveKind, UploadError, UploadLimits,
+    };
+
+    fn scratch_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("upload-test-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn zip_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(content).unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    fn crate_of(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (name, content) in files {
+            let mut header = tar::Header::new_gnu();
+            // written by hand, `set_path` refuses `..`
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append(&header, *content).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    #[test]
+    fn test_archive_kind() {
+        assert_eq!(ArchiveKind::from_file_name("a.ZIP"), Some(ArchiveKind::Zip));
+        assert_eq!(
+            ArchiveKind::from_file_name("serde-1.0.0.crate"),
+            Some(ArchiveKind::Crate)
+        );
+        assert_eq!(
+            ArchiveKind::from_file_name("a.tar.gz"),
+            Some(ArchiveKind::Crate)
+        );
+        assert_eq!(ArchiveKind::from_file_name("a.rs"), None);
+    }
+
+    #[test]
+    fn test_parse_github_link() {
+        assert_eq!(
+            parse_github_link("https://github.com/tokio-rs/tokio.git"),
+            Some(("tokio-rs".to_string(), "tokio".to_string()))
+        );
+        assert_eq!(parse_github_link("https://github.com/a/b/tree/main"), None);
+        assert_eq!(parse_github_link("https://github.com/a/.."), None);
+        assert_eq!(parse_github_link("http://example.com/a/b"), None);
+    }
+
+    #[test]
+    fn test_user_import_message_carries_upload_id() {
+        let message = user_import_message("42", "tokio", "https://github.com/tokio-rs/tokio", None);
+        let json: serde_json::Value = serde_json::to_value(&message).unwrap();
+        assert_eq!(json["message_kind"], "User");
+        assert_eq!(json["extra_field"], "42");
+
+        let message = user_import_message("42", "tokio", &upload_location("42"), None);
+        assert_eq!(
+            model::repo_sync_model::upload_id_of_location(&message.db_model.mega_url),
+            Some("42")
+        );
+
+        // versions announced before uploads were tracked still parse
+        let version: model::general_model::VersionWithTag = serde_json::from_str(
+            r#"{"name":"tokio","version":"1.0.0","git_url":"u","tag":"v1.0.0"}"#,
+        )
+        .unwrap();
+        assert_eq!(version.upload_id, None);
+    }
+
+    #[test]
+    fn test_safe_join() {
+        let dest = Path::new("/uploads/1");
+        assert_eq!(
+            safe_join(dest, Path::new("./a/b.rs")),
+            Some(PathBuf::from("/uploads/1/a/b.rs"))
+        );
+        assert_eq!(safe_join(dest, Path::new("a/../../b")), None);
+        assert_eq!(safe_join(dest, Path::new("/etc/passwd")), None);
+        assert_eq!(safe_join(dest, Path::new(".")), None);
+    }
+
+    #[test]
+    fn test_extract_zip_and_find_name() {
+        let dest = scratch_dir();
+        let data = zip_of(&[
+            ("foo/Cargo.toml", b"[package]\nname = \"foo\"\n"),
+            ("foo/src/lib.rs", b"pub fn foo() {}\n"),
+        ]);
+        let written =
+            extract_archive(ArchiveKind::Zip, &data, &dest, &UploadLimits::default()).unwrap();
+        assert_eq!(written, 39);
+        assert_eq!(find_crate_name(&dest), Some("foo".to_string()));
+        std::fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn test_extract_crate() {
+        let dest = scratch_dir();
+        let data = crate_of(&[("bar-0.1.0/Cargo.toml", b"[package]\nname = \"bar\"\n")]);
+        extract_archive(ArchiveKind::Crate, &data, &dest, &UploadLimits::default()).unwrap();
+        assert_eq!(find_crate_name(&dest), Some("bar".to_string()));
+        std::fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_traversal() {
+        let dest = scratch_dir();
+        let data = crate_of(&[("../evil.rs", b"evil")]);
+        assert!(matches!(
+            extract_archive(ArchiveKind::Crate, &data, &dest, &UploadLimits::default()),
+            Err(UploadError::UnsafePath(_))
+        ));
+        let data = zip_of(&[("../evil.rs", b"evil")]);
+        assert!(matches!(
+            extract_archive(ArchiveKind::Zip, &data, &dest, &UploadLimits::default()),
+            Err(UploadError::UnsafePath(_))
+        ));
+        assert!(!dest.parent().unwrap().join("evil.rs").exists());
+        std::fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn test_rejects_bombs() {
+        let dest = scratch_dir();
+        let zeros = vec![0u8; 1024 * 1024];
+        let data = zip_of(&[("zeros", &zeros)]);
+        assert!(matches!(
+            extract_archive(ArchiveKind::Zip, &data, &dest, &UploadLimits::default()),
+            Err(UploadError::SuspiciousRatio(100))
+        ));
+        let data = crate_of(&[("zeros", &zeros)]);
+        assert!(matches!(
+            extract_archive(ArchiveKind::Crate, &data, &dest, &UploadLimits::default()),
+            Err(UploadError::SuspiciousRatio(100))
+        ));
+
+        let limits = UploadLimits {
+            max_entries: 1,
+            ..UploadLimits::default()
+        };
+        let data = zip_of(&[("a", b"a"), ("b", b"b")]);
+        assert!(matches!(
+            extract_archive(ArchiveKind::Zip, &data, &dest, &limits),
+            Err(UploadError::TooManyEntries(1))
+        ));
+        let limits = UploadLimits {
+            max_upload_bytes: 10,
+            ..UploadLimits::default()
+        };
+        assert!(matches!(
+            extract_archive(ArchiveKind::Zip, &data, &dest, &limits),
+            Err(UploadError::TooLarge(10))
+        ));
+        std::fs::remove_dir_all(dest).unwrap();
+    }
+
+    #[test]
+    fn test_upload_status_transitions() {
+        use model::repo_sync_model::UploadStatus::*;
+
+        let moves = |from, to: model::repo_sync_model::UploadStatus| to.previous().contains(&from);
+        assert!(moves(Received, Queued));
+        assert!(moves(Queued, Imported));
+        assert!(moves(Imported, Analysing));
+        assert!(moves(Analysing, Analysed));
+        assert!(moves(Analysed, Failed));
+        // the importer reports late, the analysis already started
+        assert!(!moves(Analysing, Imported));
+        assert!(!moves(Analysed, Imported));
+        // a failed version is not hidden by the others
+        for status in [Received, Queued, Imported, Analysing, Analysed, Failed] {
+            assert!(!moves(Failed, status));
+        }
+    }
+}
@@ -0,0 +1,326 @@
+//! Reachability of RustSec advisories through the dependency graph.
+//!
+//! For every vulnerable crate in the closure of a version we report the paths
+//! leading to it from the root, and for every direct dependency on those
+//! paths the smallest newer version whose own closure no longer reaches the
+//! advisory.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    error::Error,
+};
+
+use semver::Version;
+
+use crate::{
+    data_reader::{DataReader, DataReaderTrait},
+    db::DBHandler,
+    handler::{DirectUpgrade, VulnerabilityReport, VulnerableDependency},
+    NameVersion,
+};
+
+/// Upper bound of the paths listed per vulnerable dependency, the number of
+/// paths grows exponentially with the depth of the graph.
+const MAX_PATHS_PER_DEPENDENCY: usize = 100;
+
+/// Upper bound of the unfinished paths kept while looking for the paths to a
+/// dependency, they multiply with every level of the graph.
+const MAX_PARTIAL_PATHS: usize = 10_000;
+
+/// Upper bound of the newer versions of a direct dependency that are checked
+/// before giving up on finding an upgrade.
+const MAX_UPGRADE_CANDIDATES: usize = 30;
+
+pub async fn build_vulnerability_report(
+    reader: &DataReader,
+    dbhandler: &DBHandler,
+    namespace: &str,
+    name: &str,
+    version: &str,
+) -> Result<VulnerabilityReport, Box<dyn Error>> {
+    let root = name.to_string() + "/" + version;
+    let (graph, graph_truncated) = reader.get_dependency_graph(namespace, &root).await?;
+    let distances = shortest_distances(&graph, &root);
+    let mut vulnerable_nodes: Vec<&String> =
+        distances.keys().filter(|node| **node != root).collect();
+    vulnerable_nodes.sort();
+
+    let mut vulnerabilities = vec![];
+    let mut paths_truncated = false;
+    for node in vulnerable_nodes {
+        let Some(dep) = NameVersion::from_string(node) else {
+            continue;
+        };
+        let advisories = dbhandler
+            .get_direct_rustsec(&dep.name, &dep.version)
+            .await?;
+        if advisories.is_empty() {
+            continue;
+        }
+        let (paths, truncated) = dependency_paths(&graph, &root, node, MAX_PATHS_PER_DEPENDENCY);
+        paths_truncated |= truncated;
+        let advisory_ids: HashSet<String> = advisories.iter().map(|a| a.id.clone()).collect();
+        let mut direct_deps: Vec<&String> = paths
+            .iter()
+            .filter_map(|path| path.get(1))
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        direct_deps.sort();
+        let mut upgrades = vec![];
+        for direct in direct_deps {
+            let Some(direct) = NameVersion::from_string(direct) else {
+                continue;
+            };
+            let minimal_version =
+                find_minimal_upgrade(reader, dbhandler, &direct, &dep.name, &advisory_ids).await?;
+            upgrades.push(DirectUpgrade {
+                crate_name: direct.name,
+                current_version: direct.version,
+                minimal_version,
+            });
+        }
+        vulnerabilities.push(VulnerableDependency {
+            crate_name: dep.name,
+            version: dep.version,
+            advisories,
+            shortest_path_len: distances[node],
+            paths,
+            upgrades,
+        });
+    }
+
+    Ok(VulnerabilityReport {
+        crate_name: name.to_string(),
+        version: version.to_string(),
+        vulnerable_count: vulnerabilities.len(),
+        vulnerabilities,
+        graph_truncated,
+        paths_truncated,
+    })
+}
+
+/// Smallest version of `direct` newer than the current one whose closure
+/// holds no version of `vulnerable_crate` affected by any of `advisory_ids`.
+async fn find_minimal_upgrade(
+    reader: &DataReader,
+    dbhandler: &DBHandler,
+    direct: &NameVersion,
+    vulnerable_crate: &str,
+    advisory_ids: &HashSet<String>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let Ok(current) = Version::parse(&direct.version) else {
+        return Ok(None);
+    };
+    let candidates = newer_versions(reader.get_lib_version(direct.name.clone()).await?, &current);
+    for candidate in candidates.into_iter().take(MAX_UPGRADE_CANDIDATES) {
+        let candidate = NameVersion {
+            name: direct.name.clone(),
+            version: candidate.to_string(),
+        };
+        let mut closure = reader.get_all_dependencies(candidate.clone()).await?;
+        closure.insert(candidate.name.clone() + "/" + &candidate.version);
+        let mut reaches_advisory = false;
+        for node in closure {
+            let Some(nv) = NameVersion::from_string(&node) else {
+                continue;
+            };
+            if nv.name != vulnerable_crate {
+                continue;
+            }
+            let advisories = dbhandler.get_direct_rustsec(&nv.name, &nv.version).await?;
+            if advisories.iter().any(|a| advisory_ids.contains(&a.id)) {
+                reaches_advisory = true;
+                break;
+            }
+        }
+        if !reaches_advisory {
+            return Ok(Some(candidate.version));
+        }
+    }
+    Ok(None)
+}
+
+/// Versions strictly greater than `current`, in ascending order. Versions that
+/// are not semver are skipped.
+fn newer_versions(versions: Vec<String>, current: &Version) -> Vec<Version> {
+    let mut newer: Vec<Version> = versions
+        .iter()
+        .filter_map(|v| Version::parse(v).ok())
+        .filter(|v| v > current)
+        .collect();
+    newer.sort();
+    newer.dedup();
+    newer
+}
+
+/// Number of edges on the shortest path from `root` to every reachable node.
+fn shortest_distances(graph: &HashMap<String, Vec<String>>, root: &str) -> HashMap<String, usize> {
+    let mut distances = HashMap::new();
+    let mut queue = VecDeque::new();
+    distances.insert(root.to_string(), 0);
+    queue.push_back(root.to_string());
+    while let Some(current) = queue.pop_front() {
+        let distance = distances[&current];
+        for next in graph.get(&current).into_iter().flatten() {
+            if !distances.contains_key(next) {
+                distances.insert(next.clone(), distance + 1);
+                queue.push_back(next.clone());
+            }
+        }
+    }
+    distances
+}
+
+/// Acyclic paths from `root` to `target`, shortest first, at most `limit` of
+/// them, and whether some were left out. Paths are grown breadth first, so
+/// one is only listed after every shorter one. Only nodes that can still
+/// reach `target` are explored, so the walk never wanders into unrelated
+/// parts of the graph.
+fn dependency_paths(
+    graph: &HashMap<String, Vec<String>>,
+    root: &str,
+    target: &str,
+    limit: usize,
+) -> (Vec<Vec<String>>, bool) {
+    let mut reverse: HashMap<&str, Vec<&str>> = HashMap::new();
+    for (from, tos) in graph {
+        for to in tos {
+            reverse.entry(to.as_str()).or_default().push(from.as_str());
+        }
+    }
+    let mut reaches_target = HashSet::new();
+    let mut queue = VecDeque::from([target]);
+    while let Some(current) = queue.pop_front() {
+        if reaches_target.insert(current) {
+            queue.extend(reverse.get(current).into_iter().flatten().copied());
+        }
+    }
+
+    let mut paths = vec![];
+    if !reaches_target.contains(root) {
+        return (paths, false);
+    }
+    let mut partial_paths = VecDeque::from([vec![root.to_string()]]);
+    while let Some(path) = partial_paths.pop_front() {
+        let current = path.last().unwrap();
+        if current == target {
+            if paths.len() == limit {
+                return (paths, true);
+            }
+            paths.push(path);
+            continue;
+        }
+        let mut nexts: Vec<&String> = graph
+            .get(current)
+            .into_iter()
+            .flatten()
+            .filter(|next| reaches_target.contains(next.as_str()) && !path.contains(next))
+            .collect();
+        nexts.sort();
+        nexts.dedup();
+        for next in nexts {
+            if partial_paths.len() >= MAX_PARTIAL_PATHS {
+                return (paths, true);
+            }
+            let mut longer = path.clone();
+            longer.push(next.clone());
+            partial_paths.push_back(longer);
+        }
+    }
+    (paths, false)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use semver::Version;
+
+    use super::{dependency_paths, newer_versions, shortest_distances};
+
+    fn graph(edges: &[(&str, &str)]) -> HashMap<String, Vec<String>> {
+        let mut graph: HashMap<String, Vec<String>> = HashMap::new();
+        for (from, to) in edges {
+            graph
+                .entry(from.to_string())
+                .or_default()
+                .push(to.to_string());
+        }
+        graph
+    }
+
+    #[test]
+    fn test_paths_through_diamond_and_cycle() {
+        let g = graph(&[
+            ("root/1.0.0", "a/1.0.0"),
+            ("root/1.0.0", "b/1.0.0"),
+            ("root/1.0.0", "vuln/0.1.0"),
+            ("a/1.0.0", "c/1.0.0"),
+            ("b/1.0.0", "c/1.0.0"),
+            ("c/1.0.0", "vuln/0.1.0"),
+            ("c/1.0.0", "a/1.0.0"),
+            ("b/1.0.0", "unrelated/1.0.0"),
+        ]);
+        let (paths, truncated) = dependency_paths(&g, "root/1.0.0", "vuln/0.1.0", 100);
+        assert!(!truncated);
+        let expected = vec![
+            vec!["root/1.0.0", "vuln/0.1.0"],
+            vec!["root/1.0.0", "a/1.0.0", "c/1.0.0", "vuln/0.1.0"],
+            vec!["root/1.0.0", "b/1.0.0", "c/1.0.0", "vuln/0.1.0"],
+        ];
+        assert_eq!(paths, expected);
+        assert_eq!(shortest_distances(&g, "root/1.0.0")["vuln/0.1.0"], 1);
+        assert_eq!(shortest_distances(&g, "root/1.0.0")["c/1.0.0"], 2);
+    }
+
+    #[test]
+    fn test_paths_limit_and_unreachable() {
+        let g = graph(&[
+            ("root/1.0.0", "a/1.0.0"),
+            ("root/1.0.0", "b/1.0.0"),
+            ("a/1.0.0", "vuln/0.1.0"),
+            ("b/1.0.0", "vuln/0.1.0"),
+        ]);
+        let (paths, truncated) = dependency_paths(&g, "root/1.0.0", "vuln/0.1.0", 1);
+        assert_eq!(paths.len(), 1);
+        assert!(truncated);
+        let (paths, truncated) = dependency_paths(&g, "root/1.0.0", "vuln/0.1.0", 2);
+        assert_eq!(paths.len(), 2);
+        assert!(!truncated);
+        assert_eq!(
+            dependency_paths(&g, "root/1.0.0", "missing/1.0.0", 100),
+            (vec![], false)
+        );
+    }
+
+    #[test]
+    fn test_paths_shortest_before_deep() {
+        // the deep chain sorts first, a depth first walk would list it alone
+        let g = graph(&[
+            ("root/1.0.0", "a/1.0.0"),
+            ("a/1.0.0", "b/1.0.0"),
+            ("b/1.0.0", "c/1.0.0"),
+            ("c/1.0.0", "vuln/0.1.0"),
+            ("root/1.0.0", "z/1.0.0"),
+            ("z/1.0.0", "vuln/0.1.0"),
+        ]);
+        let (paths, truncated) = dependency_paths(&g, "root/1.0.0", "vuln/0.1.0", 1);
+        assert_eq!(paths, vec![vec!["root/1.0.0", "z/1.0.0", "vuln/0.1.0"]]);
+        assert!(truncated);
+    }
+
+    #[test]
+    fn test_newer_versions() {
+        let versions = ["1.2.0", "0.9.0", "1.10.0", "1.1.0", "not-semver", "1.2.0"]
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        let current = Version::parse("1.1.0").unwrap();
+        let newer: Vec<String> = newer_versions(versions, &current)
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(newer, vec!["1.2.0", "1.10.0"]);
+    }
+}
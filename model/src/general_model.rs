@@ -1,3 +1,5 @@
+use std::{fmt, str::FromStr};
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Deserialize, Serialize, Hash, PartialEq, Eq, PartialOrd, Ord, Clone)]
@@ -21,6 +23,9 @@ pub struct VersionWithTag {
     pub version: String,
     pub git_url: String,
     pub tag: String,
+    /// Id of the user upload the version was imported from, if any.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upload_id: Option<String>,
 }
 
 impl VersionWithTag {
@@ -30,6 +35,7 @@ impl VersionWithTag {
             version: version.to_string(),
             git_url: git_url.to_string(),
             tag: tag.to_string(),
+            upload_id: None,
         }
     }
 }
@@ -48,3 +54,89 @@ impl Program {
         }
     }
 }
+
+/// The section of a Cargo.toml a dependency is declared in.
+#[derive(
+    Debug, Deserialize, Serialize, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    /// `[dependencies]`
+    #[default]
+    Normal,
+    /// `[dev-dependencies]`, only built for tests, examples and benchmarks.
+    Dev,
+    /// `[build-dependencies]`, only built for the build script.
+    Build,
+}
+
+impl DependencyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencyKind::Normal => "normal",
+            DependencyKind::Dev => "dev",
+            DependencyKind::Build => "build",
+        }
+    }
+
+    /// The kind of a Cargo.toml dependency table, `dev_dependencies` and
+    /// `build_dependencies` are the legacy spellings still accepted by Cargo.
+    pub fn from_table_name(table: &str) -> Option<Self> {
+        match table {
+            "dependencies" => Some(DependencyKind::Normal),
+            "dev-dependencies" | "dev_dependencies" => Some(DependencyKind::Dev),
+            "build-dependencies" | "build_dependencies" => Some(DependencyKind::Build),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for DependencyKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DependencyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(DependencyKind::Normal),
+            "dev" => Ok(DependencyKind::Dev),
+            "build" => Ok(DependencyKind::Build),
+            _ => Err(format!("unknown dependency kind '{}'", s)),
+        }
+    }
+}
+
+/// Where a dependency is taken from.
+#[derive(
+    Debug, Deserialize, Serialize, Hash, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencySource {
+    /// A version requirement resolved against a registry.
+    #[default]
+    Registry,
+    /// `path = "..."`, a package of the same repository.
+    Path,
+    /// `git = "..."`
+    Git,
+}
+
+impl DependencySource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DependencySource::Registry => "registry",
+            DependencySource::Path => "path",
+            DependencySource::Git => "git",
+        }
+    }
+}
+
+impl fmt::Display for DependencySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
@@ -31,6 +31,51 @@ pub enum RepoSyncStatus {
     Analysed,
 }
 
+/// Lifecycle of a user upload in the `upload_status` table, from
+/// `/api/submitCrate` through `ImportDriver` to the analysis workers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadStatus {
+    Received,
+    /// Published on the user import topic.
+    Queued,
+    Imported,
+    Analysing,
+    Analysed,
+    /// Refused or failed at any step, see the message.
+    Failed,
+}
+
+impl UploadStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UploadStatus::Received => "received",
+            UploadStatus::Queued => "queued",
+            UploadStatus::Imported => "imported",
+            UploadStatus::Analysing => "analysing",
+            UploadStatus::Analysed => "analysed",
+            UploadStatus::Failed => "failed",
+        }
+    }
+
+    /// Statuses an upload may move to `self` from. The importer and the
+    /// analysis workers report concurrently, so an update from any other
+    /// status is dropped: a late `imported` does not undo the analysis, and
+    /// `failed` is final.
+    pub fn previous(&self) -> &'static [UploadStatus] {
+        use UploadStatus::*;
+        match self {
+            Received => &[],
+            Queued => &[Received],
+            Imported => &[Received, Queued],
+            // each version of an import is analysed on its own
+            Analysing => &[Queued, Imported, Analysing],
+            Analysed => &[Queued, Imported, Analysing, Analysed],
+            Failed => &[Received, Queued, Imported, Analysing, Analysed],
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
 pub enum MessageKind {
     Mega,
@@ -43,6 +88,32 @@ pub enum SourceOfData {
     Github,
 }
 
+/// Start of the `mega_url` of an archive uploaded by a user.
+///
+/// The API server extracts an upload to `UPLOAD_DIR/<id>/`, on storage shared
+/// with the importer, and publishes it as `upload:///uploads/<id>`. Like a git
+/// URL, its last two segments are the namespace of the crate, `uploads/<id>`.
+const UPLOAD_LOCATION_PREFIX: &str = "upload:///uploads/";
+
+/// The `mega_url` of the uploaded archive `upload_id`.
+pub fn upload_location(upload_id: &str) -> String {
+    format!("{}{}", UPLOAD_LOCATION_PREFIX, upload_id)
+}
+
+/// The upload id of a `mega_url` made by [`upload_location`], `None` for any
+/// other URL or an id that is not a single path segment.
+pub fn upload_id_of_location(mega_url: &str) -> Option<&str> {
+    mega_url
+        .strip_prefix(UPLOAD_LOCATION_PREFIX)
+        .filter(|id| !id.is_empty() && *id != "." && *id != "..")
+        .filter(|id| !id.contains(['/', '\\']))
+}
+
+/// The namespace of the crate of the uploaded archive `upload_id`.
+pub fn upload_namespace(upload_id: &str) -> String {
+    format!("uploads/{}", upload_id)
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MessageModel {
     pub db_model: Model, // 包装数据库 Model
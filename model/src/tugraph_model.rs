@@ -186,6 +186,9 @@ impl ApplicationVersion {
 pub struct Version {
     /// The name and version of the crate.
     pub name_and_version: String,
+    /// The `[features]` table of the version, as a JSON object.
+    #[serde(default)]
+    pub features: String,
 }
 
 impl Version {
@@ -193,6 +196,7 @@ impl Version {
     pub fn new(name_and_version: &str) -> Self {
         Version {
             name_and_version: name_and_version.to_string(),
+            features: "{}".to_string(),
         }
     }
 }
@@ -219,10 +223,26 @@ pub struct HasDepVersion {
 }
 
 #[allow(non_snake_case)]
-#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
 pub struct DependsOn {
     pub SRC_ID: String,
     pub DST_ID: String,
+    /// `normal`, `dev` or `build`.
+    pub kind: String,
+    /// The `cfg(..)` or target triple of a `[target.'..'.dependencies]`
+    /// table, empty for dependencies of every target.
+    pub target: String,
+    /// The key of a renamed dependency (`key = { package = "..." }`), empty
+    /// if it is not renamed.
+    pub rename: String,
+    /// The version requirement the dependent declared, e.g. `^1.2`.
+    pub requirement: String,
+    pub optional: bool,
+    pub default_features: bool,
+    /// Requested features, joined by `,`.
+    pub features: String,
+    /// `registry`, `path` or `git`.
+    pub source: String,
 }
 
 pub trait CrateType2Idx {
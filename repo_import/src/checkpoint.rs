@@ -0,0 +1,318 @@
+//! The on-disk format of [`ImportContext`] checkpoints.
+//!
+//! bincode writes the fields of a struct in order and without their names,
+//! `#[serde(default)]` has no effect on it: a field added to a checkpointed
+//! struct makes every older checkpoint unreadable. A checkpoint therefore
+//! starts with [`MAGIC`] and the version of its format, and each older format
+//! is read into its own structs and migrated to the current one. Checkpoints
+//! written before the header existed are version 0, they cannot start with
+//! [`MAGIC`] since their first byte is the `dont_clone` bool.
+//!
+//! A change to a checkpointed struct (`ImportContext`, `VersionUpdater`,
+//! `VersionParser`, `ReverseDependency`, `DependencyEdge`, `DependencyUsage`
+//! and the model types they hold) must bump [`CURRENT_VERSION`] and add the
+//! migration from the previous version.
+
+use crate::ImportContext;
+
+const MAGIC: &[u8; 8] = b"CRPROCKP";
+
+/// The format written by [`encode`].
+pub(crate) const CURRENT_VERSION: u32 = 1;
+
+pub(crate) fn encode(context: &ImportContext) -> Result<Vec<u8>, String> {
+    let body = bincode::serialize(context).map_err(|e| format!("Serialization error: {}", e))?;
+    let mut content = Vec::with_capacity(MAGIC.len() + 4 + body.len());
+    content.extend_from_slice(MAGIC);
+    content.extend_from_slice(&CURRENT_VERSION.to_le_bytes());
+    content.extend_from_slice(&body);
+    Ok(content)
+}
+
+pub(crate) fn decode(content: &[u8]) -> Result<ImportContext, String> {
+    let Some(rest) = content.strip_prefix(MAGIC.as_slice()) else {
+        tracing::info!("Migrating a checkpoint of version 0");
+        return v0::decode(content);
+    };
+    let (version, body) = rest
+        .split_first_chunk::<4>()
+        .ok_or("Truncated checkpoint header")?;
+    match u32::from_le_bytes(*version) {
+        CURRENT_VERSION => {
+            bincode::deserialize(body).map_err(|e| format!("Deserialization error: {}", e))
+        }
+        version => Err(format!(
+            "Unsupported checkpoint version {}, this importer reads versions up to {}",
+            version, CURRENT_VERSION
+        )),
+    }
+}
+
+/// The checkpoints written before dependency kinds, features, publish times
+/// and unresolved requirements were recorded.
+mod v0 {
+    use std::collections::{HashMap, HashSet};
+
+    use model::general_model;
+    use model::tugraph_model::{
+        Application, ApplicationVersion, HasDepVersion, HasType, HasVersion, Library,
+        LibraryVersion, Program,
+    };
+    use serde::Deserialize;
+
+    use crate::version_info::{
+        DependencyEdge, DependencySpec, DependencyUsage, ReverseDependency, VersionParser,
+        VersionUpdater,
+    };
+    use crate::Licenses;
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(serde::Serialize, Default))]
+    pub(super) struct ImportContext {
+        pub dont_clone: bool,
+        pub programs: Vec<Program>,
+        pub libraries: Vec<Library>,
+        pub applications: Vec<Application>,
+        pub library_versions: Vec<LibraryVersion>,
+        pub application_versions: Vec<ApplicationVersion>,
+        pub versions: Vec<Version>,
+        pub licenses: Vec<Licenses>,
+        pub has_lib_type: Vec<HasType>,
+        pub has_app_type: Vec<HasType>,
+        pub lib_has_version: Vec<HasVersion>,
+        pub app_has_version: Vec<HasVersion>,
+        pub lib_has_dep_version: Vec<HasDepVersion>,
+        pub app_has_dep_version: Vec<HasDepVersion>,
+        /// Read past, the edges are rebuilt from the version updater.
+        #[allow(dead_code)]
+        pub depends_on: Vec<DependsOn>,
+        pub program_memory: HashSet<general_model::Program>,
+        pub version_memory: HashSet<general_model::Version>,
+        pub version_updater: Updater,
+        pub kafka_offset: Option<i64>,
+    }
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(serde::Serialize, Default))]
+    pub(super) struct Version {
+        pub name_and_version: String,
+    }
+
+    #[allow(non_snake_case, dead_code)]
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(serde::Serialize, Default))]
+    pub(super) struct DependsOn {
+        pub SRC_ID: String,
+        pub DST_ID: String,
+    }
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(serde::Serialize, Default))]
+    pub(super) struct Updater {
+        /// `(requirement, dependent)` of every crate name
+        pub reverse_depends_on_map: HashMap<String, Vec<(String, general_model::Version)>>,
+        pub actually_depends_on_map: HashMap<general_model::Version, Vec<general_model::Version>>,
+        pub version_parser: Parser,
+    }
+
+    #[derive(Deserialize)]
+    #[cfg_attr(test, derive(serde::Serialize, Default))]
+    pub(super) struct Parser {
+        pub version_map: HashMap<String, Vec<String>>,
+    }
+
+    pub(super) fn decode(content: &[u8]) -> Result<crate::ImportContext, String> {
+        let context: ImportContext = bincode::deserialize(content)
+            .map_err(|e| format!("Deserialization error (version 0): {}", e))?;
+        Ok(context.into())
+    }
+
+    /// A version 0 dependency was a registry `[dependencies]` entry as far as
+    /// anyone knew, only its requirement was recorded.
+    fn usage(requirement: &str) -> DependencyUsage {
+        DependencyUsage::from(&DependencySpec {
+            version: requirement.to_string(),
+            ..Default::default()
+        })
+    }
+
+    impl From<Updater> for VersionUpdater {
+        fn from(old: Updater) -> Self {
+            let requirements: HashMap<(&str, &general_model::Version), &str> = old
+                .reverse_depends_on_map
+                .iter()
+                .flat_map(|(name, reverses)| {
+                    reverses.iter().map(move |(requirement, dependent)| {
+                        ((name.as_str(), dependent), requirement.as_str())
+                    })
+                })
+                .collect();
+            let actually_depends_on_map = old
+                .actually_depends_on_map
+                .iter()
+                .map(|(dependent, dependencies)| {
+                    let edges = dependencies
+                        .iter()
+                        .map(|dependency| DependencyEdge {
+                            dependency: dependency.clone(),
+                            usage: usage(
+                                requirements
+                                    .get(&(dependency.name.as_str(), dependent))
+                                    .copied()
+                                    .unwrap_or_default(),
+                            ),
+                        })
+                        .collect();
+                    (dependent.clone(), edges)
+                })
+                .collect();
+            let reverse_depends_on_map = old
+                .reverse_depends_on_map
+                .into_iter()
+                .map(|(name, reverses)| {
+                    let reverses = reverses
+                        .into_iter()
+                        .map(|(requirement, dependent)| ReverseDependency {
+                            usage: usage(&requirement),
+                            requirement,
+                            dependent,
+                        })
+                        .collect();
+                    (name, reverses)
+                })
+                .collect();
+            let mut updater = VersionUpdater::default();
+            updater.reverse_depends_on_map = reverse_depends_on_map;
+            updater.actually_depends_on_map = actually_depends_on_map;
+            updater.version_parser = VersionParser::with_versions(old.version_parser.version_map);
+            updater
+        }
+    }
+
+    /// Tag times of the versions were not recorded, `depends_on_at_publish`
+    /// only covers versions with a crates.io publish time until they are
+    /// imported again.
+    impl From<ImportContext> for crate::ImportContext {
+        fn from(old: ImportContext) -> Self {
+            crate::ImportContext {
+                dont_clone: old.dont_clone,
+                programs: old.programs,
+                libraries: old.libraries,
+                applications: old.applications,
+                library_versions: old.library_versions,
+                application_versions: old.application_versions,
+                versions: old
+                    .versions
+                    .iter()
+                    .map(|v| model::tugraph_model::Version::new(&v.name_and_version))
+                    .collect(),
+                licenses: old.licenses,
+                has_lib_type: old.has_lib_type,
+                has_app_type: old.has_app_type,
+                lib_has_version: old.lib_has_version,
+                app_has_version: old.app_has_version,
+                lib_has_dep_version: old.lib_has_dep_version,
+                app_has_dep_version: old.app_has_dep_version,
+                // rebuilt from the version updater before they are written
+                depends_on: vec![],
+                depends_on_at_publish: vec![],
+                unresolved_dependencies: vec![],
+                program_memory: old.program_memory,
+                version_memory: old.version_memory,
+                version_updater: old.version_updater.into(),
+                kafka_offset: old.kafka_offset,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use model::general_model::{self, DependencyKind, DependencySource};
+
+    use super::{decode, encode, v0, CURRENT_VERSION, MAGIC};
+    use crate::ImportContext;
+
+    fn version(name: &str, version: &str) -> general_model::Version {
+        general_model::Version::new(name, version)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut context = ImportContext {
+            kafka_offset: Some(42),
+            ..Default::default()
+        };
+        context
+            .versions
+            .push(model::tugraph_model::Version::new("serde/1.0.0"));
+        context
+            .version_updater
+            .published_at
+            .insert(version("serde", "1.0.0"), 1_600_000_000);
+
+        let content = encode(&context).unwrap();
+        assert!(content.starts_with(MAGIC));
+        let loaded = decode(&content).unwrap();
+        assert_eq!(loaded.kafka_offset, Some(42));
+        assert_eq!(loaded.versions[0].features, "{}");
+        assert_eq!(
+            loaded.version_updater.published_at[&version("serde", "1.0.0")],
+            1_600_000_000
+        );
+    }
+
+    #[test]
+    fn test_unknown_version_is_refused() {
+        let mut content = MAGIC.to_vec();
+        content.extend_from_slice(&(CURRENT_VERSION + 1).to_le_bytes());
+        content.extend_from_slice(&[0; 16]);
+        let err = decode(&content).err().unwrap();
+        assert!(err.contains("Unsupported checkpoint version"), "{}", err);
+        assert!(decode(&MAGIC[..]).is_err());
+    }
+
+    #[test]
+    fn test_migrate_version_0() {
+        let dependent = version("app", "0.1.0");
+        let mut old = v0::ImportContext {
+            kafka_offset: Some(7),
+            ..Default::default()
+        };
+        old.versions.push(v0::Version {
+            name_and_version: "serde/1.0.0".to_string(),
+        });
+        old.depends_on.push(v0::DependsOn {
+            SRC_ID: "app/0.1.0".to_string(),
+            DST_ID: "serde/1.0.0".to_string(),
+        });
+        old.version_updater.reverse_depends_on_map.insert(
+            "serde".to_string(),
+            vec![("^1".to_string(), dependent.clone())],
+        );
+        old.version_updater
+            .actually_depends_on_map
+            .insert(dependent.clone(), vec![version("serde", "1.0.0")]);
+        old.version_updater
+            .version_parser
+            .version_map
+            .insert("serde".to_string(), vec!["1.0.0".to_string()]);
+
+        let context = decode(&bincode::serialize(&old).unwrap()).unwrap();
+        assert_eq!(context.kafka_offset, Some(7));
+        assert_eq!(context.versions[0].name_and_version, "serde/1.0.0");
+        assert_eq!(context.versions[0].features, "{}");
+
+        let updater = &context.version_updater;
+        assert!(updater.version_parser.exists("serde", "1.0.0"));
+        let reverse = &updater.reverse_depends_on_map["serde"][0];
+        assert_eq!(reverse.requirement, "^1");
+        assert_eq!(reverse.dependent, dependent);
+        let edge = &updater.actually_depends_on_map[&dependent][0];
+        assert_eq!(edge.dependency, version("serde", "1.0.0"));
+        assert_eq!(edge.usage.requirement, "^1");
+        assert_eq!(edge.usage.kind, DependencyKind::Normal);
+        assert_eq!(edge.usage.source, DependencySource::Registry);
+        assert!(edge.usage.default_features);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::{
     utils::{get_namespace_by_repo_path, insert_program_by_name},
+    workspace::{inherit_from_workspace, relative_root},
     Licenses,
 };
 use model::tugraph_model::{Application, HasType, Library, Program, UProgram};
@@ -170,8 +171,12 @@ async fn from_cargo_toml(
     id: &str,
     lic: &mut Vec<Licenses>,
 ) -> Result<Program, Box<dyn std::error::Error>> {
-    let content = fs::read_to_string(cargo_toml_path)?;
-    let parsed = content.parse::<Value>()?;
+    let content = fs::read_to_string(&cargo_toml_path)?;
+    let mut parsed = content.parse::<Value>()?;
+    if let Some((root_dir, root)) = read_workspace_root(&local_repo_path, &cargo_toml_path) {
+        let member_dir = cargo_toml_path.parent().unwrap_or(&root_dir);
+        inherit_from_workspace(&mut parsed, &root, &relative_root(member_dir, &root_dir));
+    }
 
     // 处理description,将多行字符串转换为单行,用\n替换换行符
     let description = parsed["package"]
@@ -234,3 +239,20 @@ async fn from_cargo_toml(
     lic.push(newlicense);
     Ok(program)
 }
+
+/// The nearest manifest with a `[workspace]` table between the package and
+/// the repository root, the package's own manifest included.
+fn read_workspace_root(local_repo_path: &Path, cargo_toml_path: &Path) -> Option<(PathBuf, Value)> {
+    cargo_toml_path
+        .parent()?
+        .ancestors()
+        .take_while(|dir| dir.starts_with(local_repo_path))
+        .find_map(|dir| {
+            let content = fs::read_to_string(dir.join("Cargo.toml")).ok()?;
+            let manifest = content.parse::<Value>().ok()?;
+            manifest
+                .get("workspace")
+                .is_some()
+                .then(|| (dir.to_path_buf(), manifest))
+        })
+}
@@ -0,0 +1,44 @@
+//! Lookups in the crates.io database dump.
+
+use std::env;
+use std::error::Error;
+
+use sqlx::{postgres::PgConnection, Connection};
+
+fn cratesio_url_from_env() -> String {
+    format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env::var("POSTGRES_USER_NAME").unwrap(),
+        env::var("POSTGRES_USER_PASSWORD").unwrap(),
+        env::var("POSTGRES_HOST_IP").unwrap(),
+        env::var("POSTGRES_HOST_PORT").unwrap(),
+        env::var("POSTGRES_CRATESIO_DB").unwrap()
+    )
+}
+
+/// `(name, version)` of every yanked version.
+pub(crate) async fn get_yanked_versions() -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    let mut conn = PgConnection::connect(&cratesio_url_from_env()).await?;
+    let yanked = sqlx::query_as::<_, (String, String)>(
+        "SELECT crates.name, versions.num FROM versions \
+         JOIN crates ON crates.id = versions.crate_id \
+         WHERE versions.yanked",
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    conn.close().await?;
+    Ok(yanked)
+}
+
+/// `(name, version, publish time in seconds)` of every version.
+pub(crate) async fn get_publish_times() -> Result<Vec<(String, String, i64)>, Box<dyn Error>> {
+    let mut conn = PgConnection::connect(&cratesio_url_from_env()).await?;
+    let times = sqlx::query_as::<_, (String, String, i64)>(
+        "SELECT crates.name, versions.num, EXTRACT(EPOCH FROM versions.created_at)::BIGINT \
+         FROM versions JOIN crates ON crates.id = versions.crate_id",
+    )
+    .fetch_all(&mut conn)
+    .await?;
+    conn.close().await?;
+    Ok(times)
+}
@@ -1,14 +1,20 @@
+mod checkpoint;
 mod crate_info;
+mod cratesio;
 mod git;
 mod kafka_handler;
+mod local_upload;
+mod upload_status;
 mod utils;
 mod version_info;
+mod workspace;
 
 extern crate lazy_static;
 extern crate pretty_env_logger;
 
 use crate::crate_info::extract_info_local;
 use crate::kafka_handler::KafkaHandler;
+use crate::upload_status::report_upload;
 use crate::utils::{
     extract_namespace, get_program_by_name, insert_namespace_by_repo_path, name_join_version,
     write_into_csv,
@@ -16,7 +22,10 @@ use crate::utils::{
 
 //use git::hard_reset_to_head;
 use git2::{ObjectType, Oid, Repository};
-use model::{repo_sync_model, tugraph_model::*};
+use model::{
+    repo_sync_model::{self, UploadStatus},
+    tugraph_model::*,
+};
 use rdkafka::error::KafkaError;
 use rdkafka::message::BorrowedMessage;
 use rdkafka::Message;
@@ -32,7 +41,7 @@ use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
 use url::Url;
-use version_info::VersionUpdater;
+use version_info::{UnresolvedDependency, VersionUpdater};
 
 const CLONE_CRATES_DIR: &str = "/mnt/crates/local_crates_file/";
 // const TUGRAPH_IMPORT_FILES_PG: &str = "./tugraph_import_files_mq/";
@@ -73,12 +82,15 @@ impl ImportDriver {
             .await
             .expect("Failed to initialize Kafka handlers");
 
-        let context = if !should_reset_kafka_offset {
+        let mut context = if !should_reset_kafka_offset {
             // 如果不需要重置offset，则从checkpoint中恢复context
             let checkpoint_dir =
                 env::var("CHECKPOINT_DIR").unwrap_or_else(|_| "./checkpoints".to_string());
             let checkpoint_path = format!("{}/latest.json", checkpoint_dir);
 
+            let checkpoint_exists = tokio::fs::try_exists(&checkpoint_path)
+                .await
+                .unwrap_or(false);
             match ImportContext::load_from_file(&checkpoint_path).await {
                 Ok(mut ctx) => {
                     // 如果有保存的 offset 且不需要重置到0，则恢复到该位置
@@ -92,6 +104,11 @@ impl ImportDriver {
                     tracing::info!("Restored context from checkpoint");
                     ctx
                 }
+                // starting over would silently drop everything imported so far
+                Err(e) if checkpoint_exists => panic!(
+                    "Failed to load checkpoint {}: {}, set SHOULD_RESET_KAFKA_OFFSET=1 to import from scratch",
+                    checkpoint_path, e
+                ),
                 Err(e) => {
                     tracing::warn!("Failed to load checkpoint: {}", e);
                     ImportContext {
@@ -111,6 +128,25 @@ impl ImportDriver {
 
         tracing::info!("Finish to setup Kafka client.");
 
+        if env::var("IMPORT_EXCLUDE_YANKED").is_ok_and(|v| v == "1") {
+            match cratesio::get_yanked_versions().await {
+                Ok(yanked) => {
+                    tracing::info!("Excluding {} yanked versions", yanked.len());
+                    context.version_updater.version_parser.set_yanked(yanked);
+                }
+                Err(e) => tracing::error!("Failed to read yanked versions: {}", e),
+            }
+        }
+        if env::var("IMPORT_CRATESIO_PUBLISH_TIMES").is_ok_and(|v| v == "1") {
+            match cratesio::get_publish_times().await {
+                Ok(times) => {
+                    tracing::info!("Read {} crates.io publish times", times.len());
+                    context.version_updater.set_cratesio_publish_times(times);
+                }
+                Err(e) => tracing::error!("Failed to read publish times: {}", e),
+            }
+        }
+
         Self {
             context,
             import_handler,
@@ -184,148 +220,131 @@ impl ImportDriver {
             self.context.print_status().await;
         }
 
-        /*if matches!(kind, MessageKind::UserUpload) {
-            //from user upload
-            tracing::info!("user upload path:{}", model.clone().unwrap().mega_url);
-            let usr_upload_path = model.unwrap().mega_url;
-            let namespace = extract_namespace(&usr_upload_path).expect("Failed to parse URL");
-            let path = PathBuf::from(&usr_upload_path);
-            insert_namespace_by_repo_path(path.to_str().unwrap().to_string(), namespace.clone());
-            let new_versions = self
-                .context
-                .parse_a_local_repo_and_return_new_versions(path, "".to_string())
-                .await
-                .unwrap();
-            for ver in new_versions {
-                self.sender_handler
-                    .send_message(
-                        &kafka_analysis_topic,
-                        "",
-                        &serde_json::to_string(&ver).unwrap(),
-                    )
-                    .await;
+        // user uploads carry the id of their `upload_status` row
+        let upload_id = match kind {
+            MessageKind::UserUpload => model
+                .as_ref()
+                .map(|m| m.extra_field.clone())
+                .filter(|id| !id.is_empty()),
+            MessageKind::Mega => None,
+        };
+
+        let model = model.unwrap().db_model;
+        let mega_url_suffix = model.mega_url;
+        // archives uploaded by users are read from the shared upload directory
+        let local_upload = match kind {
+            MessageKind::UserUpload => {
+                repo_sync_model::upload_id_of_location(&mega_url_suffix).map(str::to_string)
             }
-        } else {*/
-        //from mega
-        let mega_url_suffix = model.unwrap().db_model.mega_url;
+            MessageKind::Mega => None,
+        };
 
         let clone_crates_dir =
             env::var("NEW_CRATES_DIR").unwrap_or_else(|_| CLONE_CRATES_DIR.to_string());
         let split_crates_dir =
             env::var("SPLIT_CRATES_DIR").unwrap_or_else(|_| CLONE_CRATES_DIR.to_string());
-        //changes clone_or_not_clone
-        let git_url = {
-            let git_url_base = Url::parse(&git_url_base)
-                .unwrap_or_else(|_| panic!("Failed to parse mega url base: {}", &git_url_base));
-            git_url_base
-                .join(&mega_url_suffix)
-                .expect("Failed to join url path")
+        let (namespace, crate_name) = match &local_upload {
+            Some(id) => (repo_sync_model::upload_namespace(id), model.crate_name),
+            None => {
+                let git_url_base = Url::parse(&git_url_base)
+                    .unwrap_or_else(|_| panic!("Failed to parse mega url base: {}", &git_url_base));
+                let git_url = git_url_base
+                    .join(&mega_url_suffix)
+                    .expect("Failed to join url path");
+                let namespace = extract_namespace(git_url.as_ref()).expect("Failed to parse URL");
+                let parts: Vec<&str> = namespace.split("/").collect();
+                let mut crate_name = "".to_string();
+                if parts.len() == 2 {
+                    crate_name = parts[1].to_string();
+                }
+                (namespace, crate_name)
+            }
         };
-        let namespace = extract_namespace(git_url.as_ref()).expect("Failed to parse URL");
         let path = PathBuf::from(&clone_crates_dir).join(namespace.clone());
 
-        //changes
         if !path.is_dir() {
-            //if user_upload, no clone
             tracing::info!("dir {} not exist", path.to_str().unwrap().to_string());
             let clone_start_time = Instant::now();
-            let local_repo_path = match self
-                .context
-                .clone_a_repo_by_url(&clone_crates_dir, &git_url_base, &mega_url_suffix)
-                .await
-            {
-                Ok(x) => x,
-                _ => {
-                    tracing::error!("Failed to clone repo {}", mega_url_suffix);
-                    return Err(());
-                }
+            let cloned = match &local_upload {
+                Some(id) => local_upload::clone_upload(&local_upload::upload_root(), id, &path),
+                None => self
+                    .context
+                    .clone_a_repo_by_url(&clone_crates_dir, &git_url_base, &mega_url_suffix)
+                    .await
+                    .map(|_| ())
+                    .map_err(|_| format!("failed to clone {}", mega_url_suffix)),
             };
+            if let Err(reason) = cloned {
+                tracing::error!("Failed to clone repo {}: {}", mega_url_suffix, reason);
+                if let Some(id) = &upload_id {
+                    report_upload(id, UploadStatus::Failed, Some(&reason)).await;
+                }
+                return Err(());
+            }
             let clone_need_time = clone_start_time.elapsed();
             tracing::trace!("clone need time: {:?}", clone_need_time);
-            let parts: Vec<&str> = namespace.split("/").collect();
-            let mut crate_name = "".to_string();
-            if parts.len() == 2 {
-                crate_name = parts[1].to_string();
-            }
-            let _ = self
-                .export_tags(
-                    path.to_str().unwrap(),
-                    &split_crates_dir,
-                    namespace.clone(),
-                    crate_name,
-                )
-                .await
-                .unwrap();
-            let new_versions = self
-                .context
-                .parse_a_local_repo_and_return_new_versions(local_repo_path, mega_url_suffix)
-                .await
-                .unwrap();
-
-            //if matches!(kind, MessageKind::UserUpload) {
-            for ver in new_versions {
-                self.sender_handler
-                    .send_message(
-                        &kafka_analysis_topic,
-                        "",
-                        &serde_json::to_string(&ver).unwrap(),
-                    )
-                    .await;
-                tracing::info!(
-                    "send message successfully:{},{},{}",
-                    ver.name,
-                    ver.version,
-                    ver.git_url
-                );
-            }
-            //}
         } else {
             tracing::info!("dir {} already exist", path.to_str().unwrap().to_string());
-            let insert_time = Instant::now();
-            insert_namespace_by_repo_path(path.to_str().unwrap().to_string(), namespace.clone());
-            let insert_need_time = insert_time.elapsed();
-            tracing::trace!(
-                "insert_namespace_by_repo_path need time: {:?}",
-                insert_need_time
-            );
-            let parts: Vec<&str> = namespace.split("/").collect();
-            let mut crate_name = "".to_string();
-            if parts.len() == 2 {
-                crate_name = parts[1].to_string();
+        }
+        let insert_time = Instant::now();
+        insert_namespace_by_repo_path(path.to_str().unwrap().to_string(), namespace.clone());
+        let insert_need_time = insert_time.elapsed();
+        tracing::trace!(
+            "insert_namespace_by_repo_path need time: {:?}",
+            insert_need_time
+        );
+        if let Err(e) = self
+            .export_tags(
+                path.to_str().unwrap(),
+                &split_crates_dir,
+                namespace.clone(),
+                crate_name,
+            )
+            .await
+            .map_err(|e| e.to_string())
+        {
+            tracing::error!("Failed to export tags of {}: {}", mega_url_suffix, e);
+            if let Some(id) = &upload_id {
+                let reason = format!("failed to export the tags: {}", e);
+                report_upload(id, UploadStatus::Failed, Some(&reason)).await;
             }
-            let _ = self
-                .export_tags(
-                    path.to_str().unwrap(),
-                    &split_crates_dir,
-                    namespace.clone(),
-                    crate_name,
-                )
-                .await
-                .unwrap();
-            let new_versions = self
-                .context
-                .parse_a_local_repo_and_return_new_versions(path, mega_url_suffix)
-                .await
-                .unwrap();
-            //if matches!(kind, MessageKind::UserUpload) {
-            for ver in new_versions {
-                self.sender_handler
-                    .send_message(
-                        &kafka_analysis_topic,
-                        "",
-                        &serde_json::to_string(&ver).unwrap(),
-                    )
-                    .await;
-                tracing::info!(
-                    "send message successfully:{},{},{}",
-                    ver.name,
-                    ver.version,
-                    ver.git_url
-                );
+            return Err(());
+        }
+        let new_versions = match self
+            .context
+            .parse_a_local_repo_and_return_new_versions(path, mega_url_suffix.clone())
+            .await
+        {
+            Ok(new_versions) => new_versions,
+            Err(e) => {
+                tracing::error!("Failed to parse repo {}: {}", mega_url_suffix, e);
+                if let Some(id) = &upload_id {
+                    let reason = format!("failed to parse the crate: {}", e);
+                    report_upload(id, UploadStatus::Failed, Some(&reason)).await;
+                }
+                return Err(());
             }
-            //}
-        } //changes
-          //self.context.write_tugraph_import_files();
+        };
+        // before the analysis messages, which the workers may pick up at once
+        if let Some(id) = &upload_id {
+            report_upload(id, UploadStatus::Imported, None).await;
+        }
+        for mut ver in new_versions {
+            ver.upload_id = upload_id.clone();
+            self.sender_handler
+                .send_message(
+                    &kafka_analysis_topic,
+                    "",
+                    &serde_json::to_string(&ver).unwrap(),
+                )
+                .await;
+            tracing::info!(
+                "send message successfully:{},{},{}",
+                ver.name,
+                ver.version,
+                ver.git_url
+            );
+        }
         tracing::info!("Finish to import from a message!");
         Ok(())
     }
@@ -514,6 +533,10 @@ pub struct ImportContext {
     app_has_dep_version: Vec<HasDepVersion>,
 
     pub depends_on: Vec<DependsOn>,
+    /// `depends_on` as resolved when the dependent was published
+    pub depends_on_at_publish: Vec<DependsOn>,
+    /// requirements that did not resolve to a version
+    pub unresolved_dependencies: Vec<UnresolvedDependency>,
 
     /// help is judge whether it is a new program
     program_memory: HashSet<model::general_model::Program>,
@@ -763,6 +786,7 @@ impl ImportContext {
 
                         let dep_version = Version {
                             name_and_version: name_join_version(&name, &version),
+                            features: serde_json::to_string(&dependencies.features).unwrap(),
                         };
 
                         #[allow(non_snake_case)]
@@ -916,6 +940,10 @@ impl ImportContext {
     async fn normalize(&mut self) {
         self.depends_on
             .clone_from(&(self.version_updater.to_depends_on_edges().await));
+        self.depends_on_at_publish
+            .clone_from(&(self.version_updater.to_depends_on_at_publish_edges().await));
+        self.unresolved_dependencies
+            .clone_from(&(self.version_updater.to_unresolved_dependencies().await));
     }
 
     /// write data base into tugraph import files
@@ -995,6 +1023,14 @@ impl ImportContext {
             tugraph_import_files.join("depends_on.csv"),
             self.depends_on.clone(),
         );
+        let _ = write_into_csv(
+            tugraph_import_files.join("depends_on_at_publish.csv"),
+            self.depends_on_at_publish.clone(),
+        );
+        let _ = write_into_csv(
+            tugraph_import_files.join("unresolved_dependencies.csv"),
+            self.unresolved_dependencies.clone(),
+        );
         tracing::info!("Finish to write");
         let write_need_time = write_time.elapsed();
         tracing::trace!("write need time: {:?}", write_need_time);
@@ -1002,8 +1038,7 @@ impl ImportContext {
 
     pub async fn save_to_file(&mut self, path: &str) -> Result<(), String> {
         self.normalize().await;
-        let serialized =
-            bincode::serialize(self).map_err(|e| format!("Serialization error: {}", e))?;
+        let serialized = checkpoint::encode(self)?;
 
         let mut file = File::create(path)
             .await
@@ -1023,8 +1058,7 @@ impl ImportContext {
             .await
             .map_err(|e| format!("Failed to read file: {}", e))?;
 
-        let context: ImportContext =
-            bincode::deserialize(&content).map_err(|e| format!("Deserialization error: {}", e))?;
+        let context = checkpoint::decode(&content)?;
         tracing::info!(
             "Context loaded successfully, there are {} programs",
             context.programs.len()
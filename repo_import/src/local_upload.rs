@@ -0,0 +1,173 @@
+//! Archives uploaded by users, imported from disk instead of cloned from mega.
+//!
+//! The API server extracts an upload to `UPLOAD_DIR/<id>/` and publishes it
+//! with the `mega_url` of [`model::repo_sync_model::upload_location`]. The
+//! extracted sources are no git repository: they are committed as one, tagged
+//! with the version of the crate, and cloned into the crates directory like a
+//! repository of mega, so that its tag is exported and its versions parsed
+//! the same way.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use git2::{IndexAddOption, Repository, Signature};
+use walkdir::WalkDir;
+
+/// `UPLOAD_DIR`, shared with the API server, `target/www/uploads` by default.
+pub(crate) fn upload_root() -> PathBuf {
+    env::var("UPLOAD_DIR")
+        .unwrap_or_else(|_| "target/www/uploads".to_string())
+        .into()
+}
+
+/// Clones the upload `upload_id` of `root` into `dest`.
+pub(crate) fn clone_upload(root: &Path, upload_id: &str, dest: &Path) -> Result<(), String> {
+    let source = root.join(upload_id);
+    if !source.is_dir() {
+        return Err(format!("upload directory {} not found", source.display()));
+    }
+    snapshot(&source)?;
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("cannot create {}: {}", parent.display(), e))?;
+    }
+    let source = source
+        .to_str()
+        .ok_or_else(|| format!("upload directory {} is not UTF-8", source.display()))?;
+    Repository::clone(source, dest)
+        .map_err(|e| format!("cannot clone upload {}: {}", upload_id, e))?;
+    Ok(())
+}
+
+/// Commits the sources in `dir` and tags the commit with the version of the
+/// crate. A repository left by an earlier delivery of the message is kept.
+fn snapshot(dir: &Path) -> Result<(), String> {
+    let git_error = |e: git2::Error| format!("cannot commit upload {}: {}", dir.display(), e);
+    if Repository::open(dir).is_ok() {
+        return Ok(());
+    }
+    let version = package_version(dir)?;
+    let repo = Repository::init(dir).map_err(git_error)?;
+    let mut index = repo.index().map_err(git_error)?;
+    index
+        .add_all(["*"], IndexAddOption::DEFAULT, None)
+        .map_err(git_error)?;
+    index.write().map_err(git_error)?;
+    let tree = repo
+        .find_tree(index.write_tree().map_err(git_error)?)
+        .map_err(git_error)?;
+    let signature = Signature::now("crates-pro", "crates-pro@localhost").map_err(git_error)?;
+    let commit = repo
+        .commit(Some("HEAD"), &signature, &signature, "upload", &tree, &[])
+        .map_err(git_error)?;
+    let commit = repo.find_object(commit, None).map_err(git_error)?;
+    repo.tag_lightweight(&version, &commit, false)
+        .map_err(git_error)?;
+    Ok(())
+}
+
+/// The version of the package of the shallowest `Cargo.toml` under `dir`.
+fn package_version(dir: &Path) -> Result<String, String> {
+    WalkDir::new(dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_name() == "Cargo.toml")
+        .min_by_key(|entry| entry.depth())
+        .and_then(|entry| fs::read_to_string(entry.path()).ok())
+        .and_then(|content| content.parse::<toml::Value>().ok())
+        .and_then(|manifest| {
+            manifest
+                .get("package")?
+                .get("version")?
+                .as_str()
+                .map(str::to_string)
+        })
+        .ok_or_else(|| format!("no package version in the upload {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use model::repo_sync_model::{
+        upload_id_of_location, upload_location, upload_namespace, MessageModel,
+    };
+
+    use super::clone_upload;
+    use crate::ImportContext;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{}-{}", name, uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_upload_location() {
+        assert_eq!(upload_id_of_location(&upload_location("42")), Some("42"));
+        assert_eq!(upload_id_of_location("upload:///uploads/../etc"), None);
+        assert_eq!(upload_id_of_location("upload:///uploads/a/b"), None);
+        assert_eq!(upload_id_of_location("upload:///uploads/"), None);
+        assert_eq!(upload_id_of_location("/third-party/tokio"), None);
+    }
+
+    #[tokio::test]
+    async fn test_import_an_uploaded_archive() {
+        // as extracted by the API server, a `.crate` unpacks into `<name>-<version>/`
+        let root = scratch_dir("uploads");
+        let sources = root.join("42").join("demo-0.3.1");
+        fs::create_dir_all(sources.join("src")).unwrap();
+        fs::write(
+            sources.join("Cargo.toml"),
+            "[package]\nname = \"demo\"\nversion = \"0.3.1\"\n\n[dependencies]\nserde = \"1\"\n",
+        )
+        .unwrap();
+        fs::write(sources.join("src/lib.rs"), "pub fn demo() {}\n").unwrap();
+        // the payload the API server publishes for the upload
+        let payload = serde_json::json!({
+            "db_model": {
+                "id": 0,
+                "crate_name": "demo",
+                "github_url": null,
+                "mega_url": upload_location("42"),
+                "crate_type": "Lib",
+                "status": "Syncing",
+                "err_message": null,
+            },
+            "message_kind": "User",
+            "source_of_data": "Github",
+            "timestamp": "2025-01-01T00:00:00Z",
+            "extra_field": "42",
+        });
+        let message: MessageModel = serde_json::from_value(payload).unwrap();
+
+        let upload = upload_id_of_location(&message.db_model.mega_url).unwrap();
+        let crates_dir = scratch_dir("crates");
+        let path = crates_dir.join(upload_namespace(upload));
+        clone_upload(&root, upload, &path).unwrap();
+        // a second delivery of the message reuses the snapshot
+        clone_upload(&root, upload, &crates_dir.join("again")).unwrap();
+
+        crate::utils::insert_namespace_by_repo_path(
+            path.to_str().unwrap().to_string(),
+            upload_namespace(upload),
+        );
+        let mut context = ImportContext::default();
+        let versions = context
+            .parse_a_local_repo_and_return_new_versions(path, message.db_model.mega_url.clone())
+            .await
+            .unwrap();
+        assert_eq!(versions.len(), 1);
+        assert_eq!(versions[0].name, "demo");
+        assert_eq!(versions[0].version, "0.3.1");
+        assert_eq!(versions[0].git_url, message.db_model.mega_url);
+
+        assert!(clone_upload(&root, "missing", &crates_dir.join("missing")).is_err());
+        fs::remove_dir_all(root).unwrap();
+        fs::remove_dir_all(crates_dir).unwrap();
+    }
+}
@@ -0,0 +1,49 @@
+//! Progress of user uploads in the `upload_status` table of the cratespro
+//! database, see `/api/uploads/{id}`.
+
+use std::env;
+use std::error::Error;
+
+use model::repo_sync_model::UploadStatus;
+use sqlx::{postgres::PgConnection, Connection};
+
+fn cratespro_url_from_env() -> Result<String, env::VarError> {
+    Ok(format!(
+        "postgres://{}:{}@{}:{}/{}",
+        env::var("POSTGRES_USER_NAME")?,
+        env::var("POSTGRES_USER_PASSWORD")?,
+        env::var("POSTGRES_HOST_IP")?,
+        env::var("POSTGRES_HOST_PORT")?,
+        env::var("POSTGRES_CRATESPRO_DB")?
+    ))
+}
+
+async fn update(
+    id: &str,
+    status: UploadStatus,
+    message: Option<&str>,
+) -> Result<(), Box<dyn Error>> {
+    let previous: Vec<&str> = status.previous().iter().map(UploadStatus::as_str).collect();
+    let mut conn = PgConnection::connect(&cratespro_url_from_env()?).await?;
+    sqlx::query(
+        "UPDATE upload_status SET status = $2, message = $3, updated_at = NOW()
+        WHERE id = $1 AND status = ANY($4)",
+    )
+    .bind(id)
+    .bind(status.as_str())
+    .bind(message)
+    .bind(previous)
+    .execute(&mut conn)
+    .await?;
+    conn.close().await?;
+    Ok(())
+}
+
+/// Moves upload `id` to `status` if it is in one of
+/// [`UploadStatus::previous`], failures are logged since the import itself
+/// went on.
+pub(crate) async fn report_upload(id: &str, status: UploadStatus, message: Option<&str>) {
+    if let Err(e) = update(id, status, message).await {
+        tracing::error!("Failed to set upload {} to {}: {}", id, status.as_str(), e);
+    }
+}
@@ -1,13 +1,17 @@
 use crate::git::get_all_git_tags_with_time_sorted;
-use crate::utils::name_join_version;
+use crate::utils::{get_program_by_name, name_join_version};
+use crate::workspace::{
+    find_workspace_root, inherit_from_workspace, relative_root, resolve_relative,
+};
 use crate::ImportContext;
 use git2::{Oid, Repository};
 use git2::{TreeWalkMode, TreeWalkResult};
+use model::general_model::{DependencyKind, DependencySource};
 use model::tugraph_model::DependsOn;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::mem;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use toml::Value;
 
 /// A representation for the info
@@ -17,10 +21,276 @@ use toml::Value;
 pub struct Dependencies {
     pub(crate) crate_name: String,
     pub(crate) version: String,
-    pub(crate) dependencies: Vec<(String, String)>,
+    pub(crate) dependencies: Vec<DependencySpec>,
+    /// The `[features]` table.
+    pub(crate) features: BTreeMap<String, Vec<String>>,
 
     pub(crate) git_url: String,
     pub(crate) tag_name: String,
+    /// Commit time of the earliest tag holding this version, in seconds.
+    pub(crate) published_at: Option<i64>,
+}
+
+/// A dependency as declared in a `Cargo.toml`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DependencySpec {
+    /// The real package name, i.e. the `package` key of a renamed dependency.
+    pub(crate) name: String,
+    /// The key in the dependency table when it differs from `name`, features
+    /// of the dependent refer to the dependency by this key.
+    pub(crate) rename: Option<String>,
+    /// The version requirement, e.g. `1.0` or `>=0.3, <0.5`.
+    pub(crate) version: String,
+    pub(crate) kind: DependencyKind,
+    /// The key of a `[target.'..']` table, e.g. `cfg(unix)`.
+    pub(crate) target: Option<String>,
+    pub(crate) optional: bool,
+    pub(crate) default_features: bool,
+    pub(crate) features: Vec<String>,
+    pub(crate) source: DependencySource,
+    /// `path = ".."`, relative to the directory of the manifest.
+    pub(crate) path: Option<String>,
+    /// `git = ".."`
+    pub(crate) git: Option<String>,
+}
+
+impl Default for DependencySpec {
+    fn default() -> Self {
+        DependencySpec {
+            name: String::new(),
+            rename: None,
+            version: String::new(),
+            kind: DependencyKind::Normal,
+            target: None,
+            optional: false,
+            default_features: true,
+            features: vec![],
+            source: DependencySource::Registry,
+            path: None,
+            git: None,
+        }
+    }
+}
+
+/// How a dependent uses a dependency, shared by the reverse map and the edges.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DependencyUsage {
+    /// The version requirement as written, e.g. `1.0` or `>=0.3, <0.5`.
+    pub requirement: String,
+    pub kind: DependencyKind,
+    pub target: Option<String>,
+    pub rename: Option<String>,
+    pub optional: bool,
+    pub default_features: bool,
+    pub features: Vec<String>,
+    pub source: DependencySource,
+    pub git: Option<String>,
+}
+
+impl From<&DependencySpec> for DependencyUsage {
+    fn from(spec: &DependencySpec) -> Self {
+        DependencyUsage {
+            requirement: spec.version.clone(),
+            kind: spec.kind,
+            target: spec.target.clone(),
+            rename: spec.rename.clone(),
+            optional: spec.optional,
+            default_features: spec.default_features,
+            features: spec.features.clone(),
+            source: spec.source,
+            git: spec.git.clone(),
+        }
+    }
+}
+
+impl DependencyEdge {
+    fn to_depends_on(&self, src: &model::general_model::Version) -> DependsOn {
+        let usage = &self.usage;
+        DependsOn {
+            SRC_ID: name_join_version(&src.name, &src.version),
+            DST_ID: name_join_version(&self.dependency.name, &self.dependency.version),
+            kind: usage.kind.to_string(),
+            target: usage.target.clone().unwrap_or_default(),
+            rename: usage.rename.clone().unwrap_or_default(),
+            requirement: usage.requirement.clone(),
+            optional: usage.optional,
+            default_features: usage.default_features,
+            features: usage.features.join(","),
+            source: usage.source.to_string(),
+        }
+    }
+}
+
+impl DependencyUsage {
+    /// Whether `dependency` is what this usage points at. A git dependency
+    /// only links to a program cloned from the same repository.
+    fn accepts(&self, dependency: &str) -> bool {
+        let Some(git) = &self.git else {
+            return true;
+        };
+        let Some((program, _)) = get_program_by_name(dependency) else {
+            return false;
+        };
+        [program.mega_url, program.github_url]
+            .iter()
+            .flatten()
+            .any(|url| same_repository(url, git))
+    }
+}
+
+fn same_repository(a: &str, b: &str) -> bool {
+    let normalize = |url: &str| {
+        let url = url.trim().trim_end_matches('/');
+        url.strip_suffix(".git").unwrap_or(url).to_lowercase()
+    };
+    normalize(a) == normalize(b)
+}
+
+/// An outgoing `depends_on` edge of a version.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DependencyEdge {
+    pub dependency: model::general_model::Version,
+    pub usage: DependencyUsage,
+}
+
+/// A dependency requirement no imported version satisfies, written to
+/// `unresolved_dependencies.csv` instead of a `depends_on` edge.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnresolvedDependency {
+    /// `name/version` of the dependent.
+    pub dependent: String,
+    pub name: String,
+    pub requirement: String,
+    pub kind: String,
+    pub source: String,
+    pub reason: String,
+}
+
+/// A dependent waiting for a version of the crate it requires.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReverseDependency {
+    pub requirement: String,
+    pub dependent: model::general_model::Version,
+    pub usage: DependencyUsage,
+}
+
+/// Every dependency of a manifest: the top-level `[dependencies]`,
+/// `[dev-dependencies]` and `[build-dependencies]` tables, and the same
+/// tables nested under `[target.'..']`.
+pub(crate) fn parse_dependency_specs(toml: &Value) -> Vec<DependencySpec> {
+    let mut specs = vec![];
+    collect_dependency_tables(toml, None, &mut specs);
+    if let Some(targets) = toml.get("target").and_then(|t| t.as_table()) {
+        for (target, table) in targets {
+            collect_dependency_tables(table, Some(target), &mut specs);
+        }
+    }
+    specs
+}
+
+/// The `[features]` table of a manifest.
+pub(crate) fn parse_feature_table(toml: &Value) -> BTreeMap<String, Vec<String>> {
+    let mut features = BTreeMap::new();
+    if let Some(table) = toml.get("features").and_then(|f| f.as_table()) {
+        for (feature, enables) in table {
+            let enables = enables
+                .as_array()
+                .map(|values| {
+                    values
+                        .iter()
+                        .filter_map(|v| v.as_str().map(|v| v.to_string()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            features.insert(feature.clone(), enables);
+        }
+    }
+    features
+}
+
+fn collect_dependency_tables(table: &Value, target: Option<&str>, specs: &mut Vec<DependencySpec>) {
+    let Some(table) = table.as_table() else {
+        return;
+    };
+    for (table_name, deps) in table {
+        let Some(kind) = DependencyKind::from_table_name(table_name) else {
+            continue;
+        };
+        let Some(deps) = deps.as_table() else {
+            continue;
+        };
+        for (key, val) in deps {
+            let path = val.get("path").and_then(|p| p.as_str());
+            let git = val.get("git").and_then(|g| g.as_str());
+            let version = if let Some(version) = val.as_str() {
+                version
+            } else if let Some(version) = val.get("version").and_then(|v| v.as_str()) {
+                version
+            } else if git.is_some() {
+                "*"
+            } else if path.is_some() {
+                // resolved to the package found at the path
+                ""
+            } else {
+                continue;
+            };
+            let source = match (path, git) {
+                (Some(_), _) => DependencySource::Path,
+                (None, Some(_)) => DependencySource::Git,
+                (None, None) => DependencySource::Registry,
+            };
+            let package = val.get("package").and_then(|p| p.as_str());
+            let flag = |name: &str, default: bool| {
+                val.get(name).and_then(|v| v.as_bool()).unwrap_or(default)
+            };
+            specs.push(DependencySpec {
+                name: package.unwrap_or(key).to_string(),
+                rename: package.filter(|p| p != key).map(|_| key.clone()),
+                version: version.to_owned(),
+                kind,
+                target: target.map(|t| t.to_string()),
+                optional: flag("optional", false),
+                // `default_features` is the legacy spelling
+                default_features: flag("default-features", flag("default_features", true)),
+                features: val
+                    .get("features")
+                    .and_then(|f| f.as_array())
+                    .map(|features| {
+                        features
+                            .iter()
+                            .filter_map(|f| f.as_str().map(|f| f.to_string()))
+                            .collect()
+                    })
+                    .unwrap_or_default(),
+                source,
+                path: path.map(|p| p.to_string()),
+                git: git.map(|g| g.to_string()),
+            });
+        }
+    }
+}
+
+/// Pins every path dependency to the version of the package found at its
+/// path in the same tree. Path dependencies leading nowhere keep their
+/// `version` if they have one and are dropped otherwise.
+pub(crate) fn resolve_path_dependencies(
+    specs: &mut Vec<DependencySpec>,
+    member_dir: &Path,
+    packages: &BTreeMap<PathBuf, (String, String)>,
+) {
+    specs.retain_mut(|spec| {
+        let Some(path) = &spec.path else {
+            return true;
+        };
+        let sibling = resolve_relative(member_dir, path).and_then(|dir| packages.get(&dir));
+        match sibling {
+            Some((name, version)) if *name == spec.name => {
+                spec.version = format!("={}", version);
+                true
+            }
+            _ => !spec.version.is_empty(),
+        }
+    });
 }
 
 impl ImportContext {
@@ -36,7 +306,7 @@ impl ImportContext {
         let versions = get_all_git_tags_with_time_sorted(repo_path).await; //tag id time
 
         // parse each version of a repository with an order of time, walk all the packages of it
-        for (tag_name, tree, _) in versions.iter() {
+        for (tag_name, tree, time) in versions.iter() {
             let all_packages_dependencies = self
                 .parse_a_repo_of_a_version(repo_path, git_url, tag_name, *tree)
                 .await;
@@ -44,9 +314,14 @@ impl ImportContext {
             // NOTE: At certain time, a version in Cargo.toml will exists in several tags,
             //  while a tag corresponds to a unique Cargo.toml version.
             //  So, I use a map to select the lastest tag which contains the version.
-            for dependencies in all_packages_dependencies {
+            //  The version was published with the earliest of those tags.
+            for mut dependencies in all_packages_dependencies {
                 let name = dependencies.crate_name.clone();
                 let version = dependencies.version.clone();
+                let published_at = crate_version_map
+                    .get(&(name.clone(), version.clone()))
+                    .and_then(|earlier| earlier.published_at);
+                dependencies.published_at = published_at.or(Some(*time));
                 crate_version_map.insert((name.clone(), version.clone()), dependencies);
             }
         }
@@ -62,14 +337,14 @@ impl ImportContext {
         tag_name: &str,
         tree: Oid,
     ) -> Vec<Dependencies> {
-        let mut res = Vec::new();
+        let mut manifests = BTreeMap::new();
 
         // Lock the repository and tree for reading
         let repo = Repository::open(repo_path).unwrap();
         let tree = repo.find_tree(tree).expect("Failed to find tree");
 
         // Walk the tree to find Cargo.toml
-        tree.walk(TreeWalkMode::PostOrder, |_, entry| {
+        tree.walk(TreeWalkMode::PostOrder, |root, entry| {
             if entry.name() == Some("Cargo.toml") {
                 // for each Cargo.toml in repo of given commit
                 let obj = entry
@@ -79,95 +354,107 @@ impl ImportContext {
                 let content = std::str::from_utf8(blob.content())
                     .expect("Cargo.toml content is not valid UTF-8");
 
-                if let Some(dependencies) =
-                    self.parse_a_package_of_a_version(content, git_url, tag_name)
-                {
-                    res.push(dependencies);
+                match content.parse::<Value>() {
+                    Ok(toml) => {
+                        manifests.insert(PathBuf::from(root), toml);
+                    }
+                    Err(_) => tracing::error!("Failed to parse Cargo.toml for {:?}", content),
                 }
             }
 
             TreeWalkResult::Ok
         })
         .unwrap();
+        // the root manifests as written, `manifests` is updated in place
+        let snapshot = manifests.clone();
+
+        // a member needs the root manifest of its workspace and path
+        // dependencies need their sibling, so every manifest of the tree is
+        // read before any package is parsed
+        for (dir, manifest) in manifests.iter_mut() {
+            if let Some((root_dir, root)) = find_workspace_root(&snapshot, dir) {
+                inherit_from_workspace(manifest, root, &relative_root(dir, root_dir));
+            }
+        }
+        let packages: BTreeMap<PathBuf, (String, String)> = manifests
+            .iter()
+            .filter_map(|(dir, manifest)| {
+                let package = manifest.get("package")?;
+                let name = package.get("name")?.as_str()?;
+                let version = package.get("version")?.as_str()?;
+                Some((dir.clone(), (name.to_string(), version.to_string())))
+            })
+            .collect();
+
+        let mut res = Vec::new();
+        for (dir, manifest) in &manifests {
+            if let Some(mut dependencies) =
+                self.parse_a_package_of_a_version(manifest, git_url, tag_name)
+            {
+                resolve_path_dependencies(&mut dependencies.dependencies, dir, &packages);
+                res.push(dependencies);
+            }
+        }
 
         res
     }
 
     fn parse_a_package_of_a_version(
         &self,
-        cargo_toml_content: &str,
+        toml: &Value,
         git_url: &str,
         tag_name: &str,
     ) -> Option<Dependencies> {
-        match cargo_toml_content.parse::<Value>() {
-            Ok(toml) => {
-                if let Some(package) = toml.get("package") {
-                    if let Some(crate_name) = package.get("name") {
-                        let crate_name = crate_name.as_str()?.to_string();
-                        let version = package.get("version")?.as_str()?.to_string();
-
-                        // e.g. 0.1.53a2 is invalid version number.
-                        if semver::Version::parse(&version).is_err() {
-                            return None;
-                        }
-
-                        // dedup
-                        if self
-                            .version_updater
-                            .version_parser
-                            .exists(&crate_name, &version)
-                        {
-                            return None;
-                        }
-
-                        let mut dependencies = vec![];
-
-                        if let Some(dep_table) = toml.get("dependencies") {
-                            if let Some(deps_table) = dep_table.as_table() {
-                                for (name, val) in deps_table {
-                                    if let Some(version) = val.as_str() {
-                                        dependencies.push((name.clone(), version.to_owned()));
-                                    } else if let Some(ver_tab) = val.as_table() {
-                                        if let Some(val) = ver_tab.get("version") {
-                                            if let Some(version) = val.as_str() {
-                                                dependencies
-                                                    .push((name.clone(), version.to_owned()));
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-
-                        let dependencies = Dependencies {
-                            crate_name,
-                            version,
-                            dependencies,
-                            git_url: git_url.to_string(),
-                            tag_name: tag_name.to_string(),
-                        };
-
-                        return Some(dependencies);
-                    }
-                }
-            }
-            Err(_) => tracing::error!("Failed to parse Cargo.toml for {:?}", cargo_toml_content),
+        let package = toml.get("package")?;
+        let crate_name = package.get("name")?.as_str()?.to_string();
+        let version = package.get("version")?.as_str()?.to_string();
+
+        // e.g. 0.1.53a2 is invalid version number.
+        if semver::Version::parse(&version).is_err() {
+            return None;
+        }
+
+        // dedup
+        if self
+            .version_updater
+            .version_parser
+            .exists(&crate_name, &version)
+        {
+            return None;
         }
-        None
+
+        let dependencies = parse_dependency_specs(toml);
+        let features = parse_feature_table(toml);
+
+        Some(Dependencies {
+            crate_name,
+            version,
+            dependencies,
+            features,
+            git_url: git_url.to_string(),
+            tag_name: tag_name.to_string(),
+            published_at: None,
+        })
     }
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct VersionUpdater {
     /// a reverse record: who depends on the key?
-    pub reverse_depends_on_map: HashMap<String, Vec<(String, model::general_model::Version)>>,
+    pub reverse_depends_on_map: HashMap<String, Vec<ReverseDependency>>,
 
     /// a actual map: a crate **actually** depends on which?
     /// it is used to build `depends_on` edges.
-    pub actually_depends_on_map:
-        HashMap<model::general_model::Version, Vec<model::general_model::Version>>,
+    pub actually_depends_on_map: HashMap<model::general_model::Version, Vec<DependencyEdge>>,
 
     pub version_parser: VersionParser,
+
+    /// When each version was published, in seconds. crates.io publish times
+    /// take precedence over tag commit times.
+    pub published_at: HashMap<model::general_model::Version, i64>,
+    /// Not checkpointed, read again from crates.io at startup.
+    #[serde(skip)]
+    cratesio_published_at: HashMap<model::general_model::Version, i64>,
 }
 
 impl VersionUpdater {
@@ -175,24 +462,113 @@ impl VersionUpdater {
         let mut edges = vec![];
         for (src, dsts) in &self.actually_depends_on_map {
             for dst in dsts {
-                #[allow(non_snake_case)]
-                let SRC_ID = name_join_version(&src.name, &src.version);
+                edges.push(dst.to_depends_on(src));
+            }
+        }
+        edges
+    }
 
-                #[allow(non_snake_case)]
-                let DST_ID = name_join_version(&dst.name, &dst.version);
-                let depends_on = DependsOn { SRC_ID, DST_ID };
-                edges.push(depends_on);
+    /// `depends_on_at_publish` edges: every requirement bound to the newest
+    /// matching version already published when the dependent was, unlike
+    /// `depends_on` which follows the newest matching version known today.
+    pub async fn to_depends_on_at_publish_edges(&self) -> Vec<DependsOn> {
+        let mut edges = vec![];
+        for (name, reverses) in &self.reverse_depends_on_map {
+            for reverse in reverses {
+                let Some(dependent_time) = self.publish_time(&reverse.dependent) else {
+                    continue;
+                };
+                let published_before = |version: &str| {
+                    self.publish_time(&model::general_model::Version::new(name, version))
+                        .is_some_and(|time| time <= dependent_time)
+                };
+                let Ok(version) =
+                    self.version_parser
+                        .resolve_where(name, &reverse.requirement, published_before)
+                else {
+                    continue;
+                };
+                if !reverse.usage.accepts(name) {
+                    continue;
+                }
+                let edge = DependencyEdge {
+                    dependency: model::general_model::Version::new(name, &version),
+                    usage: reverse.usage.clone(),
+                };
+                edges.push(edge.to_depends_on(&reverse.dependent));
             }
         }
+        edges.sort_by(|a, b| (&a.SRC_ID, &a.DST_ID).cmp(&(&b.SRC_ID, &b.DST_ID)));
+        edges.dedup();
         edges
     }
 
+    pub fn set_cratesio_publish_times(
+        &mut self,
+        times: impl IntoIterator<Item = (String, String, i64)>,
+    ) {
+        self.cratesio_published_at = times
+            .into_iter()
+            .map(|(name, version, time)| {
+                (model::general_model::Version::new(&name, &version), time)
+            })
+            .collect();
+    }
+
+    fn publish_time(&self, version: &model::general_model::Version) -> Option<i64> {
+        self.cratesio_published_at
+            .get(version)
+            .or_else(|| self.published_at.get(version))
+            .copied()
+    }
+
+    /// Requirements of imported versions that did not become an edge.
+    pub async fn to_unresolved_dependencies(&self) -> Vec<UnresolvedDependency> {
+        let mut unresolved = vec![];
+        for (name, reverses) in &self.reverse_depends_on_map {
+            for reverse in reverses {
+                let linked = self
+                    .actually_depends_on_map
+                    .get(&reverse.dependent)
+                    .is_some_and(|edges| {
+                        edges
+                            .iter()
+                            .any(|e| e.dependency.name == *name && e.usage == reverse.usage)
+                    });
+                if linked {
+                    continue;
+                }
+                let reason = match self.version_parser.resolve(name, &reverse.requirement) {
+                    Err(reason) => reason,
+                    Ok(_) if !reverse.usage.accepts(name) => UnresolvedReason::UnmatchedGitSource,
+                    Ok(_) => continue,
+                };
+                unresolved.push(UnresolvedDependency {
+                    dependent: name_join_version(
+                        &reverse.dependent.name,
+                        &reverse.dependent.version,
+                    ),
+                    name: name.clone(),
+                    requirement: reverse.requirement.clone(),
+                    kind: reverse.usage.kind.to_string(),
+                    source: reverse.usage.source.to_string(),
+                    reason: reason.as_str().to_string(),
+                });
+            }
+        }
+        unresolved.sort_by(|a, b| (&a.dependent, &a.name).cmp(&(&b.dependent, &b.name)));
+        unresolved
+    }
+
     /// Given a dependency list,
     pub async fn update_depends_on(&mut self, info: &Dependencies) {
         self.version_parser
             .insert_version(&info.crate_name, &info.version)
             .await;
         let cur_release = model::general_model::Version::new(&info.crate_name, &info.version);
+        if let Some(published_at) = info.published_at {
+            self.published_at.insert(cur_release.clone(), published_at);
+        }
         self.ensure_dependencies(&cur_release, info).await;
         self.ensure_dependents(&cur_release).await;
     }
@@ -202,10 +578,8 @@ impl VersionUpdater {
         cur_release: &model::general_model::Version,
         info: &Dependencies,
     ) {
-        for (name, version) in &info.dependencies {
-            //let dep = model::general_model::Version::new(&name, &version);
-            self.insert_reverse_dep(name, version, &cur_release.name, &cur_release.version)
-                .await;
+        for spec in &info.dependencies {
+            self.insert_reverse_dep(spec, cur_release).await;
         }
 
         // a new version should not exist before.
@@ -215,18 +589,30 @@ impl VersionUpdater {
             .insert(cur_release.clone(), cur_dependencies);
     }
 
-    async fn search_dependencies(&self, info: &Dependencies) -> Vec<model::general_model::Version> {
-        let mut res: Vec<model::general_model::Version> = vec![];
-        for (dependency_name, dependency_version) in &info.dependencies {
+    async fn search_dependencies(&self, info: &Dependencies) -> Vec<DependencyEdge> {
+        let mut res: Vec<DependencyEdge> = vec![];
+        for spec in &info.dependencies {
             let version_option = self
                 .version_parser
-                .find_latest_matching_version(dependency_name, dependency_version)
+                .find_latest_matching_version(&spec.name, &spec.version)
                 .await;
 
             if let Some(dependency_actual_version) = &version_option {
-                let dependency =
-                    model::general_model::Version::new(dependency_name, dependency_actual_version);
-                res.push(dependency);
+                let usage: DependencyUsage = spec.into();
+                if !usage.accepts(&spec.name) {
+                    continue;
+                }
+                let edge = DependencyEdge {
+                    dependency: model::general_model::Version::new(
+                        &spec.name,
+                        dependency_actual_version,
+                    ),
+                    usage,
+                };
+                // the same crate may be listed for several targets
+                if !res.contains(&edge) {
+                    res.push(edge);
+                }
             }
         }
         res
@@ -237,8 +623,8 @@ impl VersionUpdater {
             .unwrap_or_else(|_| panic!("failed to parse version {:?}", &cur_release));
         let wrapped_reverse_map = self.reverse_depends_on_map.get(&cur_release.name);
         if let Some(reverse_map) = wrapped_reverse_map {
-            for (required_version, reverse_dep) in reverse_map {
-                let requirement = match semver::VersionReq::parse(required_version) {
+            for reverse in reverse_map {
+                let requirement = match semver::VersionReq::parse(&reverse.requirement) {
                     Ok(req) => req,
                     Err(_) => {
                         tracing::error!("failed to transform to VersionReq");
@@ -246,44 +632,23 @@ impl VersionUpdater {
                     }
                 };
 
-                if requirement.matches(&sem_ver) {
-                    if let Some(v) = self.actually_depends_on_map.get_mut(reverse_dep) {
-                        let mut found = false;
-                        let mut exist = false;
-                        for x in &mut *v {
-                            if x.name == cur_release.name {
-                                found = true;
-                                let prev_sem_ver = semver::Version::parse(&x.version).unwrap();
-                                if sem_ver == prev_sem_ver {
-                                    exist = true;
-                                    //replace
-                                    //x.version.clone_from(&cur_release.version);
-                                }
-                                //found break;
-                                //break;
-                            }
-                        }
-                        #[allow(clippy::if_same_then_else)]
-                        if !found {
-                            v.push(model::general_model::Version::new(
-                                &cur_release.name,
-                                &cur_release.version,
-                            ));
-                        } else if !exist {
-                            v.push(model::general_model::Version::new(
-                                &cur_release.name,
-                                &cur_release.version,
-                            ));
-                        }
-                    } else {
-                        // No vec
-                        self.actually_depends_on_map.insert(
-                            reverse_dep.clone(),
-                            vec![model::general_model::Version::new(
-                                &cur_release.name,
-                                &cur_release.version,
-                            )],
-                        );
+                if requirement.matches(&sem_ver)
+                    && reverse.usage.accepts(&cur_release.name)
+                    && !self
+                        .version_parser
+                        .is_yanked(&cur_release.name, &cur_release.version)
+                {
+                    let edge = DependencyEdge {
+                        dependency: cur_release.clone(),
+                        usage: reverse.usage.clone(),
+                    };
+                    let edges = self
+                        .actually_depends_on_map
+                        .entry(reverse.dependent.clone())
+                        .or_default();
+                    // an edge to this exact version and kind may already exist
+                    if !edges.contains(&edge) {
+                        edges.push(edge);
                     }
                 }
             }
@@ -294,26 +659,66 @@ impl VersionUpdater {
     /// notice that: dependent is unique, but dependency should be newest.
     pub async fn insert_reverse_dep(
         &mut self,
-        dependency_name: &str,
-        dependency_version: &str,
-        dependent_name: &str,
-        dependent_version: &str,
+        spec: &DependencySpec,
+        dependent: &model::general_model::Version,
     ) {
-        //let dependency = model::general_model::Version::new(dependency_name, dependency_version);
-        let dependent = model::general_model::Version::new(dependent_name, dependent_version);
         self.reverse_depends_on_map
-            .entry(dependency_name.to_string())
+            .entry(spec.name.clone())
             .or_default()
-            .push((dependency_version.to_string(), dependent));
+            .push(ReverseDependency {
+                requirement: spec.version.clone(),
+                dependent: dependent.clone(),
+                usage: spec.into(),
+            });
     }
 }
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct VersionParser {
     version_map: HashMap<String, Vec<String>>,
+    /// `(name, version)` pairs yanked on crates.io, never picked by a
+    /// requirement. Not checkpointed, read again from crates.io at startup.
+    #[serde(skip)]
+    yanked: HashSet<(String, String)>,
+}
+
+/// Why a dependency requirement did not resolve to any version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnresolvedReason {
+    /// Not a requirement `semver::VersionReq` (and so Cargo) understands.
+    InvalidRequirement,
+    /// No version of the crate has been imported.
+    UnknownCrate,
+    /// Versions exist but none matches, pre-releases only match when asked for.
+    NoMatchingVersion,
+    /// Only yanked versions match.
+    Yanked,
+    /// A git dependency whose repository is not the one of the crate imported
+    /// under that name.
+    UnmatchedGitSource,
+}
+
+impl UnresolvedReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UnresolvedReason::InvalidRequirement => "invalid_requirement",
+            UnresolvedReason::UnknownCrate => "unknown_crate",
+            UnresolvedReason::NoMatchingVersion => "no_matching_version",
+            UnresolvedReason::Yanked => "yanked",
+            UnresolvedReason::UnmatchedGitSource => "unmatched_git_source",
+        }
+    }
 }
 
 impl VersionParser {
+    /// A parser knowing `version_map`, the versions of every crate name.
+    pub(crate) fn with_versions(version_map: HashMap<String, Vec<String>>) -> Self {
+        VersionParser {
+            version_map,
+            ..Default::default()
+        }
+    }
+
     pub async fn insert_version(&mut self, crate_name: &str, version: &str) {
         self.version_map
             .entry(crate_name.to_string())
@@ -332,35 +737,64 @@ impl VersionParser {
         self.version_map.remove(name);
     }
 
+    pub fn set_yanked(&mut self, yanked: impl IntoIterator<Item = (String, String)>) {
+        self.yanked = yanked.into_iter().collect();
+    }
+
+    pub(crate) fn is_yanked(&self, name: &str, version: &str) -> bool {
+        self.yanked
+            .contains(&(name.to_string(), version.to_string()))
+    }
+
     pub async fn find_latest_matching_version(
         &self,
         target_lib: &str,
         target_version: &str,
     ) -> Option<String> {
-        if let Some(lib_map) = self.version_map.get(target_lib) {
-            // if the lib exists
-            let req_str = if target_version.contains('.') {
-                format!("^{}", target_version)
-            } else {
-                format!("{}.*", target_version)
-            };
+        self.resolve(target_lib, target_version).ok()
+    }
 
-            let requirement = match semver::VersionReq::parse(&req_str) {
-                Ok(req) => req,
-                Err(_) => return None, // 如果无法解析为有效的版本请求，则返回 None
-            };
+    /// The newest version of `name` matching `requirement` as Cargo reads it:
+    /// `1.2` means `^1.2`, comma separated comparators must all hold, and a
+    /// pre-release only matches a comparator naming a pre-release of the same
+    /// `major.minor.patch`.
+    pub(crate) fn resolve(
+        &self,
+        name: &str,
+        requirement: &str,
+    ) -> Result<String, UnresolvedReason> {
+        self.resolve_where(name, requirement, |_| true)
+    }
 
-            let mut matching_versions: Vec<semver::Version> = lib_map
-                .iter()
-                .filter_map(|ver| semver::Version::parse(ver).ok()) // 将所有版本字符串解析为 Version 对象
-                .filter(|ver| requirement.matches(ver))
-                .collect();
+    /// `resolve` among the versions accepted by `candidate` only.
+    pub(crate) fn resolve_where(
+        &self,
+        name: &str,
+        requirement: &str,
+        candidate: impl Fn(&str) -> bool,
+    ) -> Result<String, UnresolvedReason> {
+        let requirement = semver::VersionReq::parse(requirement)
+            .map_err(|_| UnresolvedReason::InvalidRequirement)?;
+        let versions = self
+            .version_map
+            .get(name)
+            .ok_or(UnresolvedReason::UnknownCrate)?;
 
-            // Sort the matched versions and return the last (largest) one
-            matching_versions.sort();
-            return matching_versions.last().map(|v| v.to_string());
+        let matching: Vec<semver::Version> = versions
+            .iter()
+            .filter(|ver| candidate(ver.as_str()))
+            .filter_map(|ver| semver::Version::parse(ver).ok())
+            .filter(|ver| requirement.matches(ver))
+            .collect();
+        if matching.is_empty() {
+            return Err(UnresolvedReason::NoMatchingVersion);
         }
-        None
+        matching
+            .into_iter()
+            .filter(|ver| !self.is_yanked(name, &ver.to_string()))
+            .max()
+            .map(|ver| ver.to_string())
+            .ok_or(UnresolvedReason::Yanked)
     }
 }
 
@@ -374,17 +808,17 @@ impl VersionUpdater {
         // Calculate heap size for reverse_depends_on_map
         for (key, value) in &self.reverse_depends_on_map {
             heap_size += key.capacity() * mem::size_of::<char>(); // String capacity
-            heap_size +=
-                value.capacity() * mem::size_of::<(String, model::general_model::Version)>();
-            for (s, _) in value {
-                heap_size += s.capacity() * mem::size_of::<char>(); // String capacity
+            heap_size += value.capacity() * mem::size_of::<ReverseDependency>();
+            for reverse in value {
+                heap_size += reverse.requirement.capacity() * mem::size_of::<char>();
+                // String capacity
             }
         }
 
         // Calculate heap size for actually_depends_on_map
         for value in self.actually_depends_on_map.values() {
             heap_size += mem::size_of::<model::general_model::Version>(); // Key size
-            heap_size += value.capacity() * mem::size_of::<model::general_model::Version>();
+            heap_size += value.capacity() * mem::size_of::<DependencyEdge>();
         }
 
         format!(" [Version Updater: {}] ", stack_size + heap_size)
@@ -413,7 +847,188 @@ impl VersionParser {
 
 #[cfg(test)]
 mod tests {
-    use super::VersionParser;
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    use model::general_model::{DependencyKind, DependencySource};
+
+    use super::{
+        parse_dependency_specs, parse_feature_table, resolve_path_dependencies, Dependencies,
+        DependencySpec, UnresolvedReason, VersionParser, VersionUpdater,
+    };
+
+    #[test]
+    fn test_parse_dependency_kinds_and_targets() {
+        let manifest: toml::Value = r#"
+            [package]
+            name = "demo"
+            version = "0.1.0"
+
+            [dependencies]
+            serde = "1.0"
+            tokio = { version = "1", features = ["full"] }
+            local = { path = "../local" }
+
+            [dev-dependencies]
+            criterion = "0.5"
+
+            [build-dependencies]
+            cc = { version = "1.0" }
+
+            [target.'cfg(windows)'.dependencies]
+            winapi = "0.3"
+
+            [target.x86_64-unknown-linux-gnu.dev-dependencies]
+            libc = "0.2"
+        "#
+        .parse()
+        .unwrap();
+        let spec = |name: &str, version: &str, kind, target: Option<&str>| DependencySpec {
+            name: name.to_string(),
+            version: version.to_string(),
+            kind,
+            target: target.map(|t| t.to_string()),
+            ..Default::default()
+        };
+
+        let mut specs = parse_dependency_specs(&manifest);
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(
+            specs,
+            vec![
+                spec("cc", "1.0", DependencyKind::Build, None),
+                spec("criterion", "0.5", DependencyKind::Dev, None),
+                spec(
+                    "libc",
+                    "0.2",
+                    DependencyKind::Dev,
+                    Some("x86_64-unknown-linux-gnu")
+                ),
+                // pinned to the sibling package once the tree is known
+                DependencySpec {
+                    source: DependencySource::Path,
+                    path: Some("../local".to_string()),
+                    ..spec("local", "", DependencyKind::Normal, None)
+                },
+                spec("serde", "1.0", DependencyKind::Normal, None),
+                DependencySpec {
+                    features: vec!["full".to_string()],
+                    ..spec("tokio", "1", DependencyKind::Normal, None)
+                },
+                spec(
+                    "winapi",
+                    "0.3",
+                    DependencyKind::Normal,
+                    Some("cfg(windows)")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_renamed_optional_dependencies_and_features() {
+        let manifest: toml::Value = r#"
+            [package]
+            name = "demo"
+            version = "0.1.0"
+
+            [dependencies]
+            rt = { package = "tokio", version = "1", optional = true, default-features = false }
+            serde = { version = "1", features = ["derive"], default_features = false }
+
+            [features]
+            default = ["std"]
+            std = []
+            async = ["dep:rt", "serde/std"]
+        "#
+        .parse()
+        .unwrap();
+
+        let mut specs = parse_dependency_specs(&manifest);
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(
+            specs,
+            vec![
+                DependencySpec {
+                    name: "serde".to_string(),
+                    version: "1".to_string(),
+                    default_features: false,
+                    features: vec!["derive".to_string()],
+                    ..Default::default()
+                },
+                DependencySpec {
+                    name: "tokio".to_string(),
+                    rename: Some("rt".to_string()),
+                    version: "1".to_string(),
+                    optional: true,
+                    default_features: false,
+                    ..Default::default()
+                },
+            ]
+        );
+
+        let features = parse_feature_table(&manifest);
+        assert_eq!(features["default"], vec!["std"]);
+        assert_eq!(features["async"], vec!["dep:rt", "serde/std"]);
+        assert!(features["std"].is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_parse_and_resolve_path_and_git_dependencies() {
+        let manifest: toml::Value = r#"
+            [package]
+            name = "cli"
+            version = "0.2.0"
+
+            [dependencies]
+            core = { path = "../core" }
+            macros = { path = "../macros", version = "0.1" }
+            gone = { path = "../../elsewhere" }
+            fork = { git = "https://github.com/someone/fork" }
+        "#
+        .parse()
+        .unwrap();
+
+        let mut specs = parse_dependency_specs(&manifest);
+        specs.sort_by(|a, b| a.name.cmp(&b.name));
+        let fork = &specs[1];
+        assert_eq!(fork.name, "fork");
+        assert_eq!(fork.source, DependencySource::Git);
+        assert_eq!(fork.version, "*");
+        assert_eq!(fork.git.as_deref(), Some("https://github.com/someone/fork"));
+        assert!(specs
+            .iter()
+            .filter(|s| s.name != "fork")
+            .all(|s| s.source == DependencySource::Path));
+
+        let mut packages = BTreeMap::new();
+        packages.insert(
+            PathBuf::from("crates/core"),
+            ("core".to_string(), "0.2.0".to_string()),
+        );
+        resolve_path_dependencies(&mut specs, Path::new("crates/cli"), &packages);
+        let resolved: Vec<(&str, &str)> = specs
+            .iter()
+            .map(|s| (s.name.as_str(), s.version.as_str()))
+            .collect();
+        // `gone` leads outside of the repository and has no version to fall back on
+        assert_eq!(
+            resolved,
+            vec![("core", "=0.2.0"), ("fork", "*"), ("macros", "0.1")]
+        );
+
+        let mut parser = VersionParser::default();
+        parser.insert_version("core", "0.2.0").await;
+        parser.insert_version("core", "0.2.1").await;
+        assert_eq!(
+            parser.find_latest_matching_version("core", "=0.2.0").await,
+            Some("0.2.0".to_string())
+        );
+        assert_eq!(
+            parser.find_latest_matching_version("core", "*").await,
+            Some("0.2.1".to_string())
+        );
+    }
 
     #[tokio::test]
     async fn test_insert_and_find_version() {
@@ -458,4 +1073,142 @@ mod tests {
             None
         );
     }
+    #[tokio::test]
+    async fn test_resolve_with_cargo_semantics() {
+        let mut parser = VersionParser::default();
+        for version in [
+            "1.0.3",
+            "1.2.0",
+            "1.2.7",
+            "1.9.0",
+            "2.0.0-beta.1",
+            "2.0.0",
+            "2.1.0",
+        ] {
+            parser.insert_version("lib", version).await;
+        }
+        parser.set_yanked([("lib".to_string(), "2.1.0".to_string())]);
+
+        let resolve = |req: &str| parser.resolve("lib", req);
+        assert_eq!(resolve(">=1.2, <2"), Ok("1.9.0".to_string()));
+        assert_eq!(resolve("=1.0.3"), Ok("1.0.3".to_string()));
+        assert_eq!(resolve("~1.2"), Ok("1.2.7".to_string()));
+        assert_eq!(resolve("*"), Ok("2.0.0".to_string()));
+        // pre-releases only match when the requirement asks for one
+        assert_eq!(resolve("^2.0.0-alpha"), Ok("2.0.0".to_string()));
+        assert_eq!(resolve("=2.0.0-beta.1"), Ok("2.0.0-beta.1".to_string()));
+        assert_eq!(
+            resolve(">=2.0.0-beta.1, <2.0.0"),
+            Ok("2.0.0-beta.1".to_string())
+        );
+        assert_eq!(
+            resolve(">1.9.0, <2.0.0"),
+            Err(UnresolvedReason::NoMatchingVersion)
+        );
+        assert_eq!(resolve("=2.1.0"), Err(UnresolvedReason::Yanked));
+        assert_eq!(
+            resolve("not a req"),
+            Err(UnresolvedReason::InvalidRequirement)
+        );
+        assert_eq!(
+            parser.resolve("missing", "1"),
+            Err(UnresolvedReason::UnknownCrate)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unresolved_dependencies() {
+        let mut updater = VersionUpdater::default();
+        let manifest: toml::Value = r#"
+            [dependencies]
+            lib = ">=3"
+            missing = "1"
+            present = "0.1"
+        "#
+        .parse()
+        .unwrap();
+        updater
+            .update_depends_on(&Dependencies {
+                crate_name: "present".to_string(),
+                version: "0.1.0".to_string(),
+                ..Default::default()
+            })
+            .await;
+        updater
+            .update_depends_on(&Dependencies {
+                crate_name: "lib".to_string(),
+                version: "2.0.0".to_string(),
+                ..Default::default()
+            })
+            .await;
+        updater
+            .update_depends_on(&Dependencies {
+                crate_name: "app".to_string(),
+                version: "1.0.0".to_string(),
+                dependencies: parse_dependency_specs(&manifest),
+                ..Default::default()
+            })
+            .await;
+
+        let unresolved: Vec<(String, String)> = updater
+            .to_unresolved_dependencies()
+            .await
+            .into_iter()
+            .map(|u| (u.name, u.reason))
+            .collect();
+        assert_eq!(
+            unresolved,
+            vec![
+                ("lib".to_string(), "no_matching_version".to_string()),
+                ("missing".to_string(), "unknown_crate".to_string()),
+            ]
+        );
+        assert_eq!(updater.to_depends_on_edges().await.len(), 1);
+    }
+    #[tokio::test]
+    async fn test_depends_on_at_publish() {
+        let mut updater = VersionUpdater::default();
+        let manifest: toml::Value = "[dependencies]\nlib = \"1\"\n".parse().unwrap();
+        let release = |name: &str, version: &str, published_at: i64| Dependencies {
+            crate_name: name.to_string(),
+            version: version.to_string(),
+            published_at: Some(published_at),
+            ..Default::default()
+        };
+        updater
+            .update_depends_on(&release("lib", "1.0.0", 100))
+            .await;
+        updater
+            .update_depends_on(&Dependencies {
+                dependencies: parse_dependency_specs(&manifest),
+                ..release("app", "0.1.0", 200)
+            })
+            .await;
+        updater
+            .update_depends_on(&release("lib", "1.1.0", 300))
+            .await;
+        updater
+            .update_depends_on(&release("lib", "1.0.1", 150))
+            .await;
+
+        let targets = |edges: Vec<model::tugraph_model::DependsOn>| -> Vec<String> {
+            let mut targets: Vec<String> = edges.into_iter().map(|e| e.DST_ID).collect();
+            targets.sort();
+            targets
+        };
+        // `depends_on` picks up every newer match, the publish-time edge
+        // stays on what existed at 200
+        assert!(targets(updater.to_depends_on_edges().await).contains(&"lib/1.1.0".to_string()));
+        assert_eq!(
+            targets(updater.to_depends_on_at_publish_edges().await),
+            vec!["lib/1.0.1"]
+        );
+
+        // crates.io publish times take precedence over tag times
+        updater.set_cratesio_publish_times([("lib".to_string(), "1.1.0".to_string(), 180)]);
+        assert_eq!(
+            targets(updater.to_depends_on_at_publish_edges().await),
+            vec!["lib/1.1.0"]
+        );
+    }
 }
@@ -0,0 +1,260 @@
+//! Workspace inheritance of `Cargo.toml`.
+//!
+//! Members of a workspace may write `version.workspace = true` or
+//! `dep = { workspace = true }`, the actual values then live in the
+//! `[workspace.package]` and `[workspace.dependencies]` tables of the root
+//! manifest. Resolving them up front lets the rest of the importer read a
+//! member manifest as if everything had been written out.
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use model::general_model::DependencyKind;
+use toml::{map::Map, Value};
+
+/// The root manifest governing the package in `member_dir`: the nearest
+/// manifest with a `[workspace]` table in `member_dir` or one of its parents.
+pub(crate) fn find_workspace_root<'a>(
+    manifests: &'a BTreeMap<PathBuf, Value>,
+    member_dir: &Path,
+) -> Option<(&'a Path, &'a Value)> {
+    member_dir.ancestors().find_map(|dir| {
+        manifests
+            .get_key_value(dir)
+            .filter(|(_, manifest)| manifest.get("workspace").is_some())
+            .map(|(dir, manifest)| (dir.as_path(), manifest))
+    })
+}
+
+/// The way from `member_dir` up to its ancestor `root_dir`, e.g. `../..`.
+pub(crate) fn relative_root(member_dir: &Path, root_dir: &Path) -> PathBuf {
+    let depth = member_dir.components().count() - root_dir.components().count();
+    (0..depth).map(|_| "..").collect()
+}
+
+/// `relative` joined onto `base` with `.` and `..` folded away, `None` if it
+/// leads out of the repository.
+pub(crate) fn resolve_relative(base: &Path, relative: &str) -> Option<PathBuf> {
+    let mut resolved = PathBuf::new();
+    for component in base.join(relative).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !resolved.pop() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    Some(resolved)
+}
+
+/// Replaces the inherited package fields and dependency specs of `manifest`
+/// with the values of `root`. Fields missing from the workspace are left as
+/// they are. `root_from_member` rebases the `path` of inherited dependencies,
+/// which is relative to the root in `[workspace.dependencies]`.
+pub(crate) fn inherit_from_workspace(manifest: &mut Value, root: &Value, root_from_member: &Path) {
+    let Some(workspace) = root.get("workspace") else {
+        return;
+    };
+
+    if let Some(package) = manifest.get_mut("package").and_then(|p| p.as_table_mut()) {
+        let fields = workspace.get("package");
+        for (field, value) in package.iter_mut() {
+            if !is_inherited(value) {
+                continue;
+            }
+            if let Some(inherited) = fields.and_then(|f| f.get(field)) {
+                *value = inherited.clone();
+            }
+        }
+    }
+
+    let dependencies = workspace.get("dependencies").and_then(|d| d.as_table());
+    inherit_dependency_tables(manifest, dependencies, root_from_member);
+    if let Some(targets) = manifest.get_mut("target").and_then(|t| t.as_table_mut()) {
+        for (_, target) in targets.iter_mut() {
+            inherit_dependency_tables(target, dependencies, root_from_member);
+        }
+    }
+}
+
+fn is_inherited(value: &Value) -> bool {
+    value.get("workspace").and_then(|w| w.as_bool()) == Some(true)
+}
+
+fn inherit_dependency_tables(
+    table: &mut Value,
+    workspace_deps: Option<&Map<String, Value>>,
+    root_from_member: &Path,
+) {
+    let Some(table) = table.as_table_mut() else {
+        return;
+    };
+    for (table_name, deps) in table.iter_mut() {
+        if DependencyKind::from_table_name(table_name).is_none() {
+            continue;
+        }
+        let Some(deps) = deps.as_table_mut() else {
+            continue;
+        };
+        for (key, spec) in deps.iter_mut() {
+            if !is_inherited(spec) {
+                continue;
+            }
+            let Some(base) = workspace_deps.and_then(|d| d.get(key)) else {
+                continue;
+            };
+            if let Some(mut resolved) = merge_dependency(base, spec) {
+                if let Some(path) = resolved.get("path").and_then(|p| p.as_str()) {
+                    let path = root_from_member.join(path).to_string_lossy().into_owned();
+                    resolved["path"] = Value::String(path);
+                }
+                *spec = resolved;
+            }
+        }
+    }
+}
+
+/// The workspace spec `base` overridden by the member spec: the member may
+/// add `features` and set `optional`.
+fn merge_dependency(base: &Value, member: &Value) -> Option<Value> {
+    let mut resolved = match base {
+        Value::String(version) => {
+            let mut table = Map::new();
+            table.insert("version".to_string(), Value::String(version.clone()));
+            table
+        }
+        Value::Table(table) => table.clone(),
+        _ => return None,
+    };
+    for (key, value) in member.as_table()? {
+        match key.as_str() {
+            "workspace" => {}
+            "features" => {
+                let features = resolved
+                    .entry("features")
+                    .or_insert_with(|| Value::Array(vec![]));
+                if let (Some(features), Some(added)) = (features.as_array_mut(), value.as_array()) {
+                    for feature in added {
+                        if !features.contains(feature) {
+                            features.push(feature.clone());
+                        }
+                    }
+                }
+            }
+            _ => {
+                resolved.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Some(Value::Table(resolved))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::path::{Path, PathBuf};
+
+    use toml::Value;
+
+    use super::{find_workspace_root, inherit_from_workspace, relative_root, resolve_relative};
+
+    const ROOT: &str = r#"
+        [workspace]
+        members = ["crates/*"]
+
+        [workspace.package]
+        version = "0.3.1"
+        license = "MIT OR Apache-2.0"
+
+        [workspace.dependencies]
+        serde = { version = "1.0", features = ["derive"] }
+        log = "0.4"
+        core = { path = "crates/core", version = "0.3.1" }
+    "#;
+
+    const MEMBER: &str = r#"
+        [package]
+        name = "member"
+        version.workspace = true
+        license = { workspace = true }
+        edition = "2021"
+
+        [dependencies]
+        serde = { workspace = true, features = ["rc"], optional = true }
+        toml = "0.8"
+        core.workspace = true
+
+        [target.'cfg(unix)'.dev-dependencies]
+        log.workspace = true
+    "#;
+
+    #[test]
+    fn test_inherit_package_fields_and_dependencies() {
+        let root: Value = ROOT.parse().unwrap();
+        let mut member: Value = MEMBER.parse().unwrap();
+        inherit_from_workspace(&mut member, &root, Path::new("../.."));
+
+        let package = &member["package"];
+        assert_eq!(package["version"].as_str(), Some("0.3.1"));
+        assert_eq!(package["license"].as_str(), Some("MIT OR Apache-2.0"));
+        assert_eq!(package["edition"].as_str(), Some("2021"));
+
+        let serde = &member["dependencies"]["serde"];
+        assert_eq!(serde["version"].as_str(), Some("1.0"));
+        assert_eq!(serde["optional"].as_bool(), Some(true));
+        assert_eq!(
+            serde["features"],
+            Value::Array(vec!["derive".into(), "rc".into()])
+        );
+        assert!(serde.get("workspace").is_none());
+        assert_eq!(member["dependencies"]["toml"].as_str(), Some("0.8"));
+
+        let log = &member["target"]["cfg(unix)"]["dev-dependencies"]["log"];
+        assert_eq!(log["version"].as_str(), Some("0.4"));
+
+        let core = &member["dependencies"]["core"];
+        assert_eq!(core["path"].as_str(), Some("../../crates/core"));
+    }
+
+    #[test]
+    fn test_relative_paths() {
+        let member = Path::new("crates/member");
+        assert_eq!(relative_root(member, Path::new("")), PathBuf::from("../.."));
+        assert_eq!(relative_root(member, member), PathBuf::new());
+        assert_eq!(
+            resolve_relative(member, "../../crates/./core"),
+            Some(PathBuf::from("crates/core"))
+        );
+        assert_eq!(resolve_relative(Path::new(""), "."), Some(PathBuf::new()));
+        assert_eq!(resolve_relative(member, "../../../outside"), None);
+    }
+
+    #[test]
+    fn test_find_nearest_workspace_root() {
+        let mut manifests = BTreeMap::new();
+        manifests.insert(PathBuf::from(""), ROOT.parse::<Value>().unwrap());
+        manifests.insert(
+            PathBuf::from("crates/member"),
+            MEMBER.parse::<Value>().unwrap(),
+        );
+        manifests.insert(
+            PathBuf::from("vendor/other"),
+            "[workspace]\n[package]\nname = \"other\"\n"
+                .parse::<Value>()
+                .unwrap(),
+        );
+
+        let (root_dir, root) = find_workspace_root(&manifests, Path::new("crates/member")).unwrap();
+        assert_eq!(root_dir, Path::new(""));
+        assert_eq!(
+            root["workspace"]["package"]["version"].as_str(),
+            Some("0.3.1")
+        );
+        let (_, other) = find_workspace_root(&manifests, Path::new("vendor/other")).unwrap();
+        assert!(other.get("package").is_some());
+    }
+}
@@ -1,5 +1,7 @@
 use semver::Version;
 use std::env;
+use std::str::FromStr;
+use tokio_postgres::types::ToSql;
 use tokio_postgres::Client as PgClient;
 
 pub struct SearchModule<'a> {
@@ -7,10 +9,64 @@ pub struct SearchModule<'a> {
     table_name: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SearchSortCriteria {
+    /// Relevance weighted with downloads.
     Comprehensive,
     Relavance,
     Downloads,
+    /// Latest imported version first.
+    RecentlyUpdated,
+}
+
+impl FromStr for SearchSortCriteria {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "comprehensive" => Ok(SearchSortCriteria::Comprehensive),
+            "relevance" => Ok(SearchSortCriteria::Relavance),
+            "downloads" => Ok(SearchSortCriteria::Downloads),
+            "recently_updated" => Ok(SearchSortCriteria::RecentlyUpdated),
+            _ => Err(format!(
+                "unknown sort '{}', expected relevance, downloads, comprehensive or recently_updated",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramType {
+    Library,
+    Application,
+}
+
+impl FromStr for ProgramType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "library" => Ok(ProgramType::Library),
+            "application" => Ok(ProgramType::Application),
+            _ => Err(format!(
+                "unknown program type '{}', expected library or application",
+                s
+            )),
+        }
+    }
+}
+
+/// Conditions on the searched programs, `None` does not filter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SearchFilters {
+    pub program_type: Option<ProgramType>,
+    /// Part of the license expression, case insensitive.
+    pub license: Option<String>,
+    /// Whether a RustSec advisory names the crate.
+    pub has_advisories: Option<bool>,
+    /// Whether the program is published on crates.io.
+    pub in_cratesio: Option<bool>,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -22,6 +78,8 @@ pub struct RecommendCrate {
     pub namespace: String,
     pub max_version: String,
     pub rank: f32,
+    /// Unix time of the latest imported version, only read when sorting by it.
+    pub updated_at: Option<i64>,
 }
 
 impl<'a> SearchModule<'a> {
@@ -37,10 +95,24 @@ impl<'a> SearchModule<'a> {
         &self,
         keyword: &str,
         sort_by: SearchSortCriteria,
+        filters: &SearchFilters,
     ) -> Result<Vec<RecommendCrate>, Box<dyn std::error::Error>> {
-        let mut crates = search_crate_without_ai(self.pg_client, &self.table_name, keyword).await?;
+        let mut crates = search_crate_without_ai(
+            self.pg_client,
+            &self.table_name,
+            keyword,
+            filters,
+            sort_by == SearchSortCriteria::RecentlyUpdated,
+        )
+        .await?;
         sort_crates(&mut crates, sort_by);
-        rearrange_crates(&mut crates, keyword);
+        // 只有按相关度排序时才把同名 crate 提到最前
+        if matches!(
+            sort_by,
+            SearchSortCriteria::Relavance | SearchSortCriteria::Comprehensive
+        ) {
+            rearrange_crates(&mut crates, keyword);
+        }
         Ok(crates)
     }
 }
@@ -72,6 +144,24 @@ fn normalize_downloads(downloads: i64, max_downloads: i64) -> f32 {
 }
 
 fn sort_crates_by_downloads(crate_vec: &mut [RecommendCrate]) {
+    crate_vec.sort_by(|a, b| {
+        b.downloads
+            .cmp(&a.downloads)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| version_cmp(a, b))
+    });
+}
+
+fn sort_crates_by_updated_at(crate_vec: &mut [RecommendCrate]) {
+    crate_vec.sort_by(|a, b| {
+        b.updated_at
+            .cmp(&a.updated_at)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| version_cmp(a, b))
+    });
+}
+
+fn sort_crates_comprehensively(crate_vec: &mut [RecommendCrate]) {
     let max_downloads = crate_vec.iter().map(|c| c.downloads).max().unwrap_or(1);
 
     crate_vec.sort_by(|a, b| {
@@ -91,12 +181,18 @@ fn sort_crates_by_downloads(crate_vec: &mut [RecommendCrate]) {
 
 fn sort_crates(crate_vec: &mut [RecommendCrate], sort_by: SearchSortCriteria) {
     match sort_by {
-        SearchSortCriteria::Comprehensive | SearchSortCriteria::Relavance => {
+        SearchSortCriteria::Relavance => {
             sort_crates_by_relevance(crate_vec);
         }
+        SearchSortCriteria::Comprehensive => {
+            sort_crates_comprehensively(crate_vec);
+        }
         SearchSortCriteria::Downloads => {
             sort_crates_by_downloads(crate_vec);
         }
+        SearchSortCriteria::RecentlyUpdated => {
+            sort_crates_by_updated_at(crate_vec);
+        }
     }
 }
 
@@ -114,22 +210,92 @@ fn rearrange_crates(crates: &mut Vec<RecommendCrate>, keyword: &str) {
     crates.splice(0..0, matching_crates);
 }
 
+/// A value bound by the conditions of [`filter_conditions`].
+#[derive(Debug, PartialEq)]
+enum FilterParam {
+    Text(String),
+    Flag(bool),
+}
+
+impl FilterParam {
+    fn as_sql(&self) -> &(dyn ToSql + Sync) {
+        match self {
+            FilterParam::Text(text) => text,
+            FilterParam::Flag(flag) => flag,
+        }
+    }
+}
+
+/// The `AND ...` conditions of `filters` on `table_name`, their values are
+/// bound from `$2` on.
+fn filter_conditions(table_name: &str, filters: &SearchFilters) -> (String, Vec<FilterParam>) {
+    let mut conditions = String::new();
+    let mut params = vec![];
+    if let Some(program_type) = filters.program_type {
+        params.push(FilterParam::Text(
+            match program_type {
+                ProgramType::Library => "Library",
+                ProgramType::Application => "Application",
+            }
+            .to_string(),
+        ));
+        conditions += &format!(" AND {}.program_type = ${}", table_name, params.len() + 1);
+    }
+    if let Some(license) = &filters.license {
+        params.push(FilterParam::Text(license.clone()));
+        conditions += &format!(
+            " AND EXISTS (SELECT 1 FROM license l WHERE l.program_id = {}.id AND l.license ILIKE '%' || ${} || '%')",
+            table_name,
+            params.len() + 1
+        );
+    }
+    if let Some(has_advisories) = filters.has_advisories {
+        conditions += &format!(
+            " AND {}EXISTS (SELECT 1 FROM rustsecs r WHERE r.cratename = {}.name)",
+            if has_advisories { "" } else { "NOT " },
+            table_name
+        );
+    }
+    if let Some(in_cratesio) = filters.in_cratesio {
+        params.push(FilterParam::Flag(in_cratesio));
+        conditions += &format!(" AND {}.in_cratesio = ${}", table_name, params.len() + 1);
+    }
+    (conditions, params)
+}
+
 async fn search_crate_without_ai(
     client: &PgClient,
     table_name: &str,
     keyword: &str,
+    filters: &SearchFilters,
+    with_updated_at: bool,
 ) -> Result<Vec<RecommendCrate>, Box<dyn std::error::Error>> {
     let tsquery_keyword = keyword.replace(" ", " & ");
     let query = format!("{}:*", tsquery_keyword);
+    let (conditions, filter_params) = filter_conditions(table_name, filters);
+
+    // 版本的导入时间只在 program_versions 中
+    let updated_at = if with_updated_at {
+        format!(
+            "(SELECT EXTRACT(EPOCH FROM MAX(v.created_at))::bigint FROM program_versions v WHERE v.id = {}.id)",
+            table_name
+        )
+    } else {
+        "NULL::bigint".to_string()
+    };
 
     let statement = format!(
-        "SELECT {0}.id::text, {0}.name, {0}.description, ts_rank({0}.tsv, to_tsquery($1)) AS rank,{0}.downloads,{0}.namespace,{0}.max_version
+        "SELECT {0}.id::text, {0}.name, {0}.description, ts_rank({0}.tsv, to_tsquery($1)) AS rank,{0}.downloads,{0}.namespace,{0}.max_version,{1} AS updated_at
         FROM {0}
-        WHERE {0}.tsv @@ to_tsquery($1)
+        WHERE {0}.tsv @@ to_tsquery($1){2}
         ORDER BY rank DESC",
-        table_name
+        table_name, updated_at, conditions
     );
-    let rows = client.query(statement.as_str(), &[&query]).await?;
+    let mut params: Vec<&(dyn ToSql + Sync)> = vec![&query];
+    for param in &filter_params {
+        params.push(param.as_sql());
+    }
+    let rows = client.query(statement.as_str(), &params).await?;
     let mut recommend_crates = Vec::<RecommendCrate>::new();
 
     for row in rows.iter() {
@@ -140,6 +306,7 @@ async fn search_crate_without_ai(
         let namespace: Option<String> = row.get("namespace");
         let max_version: Option<String> = row.get("max_version");
         let rank: Option<f32> = row.get("rank");
+        let updated_at: Option<i64> = row.get("updated_at");
 
         recommend_crates.push(RecommendCrate {
             id: id.unwrap_or_default(),
@@ -149,8 +316,54 @@ async fn search_crate_without_ai(
             namespace: namespace.unwrap_or_default(),
             max_version: max_version.unwrap_or_default(),
             rank: rank.unwrap_or(0.0),
+            updated_at,
         });
     }
 
     Ok(recommend_crates)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{filter_conditions, FilterParam, ProgramType, SearchFilters, SearchSortCriteria};
+
+    #[test]
+    fn test_filter_conditions() {
+        let (conditions, params) = filter_conditions("programs", &SearchFilters::default());
+        assert!(conditions.is_empty());
+        assert!(params.is_empty());
+
+        let filters = SearchFilters {
+            program_type: Some(ProgramType::Library),
+            license: Some("MIT".to_string()),
+            has_advisories: Some(false),
+            in_cratesio: Some(true),
+        };
+        let (conditions, params) = filter_conditions("programs", &filters);
+        assert_eq!(
+            params,
+            [
+                FilterParam::Text("Library".to_string()),
+                FilterParam::Text("MIT".to_string()),
+                FilterParam::Flag(true),
+            ]
+        );
+        assert!(conditions.contains("programs.program_type = $2"));
+        assert!(conditions.contains("ILIKE '%' || $3 || '%'"));
+        assert!(conditions.contains("AND NOT EXISTS (SELECT 1 FROM rustsecs"));
+        assert!(conditions.contains("programs.in_cratesio = $4"));
+    }
+
+    #[test]
+    fn test_parse_sort() {
+        assert_eq!(
+            "recently_updated".parse::<SearchSortCriteria>(),
+            Ok(SearchSortCriteria::RecentlyUpdated)
+        );
+        assert_eq!(
+            "Relevance".parse::<SearchSortCriteria>(),
+            Ok(SearchSortCriteria::Relavance)
+        );
+        assert!("stars".parse::<SearchSortCriteria>().is_err());
+    }
+}
@@ -0,0 +1,266 @@
+//! Cypher statements with bound parameters.
+//!
+//! Values that come from users or from the crates being imported (names,
+//! namespaces, versions) are never spliced into the text of a statement,
+//! they are sent next to it as bolt parameters and referenced as `$key`.
+
+use std::fmt;
+
+pub use neo4rs::{BoltType, DeError, Node};
+use neo4rs::{Query, Row};
+use serde::de::DeserializeOwned;
+
+/// The text of a Cypher statement and the parameters it references.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CypherQuery {
+    text: String,
+    params: Vec<(String, BoltType)>,
+}
+
+impl CypherQuery {
+    pub fn new(text: impl Into<String>) -> Self {
+        CypherQuery {
+            text: text.into(),
+            params: vec![],
+        }
+    }
+
+    /// Binds `$key` to `value`, replacing an earlier binding of `key`.
+    pub fn param(mut self, key: &str, value: impl Into<BoltType>) -> Self {
+        let value = value.into();
+        match self.params.iter_mut().find(|(k, _)| k == key) {
+            Some((_, bound)) => *bound = value,
+            None => self.params.push((key.to_string(), value)),
+        }
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The value bound to `$key`, if any.
+    pub fn get_param(&self, key: &str) -> Option<&BoltType> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value)
+    }
+
+    /// The neo4rs query sent over bolt.
+    pub fn to_query(&self) -> Query {
+        self.params
+            .iter()
+            .fold(Query::new(self.text.clone()), |query, (key, value)| {
+                query.param(key, value.clone())
+            })
+    }
+}
+
+impl From<&str> for CypherQuery {
+    fn from(text: &str) -> Self {
+        CypherQuery::new(text)
+    }
+}
+
+/// Why a query returned nothing usable.
+#[derive(Debug)]
+pub enum QueryError {
+    /// The statement failed or the connection broke.
+    Bolt(neo4rs::Error),
+    /// A row does not have the shape of the type it is decoded into, a field
+    /// is missing or has another type.
+    Decode {
+        /// Index of the row in the result.
+        row: usize,
+        /// The column decoded, `None` when the whole row is.
+        column: Option<String>,
+        source: DeError,
+    },
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Bolt(e) => write!(f, "TuGraph query failed: {}", e),
+            QueryError::Decode {
+                row,
+                column: Some(column),
+                source,
+            } => write!(
+                f,
+                "cannot decode column `{}` of row {}: {}",
+                column, row, source
+            ),
+            QueryError::Decode {
+                row,
+                column: None,
+                source,
+            } => write!(f, "cannot decode row {}: {}", row, source),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            QueryError::Bolt(e) => Some(e),
+            QueryError::Decode { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<neo4rs::Error> for QueryError {
+    fn from(e: neo4rs::Error) -> Self {
+        QueryError::Bolt(e)
+    }
+}
+
+/// Decodes the `index`th row of a result, its columns as the fields of `T`,
+/// or its only column as `T` when it has one.
+pub(crate) fn decode_row<T: DeserializeOwned>(index: usize, row: &Row) -> Result<T, QueryError> {
+    row.to::<T>().map_err(|source| QueryError::Decode {
+        row: index,
+        column: None,
+        source,
+    })
+}
+
+/// Decodes `column` of the `index`th row of a result.
+pub(crate) fn decode_column<T: DeserializeOwned>(
+    index: usize,
+    row: &Row,
+    column: &str,
+) -> Result<T, QueryError> {
+    row.get::<T>(column).map_err(|source| QueryError::Decode {
+        row: index,
+        column: Some(column.to_string()),
+        source,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use neo4rs::{BoltList, BoltNode, BoltString, BoltType, Row};
+    use serde::Deserialize;
+
+    use super::{decode_column, decode_row, CypherQuery, QueryError};
+
+    const HOSTILE: &[&str] = &[
+        "it's",
+        r"back\slash",
+        "'}) DETACH DELETE n //",
+        "\" OR 1=1 //",
+        "x'}) RETURN 1 UNION MATCH (n) RETURN n //",
+    ];
+
+    #[test]
+    fn test_params_stay_out_of_the_text() {
+        for name in HOSTILE {
+            let query =
+                CypherQuery::new("MATCH (p:program {name: $name}) RETURN p").param("name", *name);
+            assert_eq!(query.text(), "MATCH (p:program {name: $name}) RETURN p");
+            assert_eq!(query.get_param("name"), Some(&BoltType::from(*name)));
+            let query = query.to_query();
+            assert!(query.has_param_key("name"));
+        }
+    }
+
+    #[test]
+    fn test_param_rebinding_and_lists() {
+        let query = CypherQuery::new("MATCH (p:program) WHERE p.name IN $names RETURN p")
+            .param("names", vec!["serde", "it's"])
+            .param("limit", 10i64)
+            .param("limit", 20i64);
+        assert_eq!(query.get_param("limit"), Some(&BoltType::from(20i64)));
+        assert_eq!(
+            query.get_param("names"),
+            Some(&BoltType::from(vec!["serde", "it's"]))
+        );
+        assert_eq!(query.get_param("missing"), None);
+        assert!(query.to_query().has_param_key("names"));
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Dependency {
+        name_and_version: String,
+        kind: Option<String>,
+    }
+
+    #[derive(Deserialize, Debug, PartialEq)]
+    struct Program {
+        id: String,
+        name: String,
+    }
+
+    fn row(columns: Vec<(&str, BoltType)>) -> Row {
+        let (fields, data): (Vec<_>, Vec<_>) = columns
+            .into_iter()
+            .map(|(field, value)| (BoltType::from(field), value))
+            .unzip();
+        Row::new(BoltList::from(fields), BoltList::from(data))
+    }
+
+    #[test]
+    fn test_decode_row_columns() {
+        let dependency = row(vec![
+            ("name_and_version", "serde/1.0.200".into()),
+            ("kind", BoltType::from(None::<String>)),
+        ]);
+        assert_eq!(
+            decode_row::<Dependency>(0, &dependency).unwrap(),
+            Dependency {
+                name_and_version: "serde/1.0.200".to_string(),
+                kind: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_decode_node_column() {
+        let mut properties = neo4rs::BoltMap::default();
+        properties.put(BoltString::from("id"), "1".into());
+        properties.put(BoltString::from("name"), "serde".into());
+        let node = BoltNode::new(
+            42.into(),
+            BoltList::from(vec![BoltType::from("program")]),
+            properties,
+        );
+        let program = row(vec![("p", BoltType::Node(node))]);
+        let expected = Program {
+            id: "1".to_string(),
+            name: "serde".to_string(),
+        };
+        assert_eq!(
+            decode_column::<Program>(0, &program, "p").unwrap(),
+            expected
+        );
+        // a lone column is decoded as the whole row
+        assert_eq!(decode_row::<Program>(0, &program).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        let missing = row(vec![("kind", "dev".into()), ("target", "".into())]);
+        let err = decode_row::<Dependency>(3, &missing).unwrap_err();
+        assert!(matches!(
+            err,
+            QueryError::Decode {
+                row: 3,
+                column: None,
+                ..
+            }
+        ));
+        assert!(err.to_string().contains("name_and_version"), "{}", err);
+
+        let mistyped = row(vec![("name_and_version", 7i64.into())]);
+        let err = decode_column::<String>(1, &mistyped, "name_and_version").unwrap_err();
+        assert!(matches!(
+            &err,
+            QueryError::Decode { row: 1, column: Some(column), .. } if column == "name_and_version"
+        ));
+        assert!(err
+            .to_string()
+            .starts_with("cannot decode column `name_and_version` of row 1"));
+    }
+}
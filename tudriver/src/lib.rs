@@ -1,3 +1,6 @@
+pub mod cypher;
+pub mod migration;
+pub mod schema;
 pub mod tugraph_client;
 
 #[cfg(test)]
@@ -0,0 +1,457 @@
+//! Versioned migrations of the graph schema, the TuGraph counterpart of
+//! `database/migration`.
+//!
+//! Applied versions are recorded as `graph_schema_version` vertices. Each
+//! change is checked against the live schema first, so a graph imported
+//! with `lgraph_import` and `import.config`, which already has some labels
+//! but no recorded version, is migrated without recreating them.
+
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    error::Error,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use model::tugraph_model::DependsOn;
+
+use crate::{
+    cypher::CypherQuery,
+    schema::{
+        edge_labels, vertex_labels, EdgeLabel, GraphProperties, LabelKind, Property, PropertyType,
+        VertexLabel, APPLICATION_VERSION, DEPENDS_ON, DEPENDS_ON_AT_PUBLISH, LIBRARY_VERSION,
+        PROGRAM, VERSION,
+    },
+    tugraph_client::TuGraphClient,
+};
+
+/// The vertex label recording applied migrations.
+pub const SCHEMA_VERSION_LABEL: &str = "graph_schema_version";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaChange {
+    CreateVertexLabel(VertexLabel),
+    CreateEdgeLabel(EdgeLabel),
+    AddFields {
+        kind: LabelKind,
+        label: &'static str,
+        fields: Vec<Property>,
+    },
+    AddIndex {
+        label: &'static str,
+        field: &'static str,
+        unique: bool,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub struct GraphMigration {
+    pub version: i64,
+    pub name: &'static str,
+    pub changes: Vec<SchemaChange>,
+}
+
+/// Every migration, in version order.
+pub fn migrations() -> Vec<GraphMigration> {
+    let dependency_fields = DependsOn::properties();
+    vec![
+        GraphMigration {
+            version: 1,
+            name: "create_labels",
+            changes: vertex_labels()
+                .into_iter()
+                .map(SchemaChange::CreateVertexLabel)
+                .chain(edge_labels().into_iter().map(SchemaChange::CreateEdgeLabel))
+                .collect(),
+        },
+        GraphMigration {
+            version: 2,
+            name: "add_dependency_properties",
+            changes: vec![
+                SchemaChange::AddFields {
+                    kind: LabelKind::Edge,
+                    label: DEPENDS_ON,
+                    fields: dependency_fields.clone(),
+                },
+                SchemaChange::AddFields {
+                    kind: LabelKind::Edge,
+                    label: DEPENDS_ON_AT_PUBLISH,
+                    fields: dependency_fields,
+                },
+                SchemaChange::AddFields {
+                    kind: LabelKind::Vertex,
+                    label: VERSION,
+                    fields: vec![Property::optional("features", PropertyType::String)],
+                },
+            ],
+        },
+        GraphMigration {
+            version: 3,
+            name: "add_lookup_indexes",
+            changes: [
+                (PROGRAM, "name"),
+                (PROGRAM, "namespace"),
+                (LIBRARY_VERSION, "name"),
+                (APPLICATION_VERSION, "name"),
+            ]
+            .into_iter()
+            .map(|(label, field)| SchemaChange::AddIndex {
+                label,
+                field,
+                unique: false,
+            })
+            .collect(),
+        },
+    ]
+}
+
+/// The labels, fields and indexes a graph has.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LiveSchema {
+    pub fields: HashMap<(LabelKind, String), HashSet<String>>,
+    pub indexes: HashSet<(String, String)>,
+}
+
+impl LiveSchema {
+    pub async fn load(client: &TuGraphClient) -> Result<Self, Box<dyn Error>> {
+        let mut schema = LiveSchema::default();
+        let vertex_labels = client.list_vertex_labels().await?;
+        let edge_labels = client.list_edge_labels().await?;
+        for (kind, labels) in [
+            (LabelKind::Vertex, vertex_labels),
+            (LabelKind::Edge, edge_labels),
+        ] {
+            for label in labels {
+                let fields = client.list_label_fields(kind, &label).await?;
+                schema
+                    .fields
+                    .insert((kind, label), fields.into_iter().collect());
+            }
+        }
+        schema.indexes = client.list_indexes().await?.into_iter().collect();
+        Ok(schema)
+    }
+
+    pub fn has_label(&self, kind: LabelKind, label: &str) -> bool {
+        self.fields.contains_key(&(kind, label.to_string()))
+    }
+
+    /// The part of `change` the graph lacks, `None` when it has all of it.
+    /// An existing label is kept as it is, fields are added to it by later
+    /// migrations.
+    pub fn missing(&self, change: &SchemaChange) -> Option<SchemaChange> {
+        match change {
+            SchemaChange::CreateVertexLabel(label) => {
+                (!self.has_label(LabelKind::Vertex, label.name)).then(|| change.clone())
+            }
+            SchemaChange::CreateEdgeLabel(label) => {
+                (!self.has_label(LabelKind::Edge, label.name)).then(|| change.clone())
+            }
+            SchemaChange::AddFields {
+                kind,
+                label,
+                fields,
+            } => {
+                let existing = self.fields.get(&(*kind, label.to_string()))?;
+                let fields: Vec<Property> = fields
+                    .iter()
+                    .filter(|field| !existing.contains(field.name))
+                    .copied()
+                    .collect();
+                (!fields.is_empty()).then_some(SchemaChange::AddFields {
+                    kind: *kind,
+                    label,
+                    fields,
+                })
+            }
+            SchemaChange::AddIndex { label, field, .. } => (!self
+                .indexes
+                .contains(&(label.to_string(), field.to_string())))
+            .then(|| change.clone()),
+        }
+    }
+
+    /// Updates the schema after `change` was applied.
+    pub fn record(&mut self, change: &SchemaChange) {
+        let (key, fields) = match change {
+            SchemaChange::CreateVertexLabel(label) => (
+                (LabelKind::Vertex, label.name.to_string()),
+                &label.properties,
+            ),
+            SchemaChange::CreateEdgeLabel(label) => {
+                ((LabelKind::Edge, label.name.to_string()), &label.properties)
+            }
+            SchemaChange::AddFields {
+                kind,
+                label,
+                fields,
+            } => ((*kind, label.to_string()), fields),
+            SchemaChange::AddIndex { label, field, .. } => {
+                self.indexes.insert((label.to_string(), field.to_string()));
+                return;
+            }
+        };
+        self.fields
+            .entry(key)
+            .or_default()
+            .extend(fields.iter().map(|field| field.name.to_string()));
+    }
+}
+
+/// The changes each migration after `applied` still has to make to `live`,
+/// in order, with the version they belong to.
+pub fn plan(
+    migrations: &[GraphMigration],
+    applied: i64,
+    live: &LiveSchema,
+) -> Vec<(i64, Vec<SchemaChange>)> {
+    let mut live = live.clone();
+    migrations
+        .iter()
+        .filter(|migration| migration.version > applied)
+        .map(|migration| {
+            let mut changes = vec![];
+            for change in &migration.changes {
+                if let Some(missing) = live.missing(change) {
+                    live.record(&missing);
+                    changes.push(missing);
+                }
+            }
+            (migration.version, changes)
+        })
+        .collect()
+}
+
+pub struct GraphMigrator<'a> {
+    client: &'a TuGraphClient,
+}
+
+impl<'a> GraphMigrator<'a> {
+    pub fn new(client: &'a TuGraphClient) -> Self {
+        GraphMigrator { client }
+    }
+
+    /// The newest applied migration, 0 for none.
+    pub async fn applied_version(&self) -> Result<i64, Box<dyn Error>> {
+        let versions: Vec<i64> = self
+            .client
+            .query_column_as(
+                &CypherQuery::new(format!(
+                    "MATCH (v:{}) RETURN v.version AS version",
+                    SCHEMA_VERSION_LABEL
+                )),
+                "version",
+            )
+            .await?;
+        Ok(versions.into_iter().max().unwrap_or(0))
+    }
+
+    /// Applies the pending migrations, returns their versions.
+    pub async fn up(&self) -> Result<Vec<i64>, Box<dyn Error>> {
+        let mut live = LiveSchema::load(self.client).await?;
+        if !live.has_label(LabelKind::Vertex, SCHEMA_VERSION_LABEL) {
+            let label = schema_version_label();
+            self.apply(&SchemaChange::CreateVertexLabel(label.clone()))
+                .await?;
+            live.record(&SchemaChange::CreateVertexLabel(label));
+        }
+        let migrations = migrations();
+        let applied = self.applied_version().await?;
+        let mut versions = vec![];
+        for (version, changes) in plan(&migrations, applied, &live) {
+            for change in &changes {
+                self.apply(change).await?;
+                live.record(change);
+            }
+            let name = migrations
+                .iter()
+                .find(|migration| migration.version == version)
+                .map_or("", |migration| migration.name);
+            self.record_version(version, name).await?;
+            tracing::info!("applied graph migration {} {}", version, name);
+            versions.push(version);
+        }
+        Ok(versions)
+    }
+
+    async fn apply(&self, change: &SchemaChange) -> Result<(), Box<dyn Error>> {
+        match change {
+            SchemaChange::CreateVertexLabel(label) => {
+                let specs: Vec<_> = label.properties.iter().map(Property::field_spec).collect();
+                self.client
+                    .create_vertex_label(label.name, label.primary, &specs)
+                    .await
+            }
+            SchemaChange::CreateEdgeLabel(label) => {
+                let constraints: Vec<_> = label
+                    .constraints
+                    .iter()
+                    .map(|(src, dst)| (src.to_string(), dst.to_string()))
+                    .collect();
+                let specs: Vec<_> = label.properties.iter().map(Property::field_spec).collect();
+                self.client
+                    .create_edge_label(label.name.to_string(), &constraints, &specs)
+                    .await
+            }
+            SchemaChange::AddFields {
+                kind,
+                label,
+                fields,
+            } => self.client.add_label_fields(*kind, label, fields).await,
+            SchemaChange::AddIndex {
+                label,
+                field,
+                unique,
+            } => self.client.add_index(label, field, *unique).await,
+        }
+    }
+
+    async fn record_version(&self, version: i64, name: &str) -> Result<(), Box<dyn Error>> {
+        let applied_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64;
+        let query = CypherQuery::new(format!(
+            "CREATE (v:{} {{version: $version, name: $name, applied_at: $applied_at}})",
+            SCHEMA_VERSION_LABEL
+        ))
+        .param("version", version)
+        .param("name", name)
+        .param("applied_at", applied_at);
+        self.client.exec_cypher(&query).await?;
+        Ok(())
+    }
+}
+
+fn schema_version_label() -> VertexLabel {
+    VertexLabel {
+        name: SCHEMA_VERSION_LABEL,
+        primary: "version",
+        properties: vec![
+            Property::required("version", PropertyType::Int64),
+            Property::required("name", PropertyType::String),
+            Property::required("applied_at", PropertyType::Int64),
+        ],
+    }
+}
+
+/// Connects to `TUGRAPH_CRATESPRO_DB` and applies the pending migrations.
+pub async fn migrate_from_env() -> Result<Vec<i64>, Box<dyn Error>> {
+    let client = TuGraphClient::with_max_connections(
+        &env::var("TUGRAPH_BOLT_URL")?,
+        &env::var("TUGRAPH_USER_NAME")?,
+        &env::var("TUGRAPH_USER_PASSWORD")?,
+        &env::var("TUGRAPH_CRATESPRO_DB")?,
+        1,
+    )
+    .await?;
+    GraphMigrator::new(&client).up().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::{migrations, plan, LiveSchema, SchemaChange};
+    use crate::schema::{edge_labels, vertex_labels, LabelKind, DEPENDS_ON, VERSION};
+
+    fn names(changes: &[SchemaChange]) -> Vec<String> {
+        changes
+            .iter()
+            .map(|change| match change {
+                SchemaChange::CreateVertexLabel(label) => format!("vertex {}", label.name),
+                SchemaChange::CreateEdgeLabel(label) => format!("edge {}", label.name),
+                SchemaChange::AddFields { label, fields, .. } => format!(
+                    "fields {} {}",
+                    label,
+                    fields.iter().map(|f| f.name).collect::<Vec<_>>().join(",")
+                ),
+                SchemaChange::AddIndex { label, field, .. } => {
+                    format!("index {}.{}", label, field)
+                }
+            })
+            .collect()
+    }
+
+    /// The schema `import.config` created before the dependency edges had
+    /// properties.
+    fn imported_schema() -> LiveSchema {
+        let mut live = LiveSchema::default();
+        for label in vertex_labels() {
+            let fields = label
+                .properties
+                .iter()
+                .map(|p| p.name.to_string())
+                .filter(|name| !(label.name == VERSION && name == "features"));
+            live.fields.insert(
+                (LabelKind::Vertex, label.name.to_string()),
+                fields.collect(),
+            );
+        }
+        for label in edge_labels() {
+            live.fields
+                .insert((LabelKind::Edge, label.name.to_string()), HashSet::new());
+        }
+        live
+    }
+
+    #[test]
+    fn test_versions_are_ordered() {
+        let versions: Vec<i64> = migrations().iter().map(|m| m.version).collect();
+        assert_eq!(versions.first(), Some(&1));
+        assert!(versions.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_plan_fresh_graph() {
+        let plan = plan(&migrations(), 0, &LiveSchema::default());
+        assert_eq!(plan.len(), 3);
+        let (version, created) = &plan[0];
+        assert_eq!(*version, 1);
+        assert_eq!(created.len(), vertex_labels().len() + edge_labels().len());
+        // vertex labels before the edges between them
+        assert_eq!(names(created)[0], "vertex program");
+        assert!(names(created).last().unwrap().starts_with("edge "));
+        // the labels were created with every field
+        assert_eq!(plan[1], (2, vec![]));
+        assert_eq!(
+            names(&plan[2].1),
+            [
+                "index program.name",
+                "index program.namespace",
+                "index library_version.name",
+                "index application_version.name",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_imported_graph() {
+        let mut live = imported_schema();
+        live.indexes
+            .insert(("program".to_string(), "name".to_string()));
+        live.fields
+            .get_mut(&(LabelKind::Edge, DEPENDS_ON.to_string()))
+            .unwrap()
+            .insert("kind".to_string());
+        let plan = plan(&migrations(), 0, &live);
+        assert_eq!(plan[0], (1, vec![]));
+        assert_eq!(
+            names(&plan[1].1),
+            [
+                "fields depends_on target,rename,requirement,optional,default_features,features,source",
+                "fields depends_on_at_publish kind,target,rename,requirement,optional,default_features,features,source",
+                "fields version features",
+            ]
+        );
+        assert_eq!(plan[2].1.len(), 3);
+    }
+
+    #[test]
+    fn test_plan_skips_applied_versions() {
+        assert!(plan(&migrations(), 3, &LiveSchema::default()).is_empty());
+        let plan = plan(&migrations(), 2, &imported_schema());
+        assert_eq!(plan.len(), 1);
+        assert_eq!(plan[0].0, 3);
+    }
+}
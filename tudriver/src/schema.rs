@@ -0,0 +1,353 @@
+//! The labels of the crates-pro graph, derived from `model::tugraph_model`.
+//!
+//! Every model type stored in TuGraph implements [`GraphProperties`], the
+//! vertex and edge labels are built from it, so a field added to a model
+//! struct shows up here (and in the tests below) instead of only in
+//! `import.config`. [`crate::migration`] creates and migrates them.
+
+use model::tugraph_model::{
+    Application, ApplicationVersion, DependsOn, HasDepVersion, HasType, HasVersion, Library,
+    LibraryVersion, Program, Version,
+};
+
+use self::PropertyType::{Bool, Int64, String as Str};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyType {
+    String,
+    Int64,
+    Bool,
+}
+
+impl PropertyType {
+    /// The name of the type in TuGraph procedures.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PropertyType::String => "STRING",
+            PropertyType::Int64 => "INT64",
+            PropertyType::Bool => "BOOL",
+        }
+    }
+
+    /// The value existing vertices and edges get when a field is added.
+    pub fn default_literal(&self) -> &'static str {
+        match self {
+            PropertyType::String => "''",
+            PropertyType::Int64 => "0",
+            PropertyType::Bool => "false",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Property {
+    pub name: &'static str,
+    pub ty: PropertyType,
+    pub optional: bool,
+}
+
+impl Property {
+    pub const fn required(name: &'static str, ty: PropertyType) -> Self {
+        Property {
+            name,
+            ty,
+            optional: false,
+        }
+    }
+
+    pub const fn optional(name: &'static str, ty: PropertyType) -> Self {
+        Property {
+            name,
+            ty,
+            optional: true,
+        }
+    }
+
+    /// `(name, type, optional)`, the field spec of the label helpers of
+    /// [`crate::tugraph_client::TuGraphClient`].
+    pub fn field_spec(&self) -> (String, String, bool) {
+        (
+            self.name.to_string(),
+            self.ty.as_str().to_string(),
+            self.optional,
+        )
+    }
+}
+
+/// The properties a model type is stored with, the `SRC_ID` and `DST_ID`
+/// of edge types are not properties.
+pub trait GraphProperties {
+    fn properties() -> Vec<Property>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LabelKind {
+    Vertex,
+    Edge,
+}
+
+impl LabelKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LabelKind::Vertex => "vertex",
+            LabelKind::Edge => "edge",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VertexLabel {
+    pub name: &'static str,
+    pub primary: &'static str,
+    pub properties: Vec<Property>,
+}
+
+impl VertexLabel {
+    pub fn of<T: GraphProperties>(name: &'static str, primary: &'static str) -> Self {
+        VertexLabel {
+            name,
+            primary,
+            properties: T::properties(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdgeLabel {
+    pub name: &'static str,
+    /// The `(source, destination)` vertex labels the edge may connect.
+    pub constraints: Vec<(&'static str, &'static str)>,
+    pub properties: Vec<Property>,
+}
+
+impl EdgeLabel {
+    pub fn of<T: GraphProperties>(
+        name: &'static str,
+        constraints: &[(&'static str, &'static str)],
+    ) -> Self {
+        EdgeLabel {
+            name,
+            constraints: constraints.to_vec(),
+            properties: T::properties(),
+        }
+    }
+}
+
+pub const PROGRAM: &str = "program";
+pub const LIBRARY: &str = "library";
+pub const APPLICATION: &str = "application";
+pub const LIBRARY_VERSION: &str = "library_version";
+pub const APPLICATION_VERSION: &str = "application_version";
+pub const VERSION: &str = "version";
+pub const HAS_TYPE: &str = "has_type";
+pub const HAS_VERSION: &str = "has_version";
+pub const HAS_DEP_VERSION: &str = "has_dep_version";
+pub const DEPENDS_ON: &str = "depends_on";
+pub const DEPENDS_ON_AT_PUBLISH: &str = "depends_on_at_publish";
+
+/// The vertex labels of the graph, in creation order.
+pub fn vertex_labels() -> Vec<VertexLabel> {
+    vec![
+        VertexLabel::of::<Program>(PROGRAM, "id"),
+        VertexLabel::of::<Library>(LIBRARY, "id"),
+        VertexLabel::of::<Application>(APPLICATION, "id"),
+        VertexLabel::of::<LibraryVersion>(LIBRARY_VERSION, "name_and_version"),
+        VertexLabel::of::<ApplicationVersion>(APPLICATION_VERSION, "name_and_version"),
+        VertexLabel::of::<Version>(VERSION, "name_and_version"),
+    ]
+}
+
+/// The edge labels of the graph, their endpoints created first.
+pub fn edge_labels() -> Vec<EdgeLabel> {
+    vec![
+        EdgeLabel::of::<HasType>(HAS_TYPE, &[(PROGRAM, LIBRARY), (PROGRAM, APPLICATION)]),
+        EdgeLabel::of::<HasVersion>(
+            HAS_VERSION,
+            &[
+                (LIBRARY, LIBRARY_VERSION),
+                (APPLICATION, APPLICATION_VERSION),
+            ],
+        ),
+        EdgeLabel::of::<HasDepVersion>(
+            HAS_DEP_VERSION,
+            &[(LIBRARY_VERSION, VERSION), (APPLICATION_VERSION, VERSION)],
+        ),
+        EdgeLabel::of::<DependsOn>(DEPENDS_ON, &[(VERSION, VERSION)]),
+        EdgeLabel::of::<DependsOn>(DEPENDS_ON_AT_PUBLISH, &[(VERSION, VERSION)]),
+    ]
+}
+
+impl GraphProperties for Program {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::required("id", Str),
+            Property::required("name", Str),
+            Property::optional("description", Str),
+            Property::optional("namespace", Str),
+            Property::optional("max_version", Str),
+            Property::optional("github_url", Str),
+            Property::optional("mega_url", Str),
+            Property::optional("doc_url", Str),
+        ]
+    }
+}
+
+impl GraphProperties for Library {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::required("id", Str),
+            Property::required("name", Str),
+            Property::required("downloads", Int64),
+            Property::optional("cratesio", Str),
+        ]
+    }
+}
+
+impl GraphProperties for Application {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::required("id", Str),
+            Property::required("name", Str),
+        ]
+    }
+}
+
+impl GraphProperties for LibraryVersion {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::required("id", Str),
+            Property::required("name_and_version", Str),
+            Property::required("name", Str),
+            Property::required("version", Str),
+            Property::required("documentation", Str),
+        ]
+    }
+}
+
+impl GraphProperties for ApplicationVersion {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::required("id", Str),
+            Property::required("name_and_version", Str),
+            Property::required("name", Str),
+            Property::required("version", Str),
+        ]
+    }
+}
+
+impl GraphProperties for Version {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::required("name_and_version", Str),
+            Property::optional("features", Str),
+        ]
+    }
+}
+
+impl GraphProperties for HasType {
+    fn properties() -> Vec<Property> {
+        vec![]
+    }
+}
+
+impl GraphProperties for HasVersion {
+    fn properties() -> Vec<Property> {
+        vec![]
+    }
+}
+
+impl GraphProperties for HasDepVersion {
+    fn properties() -> Vec<Property> {
+        vec![]
+    }
+}
+
+impl GraphProperties for DependsOn {
+    fn properties() -> Vec<Property> {
+        vec![
+            Property::required("kind", Str),
+            Property::optional("target", Str),
+            Property::optional("rename", Str),
+            Property::optional("requirement", Str),
+            Property::required("optional", Bool),
+            Property::required("default_features", Bool),
+            Property::optional("features", Str),
+            Property::optional("source", Str),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+
+    use model::tugraph_model::{
+        Application, ApplicationVersion, DependsOn, HasDepVersion, HasType, HasVersion, Library,
+        LibraryVersion, Program, Version,
+    };
+    use serde::Serialize;
+    use serde_json::Value;
+
+    use super::{edge_labels, vertex_labels, GraphProperties, PropertyType};
+
+    /// The serialized fields of `T`, what the importer writes for it.
+    fn fields<T: Serialize + Default>() -> BTreeSet<String> {
+        match serde_json::to_value(T::default()).unwrap() {
+            Value::Object(map) => map
+                .into_iter()
+                .map(|(key, _)| key)
+                .filter(|key| key != "SRC_ID" && key != "DST_ID")
+                .collect(),
+            other => panic!("not a struct: {}", other),
+        }
+    }
+
+    fn assert_matches_model<T: Serialize + Default + GraphProperties>() {
+        let properties: BTreeSet<String> = T::properties()
+            .iter()
+            .map(|property| property.name.to_string())
+            .collect();
+        assert_eq!(properties, fields::<T>(), "{}", std::any::type_name::<T>());
+    }
+
+    #[test]
+    fn test_properties_match_model() {
+        assert_matches_model::<Program>();
+        assert_matches_model::<Library>();
+        assert_matches_model::<Application>();
+        assert_matches_model::<LibraryVersion>();
+        assert_matches_model::<ApplicationVersion>();
+        assert_matches_model::<Version>();
+        assert_matches_model::<HasType>();
+        assert_matches_model::<HasVersion>();
+        assert_matches_model::<HasDepVersion>();
+        assert_matches_model::<DependsOn>();
+        assert_eq!(
+            Library::properties()
+                .iter()
+                .find(|p| p.name == "downloads")
+                .map(|p| p.ty),
+            Some(PropertyType::Int64)
+        );
+    }
+
+    #[test]
+    fn test_labels_are_consistent() {
+        let vertices = vertex_labels();
+        for vertex in &vertices {
+            let primary = vertex
+                .properties
+                .iter()
+                .find(|p| p.name == vertex.primary)
+                .unwrap_or_else(|| panic!("{} has no primary field", vertex.name));
+            assert!(!primary.optional, "{}", vertex.name);
+        }
+        for edge in edge_labels() {
+            assert!(!edge.constraints.is_empty(), "{}", edge.name);
+            for (src, dst) in &edge.constraints {
+                assert!(vertices.iter().any(|v| v.name == *src), "{}", src);
+                assert!(vertices.iter().any(|v| v.name == *dst), "{}", dst);
+            }
+        }
+    }
+}
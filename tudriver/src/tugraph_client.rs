@@ -5,6 +5,122 @@ use std::error::Error;
 use std::fs::File;
 use std::io::{self, Read};
 
+use serde::{de::DeserializeOwned, Deserialize};
+
+use crate::cypher::{decode_column, decode_row, CypherQuery, QueryError};
+use crate::schema::{LabelKind, Property};
+
+/// A row of `db.getVertexSchema` or `db.getEdgeSchema`.
+#[derive(Deserialize)]
+struct LabelSchema {
+    #[serde(default)]
+    properties: Vec<FieldInfo>,
+}
+
+#[derive(Deserialize)]
+struct FieldInfo {
+    name: String,
+}
+
+/// A row of `db.listIndexes`, its columns were renamed in TuGraph 4.
+#[derive(Deserialize)]
+struct IndexInfo {
+    #[serde(alias = "label_name")]
+    label: String,
+    #[serde(alias = "field_name")]
+    field: String,
+}
+
+/// Label and field names are spliced into procedure calls, they can only be
+/// identifiers.
+fn check_identifier(name: &str) -> Result<(), io::Error> {
+    let valid = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if valid {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid label or field name `{}`", name),
+        ))
+    }
+}
+
+/// The `'name', 'TYPE', optional` arguments of `db.createVertexLabel` and
+/// `db.createEdgeLabel` for `field_specs`, each after a comma. Types are
+/// quoted in every procedure call.
+fn field_spec_args(field_specs: &[(String, String, bool)]) -> Result<String, io::Error> {
+    let mut args = String::new();
+    for (name, type_, optional) in field_specs {
+        check_identifier(name)?;
+        check_identifier(type_)?;
+        args.push_str(&format!(", '{}', '{}', {}", name, type_, optional));
+    }
+    Ok(args)
+}
+
+/// The call of [`TuGraphClient::create_vertex_label`].
+fn create_vertex_label_call(
+    label_name: &str,
+    primary_field: &str,
+    field_specs: &[(String, String, bool)],
+) -> Result<String, io::Error> {
+    check_identifier(label_name)?;
+    check_identifier(primary_field)?;
+    Ok(format!(
+        "CALL db.createVertexLabel('{}', '{}'{})",
+        label_name,
+        primary_field,
+        field_spec_args(field_specs)?
+    ))
+}
+
+/// The call of [`TuGraphClient::create_edge_label`].
+fn create_edge_label_call(
+    label_name: &str,
+    edge_constraints: &[(String, String)],
+    field_specs: &[(String, String, bool)],
+) -> Result<String, io::Error> {
+    check_identifier(label_name)?;
+    let mut constraints = vec![];
+    for (start_label, end_label) in edge_constraints {
+        check_identifier(start_label)?;
+        check_identifier(end_label)?;
+        constraints.push(format!("[\"{}\", \"{}\"]", start_label, end_label));
+    }
+    Ok(format!(
+        "CALL db.createEdgeLabel('{}', '[{}]'{})",
+        label_name,
+        constraints.join(", "),
+        field_spec_args(field_specs)?
+    ))
+}
+
+/// The call of [`TuGraphClient::add_label_fields`].
+fn add_label_fields_call(
+    kind: LabelKind,
+    label_name: &str,
+    fields: &[Property],
+) -> Result<String, io::Error> {
+    check_identifier(label_name)?;
+    let mut specs = vec![];
+    for field in fields {
+        check_identifier(field.name)?;
+        specs.push(format!(
+            "['{}', '{}', {}, {}]",
+            field.name,
+            field.ty.as_str(),
+            field.ty.default_literal(),
+            field.optional
+        ));
+    }
+    Ok(format!(
+        "CALL db.alterLabelAddFields('{}', '{}', {})",
+        kind.as_str(),
+        label_name,
+        specs.join(", ")
+    ))
+}
+
 /// doc: https://tugraph-db.readthedocs.io/zh-cn/latest/5.developer-manual/6.interface/1.query/1.cypher.html
 /// https://github.com/TuGraph-family/tugraph-db/blob/master/src/cypher/procedure/procedure.h
 #[derive(Clone)]
@@ -24,13 +140,25 @@ impl TuGraphClient {
         user: &str,
         password: &str,
         db: &str,
+    ) -> Result<Self, Box<dyn Error>> {
+        Self::with_max_connections(uri, user, password, db, 1000).await
+    }
+
+    /// Same as [`TuGraphClient::new`], with at most `max_connections` bolt
+    /// connections open in the pool of the client.
+    pub async fn with_max_connections(
+        uri: &str,
+        user: &str,
+        password: &str,
+        db: &str,
+        max_connections: usize,
     ) -> Result<Self, Box<dyn Error>> {
         let graph_name = if db.is_empty() { "default" } else { db };
         let config = ConfigBuilder::default()
             .uri(uri)
             .user(user)
             .password(password)
-            .max_connections(1000)
+            .max_connections(max_connections)
             .fetch_size(10000)
             .db(graph_name)
             .build()?;
@@ -38,7 +166,7 @@ impl TuGraphClient {
             "Begin to connect to Tugraph, uri: {uri}, user: {user}, password: {password}, db: {db}"
         );
 
-        let graph = Graph::connect(config).await.unwrap();
+        let graph = Graph::connect(config).await?;
         tracing::info!("Success to connect to Tugraph");
         Ok(TuGraphClient { graph })
     }
@@ -59,26 +187,130 @@ impl TuGraphClient {
         }
     }
 
-    pub async fn exec_query(&self, q: &str) -> Result<Vec<String>, Box<dyn Error>> {
-        let mut labels = vec![];
-        //tracing::info!("start query");
-        let mut result = self.graph.execute(query(q)).await?;
-        //tracing::info!("end query");
+    /// Runs the constant query `q`, each row as a JSON object string. Queries
+    /// built from input go through [`TuGraphClient::exec_cypher`] with their
+    /// values bound as parameters.
+    pub async fn exec_query(&self, q: &'static str) -> Result<Vec<String>, Box<dyn Error>> {
+        self.exec_cypher(&CypherQuery::new(q)).await
+    }
+
+    /// Runs `q` with its parameters bound, each row as a JSON object string.
+    pub async fn exec_cypher(&self, q: &CypherQuery) -> Result<Vec<String>, Box<dyn Error>> {
+        let rows = self.query_as::<Value>(q).await?;
+        Ok(rows
+            .iter()
+            .map(|value| serde_json::to_string(value).unwrap())
+            .collect())
+    }
+
+    /// Runs `q` and decodes every row into a `T`, the returned columns as its
+    /// fields (alias them with `AS`), or the only column as a whole, so
+    /// `RETURN p` decodes the properties of the node `p`.
+    pub async fn query_as<T: DeserializeOwned>(
+        &self,
+        q: &CypherQuery,
+    ) -> Result<Vec<T>, QueryError> {
+        let mut result = self.graph.execute(q.to_query()).await?;
+        let mut rows = vec![];
         while let Some(row) = result.next().await? {
-            let value: Value = row.to().unwrap(); // 打印出 row 的内容以调试
-                                                  //println!("{:#?}", value);
-            labels.push(serde_json::to_string(&value).unwrap());
+            rows.push(decode_row(rows.len(), &row)?);
         }
-        Ok(labels)
+        Ok(rows)
     }
 
-    pub async fn list_edge_labels(&self) -> Result<String, Box<dyn Error>> {
-        let mut labels = String::default();
-        let mut result = self.graph.execute(query("CALL db.edgeLabels()")).await?;
+    /// Runs `q` and decodes the `column` of every row into a `T`.
+    pub async fn query_column_as<T: DeserializeOwned>(
+        &self,
+        q: &CypherQuery,
+        column: &str,
+    ) -> Result<Vec<T>, QueryError> {
+        let mut result = self.graph.execute(q.to_query()).await?;
+        let mut values = vec![];
         while let Some(row) = result.next().await? {
-            labels = row.to().unwrap();
+            values.push(decode_column(values.len(), &row, column)?);
         }
-        Ok(labels)
+        Ok(values)
+    }
+
+    /// The edge labels of the graph.
+    pub async fn list_edge_labels(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .query_as(&CypherQuery::new("CALL db.edgeLabels()"))
+            .await?)
+    }
+
+    /// The vertex labels of the graph.
+    pub async fn list_vertex_labels(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        Ok(self
+            .query_as(&CypherQuery::new("CALL db.vertexLabels()"))
+            .await?)
+    }
+
+    /// The fields of the vertex or edge label `label_name`.
+    pub async fn list_label_fields(
+        &self,
+        kind: LabelKind,
+        label_name: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        check_identifier(label_name)?;
+        let procedure = match kind {
+            LabelKind::Vertex => "db.getVertexSchema",
+            LabelKind::Edge => "db.getEdgeSchema",
+        };
+        let schemas: Vec<LabelSchema> = self
+            .query_as(&CypherQuery::new(format!(
+                "CALL {}('{}')",
+                procedure, label_name
+            )))
+            .await?;
+        Ok(schemas
+            .into_iter()
+            .flat_map(|schema| schema.properties)
+            .map(|field| field.name)
+            .collect())
+    }
+
+    /// Adds `fields` to the vertex or edge label `label_name`, existing
+    /// vertices or edges get the default value of their type.
+    pub async fn add_label_fields(
+        &self,
+        kind: LabelKind,
+        label_name: &str,
+        fields: &[Property],
+    ) -> Result<(), Box<dyn Error>> {
+        let query_string = add_label_fields_call(kind, label_name, fields)?;
+        tracing::info!("Query: {}", query_string);
+        self.graph.run(query(&query_string)).await?;
+        Ok(())
+    }
+
+    /// The `(label, field)` pairs with an index.
+    pub async fn list_indexes(&self) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let indexes: Vec<IndexInfo> = self
+            .query_as(&CypherQuery::new("CALL db.listIndexes()"))
+            .await?;
+        Ok(indexes
+            .into_iter()
+            .map(|index| (index.label, index.field))
+            .collect())
+    }
+
+    /// Indexes the field `field_name` of the vertex label `label_name`.
+    pub async fn add_index(
+        &self,
+        label_name: &str,
+        field_name: &str,
+        unique: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        check_identifier(label_name)?;
+        check_identifier(field_name)?;
+        let query_string = format!(
+            "CALL db.addIndex('{}', '{}', {})",
+            label_name, field_name, unique
+        );
+        tracing::info!("Query: {}", query_string);
+        self.graph.run(query(&query_string)).await?;
+        Ok(())
     }
 
     /// Creates a vertex label in the database.
@@ -100,27 +332,15 @@ impl TuGraphClient {
         primary_field: &str,
         field_specs: &[(String, String, bool)],
     ) -> Result<(), Box<dyn Error>> {
-        let mut fields_string = field_specs
-            .iter()
-            .map(|(name, type_, option)| format!("'{}', {}, {}", name, type_, option))
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        fields_string =
-            if !fields_string.is_empty() { ", " } else { "" }.to_string() + &fields_string;
-
-        let query_string = format!(
-            "CALL db.createVertexLabel('{}', '{}'{})",
-            label_name, primary_field, fields_string
-        );
-        println!("Query: {}", query_string);
+        let query_string = create_vertex_label_call(label_name, primary_field, field_specs)?;
+        tracing::info!("Query: {}", query_string);
         self.graph.run(query(&query_string)).await?;
         Ok(())
     }
 
     pub async fn create_subgraph(&self, graph_name: &str) -> Result<(), Box<dyn Error>> {
         let query_string = format!("CALL dbms.graph.createGraph('{}')", graph_name);
-        println!("Query: {}", query_string);
+        tracing::info!("Query: {}", query_string);
         self.graph.run(query(&query_string)).await?;
         Ok(())
     }
@@ -149,29 +369,8 @@ impl TuGraphClient {
         edge_constraints: &[(String, String)],
         field_specs: &[(String, String, bool)],
     ) -> Result<(), Box<dyn Error>> {
-        let constraint_strings = edge_constraints
-            .iter()
-            .map(|(start_label, end_label)| format!("[\"{}\", \"{}\"]", start_label, end_label))
-            .collect::<Vec<_>>()
-            .join(", ");
-
-        let mut fields_string = field_specs
-            .iter()
-            .map(|(name, type_, optional)| {
-                let option_str = if *optional { "true" } else { "false" };
-                format!("'{}', '{}', {}", name, type_, option_str)
-            })
-            .collect::<Vec<_>>()
-            .join(", ");
-        fields_string =
-            if !fields_string.is_empty() { ", " } else { "" }.to_string() + &fields_string;
-
-        let query_string = format!(
-            "CALL db.createEdgeLabel('{}', '[{}]'{})",
-            label_name, constraint_strings, fields_string
-        );
-
-        println!("Query: {}", query_string);
+        let query_string = create_edge_label_call(&label_name, edge_constraints, field_specs)?;
+        tracing::info!("Query: {}", query_string);
         self.graph.run(query(&query_string)).await?;
         Ok(())
     }
@@ -224,7 +423,7 @@ impl TuGraphClient {
 
         self.graph.run(query(&query_string)).await.unwrap();
 
-        println!("load plugin {}", plugin_name);
+        tracing::info!("load plugin {}", plugin_name);
         Ok(())
     }
 
@@ -285,7 +484,7 @@ impl TuGraphClient {
         // In a real-world scenario, you should handle potential errors properly,
         // e.g., if the plugin does not exist or if the arguments are invalid.
         self.graph.run(query(&query_string)).await?;
-        println!("delete plugin {}", plugin_name);
+        tracing::info!("delete plugin {}", plugin_name);
         Ok(())
     }
 
@@ -391,3 +590,80 @@ impl TuGraphClient {
         Err(io::Error::new(io::ErrorKind::NotFound, "No data found").into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{add_label_fields_call, create_edge_label_call, create_vertex_label_call};
+    use crate::schema::{edge_labels, vertex_labels, LabelKind, Property, PropertyType};
+
+    fn specs(fields: &[Property]) -> Vec<(String, String, bool)> {
+        fields.iter().map(Property::field_spec).collect()
+    }
+
+    #[test]
+    fn test_create_vertex_label_call() {
+        let fields = [
+            Property::required("id", PropertyType::String),
+            Property::optional("downloads", PropertyType::Int64),
+        ];
+        assert_eq!(
+            create_vertex_label_call("library", "id", &specs(&fields)).unwrap(),
+            "CALL db.createVertexLabel('library', 'id', 'id', 'STRING', false, 'downloads', 'INT64', true)"
+        );
+        assert_eq!(
+            create_vertex_label_call("program", "id", &[]).unwrap(),
+            "CALL db.createVertexLabel('program', 'id')"
+        );
+        assert!(create_vertex_label_call("program') RETURN 1 //", "id", &[]).is_err());
+        for label in vertex_labels() {
+            assert!(
+                create_vertex_label_call(label.name, label.primary, &specs(&label.properties))
+                    .is_ok()
+            );
+        }
+    }
+
+    #[test]
+    fn test_create_edge_label_call() {
+        let constraints = [
+            ("version".to_string(), "version".to_string()),
+            ("library".to_string(), "library_version".to_string()),
+        ];
+        let fields = [Property::optional("kind", PropertyType::String)];
+        assert_eq!(
+            create_edge_label_call("depends_on", &constraints, &specs(&fields)).unwrap(),
+            "CALL db.createEdgeLabel('depends_on', '[[\"version\", \"version\"], [\"library\", \"library_version\"]]', 'kind', 'STRING', true)"
+        );
+        assert_eq!(
+            create_edge_label_call("has_type", &[], &[]).unwrap(),
+            "CALL db.createEdgeLabel('has_type', '[]')"
+        );
+        let injected = [("version\"]".to_string(), "version".to_string())];
+        assert!(create_edge_label_call("depends_on", &injected, &[]).is_err());
+        for label in edge_labels() {
+            let constraints: Vec<_> = label
+                .constraints
+                .iter()
+                .map(|(src, dst)| (src.to_string(), dst.to_string()))
+                .collect();
+            assert!(
+                create_edge_label_call(label.name, &constraints, &specs(&label.properties)).is_ok()
+            );
+        }
+    }
+
+    #[test]
+    fn test_add_label_fields_call() {
+        let fields = [
+            Property::optional("features", PropertyType::String),
+            Property::required("optional", PropertyType::Bool),
+            Property::required("downloads", PropertyType::Int64),
+        ];
+        assert_eq!(
+            add_label_fields_call(LabelKind::Edge, "depends_on", &fields).unwrap(),
+            "CALL db.alterLabelAddFields('edge', 'depends_on', ['features', 'STRING', '', true], ['optional', 'BOOL', false, false], ['downloads', 'INT64', 0, false])"
+        );
+        let injected = [Property::optional("x', 'y", PropertyType::String)];
+        assert!(add_label_fields_call(LabelKind::Vertex, "version", &injected).is_err());
+    }
+}